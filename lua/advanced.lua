@@ -33,7 +33,7 @@ local function check_for_updates()
     -- check for updates
     print('checking for software updates')
     local update = exec_update()
-    assert(update:status() == 0)
+    assert(update:status_code() == 0)
     print('completed software update')
     -- launch the "updated" software
     software = exec_software()
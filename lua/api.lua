@@ -19,7 +19,7 @@ print('child1 stdout:', child1:stdout())
 print('child1 stderr is empty:', child1:stderr() == nil)
 
 -- Check that process exited with code 0
-print('child1 exited normally:', child1:status() == 0)
+print('child1 exited normally:', child1:status_code() == 0)
 
 -- Start a child process asynchronously
 local child2 = init.exec('sleep', 2)
@@ -28,7 +28,7 @@ local child2 = init.exec('sleep', 2)
 child2:kill()
 
 -- Verify that the child process was killed
-print('child2 exited with SIGKILL:', child2:status() == init.signal.SIGKILL)
+print('child2 exited with SIGKILL:', child2:status_code() == init.signal.SIGKILL)
 
 -- Start another child process asynchronously
 local child3 = init.exec('sleep', 2)
@@ -37,7 +37,7 @@ local child3 = init.exec('sleep', 2)
 init.kill(child3:pid(), init.signal.SIGTERM)
 
 -- Verify that the child process was killed
-print('child3 exited with SIGTERM:', child3:status() == init.signal.SIGTERM)
+print('child3 exited with SIGTERM:', child3:status_code() == init.signal.SIGTERM)
 
 -- Define an asynchronous job that prints the current time and arguments
 local function job(...)
@@ -8,5 +8,5 @@ local child1 = init.exec("sleep", 1)
 local child2 = init.exec("echo", "hello")
 
 -- Wait for the child processes to finish
-assert(child1:status() == 0)
-assert(child2:status() == 0)
+assert(child1:status_code() == 0)
+assert(child2:status_code() == 0)
@@ -0,0 +1,386 @@
+use mlua::prelude::*;
+
+use crate::cleanup::CLEANUP;
+
+/// A single command line option accepted by a script
+#[derive(Debug, Default)]
+struct OptionSpec {
+    short: Option<char>,
+    long: Option<String>,
+    value: Option<String>,
+    help: String,
+}
+
+impl OptionSpec {
+    /// Key used for the option in the result table
+    fn key(&self) -> String {
+        match (&self.long, self.short) {
+            (Some(long), _) => long.clone(),
+            (None, Some(short)) => short.to_string(),
+            (None, None) => String::new(),
+        }
+    }
+
+    /// Left column of the option in the usage text
+    fn signature(&self) -> String {
+        let mut names = Vec::new();
+        if let Some(short) = self.short {
+            names.push(format!("-{}", short));
+        }
+        if let Some(long) = &self.long {
+            names.push(format!("--{}", long));
+        }
+        let mut signature = names.join(", ");
+        if let Some(value) = &self.value {
+            signature.push(' ');
+            signature.push_str(value);
+        }
+        signature
+    }
+}
+
+/// Result of parsing arguments against a list of options
+#[derive(Debug, Default, PartialEq)]
+struct Parsed {
+    values: Vec<(String, Option<String>)>,
+    positional: Vec<String>,
+    help: bool,
+}
+
+/// Check whether the auto generated help option is shadowed by `options`
+fn has_help(options: &[OptionSpec]) -> (bool, bool) {
+    let short = options.iter().any(|opt| opt.short == Some('h'));
+    let long = options
+        .iter()
+        .any(|opt| opt.long.as_deref() == Some("help"));
+    (short, long)
+}
+
+/// Parse getopt-style arguments
+fn parse(options: &[OptionSpec], argv: &[String]) -> Result<Parsed, String> {
+    let (short_help, long_help) = has_help(options);
+    let mut parsed = Parsed::default();
+    let mut iter = argv.iter();
+    while let Some(arg) = iter.next() {
+        if arg == "--" {
+            parsed.positional.extend(iter.by_ref().cloned());
+        } else if let Some(long) = arg.strip_prefix("--") {
+            let (name, inline) = match long.split_once('=') {
+                Some((name, value)) => (name, Some(value.to_string())),
+                None => (long, None),
+            };
+            if name == "help" && !long_help {
+                parsed.help = true;
+                continue;
+            }
+            let opt = options
+                .iter()
+                .find(|opt| opt.long.as_deref() == Some(name))
+                .ok_or_else(|| format!("unknown option '--{}'", name))?;
+            let value = match (&opt.value, inline) {
+                (Some(_), Some(value)) => Some(value),
+                (Some(_), None) => Some(
+                    iter.next()
+                        .cloned()
+                        .ok_or_else(|| format!("option '--{}' requires a value", name))?,
+                ),
+                (None, Some(_)) => return Err(format!("option '--{}' takes no value", name)),
+                (None, None) => None,
+            };
+            parsed.values.push((opt.key(), value));
+        } else if arg.len() > 1 && arg.starts_with('-') {
+            let chars: Vec<char> = arg.chars().skip(1).collect();
+            for (i, c) in chars.iter().enumerate() {
+                if *c == 'h' && !short_help {
+                    parsed.help = true;
+                    continue;
+                }
+                let opt = options
+                    .iter()
+                    .find(|opt| opt.short == Some(*c))
+                    .ok_or_else(|| format!("unknown option '-{}'", c))?;
+                if opt.value.is_none() {
+                    parsed.values.push((opt.key(), None));
+                    continue;
+                }
+                // the rest of the argument or the next argument is the value
+                let rest: String = chars[i + 1..].iter().collect();
+                let value = match rest.is_empty() {
+                    true => iter
+                        .next()
+                        .cloned()
+                        .ok_or_else(|| format!("option '-{}' requires a value", c))?,
+                    false => rest,
+                };
+                parsed.values.push((opt.key(), Some(value)));
+                break;
+            }
+        } else {
+            parsed.positional.push(arg.clone());
+        }
+    }
+    Ok(parsed)
+}
+
+/// Build the usage text for a script
+fn usage(name: &str, synopsis: &str, description: &str, options: &[OptionSpec]) -> String {
+    let mut rows: Vec<(String, String)> = options
+        .iter()
+        .map(|opt| (opt.signature(), opt.help.clone()))
+        .collect();
+    let (short_help, long_help) = has_help(options);
+    if !short_help || !long_help {
+        let help = OptionSpec {
+            short: (!short_help).then_some('h'),
+            long: (!long_help).then(|| "help".to_string()),
+            ..Default::default()
+        };
+        rows.push((help.signature(), "show this help message".to_string()));
+    }
+    let width = rows.iter().map(|(sig, _)| sig.len()).max().unwrap_or(0);
+    let mut text = format!("Usage: {} {}\n", name, synopsis);
+    if !description.is_empty() {
+        text.push_str(&format!("\n{}\n", description));
+    }
+    text.push_str("\nOptions:\n");
+    for (sig, help) in rows {
+        text.push_str(format!("  {:width$}  {}", sig, help, width = width).trim_end());
+        text.push('\n');
+    }
+    text
+}
+
+/// Convert a Lua option definition into an `OptionSpec`
+fn option_spec(table: &LuaTable) -> LuaResult<OptionSpec> {
+    let short = table
+        .get::<Option<String>>("short")?
+        .and_then(|short| short.chars().next());
+    let long = table.get::<Option<String>>("long")?;
+    if short.is_none() && long.is_none() {
+        return Err(LuaError::runtime(
+            "option requires a 'short' or 'long' name",
+        ));
+    }
+    let value = match table.get::<LuaValue>("value")? {
+        LuaValue::Nil | LuaValue::Boolean(false) => None,
+        LuaValue::Boolean(true) => Some("VALUE".to_string()),
+        value => Some(value.to_string()?),
+    };
+    let help = table.get::<Option<String>>("help")?.unwrap_or_default();
+    Ok(OptionSpec {
+        short,
+        long,
+        value,
+        help,
+    })
+}
+
+/// Collect the script arguments from the global `arg` table
+fn script_args(lua: &Lua) -> LuaResult<Vec<String>> {
+    match lua.globals().get::<Option<LuaTable>>("arg")? {
+        Some(arg) => arg.sequence_values::<String>().collect(),
+        None => Ok(Vec::new()),
+    }
+}
+
+/// Parse script arguments from Lua using a table of option definitions
+async fn lua_parse(lua: Lua, (spec, argv): (LuaTable, Option<LuaTable>)) -> LuaResult<LuaTable> {
+    let definitions = spec
+        .sequence_values::<LuaTable>()
+        .collect::<LuaResult<Vec<_>>>()?;
+    let options = definitions
+        .iter()
+        .map(option_spec)
+        .collect::<LuaResult<Vec<_>>>()?;
+    let argv = match argv {
+        Some(argv) => argv.sequence_values::<String>().collect::<LuaResult<_>>()?,
+        None => script_args(&lua)?,
+    };
+    let name = match spec.get::<Option<String>>("name")? {
+        Some(name) => name,
+        None => lua
+            .globals()
+            .get::<Option<LuaTable>>("arg")?
+            .map(|arg| arg.get::<Option<String>>(0))
+            .transpose()?
+            .flatten()
+            .unwrap_or_else(|| "script".to_string()),
+    };
+    let synopsis = spec
+        .get::<Option<String>>("usage")?
+        .unwrap_or_else(|| "[options] [args...]".to_string());
+    let description = spec
+        .get::<Option<String>>("description")?
+        .unwrap_or_default();
+    let usage = usage(&name, &synopsis, &description, &options);
+    let parsed = parse(&options, &argv)
+        .map_err(|err| LuaError::runtime(format!("{}: {} (see --help)", name, err)))?;
+    if parsed.help {
+        print!("{}", usage);
+        CLEANUP.run();
+        std::process::exit(0);
+    }
+    let result = lua.create_table()?;
+    // defaults first so that parsed values override them
+    for (opt, definition) in options.iter().zip(definitions.iter()) {
+        let default = match opt.value {
+            Some(_) => definition.get::<LuaValue>("default")?,
+            None => LuaValue::Boolean(false),
+        };
+        result.set(opt.key(), default)?;
+    }
+    for (key, value) in parsed.values {
+        match value {
+            Some(value) => result.set(key, value)?,
+            None => result.set(key, true)?,
+        }
+    }
+    for value in parsed.positional {
+        result.push(value)?;
+    }
+    result.set("usage", usage)?;
+    Ok(result)
+}
+
+/// Return the `init.args` Lua table
+pub fn args_table(lua: &Lua) -> LuaResult<LuaTable> {
+    let table = lua.create_table()?;
+    table.set("parse", lua.create_async_function(lua_parse)?)?;
+    Ok(table)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_options() -> Vec<OptionSpec> {
+        vec![
+            OptionSpec {
+                short: Some('v'),
+                long: Some("verbose".to_string()),
+                help: "be verbose".to_string(),
+                ..Default::default()
+            },
+            OptionSpec {
+                short: Some('p'),
+                long: Some("port".to_string()),
+                value: Some("PORT".to_string()),
+                help: "listen port".to_string(),
+            },
+            OptionSpec {
+                short: Some('q'),
+                ..Default::default()
+            },
+        ]
+    }
+
+    fn argv(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_long() {
+        let parsed = parse(&test_options(), &argv(&["--verbose", "--port=80", "a"])).unwrap();
+        assert_eq!(
+            parsed.values,
+            vec![
+                ("verbose".to_string(), None),
+                ("port".to_string(), Some("80".to_string()))
+            ]
+        );
+        assert_eq!(parsed.positional, argv(&["a"]));
+    }
+
+    #[test]
+    fn test_parse_short() {
+        let parsed = parse(&test_options(), &argv(&["-vq", "-p", "80", "-p8080"])).unwrap();
+        assert_eq!(
+            parsed.values,
+            vec![
+                ("verbose".to_string(), None),
+                ("q".to_string(), None),
+                ("port".to_string(), Some("80".to_string())),
+                ("port".to_string(), Some("8080".to_string())),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_positional() {
+        let parsed = parse(&test_options(), &argv(&["a", "-", "--", "-v", "b"])).unwrap();
+        assert!(parsed.values.is_empty());
+        assert_eq!(parsed.positional, argv(&["a", "-", "-v", "b"]));
+    }
+
+    #[test]
+    fn test_parse_help() {
+        assert!(parse(&test_options(), &argv(&["-h"])).unwrap().help);
+        assert!(parse(&test_options(), &argv(&["--help"])).unwrap().help);
+    }
+
+    #[test]
+    fn test_parse_err() {
+        let options = test_options();
+        assert!(parse(&options, &argv(&["--unknown"])).is_err());
+        assert!(parse(&options, &argv(&["-x"])).is_err());
+        assert!(parse(&options, &argv(&["--port"])).is_err());
+        assert!(parse(&options, &argv(&["-p"])).is_err());
+        assert!(parse(&options, &argv(&["--verbose=1"])).is_err());
+    }
+
+    #[test]
+    fn test_usage() {
+        let text = usage("test", "[options]", "A test script", &test_options());
+        assert!(text.starts_with("Usage: test [options]\n\nA test script\n"));
+        assert!(text.contains("  -v, --verbose    be verbose\n"));
+        assert!(text.contains("  -p, --port PORT  listen port\n"));
+        assert!(text.contains("  -h, --help       show this help message\n"));
+    }
+
+    #[test]
+    fn test_lua_parse() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            let spec: LuaTable = lua
+                .load(
+                    r#"{
+                        { short = "v", long = "verbose" },
+                        { short = "p", long = "port", value = "PORT", default = 8080 },
+                        { long = "name", value = true },
+                    }"#,
+                )
+                .eval()
+                .unwrap();
+            let argv = lua
+                .create_sequence_from(["-v", "--name", "x", "a"])
+                .unwrap();
+            let result = lua_parse(lua.clone(), (spec, Some(argv))).await.unwrap();
+            assert!(result.get::<bool>("verbose").unwrap());
+            assert_eq!(result.get::<i32>("port").unwrap(), 8080);
+            assert_eq!(result.get::<String>("name").unwrap(), "x");
+            assert_eq!(result.get::<String>(1).unwrap(), "a");
+        });
+    }
+
+    #[test]
+    fn test_lua_parse_arg() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            let arg = lua.create_sequence_from(["-q"]).unwrap();
+            lua.globals().set("arg", arg).unwrap();
+            let spec: LuaTable = lua.load(r#"{ { short = "q" } }"#).eval().unwrap();
+            let result = lua_parse(lua.clone(), (spec, None)).await.unwrap();
+            assert!(result.get::<bool>("q").unwrap());
+        });
+    }
+
+    #[test]
+    fn test_lua_parse_err() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            let spec = lua.create_table().unwrap();
+            let argv = lua.create_sequence_from(["--bad"]).unwrap();
+            assert!(lua_parse(lua, (spec, Some(argv))).await.is_err());
+        });
+    }
+}
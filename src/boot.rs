@@ -0,0 +1,250 @@
+use std::{
+    os::unix::{fs::MetadataExt, process::CommandExt},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+};
+
+use mlua::prelude::*;
+
+use crate::{cleanup::CLEANUP, process, unix};
+
+/// What the supervisor does after shutting down on ctrl-alt-del as pid 1
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+enum Action {
+    #[default]
+    Shutdown,
+    Reboot,
+    Poweroff,
+}
+
+impl Action {
+    /// Parse an action from its name
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "shutdown" => Some(Action::Shutdown),
+            "reboot" => Some(Action::Reboot),
+            "poweroff" => Some(Action::Poweroff),
+            _ => None,
+        }
+    }
+}
+
+/// Action taken after a ctrl-alt-del shutdown
+static ACTION: Mutex<Action> = Mutex::new(Action::Shutdown);
+
+/// Whether ctrl-alt-del was pressed
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// Check whether the supervisor is the init process of its pid namespace
+pub fn is_pid1() -> bool {
+    std::process::id() == 1
+}
+
+/// Have the kernel send `SIGINT` on ctrl-alt-del instead of rebooting immediately
+pub fn setup() {
+    if is_pid1() {
+        // fails in containers, where ctrl-alt-del is not delivered anyway
+        let _ = unix::reboot(unix::RB_DISABLE_CAD);
+    }
+}
+
+/// Record that `SIGINT` arrived, which is ctrl-alt-del when running as pid 1
+pub fn interrupted() {
+    if is_pid1() {
+        REQUESTED.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Reboot or power off after shutting down if ctrl-alt-del asked for it
+pub fn finish() -> std::io::Result<()> {
+    if !REQUESTED.load(Ordering::SeqCst) {
+        return Ok(());
+    }
+    match *ACTION.lock().unwrap_or_else(|err| err.into_inner()) {
+        Action::Shutdown => Ok(()),
+        Action::Reboot => unix::reboot(unix::RB_AUTOBOOT),
+        Action::Poweroff => unix::reboot(unix::RB_POWER_OFF),
+    }
+}
+
+/// Pseudo-filesystems mounted at boot with their type, flags, and data, parents first
+const PSEUDO: [(&str, &str, u64, Option<&str>); 4] = [
+    (
+        "/proc",
+        "proc",
+        unix::MS_NOSUID | unix::MS_NODEV | unix::MS_NOEXEC,
+        None,
+    ),
+    (
+        "/sys",
+        "sysfs",
+        unix::MS_NOSUID | unix::MS_NODEV | unix::MS_NOEXEC,
+        None,
+    ),
+    ("/dev", "devtmpfs", unix::MS_NOSUID, Some("mode=0755")),
+    (
+        "/dev/pts",
+        "devpts",
+        unix::MS_NOSUID | unix::MS_NOEXEC,
+        Some("gid=5,mode=620,ptmxmode=666"),
+    ),
+];
+
+/// Check whether a directory is a mount point by comparing its device with its parent's
+fn is_mount_point(path: &Path) -> bool {
+    let parent = path.parent().unwrap_or(path);
+    match (std::fs::metadata(path), std::fs::metadata(parent)) {
+        (Ok(dir), Ok(parent)) => dir.dev() != parent.dev() || dir.ino() == parent.ino(),
+        _ => false,
+    }
+}
+
+/// Mount the pseudo-filesystems which are missing, returning their targets
+fn mount_pseudo() -> std::io::Result<Vec<String>> {
+    let mut mounted = Vec::new();
+    for (target, fstype, flags, data) in PSEUDO {
+        if is_mount_point(Path::new(target)) {
+            continue;
+        }
+        std::fs::create_dir_all(target)?;
+        unix::mount(Some(fstype), target, Some(fstype), flags, data).map_err(|err| {
+            std::io::Error::new(err.kind(), format!("failed to mount '{}': {}", target, err))
+        })?;
+        mounted.push(target.to_string());
+    }
+    Ok(mounted)
+}
+
+/// Mount /proc, /sys, /dev, and /dev/pts when they are missing and running as pid 1
+///
+/// Returns the targets which were mounted, and does nothing outside pid 1
+/// unless `force` is set.
+pub async fn bootstrap(_lua: Lua, options: Option<LuaTable>) -> LuaResult<Vec<String>> {
+    let force = match options {
+        Some(options) => options.get::<Option<bool>>("force")?.unwrap_or(false),
+        None => false,
+    };
+    if !force && !is_pid1() {
+        return Ok(Vec::new());
+    }
+    Ok(smol::unblock(mount_pseudo).await?)
+}
+
+/// Return the path of the running executable, even if it was replaced since it started
+fn current_exe() -> std::io::Result<PathBuf> {
+    let exe = std::env::current_exe()?;
+    // the kernel marks an executable which was replaced or removed as deleted
+    match exe.to_string_lossy().strip_suffix(" (deleted)") {
+        Some(path) => Ok(PathBuf::from(path)),
+        None => Ok(exe),
+    }
+}
+
+/// Replace the supervisor with a new executable run with the same arguments
+///
+/// The process keeps its pid but starts the script over. Only returns on error,
+/// and refuses while children are running since their handles and pipes are
+/// not handed over to the new executable.
+pub async fn reexec(_lua: Lua, path: Option<String>) -> LuaResult<()> {
+    let running = process::running_children();
+    if running > 0 {
+        return Err(LuaError::runtime(format!(
+            "cannot re-exec with {} running children",
+            running
+        )));
+    }
+    let exe = match path {
+        Some(path) => PathBuf::from(path),
+        None => current_exe()?,
+    };
+    // keep readiness files and temporaries when the exec would fail anyway
+    if !exe.is_file() {
+        return Err(LuaError::runtime(format!(
+            "failed to re-exec '{}': not a file",
+            exe.display()
+        )));
+    }
+    CLEANUP.run();
+    unix::flush_stdio();
+    let err = std::process::Command::new(&exe)
+        .args(std::env::args_os().skip(1))
+        .exec();
+    Err(LuaError::runtime(format!(
+        "failed to re-exec '{}': {}",
+        exe.display(),
+        err
+    )))
+}
+
+/// Choose whether ctrl-alt-del shuts down the supervisor, or also reboots or powers off
+pub async fn on_ctrl_alt_del(_lua: Lua, name: String) -> LuaResult<()> {
+    let action = Action::parse(&name)
+        .ok_or_else(|| LuaError::runtime(format!("unknown ctrl-alt-del action '{}'", name)))?;
+    *ACTION.lock().unwrap_or_else(|err| err.into_inner()) = action;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_action_parse() {
+        assert_eq!(Action::parse("shutdown"), Some(Action::Shutdown));
+        assert_eq!(Action::parse("reboot"), Some(Action::Reboot));
+        assert_eq!(Action::parse("poweroff"), Some(Action::Poweroff));
+        assert_eq!(Action::parse("halt"), None);
+    }
+
+    #[test]
+    fn test_interrupted() {
+        interrupted();
+        assert_eq!(REQUESTED.load(Ordering::SeqCst), is_pid1());
+        assert!(finish().is_ok());
+    }
+
+    #[test]
+    fn test_is_mount_point() {
+        assert!(is_mount_point(Path::new("/")));
+        assert!(is_mount_point(Path::new("/proc")));
+        assert!(!is_mount_point(Path::new("/proc/self")));
+        assert!(!is_mount_point(Path::new("/does/not/exist")));
+    }
+
+    #[test]
+    fn test_bootstrap() {
+        smol::block_on(async {
+            let mounted = bootstrap(Lua::new(), None).await.unwrap();
+            assert!(is_pid1() || mounted.is_empty());
+        });
+    }
+
+    #[test]
+    fn test_current_exe() {
+        let exe = current_exe().unwrap();
+        assert!(exe.is_file());
+    }
+
+    #[test]
+    fn test_reexec_err() {
+        smol::block_on(async {
+            let err = reexec(Lua::new(), Some("/does/not/exist".to_string())).await;
+            let err = err.unwrap_err().to_string();
+            assert!(err.contains("failed to re-exec") || err.contains("running children"));
+        });
+    }
+
+    #[test]
+    fn test_on_ctrl_alt_del_err() {
+        smol::block_on(async {
+            let err = on_ctrl_alt_del(Lua::new(), "halt".to_string()).await;
+            assert!(err
+                .unwrap_err()
+                .to_string()
+                .contains("unknown ctrl-alt-del action"));
+        });
+    }
+}
@@ -0,0 +1,218 @@
+use std::path::{Path, PathBuf};
+
+use crate::errors::{not_found, AppResult};
+
+/// Command line options of `luavisors bundle`
+#[derive(Debug, Default, PartialEq)]
+struct BundleOptions {
+    script: PathBuf,
+    output: Option<PathBuf>,
+    deps: bool,
+}
+
+/// Parse the arguments following `bundle`
+fn parse_options(args: &[String]) -> Result<BundleOptions, String> {
+    let mut options = BundleOptions::default();
+    let mut script = None;
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--deps" => options.deps = true,
+            "-o" | "--output" => {
+                let output = iter
+                    .next()
+                    .ok_or_else(|| format!("option '{}' requires a value", arg))?;
+                options.output = Some(PathBuf::from(output));
+            }
+            arg if arg.starts_with('-') => return Err(format!("unknown option '{}'", arg)),
+            arg if script.is_none() => script = Some(PathBuf::from(arg)),
+            arg => return Err(format!("unexpected argument '{}'", arg)),
+        }
+    }
+    options.script = script.ok_or("missing script to bundle")?;
+    Ok(options)
+}
+
+/// Check whether a byte can be part of a Lua identifier
+fn is_ident(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || byte == b'_'
+}
+
+/// Find the module names passed as string literals to `require`
+fn find_requires(source: &str) -> Vec<String> {
+    let bytes = source.as_bytes();
+    let mut names = Vec::new();
+    let mut start = 0;
+    while let Some(found) = source[start..].find("require") {
+        let at = start + found;
+        start = at + "require".len();
+        if at > 0 && (is_ident(bytes[at - 1]) || bytes[at - 1] == b'.') {
+            continue;
+        }
+        let rest = source[start..].trim_start();
+        let rest = rest.strip_prefix('(').unwrap_or(rest).trim_start();
+        let Some(quote) = rest.chars().next().filter(|c| *c == '"' || *c == '\'') else {
+            continue;
+        };
+        if let Some(end) = rest[1..].find(quote) {
+            let name = &rest[1..1 + end];
+            if !name.is_empty() && !names.iter().any(|known| known == name) {
+                names.push(name.to_string());
+            }
+        }
+    }
+    names
+}
+
+/// Resolve a module name to a file below `dir` like `package.path` would
+fn resolve(dir: &Path, name: &str) -> Option<PathBuf> {
+    let base = dir.join(name.replace('.', "/"));
+    [base.with_extension("lua"), base.join("init.lua")]
+        .into_iter()
+        .find(|path| path.is_file())
+}
+
+/// Remove a leading `#!` line, keeping the line count
+fn strip_shebang(source: &str) -> &str {
+    match source.starts_with("#!") {
+        true => source.find('\n').map_or("", |end| &source[end..]),
+        false => source,
+    }
+}
+
+/// Collect the local modules required by `source`, depth first in discovery order
+fn collect(dir: &Path, source: &str, modules: &mut Vec<(String, String)>) -> std::io::Result<()> {
+    for name in find_requires(source) {
+        if modules.iter().any(|(known, _)| *known == name) {
+            continue;
+        }
+        // modules which are not local files are left to `require` at runtime
+        let Some(path) = resolve(dir, &name) else {
+            continue;
+        };
+        let module = std::fs::read_to_string(path)?;
+        modules.push((name, module.clone()));
+        collect(dir, strip_shebang(&module), modules)?;
+    }
+    Ok(())
+}
+
+/// Combine a script and the local modules it requires into a single chunk
+///
+/// Each module is registered in `package.preload`, so it is still loaded once
+/// and only when it is first required.
+fn bundle(script: &Path, deps: bool) -> std::io::Result<String> {
+    let source = std::fs::read_to_string(script)?;
+    let dir = script
+        .canonicalize()?
+        .parent()
+        .map(Path::to_path_buf)
+        .ok_or_else(|| not_found("invalid script path"))?;
+    let mut modules = Vec::new();
+    if deps {
+        collect(&dir, strip_shebang(&source), &mut modules)?;
+    }
+    let mut out = String::new();
+    if let Some(shebang) = source.lines().next().filter(|line| line.starts_with("#!")) {
+        out.push_str(shebang);
+        out.push('\n');
+    }
+    for (name, module) in modules {
+        out.push_str(&format!("package.preload[{:?}] = function(...)\n", name));
+        out.push_str(strip_shebang(&module).trim_end());
+        out.push_str("\nend\n");
+    }
+    out.push_str(strip_shebang(&source).trim_start_matches('\n'));
+    Ok(out)
+}
+
+/// Run `luavisors bundle` with the arguments following it
+pub fn run(args: &[String]) -> AppResult<()> {
+    let options = parse_options(args)
+        .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidInput, err))?;
+    let bundled = bundle(&options.script, options.deps)?;
+    match options.output {
+        Some(output) => std::fs::write(output, bundled)?,
+        None => print!("{}", bundled),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("luavisors-bundle-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(dir.join("lib/util")).unwrap();
+        std::fs::write(
+            dir.join("main.lua"),
+            "#!/usr/bin/env luavisors\nlocal s = require('lib.services')\nreturn s.name .. require 'init'.pid()",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("lib/services.lua"),
+            "local u = require(\"lib.util\")\nreturn { name = u }",
+        )
+        .unwrap();
+        std::fs::write(dir.join("lib/util/init.lua"), "return 'util'").unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_parse_options() {
+        let args: Vec<String> = ["--deps", "main.lua", "-o", "out.lua"]
+            .iter()
+            .map(|arg| arg.to_string())
+            .collect();
+        let options = parse_options(&args).unwrap();
+        assert_eq!(
+            options,
+            BundleOptions {
+                script: PathBuf::from("main.lua"),
+                output: Some(PathBuf::from("out.lua")),
+                deps: true,
+            }
+        );
+        assert!(parse_options(&[]).is_err());
+        assert!(parse_options(&["-x".to_string()]).is_err());
+        assert!(parse_options(&["a.lua".to_string(), "b.lua".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_find_requires() {
+        let source = "local a = require('a')\nrequire \"b.c\"\nlocal r = myrequire('x')\nrequire(name)\nrequire 'a'";
+        assert_eq!(find_requires(source), vec!["a", "b.c"]);
+    }
+
+    #[test]
+    fn test_strip_shebang() {
+        assert_eq!(strip_shebang("#!/bin/lua\nprint()"), "\nprint()");
+        assert_eq!(strip_shebang("print()"), "print()");
+    }
+
+    #[test]
+    fn test_bundle() {
+        let dir = test_dir("deps");
+        let bundled = bundle(&dir.join("main.lua"), true).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(bundled.starts_with("#!/usr/bin/env luavisors\n"));
+        assert!(bundled.contains("package.preload[\"lib.services\"]"));
+        assert!(bundled.contains("package.preload[\"lib.util\"]"));
+        assert!(!bundled.contains("package.preload[\"init\"]"));
+        let lua = mlua::Lua::new();
+        let code = strip_shebang(&bundled).replace("require 'init'.pid()", "''");
+        let result: String = lua.load(code).eval().unwrap();
+        assert_eq!(result, "util");
+    }
+
+    #[test]
+    fn test_bundle_without_deps() {
+        let dir = test_dir("nodeps");
+        let bundled = bundle(&dir.join("main.lua"), false).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(!bundled.contains("package.preload"));
+        assert!(bundle(Path::new("/does/not/exist.lua"), true).is_err());
+    }
+}
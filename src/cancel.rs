@@ -0,0 +1,334 @@
+use std::{
+    cell::RefCell,
+    future::Future,
+    pin::pin,
+    sync::{Arc, Mutex, OnceLock, Weak},
+};
+
+use async_signal::{Signal, Signals};
+use mlua::prelude::*;
+use smol::stream::StreamExt;
+
+use crate::{boot, errors::AppResult, sync::Event};
+
+/// Shared state of a cancellation token
+#[derive(Default)]
+struct Inner {
+    cancelled: Event,
+    children: Mutex<Vec<Weak<Inner>>>,
+}
+
+impl Inner {
+    /// Cancel this token and every live descendant
+    fn cancel(&self) {
+        self.cancelled.set();
+        let children =
+            std::mem::take(&mut *self.children.lock().unwrap_or_else(|e| e.into_inner()));
+        for child in children.iter().filter_map(Weak::upgrade) {
+            child.cancel();
+        }
+    }
+}
+
+/// Token which cancels a tree of asynchronous work together
+#[derive(Clone, Default)]
+pub struct CancelToken {
+    inner: Arc<Inner>,
+}
+
+impl CancelToken {
+    /// Cancel the token and all of its children
+    pub fn cancel(&self) {
+        self.inner.cancel();
+    }
+
+    /// Check whether the token has been cancelled
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.is_set()
+    }
+
+    /// Wait until the token is cancelled
+    pub async fn wait(&self) {
+        self.inner.cancelled.wait().await
+    }
+
+    /// Create a token which is cancelled along with this one
+    pub fn child(&self) -> CancelToken {
+        let child = CancelToken::default();
+        let mut children = self
+            .inner
+            .children
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        // check under the lock so a concurrent cancel cannot miss the child
+        if self.is_cancelled() {
+            child.cancel();
+        } else {
+            children.retain(|child| child.strong_count() > 0);
+            children.push(Arc::downgrade(&child.inner));
+        }
+        child
+    }
+}
+
+/// Lua methods for cancellation tokens
+impl LuaUserData for CancelToken {
+    fn add_methods<M: LuaUserDataMethods<Self>>(methods: &mut M) {
+        methods.add_method("cancel", |_, this, ()| {
+            this.cancel();
+            Ok(())
+        });
+        methods.add_method("cancelled", |_, this, ()| Ok(this.is_cancelled()));
+        methods.add_method("child", |_, this, ()| Ok(this.child()));
+        methods.add_async_method("wait", |_, this, ()| {
+            let this = this.clone();
+            async move {
+                this.wait().await;
+                Ok(())
+            }
+        });
+    }
+}
+
+/// Convert a Lua value into a cancellation token
+impl FromLua for CancelToken {
+    fn from_lua(value: LuaValue, _lua: &Lua) -> LuaResult<Self> {
+        match value {
+            LuaValue::UserData(ud) if ud.is::<CancelToken>() => Ok(ud.borrow::<Self>()?.clone()),
+            value => Err(LuaError::runtime(format!(
+                "expected a cancel token, got a value of type '{}'",
+                value.type_name()
+            ))),
+        }
+    }
+}
+
+/// Run a future until it finishes or the token is cancelled
+pub async fn until_cancelled<F: Future>(
+    token: Option<&CancelToken>,
+    future: F,
+) -> Option<F::Output> {
+    let Some(token) = token else {
+        return Some(future.await);
+    };
+    // poll the token first so a cancelled token wins over a ready future
+    smol::future::or(
+        async {
+            token.wait().await;
+            None
+        },
+        async { Some(future.await) },
+    )
+    .await
+}
+
+/// Time limit of an `init.deadline` call, shared by the async calls made within it
+#[derive(Clone)]
+pub struct Deadline {
+    secs: f64,
+    expired: CancelToken,
+    finished: Event,
+}
+
+thread_local! {
+    /// Deadline of the `init.deadline` call being polled on this thread
+    static CURRENT_DEADLINE: RefCell<Option<Deadline>> = const { RefCell::new(None) };
+}
+
+impl Deadline {
+    /// Create a deadline of `secs`, which also expires along with any enclosing deadline
+    pub fn new(secs: f64) -> Self {
+        let expired =
+            current_deadline().map_or_else(CancelToken::default, |outer| outer.expired.child());
+        Deadline {
+            secs,
+            expired,
+            finished: Event::default(),
+        }
+    }
+
+    /// Mark the deadline as passed, waking the calls waiting within it
+    pub fn expire(&self) {
+        self.expired.cancel();
+    }
+
+    /// Mark the call as finished, so it no longer expires
+    pub fn finish(&self) {
+        self.finished.set();
+    }
+
+    /// Check whether the deadline has passed
+    pub fn is_expired(&self) -> bool {
+        self.expired.is_cancelled()
+    }
+
+    /// Wait until the deadline passes, returning false if its call finishes first
+    pub async fn expired(&self) -> bool {
+        smol::future::or(
+            async {
+                self.expired.wait().await;
+                true
+            },
+            async {
+                self.finished.wait().await;
+                false
+            },
+        )
+        .await
+    }
+
+    /// Error raised by calls still waiting when the deadline passes
+    pub fn error(&self) -> LuaError {
+        LuaError::runtime(format!(
+            "DeadlineExceeded: did not finish within {} seconds",
+            self.secs
+        ))
+    }
+}
+
+/// Return the deadline of the `init.deadline` call being polled, if there is one
+pub fn current_deadline() -> Option<Deadline> {
+    CURRENT_DEADLINE.with_borrow(Clone::clone)
+}
+
+/// Poll a future with `deadline` as the current deadline of everything it polls
+pub async fn with_deadline<F: Future>(deadline: &Deadline, future: F) -> F::Output {
+    let mut future = pin!(future);
+    std::future::poll_fn(|cx| {
+        let outer = CURRENT_DEADLINE.replace(Some(deadline.clone()));
+        let poll = future.as_mut().poll(cx);
+        CURRENT_DEADLINE.set(outer);
+        poll
+    })
+    .await
+}
+
+/// Run a future, raising `DeadlineExceeded` if the current deadline passes first
+pub async fn until_deadline<T, F: Future<Output = LuaResult<T>>>(future: F) -> LuaResult<T> {
+    let Some(deadline) = current_deadline() else {
+        return future.await;
+    };
+    match until_cancelled(Some(&deadline.expired), future).await {
+        Some(result) => result,
+        None => Err(deadline.error()),
+    }
+}
+
+/// Return the token which is cancelled when the supervisor starts shutting down
+pub fn shutdown() -> &'static CancelToken {
+    static SHUTDOWN: OnceLock<CancelToken> = OnceLock::new();
+    SHUTDOWN.get_or_init(CancelToken::default)
+}
+
+/// Cancel the shutdown token on `SIGTERM` or `SIGINT`, exiting on a second signal
+///
+/// `SIGINT` is also ctrl-alt-del when running as pid 1.
+pub async fn watch_shutdown() -> AppResult<()> {
+    let mut signals = Signals::new([Signal::Term, Signal::Int])?;
+    while let Some(signal) = signals.next().await {
+        let signal = signal?;
+        if signal == Signal::Int {
+            boot::interrupted();
+        }
+        if shutdown().is_cancelled() {
+            std::process::exit(128 + signal as i32);
+        }
+        shutdown().cancel();
+    }
+    Ok(())
+}
+
+/// Read the optional `cancel` token from an options table
+pub fn cancel_option(options: &LuaTable) -> LuaResult<Option<CancelToken>> {
+    options.get("cancel")
+}
+
+/// Create a new cancellation token from Lua
+pub async fn cancel_token(_lua: Lua, _: ()) -> LuaResult<CancelToken> {
+    Ok(CancelToken::default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cancel_children() {
+        let root = CancelToken::default();
+        let child = root.child();
+        let grandchild = child.child();
+        child.cancel();
+        assert!(!root.is_cancelled());
+        assert!(grandchild.is_cancelled());
+        root.cancel();
+        assert!(root.is_cancelled());
+        // children of a cancelled token start cancelled
+        assert!(root.child().is_cancelled());
+    }
+
+    #[test]
+    fn test_child_dropped() {
+        let root = CancelToken::default();
+        drop(root.child());
+        let _child = root.child();
+        assert_eq!(root.inner.children.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_until_cancelled() {
+        smol::block_on(async {
+            let token = CancelToken::default();
+            assert_eq!(until_cancelled(Some(&token), async { 1 }).await, Some(1));
+            assert_eq!(until_cancelled(None, async { 2 }).await, Some(2));
+            token.cancel();
+            let pending = std::future::pending::<()>();
+            assert_eq!(until_cancelled(Some(&token), pending).await, None);
+        });
+    }
+
+    #[test]
+    fn test_until_deadline() {
+        smol::block_on(async {
+            assert_eq!(until_deadline(async { Ok(1) }).await.unwrap(), 1);
+            let outer = Deadline::new(1.0);
+            let inner = with_deadline(&outer, async { Deadline::new(5.0) }).await;
+            outer.expire();
+            assert!(inner.expired().await);
+            let pending = std::future::pending::<LuaResult<()>>();
+            let err = with_deadline(&inner, until_deadline(pending)).await;
+            assert!(err.unwrap_err().to_string().contains("within 5 seconds"));
+            let finished = Deadline::new(1.0);
+            finished.finish();
+            assert!(!finished.expired().await);
+        });
+    }
+
+    #[test]
+    fn test_cancel_token_lua() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            let token = cancel_token(lua.clone(), ()).await.unwrap();
+            lua.globals().set("token", token).unwrap();
+            let chunk = lua.load(
+                r#"
+                local child = token:child()
+                token:cancel()
+                child:wait()
+                return child:cancelled()
+                "#,
+            );
+            assert!(chunk.eval_async::<bool>().await.unwrap());
+        });
+    }
+
+    #[test]
+    fn test_shutdown() {
+        assert!(std::ptr::eq(shutdown(), shutdown()));
+    }
+
+    #[test]
+    fn test_from_lua_err() {
+        let lua = Lua::new();
+        assert!(CancelToken::from_lua(LuaValue::Integer(1), &lua).is_err());
+    }
+}
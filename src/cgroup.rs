@@ -0,0 +1,347 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use mlua::prelude::*;
+
+use crate::size;
+
+/// Mount point of the cgroup v2 hierarchy
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+
+/// Return the cgroup v2 mount, which is nested under `unified` on hybrid systems
+fn root() -> PathBuf {
+    let root = Path::new(CGROUP_ROOT);
+    match root.join("cgroup.controllers").exists() {
+        true => root.to_path_buf(),
+        false => root.join("unified"),
+    }
+}
+
+/// Return the cgroup v2 path of a process from the contents of `/proc/<pid>/cgroup`
+fn parse_proc_cgroup(contents: &str) -> Option<&str> {
+    contents.lines().find_map(|line| line.strip_prefix("0::"))
+}
+
+/// Parse flat keyed files such as `cpu.stat` into their fields
+fn parse_keyed(contents: &str) -> HashMap<String, i64> {
+    contents
+        .lines()
+        .filter_map(|line| {
+            let (key, value) = line.split_once(' ')?;
+            Some((key.to_string(), value.trim().parse().ok()?))
+        })
+        .collect()
+}
+
+/// Parse nested keyed files such as `io.stat` into fields for each device
+fn parse_nested(contents: &str) -> HashMap<String, HashMap<String, i64>> {
+    contents
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let device = fields.next()?.to_string();
+            let values = fields
+                .filter_map(|field| {
+                    let (key, value) = field.split_once('=')?;
+                    Some((key.to_string(), value.parse().ok()?))
+                })
+                .collect();
+            Some((device, values))
+        })
+        .collect()
+}
+
+/// Parse single value files such as `memory.current`, where `max` is nil
+fn parse_single(contents: &str) -> Option<i64> {
+    contents.trim().parse().ok()
+}
+
+/// Resolve a pid or a path relative to the cgroup root into a cgroup directory
+async fn cgroup_dir(target: LuaValue) -> LuaResult<PathBuf> {
+    let path = match target {
+        LuaValue::Integer(pid) => {
+            let contents = smol::fs::read_to_string(format!("/proc/{}/cgroup", pid)).await?;
+            parse_proc_cgroup(&contents)
+                .ok_or_else(|| LuaError::runtime(format!("pid {} is not in a cgroup v2", pid)))?
+                .to_string()
+        }
+        LuaValue::String(path) => path.to_str()?.to_string(),
+        value => {
+            return Err(LuaError::runtime(format!(
+                "expected a pid or cgroup path, got a value of type '{}'",
+                value.type_name()
+            )))
+        }
+    };
+    Ok(root().join(path.trim_start_matches('/')))
+}
+
+/// Return memory, cpu, pids, and io statistics of a cgroup from Lua
+///
+/// Statistics whose controller is not enabled for the cgroup are nil.
+async fn stats(lua: Lua, target: LuaValue) -> LuaResult<LuaTable> {
+    let dir = cgroup_dir(target).await?;
+    if !dir.is_dir() {
+        return Err(LuaError::runtime(format!(
+            "cgroup '{}' does not exist",
+            dir.display()
+        )));
+    }
+    let read = |name: &str| {
+        let path = dir.join(name);
+        async move { smol::fs::read_to_string(path).await.ok() }
+    };
+    let table = lua.create_table()?;
+    table.set("path", dir.to_string_lossy().into_owned())?;
+    let memory = read("memory.current").await;
+    table.set("memory_current", memory.as_deref().and_then(parse_single))?;
+    let pids = read("pids.current").await;
+    table.set("pids_current", pids.as_deref().and_then(parse_single))?;
+    if let Some(cpu) = read("cpu.stat").await {
+        table.set("cpu_stat", parse_keyed(&cpu))?;
+    }
+    if let Some(io) = read("io.stat").await {
+        table.set("io_stat", parse_nested(&io))?;
+    }
+    Ok(table)
+}
+
+/// Limit written to a cgroup as `max`, which removes it
+const MAX: u64 = u64::MAX;
+
+/// Range of the relative cpu weight of a cgroup
+const CPU_WEIGHT: std::ops::RangeInclusive<u32> = 1..=10000;
+
+/// Cgroup a child process joins before exec, and the limits written to it beforehand
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Placement {
+    name: String,
+    memory_max: Option<u64>,
+    cpu_weight: Option<u32>,
+    pids_max: Option<u64>,
+}
+
+/// Read a limit which is a number, a size such as `256M`, or `max`
+fn limit_value(value: LuaValue) -> LuaResult<Option<u64>> {
+    match value {
+        LuaValue::Nil => Ok(None),
+        LuaValue::String(s) => match &*s.to_str()? {
+            "max" => Ok(Some(MAX)),
+            s => size::parse(s).map(Some).map_err(LuaError::runtime),
+        },
+        LuaValue::Integer(limit) if limit >= 0 => Ok(Some(limit as u64)),
+        LuaValue::Number(limit) if limit >= 0.0 => Ok(Some(limit as u64)),
+        value => Err(LuaError::runtime(format!(
+            "expected cgroup limit to be a size or 'max', got '{}'",
+            value.to_string()?
+        ))),
+    }
+}
+
+/// Format a limit as it is written to a cgroup file
+fn format_limit(limit: u64) -> String {
+    match limit {
+        MAX => "max".to_string(),
+        limit => limit.to_string(),
+    }
+}
+
+/// Read a cgroup placement from the `cgroup` option of `init.exec`
+///
+/// The name is a path below the cgroup root, such as `luavisors/web`.
+pub fn placement_from_table(table: &LuaTable) -> LuaResult<Placement> {
+    let name: String = table.get("name")?;
+    let name = name.trim_matches('/').to_string();
+    if name.is_empty() || name.split('/').any(|part| part.is_empty() || part == "..") {
+        return Err(LuaError::runtime(format!("invalid cgroup name '{}'", name)));
+    }
+    let cpu_weight: Option<u32> = table.get("cpu_weight")?;
+    if let Some(weight) = cpu_weight.filter(|weight| !CPU_WEIGHT.contains(weight)) {
+        return Err(LuaError::runtime(format!(
+            "cpu weight {} is not between {} and {}",
+            weight,
+            CPU_WEIGHT.start(),
+            CPU_WEIGHT.end()
+        )));
+    }
+    Ok(Placement {
+        name,
+        memory_max: limit_value(table.get("memory_max")?)?,
+        cpu_weight,
+        pids_max: limit_value(table.get("pids_max")?)?,
+    })
+}
+
+/// Convert a cgroup placement back into its option table
+pub fn placement_table(lua: &Lua, placement: &Placement) -> LuaResult<LuaTable> {
+    let table = lua.create_table()?;
+    table.set("name", placement.name.as_str())?;
+    let limit = |limit: Option<u64>| -> LuaResult<LuaValue> {
+        match limit {
+            None => Ok(LuaValue::Nil),
+            Some(MAX) => Ok(LuaValue::String(lua.create_string("max")?)),
+            Some(limit) => Ok(LuaValue::Integer(limit as i64)),
+        }
+    };
+    table.set("memory_max", limit(placement.memory_max)?)?;
+    table.set("cpu_weight", placement.cpu_weight)?;
+    table.set("pids_max", limit(placement.pids_max)?)?;
+    Ok(table)
+}
+
+/// Return the controller, file, and value of each setting of a placement
+fn settings(placement: &Placement) -> Vec<(&'static str, &'static str, String)> {
+    let mut settings = Vec::new();
+    if let Some(limit) = placement.memory_max {
+        settings.push(("memory", "memory.max", format_limit(limit)));
+    }
+    if let Some(weight) = placement.cpu_weight {
+        settings.push(("cpu", "cpu.weight", weight.to_string()));
+    }
+    if let Some(limit) = placement.pids_max {
+        settings.push(("pids", "pids.max", format_limit(limit)));
+    }
+    settings
+}
+
+/// Create the cgroup of a placement below `root` and write its limits
+///
+/// The controllers are enabled in every ancestor first, and the path of the
+/// `cgroup.procs` file which the child joins through is returned.
+fn prepare_in(root: &Path, placement: &Placement) -> std::io::Result<PathBuf> {
+    let context = |path: &Path| {
+        let path = path.display().to_string();
+        move |err: std::io::Error| std::io::Error::new(err.kind(), format!("{}: {}", path, err))
+    };
+    let write = |path: PathBuf, value: &str| std::fs::write(&path, value).map_err(context(&path));
+    let dir = root.join(&placement.name);
+    std::fs::create_dir_all(&dir).map_err(context(&dir))?;
+    let settings = settings(placement);
+    if !settings.is_empty() {
+        let controllers = settings
+            .iter()
+            .map(|(controller, _, _)| format!("+{}", controller))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let mut parent = root.to_path_buf();
+        for part in placement.name.split('/') {
+            write(parent.join("cgroup.subtree_control"), &controllers)?;
+            parent.push(part);
+        }
+    }
+    for (_, file, value) in settings {
+        write(dir.join(file), &value)?;
+    }
+    Ok(dir.join("cgroup.procs"))
+}
+
+/// Create the cgroup of a placement and write its limits, returning its `cgroup.procs` file
+pub fn prepare(placement: &Placement) -> std::io::Result<PathBuf> {
+    prepare_in(&root(), placement)
+}
+
+/// Return the `init.cgroup` Lua table
+pub fn cgroup_table(lua: &Lua) -> LuaResult<LuaTable> {
+    let table = lua.create_table()?;
+    table.set("stats", lua.create_async_function(stats)?)?;
+    Ok(table)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_proc_cgroup() {
+        assert_eq!(
+            parse_proc_cgroup("0::/system.slice/web\n"),
+            Some("/system.slice/web")
+        );
+        assert_eq!(parse_proc_cgroup("4:memory:/a\n0::/\n"), Some("/"));
+        assert_eq!(parse_proc_cgroup("4:memory:/a\n"), None);
+    }
+
+    #[test]
+    fn test_parse_files() {
+        let cpu = parse_keyed("usage_usec 250\nuser_usec 200\nsystem_usec 50\n");
+        assert_eq!(cpu["usage_usec"], 250);
+        assert_eq!(cpu.len(), 3);
+        let io = parse_nested("8:0 rbytes=1024 wbytes=2048 rios=1 wios=2\n");
+        assert_eq!(io["8:0"]["wbytes"], 2048);
+        assert_eq!(parse_single("4096\n"), Some(4096));
+        assert_eq!(parse_single("max\n"), None);
+    }
+
+    #[test]
+    fn test_placement_from_table() {
+        let lua = Lua::new();
+        let table = lua
+            .load("{ name = '/luavisors/web/', memory_max = '256M', cpu_weight = 50, pids_max = 'max' }")
+            .eval::<LuaTable>()
+            .unwrap();
+        let placement = placement_from_table(&table).unwrap();
+        assert_eq!(
+            placement,
+            Placement {
+                name: "luavisors/web".to_string(),
+                memory_max: Some(256 << 20),
+                cpu_weight: Some(50),
+                pids_max: Some(MAX),
+            }
+        );
+        let table = placement_table(&lua, &placement).unwrap();
+        assert_eq!(placement_from_table(&table).unwrap(), placement);
+        for invalid in [
+            "{ name = '../web' }",
+            "{ name = '/' }",
+            "{ name = 'web', cpu_weight = 0 }",
+        ] {
+            let table = lua.load(invalid).eval::<LuaTable>().unwrap();
+            assert!(placement_from_table(&table).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_prepare_in() {
+        let root = std::env::temp_dir().join(format!("luavisors-cgroup-{}", std::process::id()));
+        let placement = Placement {
+            name: "luavisors/web".to_string(),
+            memory_max: Some(256 << 20),
+            cpu_weight: Some(50),
+            ..Default::default()
+        };
+        let procs = prepare_in(&root, &placement).unwrap();
+        let read = |path: &str| std::fs::read_to_string(root.join(path)).unwrap();
+        let (subtree, nested) = (
+            read("cgroup.subtree_control"),
+            read("luavisors/cgroup.subtree_control"),
+        );
+        let (memory, cpu) = (
+            read("luavisors/web/memory.max"),
+            read("luavisors/web/cpu.weight"),
+        );
+        let pids = root.join("luavisors/web/pids.max").exists();
+        std::fs::remove_dir_all(&root).unwrap();
+        assert_eq!(procs, root.join("luavisors/web/cgroup.procs"));
+        assert_eq!(
+            (subtree.as_str(), nested.as_str()),
+            ("+memory +cpu", "+memory +cpu")
+        );
+        assert_eq!(
+            (memory.as_str(), cpu.as_str(), pids),
+            ("268435456", "50", false)
+        );
+    }
+
+    #[test]
+    fn test_stats_err() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            let missing = LuaValue::String(lua.create_string("luavisors-missing").unwrap());
+            assert!(stats(lua.clone(), missing).await.is_err());
+            assert!(stats(lua, LuaValue::Boolean(true)).await.is_err());
+        });
+    }
+}
@@ -0,0 +1,86 @@
+use std::{path::PathBuf, sync::Mutex};
+
+/// Paths which are removed when the supervisor shuts down
+pub struct Cleanup {
+    paths: Mutex<Vec<PathBuf>>,
+}
+
+/// Global cleanup registry for the supervisor
+pub static CLEANUP: Cleanup = Cleanup::new();
+
+impl Cleanup {
+    /// Create an empty cleanup registry
+    pub const fn new() -> Self {
+        Cleanup {
+            paths: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Register a path to remove on shutdown
+    pub fn register(&self, path: PathBuf) {
+        let mut paths = self.paths.lock().unwrap_or_else(|err| err.into_inner());
+        if !paths.contains(&path) {
+            paths.push(path);
+        }
+    }
+
+    /// Check whether a path will be removed on shutdown
+    #[cfg(test)]
+    pub fn contains(&self, path: &std::path::Path) -> bool {
+        let paths = self.paths.lock().unwrap_or_else(|err| err.into_inner());
+        paths.iter().any(|p| p == path)
+    }
+
+    /// Remove all registered paths, most recently registered first
+    pub fn run(&self) {
+        let paths = std::mem::take(&mut *self.paths.lock().unwrap_or_else(|err| err.into_inner()));
+        for path in paths.into_iter().rev() {
+            let result = if path.is_dir() {
+                std::fs::remove_dir_all(&path)
+            } else {
+                std::fs::remove_file(&path)
+            };
+            match result {
+                Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+                    eprintln!("failed to remove '{}': {}", path.display(), err);
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("luavisors-cleanup-{}-{}", name, std::process::id()))
+    }
+
+    #[test]
+    fn test_register() {
+        let cleanup = Cleanup::new();
+        let path = test_path("register");
+        cleanup.register(path.clone());
+        cleanup.register(path.clone());
+        assert!(cleanup.contains(&path));
+        assert_eq!(cleanup.paths.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_run() {
+        let cleanup = Cleanup::new();
+        let file = test_path("file");
+        let dir = test_path("dir");
+        std::fs::write(&file, "test").unwrap();
+        std::fs::create_dir_all(dir.join("nested")).unwrap();
+        cleanup.register(file.clone());
+        cleanup.register(dir.clone());
+        cleanup.register(test_path("missing"));
+        cleanup.run();
+        assert!(!file.exists());
+        assert!(!dir.exists());
+        assert!(!cleanup.contains(&file));
+    }
+}
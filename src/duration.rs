@@ -0,0 +1,134 @@
+use std::time::{Duration, Instant};
+
+use mlua::prelude::*;
+
+/// Seconds in each supported duration unit
+const UNITS: [(&str, f64); 7] = [
+    ("ns", 1e-9),
+    ("us", 1e-6),
+    ("ms", 1e-3),
+    ("s", 1.0),
+    ("m", 60.0),
+    ("h", 3600.0),
+    ("d", 86400.0),
+];
+
+/// Parse a duration such as `1h30m15s` or `250ms` into seconds
+pub fn parse(s: &str) -> Result<f64, String> {
+    let s = s.trim();
+    if s.is_empty() {
+        return Err("empty duration".to_string());
+    }
+    // a bare number is a number of seconds
+    if let Ok(secs) = s.parse::<f64>() {
+        return check(secs).map_err(|err| format!("{} in '{}'", err, s));
+    }
+    let mut total = 0.0;
+    let mut rest = s;
+    while !rest.is_empty() {
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(rest.len());
+        let value: f64 = rest[..digits]
+            .parse()
+            .map_err(|_| format!("invalid duration '{}'", s))?;
+        rest = &rest[digits..];
+        let unit = rest
+            .find(|c: char| c.is_ascii_digit() || c == '.')
+            .unwrap_or(rest.len());
+        let scale = UNITS
+            .iter()
+            .find(|(name, _)| *name == &rest[..unit])
+            .map(|(_, scale)| scale)
+            .ok_or_else(|| format!("unknown unit '{}' in duration '{}'", &rest[..unit], s))?;
+        total += value * scale;
+        rest = &rest[unit..];
+    }
+    check(total).map_err(|err| format!("{} in '{}'", err, s))
+}
+
+/// Reject durations which cannot be slept for, including those so long that
+/// their deadline cannot be represented
+fn check(secs: f64) -> Result<f64, String> {
+    let deadline = Duration::try_from_secs_f64(secs)
+        .ok()
+        .and_then(|duration| Instant::now().checked_add(duration));
+    match deadline {
+        Some(_) => Ok(secs),
+        None => Err(format!("invalid duration {}", secs)),
+    }
+}
+
+/// Non-negative number of seconds given as a number or duration string
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Seconds(pub f64);
+
+impl Seconds {
+    /// Convert to a standard duration
+    pub fn duration(self) -> Duration {
+        Duration::from_secs_f64(self.0)
+    }
+}
+
+/// Convert a Lua number or duration string into seconds
+impl FromLua for Seconds {
+    fn from_lua(value: LuaValue, lua: &Lua) -> LuaResult<Self> {
+        let secs = match value {
+            LuaValue::String(s) => parse(&s.to_str()?),
+            value => check(f64::from_lua(value, lua)?),
+        };
+        secs.map(Seconds).map_err(LuaError::runtime)
+    }
+}
+
+/// Convert a duration string into seconds from Lua
+pub async fn duration(_lua: Lua, secs: Seconds) -> LuaResult<f64> {
+    Ok(secs.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse("1h30m15s"), Ok(5415.0));
+        assert_eq!(parse("250ms"), Ok(0.25));
+        assert_eq!(parse("1.5m"), Ok(90.0));
+        assert_eq!(parse("2d"), Ok(172800.0));
+        assert_eq!(parse(" 30 "), Ok(30.0));
+    }
+
+    #[test]
+    fn test_parse_err() {
+        assert!(parse("").is_err());
+        assert!(parse("10x").is_err());
+        assert!(parse("h").is_err());
+        assert!(parse("-5").is_err());
+        assert!(parse("1h-5m").is_err());
+        assert!(parse("1e300").is_err());
+        assert!(parse("1e300d").is_err());
+    }
+
+    #[test]
+    fn test_seconds_from_lua() {
+        let lua = Lua::new();
+        let value = LuaValue::String(lua.create_string("1m").unwrap());
+        assert_eq!(Seconds::from_lua(value, &lua).unwrap(), Seconds(60.0));
+        assert_eq!(
+            Seconds::from_lua(LuaValue::Integer(5), &lua).unwrap(),
+            Seconds(5.0)
+        );
+        assert!(Seconds::from_lua(LuaValue::Number(-1.0), &lua).is_err());
+        assert!(Seconds::from_lua(LuaValue::Number(1e300), &lua).is_err());
+        assert!(Seconds::from_lua(LuaValue::Number(f64::NAN), &lua).is_err());
+        assert!(Seconds::from_lua(LuaValue::Boolean(true), &lua).is_err());
+    }
+
+    #[test]
+    fn test_duration() {
+        let lua = Lua::new();
+        let secs = smol::block_on(duration(lua, Seconds(1.5))).unwrap();
+        assert_eq!(secs, 1.5);
+    }
+}
@@ -0,0 +1,405 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use mlua::prelude::*;
+
+use crate::{
+    cancel::{self, Deadline},
+    duration::Seconds,
+    random, task,
+};
+
+/// How the delay between retries grows
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Backoff {
+    Constant,
+    Linear,
+    Exponential,
+}
+
+/// Parse a backoff strategy name
+impl std::str::FromStr for Backoff {
+    type Err = LuaError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "constant" => Ok(Backoff::Constant),
+            "linear" => Ok(Backoff::Linear),
+            "exponential" => Ok(Backoff::Exponential),
+            _ => Err(LuaError::runtime(format!("invalid backoff '{}'", s))),
+        }
+    }
+}
+
+/// Options for `retry`
+#[derive(Debug, PartialEq)]
+struct RetryOptions {
+    attempts: u32,
+    backoff: Backoff,
+    base: f64,
+    max: f64,
+    jitter: bool,
+}
+
+impl Default for RetryOptions {
+    fn default() -> Self {
+        RetryOptions {
+            attempts: 5,
+            backoff: Backoff::Exponential,
+            base: 1.0,
+            max: 30.0,
+            jitter: true,
+        }
+    }
+}
+
+/// Read retry options from an optional Lua table
+fn retry_options(opts: Option<LuaTable>) -> LuaResult<RetryOptions> {
+    let mut options = RetryOptions::default();
+    let Some(opts) = opts else {
+        return Ok(options);
+    };
+    if let Some(attempts) = opts.get::<Option<u32>>("attempts")? {
+        options.attempts = attempts.max(1);
+    }
+    if let Some(backoff) = opts.get::<Option<String>>("backoff")? {
+        options.backoff = backoff.parse()?;
+    }
+    if let Some(Seconds(base)) = opts.get::<Option<Seconds>>("base")? {
+        options.base = base;
+    }
+    if let Some(Seconds(max)) = opts.get::<Option<Seconds>>("max")? {
+        options.max = max;
+    }
+    if let Some(jitter) = opts.get::<Option<bool>>("jitter")? {
+        options.jitter = jitter;
+    }
+    Ok(options)
+}
+
+/// Delay in seconds before retrying after the `failures`-th failure
+fn backoff_delay(options: &RetryOptions, failures: u32) -> f64 {
+    let delay = match options.backoff {
+        Backoff::Constant => options.base,
+        Backoff::Linear => options.base * failures as f64,
+        Backoff::Exponential => options.base * 2f64.powi(failures.saturating_sub(1) as i32),
+    };
+    delay.min(options.max)
+}
+
+/// Spread a delay over `[delay / 2, delay)` so retries do not synchronize
+fn with_jitter(delay: f64) -> f64 {
+    delay / 2.0 + delay / 2.0 * random::random_f64()
+}
+
+/// Call an async Lua function until it succeeds, waiting between attempts
+pub async fn retry(
+    _lua: Lua,
+    (func, opts): (LuaFunction, Option<LuaTable>),
+) -> LuaResult<LuaMultiValue> {
+    let options = retry_options(opts)?;
+    let mut attempt = 1;
+    loop {
+        let err = match func.call_async::<LuaMultiValue>(attempt).await {
+            Ok(values) => return Ok(values),
+            Err(err) => err,
+        };
+        if attempt >= options.attempts {
+            return Err(err);
+        }
+        let mut delay = backoff_delay(&options, attempt);
+        if options.jitter {
+            delay = with_jitter(delay);
+        }
+        smol::Timer::after(Duration::from_secs_f64(delay)).await;
+        attempt += 1;
+    }
+}
+
+/// Pending call of a debounced function
+struct Debounce {
+    generation: u64,
+    args: LuaMultiValue,
+}
+
+/// Wrap a function so it runs once `secs` after the last of a burst of calls
+pub async fn debounce(lua: Lua, (secs, func): (Seconds, LuaFunction)) -> LuaResult<LuaFunction> {
+    let delay = secs.duration();
+    let state = Arc::new(Mutex::new(Debounce {
+        generation: 0,
+        args: LuaMultiValue::new(),
+    }));
+    lua.create_function(move |lua, args: LuaMultiValue| {
+        let generation = {
+            let mut state = state.lock().unwrap_or_else(|err| err.into_inner());
+            state.generation += 1;
+            state.args = args;
+            state.generation
+        };
+        let weak_lua = lua.weak();
+        let state = state.clone();
+        let func = func.clone();
+        smol::spawn(async move {
+            smol::Timer::after(delay).await;
+            let args = {
+                let state = state.lock().unwrap_or_else(|err| err.into_inner());
+                // a later call restarted the timer
+                if state.generation != generation {
+                    return;
+                }
+                state.args.clone()
+            };
+            // stop task if the Lua instance has been destroyed
+            let Some(lua) = weak_lua.try_upgrade() else {
+                return;
+            };
+            if let Err(err) = func.call_async::<()>(args).await {
+                task::report_error(&lua, "init.debounce", err, None).await;
+            }
+        })
+        .detach();
+        Ok(())
+    })
+}
+
+/// Wrap a function so it runs at most once every `secs`, dropping other calls
+pub async fn throttle(lua: Lua, (secs, func): (Seconds, LuaFunction)) -> LuaResult<LuaFunction> {
+    let interval = secs.duration();
+    let last: Arc<Mutex<Option<Instant>>> = Arc::new(Mutex::new(None));
+    lua.create_async_function(move |_, args: LuaMultiValue| {
+        let func = func.clone();
+        let allowed = {
+            let mut last = last.lock().unwrap_or_else(|err| err.into_inner());
+            let now = Instant::now();
+            let allowed = last.is_none_or(|last| now.duration_since(last) >= interval);
+            if allowed {
+                *last = Some(now);
+            }
+            allowed
+        };
+        async move {
+            match allowed {
+                true => func.call_async::<LuaMultiValue>(args).await,
+                false => Ok(LuaMultiValue::new()),
+            }
+        }
+    })
+}
+
+/// Time a call past its deadline has to unwind before it is abandoned
+const UNWIND_GRACE: Duration = Duration::from_millis(100);
+
+/// Call a function which must finish within `secs`
+///
+/// Sleeping, running commands, and waiting within the call raise a
+/// `DeadlineExceeded` error once the deadline passes, so the function unwinds
+/// instead of being abandoned, and commands it started are terminated. A call
+/// still waiting on something else shortly after is dropped instead.
+pub async fn deadline(
+    _lua: Lua,
+    (secs, func, args): (Seconds, LuaFunction, LuaMultiValue),
+) -> LuaResult<LuaMultiValue> {
+    let deadline = Deadline::new(secs.0);
+    let _finished = Finished(&deadline);
+    let call = cancel::with_deadline(&deadline, func.call_async::<LuaMultiValue>(args));
+    let expire = async {
+        smol::Timer::after(secs.duration()).await;
+        deadline.expire();
+        // keep polling the call while it unwinds, dropping it if it does not
+        smol::Timer::after(UNWIND_GRACE).await;
+        Err(deadline.error())
+    };
+    smol::future::or(call, expire).await
+}
+
+/// Guard which marks a deadline as finished when its call returns or is dropped
+struct Finished<'a>(&'a Deadline);
+
+impl Drop for Finished<'_> {
+    fn drop(&mut self) {
+        self.0.finish();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(backoff: Backoff) -> RetryOptions {
+        RetryOptions {
+            backoff,
+            base: 1.0,
+            max: 5.0,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_backoff_from_str() {
+        assert_eq!("linear".parse::<Backoff>().unwrap(), Backoff::Linear);
+        assert!("random".parse::<Backoff>().is_err());
+    }
+
+    #[test]
+    fn test_backoff_delay() {
+        let exponential = options(Backoff::Exponential);
+        let delays: Vec<f64> = (1..=5).map(|n| backoff_delay(&exponential, n)).collect();
+        assert_eq!(delays, vec![1.0, 2.0, 4.0, 5.0, 5.0]);
+        let linear = options(Backoff::Linear);
+        assert_eq!(backoff_delay(&linear, 3), 3.0);
+        let constant = options(Backoff::Constant);
+        assert_eq!(backoff_delay(&constant, 3), 1.0);
+    }
+
+    #[test]
+    fn test_with_jitter() {
+        for _ in 0..100 {
+            let delay = with_jitter(2.0);
+            assert!((1.0..2.0).contains(&delay));
+        }
+    }
+
+    #[test]
+    fn test_retry_options() {
+        let lua = Lua::new();
+        assert_eq!(retry_options(None).unwrap(), RetryOptions::default());
+        let opts = lua
+            .load("{ attempts = 0, backoff = 'linear', base = 2, max = '1m', jitter = false }")
+            .eval()
+            .unwrap();
+        let options = retry_options(Some(opts)).unwrap();
+        assert_eq!(options.attempts, 1);
+        assert_eq!(options.backoff, Backoff::Linear);
+        assert_eq!(options.max, 60.0);
+        assert!(!options.jitter);
+    }
+
+    #[test]
+    fn test_retry() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            let func: LuaFunction = lua
+                .load("return function(n) if n < 3 then error('fail') end return n, 'ok' end")
+                .eval()
+                .unwrap();
+            let opts = lua.load("{ base = 0 }").eval().unwrap();
+            let values = retry(lua.clone(), (func, Some(opts))).await.unwrap();
+            assert_eq!(values.len(), 2);
+            assert_eq!(values[0].as_i32(), Some(3));
+        });
+    }
+
+    #[test]
+    fn test_debounce() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            let func: LuaFunction = lua
+                .load("calls = {}; return function(n) table.insert(calls, n) end")
+                .eval()
+                .unwrap();
+            let debounced = debounce(lua.clone(), (Seconds(0.02), func)).await.unwrap();
+            for n in 1..=5 {
+                debounced.call::<()>(n).unwrap();
+            }
+            smol::Timer::after(Duration::from_millis(100)).await;
+            let calls: Vec<i32> = lua.globals().get("calls").unwrap();
+            assert_eq!(calls, vec![5]);
+        });
+    }
+
+    #[test]
+    fn test_throttle() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            let func: LuaFunction = lua
+                .load("count = 0; return function() count = count + 1; return count end")
+                .eval()
+                .unwrap();
+            let throttled = throttle(lua.clone(), (Seconds(60.0), func)).await.unwrap();
+            let first = throttled.call_async::<Option<i32>>(()).await.unwrap();
+            let second = throttled.call_async::<Option<i32>>(()).await.unwrap();
+            assert_eq!(first, Some(1));
+            assert_eq!(second, None);
+            assert_eq!(lua.globals().get::<i32>("count").unwrap(), 1);
+        });
+    }
+
+    #[test]
+    fn test_deadline() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            let func: LuaFunction = lua.load("return function(n) return n end").eval().unwrap();
+            let args = LuaMultiValue::from(vec![LuaValue::Integer(7)]);
+            let values = deadline(lua.clone(), (Seconds(1.0), func, args))
+                .await
+                .unwrap();
+            assert_eq!(values[0].as_i32(), Some(7));
+        });
+    }
+
+    #[test]
+    fn test_deadline_exceeded() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            let sleep = lua
+                .create_async_function(|_, secs: f64| {
+                    cancel::until_deadline(async move {
+                        smol::Timer::after(Duration::from_secs_f64(secs)).await;
+                        Ok(())
+                    })
+                })
+                .unwrap();
+            lua.globals().set("sleep", sleep).unwrap();
+            // the function unwinds through its own error handling
+            let func: LuaFunction = lua
+                .load(
+                    r#"
+                    return function()
+                        local ok, err = pcall(sleep, 60)
+                        caught = tostring(err)
+                        error(err, 0)
+                    end
+                    "#,
+                )
+                .eval()
+                .unwrap();
+            let result = deadline(lua.clone(), (Seconds(0.01), func, LuaMultiValue::new())).await;
+            assert!(result.unwrap_err().to_string().contains("DeadlineExceeded"));
+            let caught: String = lua.globals().get("caught").unwrap();
+            assert!(caught.contains("within 0.01 seconds"));
+        });
+    }
+
+    #[test]
+    fn test_deadline_abandoned() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            let pending = lua
+                .create_async_function(|_, ()| std::future::pending::<LuaResult<()>>())
+                .unwrap();
+            lua.globals().set("pending", pending).unwrap();
+            // a wait which ignores the deadline is dropped after the grace period
+            let func: LuaFunction = lua.load("return function() pending() end").eval().unwrap();
+            let started = Instant::now();
+            let result = deadline(lua.clone(), (Seconds(0.01), func, LuaMultiValue::new())).await;
+            assert!(result.unwrap_err().to_string().contains("DeadlineExceeded"));
+            assert!(started.elapsed() < Duration::from_secs(1));
+        });
+    }
+
+    #[test]
+    fn test_retry_exhausted() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            let func: LuaFunction = lua
+                .load("count = 0; return function() count = count + 1; error('fail') end")
+                .eval()
+                .unwrap();
+            let opts = lua.load("{ attempts = 3, base = 0 }").eval().unwrap();
+            assert!(retry(lua.clone(), (func, Some(opts))).await.is_err());
+            assert_eq!(lua.globals().get::<i32>("count").unwrap(), 3);
+        });
+    }
+}
@@ -0,0 +1,775 @@
+use std::{
+    os::unix::fs::{DirBuilderExt, OpenOptionsExt},
+    path::{Path, PathBuf},
+};
+
+use mlua::prelude::*;
+
+use crate::{
+    cleanup::{Cleanup, CLEANUP},
+    random, unix,
+};
+
+/// Number of attempts to find an unused temporary name
+const TEMP_ATTEMPTS: usize = 16;
+
+/// Size of the chunks copied between progress callbacks
+const COPY_CHUNK: usize = 64 * 1024;
+
+/// Temporary sibling path used to atomically replace `path`
+fn sibling_tmp(path: &Path) -> PathBuf {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    path.with_file_name(format!(".{}.{}.tmp", name, std::process::id()))
+}
+
+/// Atomically write `data` to `path` by renaming a temporary file into place
+pub async fn write_atomic(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    let tmp = sibling_tmp(path);
+    smol::fs::write(&tmp, data).await?;
+    if let Err(err) = smol::fs::rename(&tmp, path).await {
+        let _ = smol::fs::remove_file(&tmp).await;
+        return Err(err);
+    }
+    Ok(())
+}
+
+/// Write the readiness file and register it with `cleanup`
+async fn mark_ready(cleanup: &Cleanup, path: PathBuf) -> std::io::Result<()> {
+    let data = format!("{}\n", std::process::id());
+    write_atomic(&path, data.as_bytes()).await?;
+    cleanup.register(path);
+    Ok(())
+}
+
+/// Create or update a readiness file which is removed on shutdown
+pub async fn ready(_lua: Lua, path: String) -> LuaResult<bool> {
+    mark_ready(&CLEANUP, PathBuf::from(path)).await?;
+    Ok(true)
+}
+
+/// Match a character against a `[...]` class starting after the `[`
+fn match_class(class: &[char], c: char) -> Option<(bool, usize)> {
+    let (negate, start) = match class.first() {
+        Some('!' | '^') => (true, 1),
+        _ => (false, 0),
+    };
+    let mut i = start;
+    let mut matched = false;
+    while i < class.len() {
+        // a `]` immediately after the opening bracket is a literal
+        if class[i] == ']' && i > start {
+            return Some((matched != negate, i + 1));
+        }
+        if i + 2 < class.len() && class[i + 1] == '-' && class[i + 2] != ']' {
+            matched |= class[i] <= c && c <= class[i + 2];
+            i += 3;
+        } else {
+            matched |= class[i] == c;
+            i += 1;
+        }
+    }
+    None
+}
+
+/// Match a file name against a shell wildcard pattern
+fn wildcard(pattern: &[char], name: &[char]) -> bool {
+    match pattern.first() {
+        None => name.is_empty(),
+        Some('*') => (0..=name.len()).any(|i| wildcard(&pattern[1..], &name[i..])),
+        Some('?') => !name.is_empty() && wildcard(&pattern[1..], &name[1..]),
+        Some('[') => {
+            let Some(&c) = name.first() else {
+                return false;
+            };
+            match match_class(&pattern[1..], c) {
+                Some((matched, len)) => matched && wildcard(&pattern[1 + len..], &name[1..]),
+                // an unterminated class matches a literal `[`
+                None => c == '[' && wildcard(&pattern[1..], &name[1..]),
+            }
+        }
+        Some(&p) => name.first() == Some(&p) && wildcard(&pattern[1..], &name[1..]),
+    }
+}
+
+/// Check whether a path component contains wildcard characters
+fn has_wildcard(component: &str) -> bool {
+    component.contains(['*', '?', '['])
+}
+
+/// Read the names of the entries in a directory, ignoring errors
+fn read_names(dir: &Path) -> Vec<String> {
+    let dir = if dir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        dir
+    };
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .collect()
+}
+
+/// Collect `dir` and all non-hidden directories below it
+fn descendants(dir: &Path, out: &mut Vec<PathBuf>) {
+    out.push(dir.to_path_buf());
+    for name in read_names(dir) {
+        let path = dir.join(&name);
+        if !name.starts_with('.') && path.is_dir() && !path.is_symlink() {
+            descendants(&path, out);
+        }
+    }
+}
+
+/// Expand a glob pattern into the sorted list of matching paths
+fn expand(pattern: &str) -> Vec<PathBuf> {
+    let root = match pattern.starts_with('/') {
+        true => PathBuf::from("/"),
+        false => PathBuf::new(),
+    };
+    let mut paths = vec![root];
+    for component in pattern.split('/').filter(|c| !c.is_empty()) {
+        let mut next = Vec::new();
+        for base in paths {
+            if component == "**" {
+                descendants(&base, &mut next);
+            } else if !has_wildcard(component) {
+                let path = base.join(component);
+                if std::fs::symlink_metadata(&path).is_ok() {
+                    next.push(path);
+                }
+            } else {
+                let pattern: Vec<char> = component.chars().collect();
+                for name in read_names(&base) {
+                    // hidden files are only matched explicitly
+                    if name.starts_with('.') && !component.starts_with('.') {
+                        continue;
+                    }
+                    let chars: Vec<char> = name.chars().collect();
+                    if wildcard(&pattern, &chars) {
+                        next.push(base.join(name));
+                    }
+                }
+            }
+        }
+        paths = next;
+    }
+    paths.retain(|path| !path.as_os_str().is_empty());
+    paths.sort();
+    paths.dedup();
+    paths
+}
+
+/// Asynchronously return the paths matching a glob pattern
+async fn glob(_lua: Lua, pattern: String) -> LuaResult<Vec<String>> {
+    let paths = smol::unblock(move || expand(&pattern)).await;
+    Ok(paths
+        .iter()
+        .map(|path| path.to_string_lossy().into_owned())
+        .collect())
+}
+
+/// Create a uniquely named entry in `dir` using `create`
+fn create_temp(
+    dir: &Path,
+    prefix: &str,
+    create: impl Fn(&Path) -> std::io::Result<()>,
+) -> std::io::Result<PathBuf> {
+    for _ in 0..TEMP_ATTEMPTS {
+        let path = dir.join(format!("{}{}", prefix, random::random_string(10)));
+        match create(&path) {
+            Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            result => return result.map(|_| path),
+        }
+    }
+    Err(std::io::Error::new(
+        std::io::ErrorKind::AlreadyExists,
+        "failed to find an unused temporary name",
+    ))
+}
+
+/// Create a private temporary directory
+fn create_tempdir(dir: &Path, prefix: &str) -> std::io::Result<PathBuf> {
+    create_temp(dir, prefix, |path| {
+        std::fs::DirBuilder::new().mode(0o700).create(path)
+    })
+}
+
+/// Create a private temporary file
+fn create_tempfile(dir: &Path, prefix: &str) -> std::io::Result<PathBuf> {
+    create_temp(dir, prefix, |path| {
+        std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(path)
+            .map(|_| ())
+    })
+}
+
+/// Options shared by `tempdir` and `tempfile`
+struct TempOptions {
+    dir: PathBuf,
+    cleanup: bool,
+}
+
+/// Read temporary file options from an optional Lua table
+fn temp_options(opts: Option<LuaTable>) -> LuaResult<TempOptions> {
+    let mut options = TempOptions {
+        dir: std::env::temp_dir(),
+        cleanup: false,
+    };
+    if let Some(opts) = opts {
+        if let Some(dir) = opts.get::<Option<String>>("dir")? {
+            options.dir = PathBuf::from(dir);
+        }
+        options.cleanup = opts.get::<Option<bool>>("cleanup")?.unwrap_or(false);
+    }
+    Ok(options)
+}
+
+/// Create a temporary path from Lua, optionally removing it on shutdown
+async fn lua_temp(
+    prefix: Option<String>,
+    opts: Option<LuaTable>,
+    create: fn(&Path, &str) -> std::io::Result<PathBuf>,
+) -> LuaResult<String> {
+    let options = temp_options(opts)?;
+    let prefix = prefix.unwrap_or_else(|| "luavisors-".to_string());
+    let path = smol::unblock(move || create(&options.dir, &prefix)).await?;
+    if options.cleanup {
+        CLEANUP.register(path.clone());
+    }
+    Ok(path.to_string_lossy().into_owned())
+}
+
+/// Create a temporary directory from Lua
+async fn tempdir(
+    _lua: Lua,
+    (prefix, opts): (Option<String>, Option<LuaTable>),
+) -> LuaResult<String> {
+    lua_temp(prefix, opts, create_tempdir).await
+}
+
+/// Create a temporary file from Lua
+async fn tempfile(
+    _lua: Lua,
+    (prefix, opts): (Option<String>, Option<LuaTable>),
+) -> LuaResult<String> {
+    lua_temp(prefix, opts, create_tempfile).await
+}
+
+/// Advisory lock on a file which is released on `unlock` or collection
+pub struct FileLock {
+    file: Option<std::fs::File>,
+}
+
+/// Lua methods for file locks
+impl LuaUserData for FileLock {
+    fn add_methods<M: LuaUserDataMethods<Self>>(methods: &mut M) {
+        methods.add_method_mut("unlock", |_, this, ()| {
+            match this.file.take() {
+                Some(file) => unix::flock(&file, unix::LOCK_UN)?,
+                None => return Ok(false),
+            }
+            Ok(true)
+        });
+        methods.add_method("locked", |_, this, ()| Ok(this.file.is_some()));
+    }
+}
+
+/// Open `path` for locking, creating it if necessary
+fn open_lock_file(path: &Path) -> std::io::Result<std::fs::File> {
+    std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .mode(0o644)
+        .open(path)
+}
+
+/// Lock a file, returning `None` if it is held elsewhere and `wait` is false
+fn lock_file(path: &Path, shared: bool, wait: bool) -> std::io::Result<Option<std::fs::File>> {
+    let file = open_lock_file(path)?;
+    let mut operation = match shared {
+        true => unix::LOCK_SH,
+        false => unix::LOCK_EX,
+    };
+    if !wait {
+        operation |= unix::LOCK_NB;
+    }
+    match unix::flock(&file, operation) {
+        Ok(()) => Ok(Some(file)),
+        Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// Asynchronously acquire a `flock` lock on a file from Lua
+async fn lock(_lua: Lua, (path, opts): (String, Option<LuaTable>)) -> LuaResult<Option<FileLock>> {
+    let (shared, wait) = match opts {
+        Some(opts) => (
+            opts.get::<Option<bool>>("shared")?.unwrap_or(false),
+            opts.get::<Option<bool>>("wait")?.unwrap_or(true),
+        ),
+        None => (false, true),
+    };
+    let file = smol::unblock(move || lock_file(Path::new(&path), shared, wait)).await?;
+    Ok(file.map(|file| FileLock { file: Some(file) }))
+}
+
+/// Pid files which stay locked for the lifetime of the supervisor
+static PIDFILES: std::sync::Mutex<Vec<std::fs::File>> = std::sync::Mutex::new(Vec::new());
+
+/// Lock a pid file exclusively and write the current pid into it
+fn write_pidfile(path: &Path) -> std::io::Result<std::fs::File> {
+    use std::io::{Read, Seek, Write};
+    let Some(mut file) = lock_file(path, false, false)? else {
+        let mut pid = String::new();
+        std::fs::File::open(path)?.read_to_string(&mut pid)?;
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::AddrInUse,
+            format!("'{}' is locked by pid {}", path.display(), pid.trim()),
+        ));
+    };
+    file.set_len(0)?;
+    file.rewind()?;
+    writeln!(file, "{}", std::process::id())?;
+    file.sync_all()?;
+    Ok(file)
+}
+
+/// Write an exclusively locked pid file which is removed on shutdown
+pub async fn pidfile(_lua: Lua, path: String) -> LuaResult<bool> {
+    let path = PathBuf::from(path);
+    let clone = path.clone();
+    let file = smol::unblock(move || write_pidfile(&clone)).await?;
+    PIDFILES
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .push(file);
+    CLEANUP.register(path);
+    Ok(true)
+}
+
+/// Options shared by `copy` and `move`
+struct CopyOptions {
+    progress: Option<LuaFunction>,
+    atomic: bool,
+}
+
+/// Read copy options from an optional Lua table
+fn copy_options(opts: Option<LuaTable>) -> LuaResult<CopyOptions> {
+    let Some(opts) = opts else {
+        return Ok(CopyOptions {
+            progress: None,
+            atomic: true,
+        });
+    };
+    Ok(CopyOptions {
+        progress: opts.get("progress")?,
+        atomic: opts.get::<Option<bool>>("atomic")?.unwrap_or(true),
+    })
+}
+
+/// Copy the contents and permissions of `src` to `dst`, reporting progress after each chunk
+async fn copy_file(src: &Path, dst: &Path, progress: Option<&LuaFunction>) -> LuaResult<u64> {
+    use smol::io::{AsyncReadExt, AsyncWriteExt};
+    let mut input = smol::fs::File::open(src).await?;
+    let metadata = input.metadata().await?;
+    let total = metadata.len();
+    let mut output = smol::fs::File::create(dst).await?;
+    let mut buffer = vec![0; COPY_CHUNK];
+    let mut copied = 0;
+    loop {
+        let read = input.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        output.write_all(&buffer[..read]).await?;
+        copied += read as u64;
+        if let Some(progress) = progress {
+            progress.call_async::<()>((copied, total)).await?;
+        }
+    }
+    output.sync_all().await?;
+    smol::fs::set_permissions(dst, metadata.permissions()).await?;
+    Ok(copied)
+}
+
+/// Copy a file, atomically renaming it into place unless disabled
+async fn copy_into_place(src: &Path, dst: &Path, options: &CopyOptions) -> LuaResult<u64> {
+    if !options.atomic {
+        return copy_file(src, dst, options.progress.as_ref()).await;
+    }
+    let tmp = sibling_tmp(dst);
+    let result = match copy_file(src, &tmp, options.progress.as_ref()).await {
+        Ok(copied) => smol::fs::rename(&tmp, dst)
+            .await
+            .map(|_| copied)
+            .map_err(LuaError::external),
+        Err(err) => Err(err),
+    };
+    if result.is_err() {
+        let _ = smol::fs::remove_file(&tmp).await;
+    }
+    result
+}
+
+/// Copy a file from Lua, returning the number of bytes copied
+async fn copy(_lua: Lua, (src, dst, opts): (String, String, Option<LuaTable>)) -> LuaResult<u64> {
+    let options = copy_options(opts)?;
+    copy_into_place(Path::new(&src), Path::new(&dst), &options).await
+}
+
+/// Move a file from Lua, copying and removing it when renaming crosses filesystems
+async fn lua_move(
+    _lua: Lua,
+    (src, dst, opts): (String, String, Option<LuaTable>),
+) -> LuaResult<bool> {
+    let options = copy_options(opts)?;
+    match smol::fs::rename(&src, &dst).await {
+        Err(err) if err.kind() == std::io::ErrorKind::CrossesDevices => {
+            copy_into_place(Path::new(&src), Path::new(&dst), &options).await?;
+            smol::fs::remove_file(&src).await?;
+        }
+        result => result?,
+    }
+    Ok(true)
+}
+
+/// Return the `init.fs` Lua table
+pub fn fs_table(lua: &Lua) -> LuaResult<LuaTable> {
+    let table = lua.create_table()?;
+    table.set("glob", lua.create_async_function(glob)?)?;
+    table.set("tempdir", lua.create_async_function(tempdir)?)?;
+    table.set("tempfile", lua.create_async_function(tempfile)?)?;
+    table.set("lock", lua.create_async_function(lock)?)?;
+    table.set("copy", lua.create_async_function(copy)?)?;
+    table.set("move", lua.create_async_function(lua_move)?)?;
+    Ok(table)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("luavisors-fs-{}-{}", name, std::process::id()))
+    }
+
+    fn matches(pattern: &str, name: &str) -> bool {
+        let pattern: Vec<char> = pattern.chars().collect();
+        let name: Vec<char> = name.chars().collect();
+        wildcard(&pattern, &name)
+    }
+
+    fn test_tree(name: &str) -> PathBuf {
+        let root = test_path(name);
+        std::fs::create_dir_all(root.join("conf.d/nested")).unwrap();
+        for file in ["a.conf", "b.conf", "c.txt", ".hidden.conf", "nested/d.conf"] {
+            std::fs::write(root.join("conf.d").join(file), "").unwrap();
+        }
+        root
+    }
+
+    #[test]
+    fn test_wildcard() {
+        assert!(matches("*.conf", "app.conf"));
+        assert!(!matches("*.conf", "app.txt"));
+        assert!(matches("a?c", "abc"));
+        assert!(!matches("a?c", "ac"));
+        assert!(matches("[ab]*", "bcd"));
+        assert!(matches("[a-c]x", "bx"));
+        assert!(!matches("[!a-c]x", "bx"));
+        assert!(matches("[]]", "]"));
+        assert!(matches("[abc", "[abc"));
+        assert!(matches("*", ""));
+    }
+
+    #[test]
+    fn test_has_wildcard() {
+        assert!(has_wildcard("*.conf"));
+        assert!(!has_wildcard("app.conf"));
+    }
+
+    #[test]
+    fn test_expand() {
+        let root = test_tree("expand");
+        let pattern = format!("{}/conf.d/*.conf", root.display());
+        let paths = expand(&pattern);
+        assert_eq!(
+            paths,
+            vec![root.join("conf.d/a.conf"), root.join("conf.d/b.conf")]
+        );
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_expand_recursive() {
+        let root = test_tree("recursive");
+        let pattern = format!("{}/**/*.conf", root.display());
+        let paths = expand(&pattern);
+        assert_eq!(paths.len(), 3);
+        assert!(paths.contains(&root.join("conf.d/nested/d.conf")));
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_expand_hidden() {
+        let root = test_tree("hidden");
+        let pattern = format!("{}/conf.d/.*.conf", root.display());
+        assert_eq!(expand(&pattern), vec![root.join("conf.d/.hidden.conf")]);
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_expand_missing() {
+        assert!(expand("/does/not/exist/*").is_empty());
+        assert!(expand("/does/not/exist").is_empty());
+    }
+
+    #[test]
+    fn test_glob() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            let paths = glob(lua, "/".to_string()).await.unwrap();
+            assert_eq!(paths, vec!["/"]);
+        });
+    }
+
+    #[test]
+    fn test_create_tempdir() {
+        let dir = create_tempdir(&std::env::temp_dir(), "luavisors-test-").unwrap();
+        let metadata = std::fs::metadata(&dir).unwrap();
+        assert!(metadata.is_dir());
+        assert_eq!(
+            std::os::unix::fs::PermissionsExt::mode(&metadata.permissions()) & 0o777,
+            0o700
+        );
+        std::fs::remove_dir(dir).unwrap();
+    }
+
+    #[test]
+    fn test_create_tempfile() {
+        let file = create_tempfile(&std::env::temp_dir(), "luavisors-test-").unwrap();
+        let metadata = std::fs::metadata(&file).unwrap();
+        assert!(metadata.is_file());
+        assert_eq!(
+            std::os::unix::fs::PermissionsExt::mode(&metadata.permissions()) & 0o777,
+            0o600
+        );
+        std::fs::remove_file(file).unwrap();
+    }
+
+    #[test]
+    fn test_create_temp_err() {
+        let dir = Path::new("/does/not/exist");
+        assert!(create_tempfile(dir, "test").is_err());
+        let exists = create_temp(&std::env::temp_dir(), "test", |_| {
+            Err(std::io::ErrorKind::AlreadyExists.into())
+        });
+        assert!(exists.is_err());
+    }
+
+    #[test]
+    fn test_tempdir() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            let opts = lua.create_table().unwrap();
+            opts.set("dir", std::env::temp_dir().to_string_lossy().as_ref())
+                .unwrap();
+            let dir = tempdir(lua, (Some("app-".to_string()), Some(opts)))
+                .await
+                .unwrap();
+            let path = PathBuf::from(&dir);
+            assert!(path
+                .file_name()
+                .unwrap()
+                .to_string_lossy()
+                .starts_with("app-"));
+            assert!(path.is_dir());
+            std::fs::remove_dir(path).unwrap();
+        });
+    }
+
+    #[test]
+    fn test_tempfile() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            let file = tempfile(lua, (None, None)).await.unwrap();
+            assert!(Path::new(&file).is_file());
+            std::fs::remove_file(file).unwrap();
+        });
+    }
+
+    #[test]
+    fn test_lock_file() {
+        let path = test_path("lock");
+        let first = lock_file(&path, false, false).unwrap();
+        assert!(first.is_some());
+        assert!(lock_file(&path, true, false).unwrap().is_none());
+        drop(first);
+        let shared = lock_file(&path, true, false).unwrap();
+        assert!(shared.is_some());
+        assert!(lock_file(&path, true, false).unwrap().is_some());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_lock() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            let path = test_path("lua-lock");
+            let name = path.to_string_lossy().into_owned();
+            let mut first = lock(lua.clone(), (name.clone(), None))
+                .await
+                .unwrap()
+                .unwrap();
+            let opts = lua.create_table().unwrap();
+            opts.set("wait", false).unwrap();
+            let second = lock(lua.clone(), (name, Some(opts))).await.unwrap();
+            assert!(second.is_none());
+            assert!(first.file.take().is_some());
+            std::fs::remove_file(path).unwrap();
+        });
+    }
+
+    #[test]
+    fn test_lock_methods() {
+        let lua = Lua::new();
+        let path = test_path("lock-methods");
+        let file = lock_file(&path, false, true).unwrap();
+        let lock = lua.create_userdata(FileLock { file }).unwrap();
+        lua.globals().set("lock", lock).unwrap();
+        let result: (bool, bool, bool) = lua
+            .load("return lock:locked(), lock:unlock(), lock:unlock()")
+            .eval()
+            .unwrap();
+        assert_eq!(result, (true, true, false));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_write_pidfile() {
+        let path = test_path("pidfile");
+        let file = write_pidfile(&path).unwrap();
+        let data = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(data.trim(), std::process::id().to_string());
+        let err = write_pidfile(&path).unwrap_err();
+        assert!(err.to_string().contains(&std::process::id().to_string()));
+        drop(file);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_copy() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            let (src, dst) = (test_path("copy-src"), test_path("copy-dst"));
+            std::fs::write(&src, vec![7; COPY_CHUNK + 1]).unwrap();
+            let calls: LuaTable = lua.create_table().unwrap();
+            let record = calls.clone();
+            let progress = lua
+                .create_function(move |_, (copied, total): (u64, u64)| {
+                    record.push(format!("{}/{}", copied, total))
+                })
+                .unwrap();
+            let opts = lua.create_table().unwrap();
+            opts.set("progress", progress).unwrap();
+            let name = |path: &Path| path.to_string_lossy().into_owned();
+            let copied = copy(lua.clone(), (name(&src), name(&dst), Some(opts)))
+                .await
+                .unwrap();
+            assert_eq!(copied, COPY_CHUNK as u64 + 1);
+            assert_eq!(std::fs::read(&dst).unwrap(), std::fs::read(&src).unwrap());
+            assert_eq!(calls.len().unwrap(), 2);
+            assert!(!sibling_tmp(&dst).exists());
+            std::fs::remove_file(&src).unwrap();
+            std::fs::remove_file(&dst).unwrap();
+        });
+    }
+
+    #[test]
+    fn test_copy_err() {
+        smol::block_on(async {
+            let dst = test_path("copy-err");
+            let options = copy_options(None).unwrap();
+            let result = copy_into_place(Path::new("/does/not/exist"), &dst, &options).await;
+            assert!(result.is_err());
+            assert!(!dst.exists() && !sibling_tmp(&dst).exists());
+        });
+    }
+
+    #[test]
+    fn test_move() {
+        smol::block_on(async {
+            let (src, dst) = (test_path("move-src"), test_path("move-dst"));
+            std::fs::write(&src, "state").unwrap();
+            let name = |path: &Path| path.to_string_lossy().into_owned();
+            assert!(lua_move(Lua::new(), (name(&src), name(&dst), None))
+                .await
+                .unwrap());
+            assert!(!src.exists());
+            assert_eq!(std::fs::read_to_string(&dst).unwrap(), "state");
+            std::fs::remove_file(&dst).unwrap();
+        });
+    }
+
+    #[test]
+    fn test_sibling_tmp() {
+        let tmp = sibling_tmp(Path::new("/run/app/ready"));
+        assert_eq!(tmp.parent(), Some(Path::new("/run/app")));
+        assert!(tmp.to_string_lossy().ends_with(".tmp"));
+    }
+
+    #[test]
+    fn test_write_atomic() {
+        smol::block_on(async {
+            let path = test_path("atomic");
+            write_atomic(&path, b"one").await.unwrap();
+            write_atomic(&path, b"two").await.unwrap();
+            assert_eq!(std::fs::read(&path).unwrap(), b"two");
+            assert!(!sibling_tmp(&path).exists());
+            std::fs::remove_file(&path).unwrap();
+        });
+    }
+
+    #[test]
+    fn test_write_atomic_err() {
+        smol::block_on(async {
+            let path = test_path("missing").join("ready");
+            assert!(write_atomic(&path, b"data").await.is_err());
+        });
+    }
+
+    #[test]
+    fn test_mark_ready() {
+        smol::block_on(async {
+            let cleanup = Cleanup::new();
+            let path = test_path("ready");
+            mark_ready(&cleanup, path.clone()).await.unwrap();
+            let data = std::fs::read_to_string(&path).unwrap();
+            assert_eq!(data.trim(), std::process::id().to_string());
+            assert!(cleanup.contains(&path));
+            cleanup.run();
+            assert!(!path.exists());
+        });
+    }
+
+    #[test]
+    fn test_ready_err() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            let path = test_path("missing").join("ready");
+            let name = path.to_string_lossy().into_owned();
+            assert!(ready(lua, name).await.is_err());
+        });
+    }
+}
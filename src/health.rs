@@ -0,0 +1,265 @@
+use std::time::Duration;
+
+use mlua::prelude::*;
+use smol::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+use crate::{duration::Seconds, process};
+
+/// Largest response read from an HTTP probe, which only needs the status line
+const MAX_RESPONSE: u64 = 4096;
+
+/// How a service is checked for health
+#[derive(Debug, Clone)]
+enum Probe {
+    Http {
+        addr: String,
+        host: String,
+        path: String,
+    },
+    Tcp(String),
+    Exec(LuaTable),
+}
+
+/// Probe of a service with how often it runs and how many failures make it unhealthy
+#[derive(Debug, Clone)]
+pub struct HealthCheck {
+    probe: Probe,
+    pub interval: Duration,
+    timeout: Duration,
+    pub failures: u32,
+}
+
+/// Split an `http://` URL into the address to connect to, its host, and its path
+fn parse_http_url(url: &str) -> Result<(String, String, String), String> {
+    let rest = url
+        .strip_prefix("http://")
+        .ok_or_else(|| format!("health check url '{}' must start with http://", url))?;
+    let (host, path) = match rest.find('/') {
+        Some(slash) => rest.split_at(slash),
+        None => (rest, "/"),
+    };
+    if host.is_empty() {
+        return Err(format!("health check url '{}' has no host", url));
+    }
+    let addr = match host.rsplit_once(':') {
+        Some((_, port)) if port.parse::<u16>().is_ok() => host.to_string(),
+        _ => format!("{}:80", host),
+    };
+    Ok((addr, host.to_string(), path.to_string()))
+}
+
+/// Read the `healthcheck` option, which needs one of `http`, `tcp`, or `exec`
+pub fn healthcheck_option(options: &LuaTable) -> LuaResult<Option<HealthCheck>> {
+    let Some(table) = options.get::<Option<LuaTable>>("healthcheck")? else {
+        return Ok(None);
+    };
+    let probe = if let Some(url) = table.get::<Option<String>>("http")? {
+        let (addr, host, path) = parse_http_url(&url).map_err(LuaError::runtime)?;
+        Probe::Http { addr, host, path }
+    } else if let Some(addr) = table.get::<Option<String>>("tcp")? {
+        Probe::Tcp(addr)
+    } else if let Some(exec) = table.get::<Option<LuaTable>>("exec")? {
+        Probe::Exec(exec)
+    } else {
+        return Err(LuaError::runtime(
+            "healthcheck needs an http, tcp, or exec probe",
+        ));
+    };
+    let interval = table
+        .get::<Option<Seconds>>("interval")?
+        .map_or(Duration::from_secs(5), Seconds::duration);
+    let timeout = table
+        .get::<Option<Seconds>>("timeout")?
+        .map_or(interval, Seconds::duration);
+    if let Probe::Exec(exec) = &probe {
+        // a probe command which hangs is killed like any other timed out child
+        if exec.get::<LuaValue>("timeout")?.is_nil() {
+            exec.set("timeout", timeout.as_secs_f64())?;
+        }
+    }
+    Ok(Some(HealthCheck {
+        probe,
+        interval,
+        timeout,
+        failures: table.get::<Option<u32>>("failures")?.unwrap_or(3).max(1),
+    }))
+}
+
+/// Check the status line of an HTTP response for a 2xx or 3xx status
+fn check_response(response: &[u8]) -> Result<(), String> {
+    let response = String::from_utf8_lossy(response);
+    let line = response.lines().next().unwrap_or_default();
+    let code = line
+        .split_whitespace()
+        .nth(1)
+        .filter(|_| line.starts_with("HTTP/"))
+        .and_then(|code| code.parse::<u16>().ok())
+        .ok_or_else(|| format!("invalid http response '{}'", line))?;
+    match code {
+        200..=399 => Ok(()),
+        code => Err(format!("http status {}", code)),
+    }
+}
+
+/// Send a GET request and check the status of its response
+async fn http_probe(addr: &str, host: &str, path: &str) -> Result<(), String> {
+    let mut stream = TcpStream::connect(addr)
+        .await
+        .map_err(|err| err.to_string())?;
+    let request = format!(
+        "GET {} HTTP/1.0\r\nHost: {}\r\nUser-Agent: luavisors\r\nConnection: close\r\n\r\n",
+        path, host
+    );
+    stream
+        .write_all(request.as_bytes())
+        .await
+        .map_err(|err| err.to_string())?;
+    let mut response = Vec::new();
+    stream
+        .take(MAX_RESPONSE)
+        .read_to_end(&mut response)
+        .await
+        .map_err(|err| err.to_string())?;
+    check_response(&response)
+}
+
+/// Run a command and check that it exits successfully
+async fn exec_probe(lua: &Lua, exec: &LuaTable) -> LuaResult<Result<(), String>> {
+    let value = LuaValue::Table(exec.clone());
+    let child = process::exec(lua.clone(), (value, LuaMultiValue::new())).await?;
+    let status: LuaTable = child
+        .get::<LuaFunction>("status")?
+        .call_async(&child)
+        .await?;
+    if status.get("success")? {
+        return Ok(Ok(()));
+    }
+    Ok(Err(match status.get::<Option<String>>("reason")? {
+        Some(reason) => format!("probe command failed with {}", reason),
+        None => match status.get::<Option<i32>>("code")? {
+            Some(code) => format!("probe command exited with code {}", code),
+            None => "probe command was killed".to_string(),
+        },
+    }))
+}
+
+/// Run a health check once, returning why it failed if it did
+pub async fn probe(lua: &Lua, check: &HealthCheck) -> LuaResult<Result<(), String>> {
+    let probed = async {
+        match &check.probe {
+            Probe::Http { addr, host, path } => Ok(http_probe(addr, host, path).await),
+            Probe::Tcp(addr) => Ok(TcpStream::connect(addr.as_str())
+                .await
+                .map(drop)
+                .map_err(|err| err.to_string())),
+            Probe::Exec(exec) => exec_probe(lua, exec).await,
+        }
+    };
+    smol::future::or(probed, async {
+        smol::Timer::after(check.timeout).await;
+        Ok(Err("health check timed out".to_string()))
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use smol::net::TcpListener;
+
+    #[test]
+    fn test_parse_http_url() {
+        assert_eq!(
+            parse_http_url("http://127.0.0.1:8080/healthz").unwrap(),
+            (
+                "127.0.0.1:8080".to_string(),
+                "127.0.0.1:8080".to_string(),
+                "/healthz".to_string()
+            )
+        );
+        let (addr, _, path) = parse_http_url("http://localhost").unwrap();
+        assert_eq!((addr.as_str(), path.as_str()), ("localhost:80", "/"));
+        assert!(parse_http_url("https://localhost/").is_err());
+        assert!(parse_http_url("http:///healthz").is_err());
+    }
+
+    #[test]
+    fn test_check_response() {
+        assert!(check_response(b"HTTP/1.1 204 No Content\r\n\r\n").is_ok());
+        assert_eq!(
+            check_response(b"HTTP/1.0 503 Service Unavailable\r\n"),
+            Err("http status 503".to_string())
+        );
+        assert!(check_response(b"SSH-2.0-OpenSSH\r\n").is_err());
+    }
+
+    #[test]
+    fn test_healthcheck_option() {
+        let lua = Lua::new();
+        let options = lua
+            .load("{ healthcheck = { exec = { 'true' }, interval = 2 } }")
+            .eval()
+            .unwrap();
+        let check = healthcheck_option(&options).unwrap().unwrap();
+        assert_eq!(
+            (check.interval, check.failures),
+            (Duration::from_secs(2), 3)
+        );
+        let Probe::Exec(exec) = check.probe else {
+            panic!("expected an exec probe");
+        };
+        assert_eq!(exec.get::<f64>("timeout").unwrap(), 2.0);
+        let options = lua.load("{}").eval().unwrap();
+        assert!(healthcheck_option(&options).unwrap().is_none());
+        let options = lua
+            .load("{ healthcheck = { interval = 1 } }")
+            .eval()
+            .unwrap();
+        assert!(healthcheck_option(&options).is_err());
+    }
+
+    #[test]
+    fn test_probe() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            let server = smol::spawn(async move {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = [0; 256];
+                let read = stream.read(&mut request).await.unwrap();
+                assert!(request[..read].starts_with(b"GET /healthz HTTP/1.0\r\n"));
+                stream.write_all(b"HTTP/1.0 200 OK\r\n\r\n").await.unwrap();
+            });
+            let options = lua
+                .load(format!(
+                    "{{ healthcheck = {{ http = 'http://{}/healthz' }} }}",
+                    addr
+                ))
+                .eval()
+                .unwrap();
+            let check = healthcheck_option(&options).unwrap().unwrap();
+            assert_eq!(probe(&lua, &check).await.unwrap(), Ok(()));
+            server.await;
+            // the listener is closed once the server is done
+            let options = lua
+                .load(format!("{{ healthcheck = {{ tcp = '{}' }} }}", addr))
+                .eval()
+                .unwrap();
+            let check = healthcheck_option(&options).unwrap().unwrap();
+            assert!(probe(&lua, &check).await.unwrap().is_err());
+            let options = lua
+                .load("{ healthcheck = { exec = { 'sh', '-c', 'exit 4' } } }")
+                .eval()
+                .unwrap();
+            let check = healthcheck_option(&options).unwrap().unwrap();
+            assert_eq!(
+                probe(&lua, &check).await.unwrap(),
+                Err("probe command exited with code 4".to_string())
+            );
+        });
+    }
+}
@@ -1,54 +1,250 @@
+use std::{sync::Arc, time::Instant};
+
 use mlua::prelude::*;
-use smol::stream::StreamExt;
 
-use crate::{process, unix};
+use crate::{
+    args, boot,
+    cancel::{self, CancelToken},
+    cgroup,
+    duration::{self, Seconds},
+    flow, fs, logfile, metrics, mock, mount, net, os, path, proc, process, profile, reaper,
+    sandbox, schedule, secrets, shell, stdin, supervise, sync, system, task, terminal, time, unix,
+    verify,
+};
 
 /// Return the current process identifier
 async fn pid(_lua: Lua, _: ()) -> LuaResult<u32> {
     Ok(std::process::id())
 }
 
-/// Sleep the Lua runtime for `n` seconds
-async fn sleep(_lua: Lua, n: f64) -> LuaResult<f64> {
-    smol::Timer::after(std::time::Duration::from_secs_f64(n)).await;
-    Ok(n)
+/// Sleep the Lua runtime for `n` seconds, returning nil if cancelled or shutting down
+async fn sleep(_lua: Lua, (n, token): (Seconds, Option<CancelToken>)) -> LuaResult<Option<f64>> {
+    let timer = smol::Timer::after(n.duration());
+    let timer = cancel::until_cancelled(token.as_ref(), timer);
+    let timer = cancel::until_cancelled(Some(cancel::shutdown()), timer);
+    cancel::until_deadline(async { Ok(timer.await.flatten().map(|_| n.0)) }).await
+}
+
+/// Options accepted by `init.every`
+#[derive(Default)]
+struct EveryOptions {
+    interval: f64,
+    cancel: Option<CancelToken>,
+    align: bool,
+    on_error: Option<LuaFunction>,
+    timeout: Option<Seconds>,
+    limits: task::Limits,
+}
+
+/// Read the interval and options of `init.every` from a number or options table
+fn every_options(lua: &Lua, value: LuaValue) -> LuaResult<EveryOptions> {
+    match value {
+        LuaValue::Table(options) => Ok(EveryOptions {
+            interval: options.get::<Seconds>(1)?.0,
+            cancel: cancel::cancel_option(&options)?,
+            align: options.get::<Option<bool>>("align")?.unwrap_or(false),
+            on_error: options.get("on_error")?,
+            timeout: options.get("timeout")?,
+            limits: task::Limits::from_options(&options)?,
+        }),
+        value => Ok(EveryOptions {
+            interval: Seconds::from_lua(value, lua)?.0,
+            ..Default::default()
+        }),
+    }
 }
 
 /// Asynchronously call a Lua function every `n` seconds
-async fn every(lua: Lua, (n, func, args): (f64, LuaFunction, LuaMultiValue)) -> LuaResult<()> {
+async fn every(lua: Lua, (n, func, args): (LuaValue, LuaFunction, LuaMultiValue)) -> LuaResult<()> {
+    let EveryOptions {
+        interval,
+        cancel: token,
+        align,
+        on_error,
+        timeout,
+        limits,
+    } = every_options(&lua, n)?;
+    let interval = std::time::Duration::from_secs_f64(interval);
+    if align && interval.is_zero() {
+        return Err(LuaError::runtime("aligned intervals must be positive"));
+    }
     let weak_lua = lua.weak();
-    smol::spawn(async move {
-        let mut timer = smol::Timer::interval(std::time::Duration::from_secs_f64(n));
-        while let Some(_instant) = timer.next().await {
-            // stop task if the Lua instance has been destroyed
-            let Some(_lua) = weak_lua.try_upgrade() else {
-                break;
+    let job: schedule::Job = Arc::new(move |due| {
+        let (weak_lua, func, args, token, on_error) = (
+            weak_lua.clone(),
+            func.clone(),
+            args.clone(),
+            token.clone(),
+            on_error.clone(),
+        );
+        Box::pin(async move {
+            // stop task if the Lua instance has been destroyed or the token is cancelled
+            let lua = weak_lua.try_upgrade()?;
+            if token.as_ref().is_some_and(CancelToken::is_cancelled) {
+                return None;
+            }
+            let call = task::call_with_limits::<()>(&lua, &func, args, limits);
+            let result = match timeout {
+                // dropping a hung call aborts it so later ticks still run
+                Some(timeout) => {
+                    smol::future::or(call, async {
+                        smol::Timer::after(timeout.duration()).await;
+                        Err(LuaError::runtime(format!(
+                            "callback timed out after {} seconds",
+                            timeout.0
+                        )))
+                    })
+                    .await
+                }
+                None => call.await,
             };
-            if let Err(err) = func.call_async::<()>(args.clone()).await {
-                eprintln!("error in 'init.every' task: {}", err);
+            if let Err(err) = result {
+                task::report_error(&lua, "init.every", err, on_error).await;
             }
-        }
-    })
-    .detach();
+            let now = Instant::now();
+            Some(match align {
+                // recompute from the wall clock each time so clock jumps are followed
+                true => now + schedule::aligned_delay(interval, schedule::wall_clock(), true),
+                false => schedule::next_interval(due, interval, now),
+            })
+        })
+    });
+    let first = match align {
+        true => schedule::aligned_delay(interval, schedule::wall_clock(), false),
+        false => interval,
+    };
+    schedule::scheduler().at(Instant::now() + first, job);
     Ok(())
 }
 
+/// Require a module again, passing the old and new module to `migrate`
+///
+/// The old module is kept loaded if the new one fails to load, so a typo in a
+/// module being edited does not break the running script.
+async fn reload(lua: Lua, (name, migrate): (String, Option<LuaFunction>)) -> LuaResult<LuaValue> {
+    let loaded = lua
+        .globals()
+        .get::<LuaTable>("package")?
+        .get::<LuaTable>("loaded")?;
+    let old = loaded.get::<LuaValue>(name.as_str())?;
+    loaded.set(name.as_str(), LuaValue::Nil)?;
+    let require = lua.globals().get::<LuaFunction>("require")?;
+    let new = match require.call_async::<LuaValue>(name.as_str()).await {
+        Ok(new) => new,
+        Err(err) => {
+            loaded.set(name.as_str(), old)?;
+            return Err(err);
+        }
+    };
+    if let Some(migrate) = migrate {
+        migrate.call_async::<()>((old, new.clone())).await?;
+    }
+    Ok(new)
+}
+
 /// Send a signal to a process from Lua
 async fn kill(_lua: Lua, (pid, sig): (i32, i32)) -> LuaResult<i32> {
-    unix::kill(pid, sig)
-        .await
-        .map_err(|err| LuaError::runtime(err))
+    unix::kill(pid, sig).await.map_err(LuaError::runtime)
 }
 
 /// Return the `init` Lua module
 pub async fn init(lua: Lua, _: ()) -> LuaResult<LuaTable> {
     let init = lua.create_table()?;
     init.set("exec", lua.create_async_function(process::exec)?)?;
+    init.set("shell", lua.create_async_function(process::shell)?)?;
+    init.set("pipeline", lua.create_async_function(process::pipeline)?)?;
+    init.set("exec_all", lua.create_async_function(process::exec_all)?)?;
+    init.set("wait_any", lua.create_async_function(process::wait_any)?)?;
+    init.set("stop", lua.create_async_function(process::stop)?)?;
+    init.set("on_spawn", lua.create_async_function(process::on_spawn)?)?;
+    init.set("mock_exec", lua.create_async_function(mock::mock_exec)?)?;
+    init.set("on_exit", lua.create_async_function(process::on_exit)?)?;
+    init.set("on_reap", lua.create_async_function(reaper::on_reap)?)?;
+    init.set(
+        "supervise",
+        lua.create_async_function(supervise::supervise)?,
+    )?;
+    init.set("services", lua.create_async_function(supervise::services)?)?;
+    init.set("subreaper", lua.create_async_function(reaper::subreaper)?)?;
+    init.set("bootstrap", lua.create_async_function(boot::bootstrap)?)?;
+    init.set("reexec", lua.create_async_function(boot::reexec)?)?;
+    init.set(
+        "on_ctrl_alt_del",
+        lua.create_async_function(boot::on_ctrl_alt_del)?,
+    )?;
     init.set("kill", lua.create_async_function(kill)?)?;
+    init.set("reload", lua.create_async_function(reload)?)?;
     init.set("pid", lua.create_async_function(pid)?)?;
     init.set("sleep", lua.create_async_function(sleep)?)?;
+    init.set("duration", lua.create_async_function(duration::duration)?)?;
     init.set("every", lua.create_async_function(every)?)?;
+    init.set("spawn", lua.create_async_function(task::spawn)?)?;
+    init.set("all", lua.create_async_function(task::all)?)?;
+    init.set("any", lua.create_async_function(task::any)?)?;
+    init.set("join", lua.create_async_function(task::join)?)?;
+    init.set("pool", lua.create_async_function(task::pool)?)?;
+    init.set(
+        "on_task_error",
+        lua.create_async_function(task::on_task_error)?,
+    )?;
+    init.set("retry", lua.create_async_function(flow::retry)?)?;
+    init.set("debounce", lua.create_async_function(flow::debounce)?)?;
+    init.set("throttle", lua.create_async_function(flow::throttle)?)?;
+    init.set("deadline", lua.create_async_function(flow::deadline)?)?;
+    init.set("ratelimit", lua.create_async_function(sync::ratelimit)?)?;
+    init.set("mutex", lua.create_async_function(sync::mutex)?)?;
+    init.set("semaphore", lua.create_async_function(sync::semaphore)?)?;
+    init.set("event", lua.create_async_function(sync::event)?)?;
+    init.set("state", sync::state_table(&lua)?)?;
+    init.set("profile", profile::profile_table(&lua)?)?;
+    init.set(
+        "cancel_token",
+        lua.create_async_function(cancel::cancel_token)?,
+    )?;
+    init.set("shutdown", cancel::shutdown().clone())?;
+    init.set("ready", lua.create_async_function(fs::ready)?)?;
+    init.set("pidfile", lua.create_async_function(fs::pidfile)?)?;
+    init.set("which", lua.create_async_function(path::which)?)?;
+    init.set("script_dir", lua.create_async_function(path::script_dir)?)?;
+    init.set("verify", lua.create_async_function(verify::verify_policy)?)?;
+    init.set("sha256", lua.create_async_function(verify::sha256)?)?;
+    init.set("sandbox", lua.create_async_function(sandbox::sandbox)?)?;
+    init.set("shellquote", lua.create_async_function(shell::shellquote)?)?;
+    init.set("shellsplit", lua.create_async_function(shell::shellsplit)?)?;
+    init.set("expand", lua.create_async_function(shell::lua_expand)?)?;
+    init.set("proc", proc::proc_table(&lua)?)?;
+    init.set("mount", mount::mount_table(&lua)?)?;
+    init.set("os", os::os_table(&lua)?)?;
+    init.set("net", net::net_table(&lua)?)?;
+    init.set("pgrep", lua.create_async_function(proc::pgrep)?)?;
+    init.set("pkill", lua.create_async_function(proc::pkill)?)?;
+    init.set("cgroup", cgroup::cgroup_table(&lua)?)?;
+    init.set("logs", logfile::logs_table(&lua)?)?;
+    init.set("metrics", lua.create_async_function(metrics::metrics)?)?;
+    init.set("sysconf", lua.create_async_function(system::sysconf)?)?;
+    init.set("rlimit", system::rlimit_table(&lua)?)?;
+    init.set("renice", lua.create_async_function(system::renice)?)?;
+    init.set("cpu_count", lua.create_async_function(system::cpu_count)?)?;
+    init.set(
+        "oom_score_adj",
+        lua.create_async_function(system::oom_score_adj)?,
+    )?;
     init.set("signal", lua.create_table_from(unix::signal_table())?)?;
+    init.set("args", args::args_table(&lua)?)?;
+    init.set("path", path::path_table(&lua)?)?;
+    init.set("fs", fs::fs_table(&lua)?)?;
+    init.set("stdin", stdin::stdin_table(&lua)?)?;
+    init.set("time", time::time_table(&lua)?)?;
+    init.set("secrets", secrets::secrets_table(&lua)?)?;
+    init.set("prompt", lua.create_async_function(terminal::prompt)?)?;
+    init.set(
+        "terminal_size",
+        lua.create_async_function(terminal::terminal_size)?,
+    )?;
+    init.set(
+        "prompt_secret",
+        lua.create_async_function(terminal::prompt_secret)?,
+    )?;
     Ok(init)
 }
 
@@ -68,15 +264,15 @@ mod tests {
     fn test_sleep() {
         let lua = Lua::new();
         let n = 0.0;
-        let result = smol::block_on(sleep(lua, n));
+        let result = smol::block_on(sleep(lua, (Seconds(n), None)));
         assert!(result.is_ok());
-        assert_eq!(result.unwrap(), n);
+        assert_eq!(result.unwrap(), Some(n));
     }
 
     #[test]
     fn test_every() {
         let lua = Lua::new();
-        let n = 0.0;
+        let n = LuaValue::Number(0.0);
         let func = lua.create_function(|_, ()| Ok(())).unwrap();
         let result = smol::block_on(every(lua, (n, func, LuaMultiValue::new())));
         assert!(result.is_ok());
@@ -87,7 +283,7 @@ mod tests {
         let lua = Lua::new();
         let globals = lua.globals();
         globals.set("count", 0).unwrap();
-        let n = 0.0;
+        let n = LuaValue::Number(0.0);
         let code = r#"
                 count = count + 1
                 if count == 1 then
@@ -106,6 +302,143 @@ mod tests {
         });
     }
 
+    #[test]
+    fn test_sleep_cancelled() {
+        let lua = Lua::new();
+        let token = CancelToken::default();
+        token.cancel();
+        let result = smol::block_on(sleep(lua, (Seconds(60.0), Some(token))));
+        assert_eq!(result.unwrap(), None);
+    }
+
+    #[test]
+    fn test_every_options() {
+        let lua = Lua::new();
+        let options = lua.load("{ 60, align = true }").eval().unwrap();
+        let options = every_options(&lua, options).unwrap();
+        assert_eq!(options.interval, 60.0);
+        assert!(options.align);
+        assert!(options.cancel.is_none());
+        let options = every_options(&lua, LuaValue::Number(5.0)).unwrap();
+        assert_eq!(options.interval, 5.0);
+        assert!(!options.align);
+        let interval = LuaValue::String(lua.create_string("1m30s").unwrap());
+        assert_eq!(every_options(&lua, interval).unwrap().interval, 90.0);
+    }
+
+    #[test]
+    fn test_every_align_zero() {
+        let lua = Lua::new();
+        let options = lua.load("{ 0, align = true }").eval().unwrap();
+        let func = lua.create_function(|_, ()| Ok(())).unwrap();
+        let result = smol::block_on(every(lua, (options, func, LuaMultiValue::new())));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_every_too_long() {
+        let lua = Lua::new();
+        let func = lua.create_function(|_, ()| Ok(())).unwrap();
+        let options = lua.load("{ 1e300 }").eval().unwrap();
+        let result = smol::block_on(every(
+            lua.clone(),
+            (options, func.clone(), LuaMultiValue::new()),
+        ));
+        assert!(result.is_err());
+        let n = LuaValue::Number(1e300);
+        let result = smol::block_on(every(lua, (n, func, LuaMultiValue::new())));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_every_on_error() {
+        let lua = Lua::new();
+        let globals = lua.globals();
+        let options = lua
+            .load("{ 0, on_error = function(err, source) last = source end }")
+            .eval()
+            .unwrap();
+        let func = lua.load("error('boom')").into_function().unwrap();
+        smol::block_on(async {
+            every(lua.clone(), (options, func, LuaMultiValue::new()))
+                .await
+                .unwrap();
+            smol::Timer::after(std::time::Duration::from_millis(20)).await;
+            assert_eq!(globals.get::<String>("last").unwrap(), "init.every");
+        });
+    }
+
+    #[test]
+    fn test_every_timeout() {
+        let lua = Lua::new();
+        let globals = lua.globals();
+        globals.set("calls", 0).unwrap();
+        let pending = lua
+            .create_async_function(|_, ()| std::future::pending::<LuaResult<()>>())
+            .unwrap();
+        globals.set("pending", pending).unwrap();
+        let options = lua
+            .load("{ 0.001, timeout = 0.001, on_error = function(err) last = err end }")
+            .eval()
+            .unwrap();
+        let func = lua
+            .load("calls = calls + 1; pending()")
+            .into_function()
+            .unwrap();
+        smol::block_on(async {
+            every(lua.clone(), (options, func, LuaMultiValue::new()))
+                .await
+                .unwrap();
+            smol::Timer::after(std::time::Duration::from_millis(50)).await;
+            assert!(globals.get::<i32>("calls").unwrap() > 1);
+            assert!(globals.get::<String>("last").unwrap().contains("timed out"));
+        });
+    }
+
+    #[test]
+    fn test_every_instructions() {
+        let lua = Lua::new();
+        let globals = lua.globals();
+        let options = lua
+            .load("{ 0.001, instructions = 10000, on_error = function(err) last = err end }")
+            .eval()
+            .unwrap();
+        let func = lua.load("while true do end").into_function().unwrap();
+        smol::block_on(async {
+            every(lua.clone(), (options, func, LuaMultiValue::new()))
+                .await
+                .unwrap();
+            smol::Timer::after(std::time::Duration::from_millis(50)).await;
+            assert!(globals.get::<String>("last").unwrap().contains("budget"));
+        });
+    }
+
+    #[test]
+    fn test_every_cancelled() {
+        let lua = Lua::new();
+        let globals = lua.globals();
+        globals.set("count", 0).unwrap();
+        let token = CancelToken::default();
+        let options = lua.create_table().unwrap();
+        options.set(1, 0.0).unwrap();
+        options.set("cancel", token.clone()).unwrap();
+        let func = lua.load("count = count + 1").into_function().unwrap();
+        smol::block_on(async {
+            every(
+                lua.clone(),
+                (LuaValue::Table(options), func, LuaMultiValue::new()),
+            )
+            .await
+            .unwrap();
+            smol::Timer::after(std::time::Duration::from_millis(10)).await;
+            token.cancel();
+            smol::Timer::after(std::time::Duration::from_millis(10)).await;
+            let count: i32 = globals.get("count").unwrap();
+            smol::Timer::after(std::time::Duration::from_millis(10)).await;
+            assert_eq!(globals.get::<i32>("count").unwrap(), count);
+        });
+    }
+
     #[test]
     fn test_kill() {
         let lua = Lua::new();
@@ -121,6 +454,48 @@ mod tests {
         assert!(result.is_err());
     }
 
+    #[test]
+    fn test_reload() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            lua.load(
+                "version = 1
+                package.preload.svc = function()
+                    if version < 0 then error('broken') end
+                    return { version = version }
+                end
+                svc = require('svc')
+                version = 2",
+            )
+            .exec()
+            .unwrap();
+            let migrate = lua
+                .load("function(old, new) new.previous = old.version end")
+                .eval::<LuaFunction>()
+                .unwrap();
+            let new = reload(lua.clone(), ("svc".to_string(), Some(migrate)))
+                .await
+                .unwrap();
+            let new = LuaTable::from_lua(new, &lua).unwrap();
+            assert_eq!(new.get::<i64>("version").unwrap(), 2);
+            assert_eq!(new.get::<i64>("previous").unwrap(), 1);
+            lua.load("version = -1").exec().unwrap();
+            assert!(reload(lua.clone(), ("svc".to_string(), None))
+                .await
+                .is_err());
+            let kept: i64 = lua.load("return require('svc').version").eval().unwrap();
+            assert_eq!(kept, 2);
+        });
+    }
+
+    #[test]
+    fn test_init_shutdown() {
+        let lua = Lua::new();
+        let init = smol::block_on(init(lua.clone(), ())).unwrap();
+        let token: CancelToken = init.get("shutdown").unwrap();
+        assert_eq!(token.is_cancelled(), cancel::shutdown().is_cancelled());
+    }
+
     #[test]
     fn test_init() {
         let lua = Lua::new();
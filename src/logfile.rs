@@ -0,0 +1,310 @@
+use std::{
+    fs::File,
+    os::{fd::AsRawFd, unix::fs::FileExt},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, OnceLock, Weak},
+};
+
+use async_signal::{Signal, Signals};
+use mlua::prelude::*;
+use smol::{io::AsyncReadExt, stream::StreamExt};
+
+use crate::unix;
+
+/// Open log file and the offset its next write goes to
+struct OpenLog {
+    file: File,
+    offset: u64,
+}
+
+impl OpenLog {
+    /// Open a log file, creating it if needed, to write after its current end
+    ///
+    /// Writes use explicit offsets instead of `O_APPEND`, since `splice` refuses
+    /// files opened for appending.
+    fn open(path: &Path) -> std::io::Result<Self> {
+        let file = File::options()
+            .create(true)
+            .write(true)
+            .truncate(false)
+            .open(path)?;
+        let offset = file.metadata()?.len();
+        Ok(OpenLog { file, offset })
+    }
+}
+
+/// File which child output is appended to and which can be reopened after rotation
+pub struct LogFile {
+    path: PathBuf,
+    open: Mutex<OpenLog>,
+}
+
+impl LogFile {
+    /// Open a log file, sharing it with other streams already writing there
+    pub fn open(path: impl AsRef<Path>) -> std::io::Result<Arc<Self>> {
+        let path = std::path::absolute(path)?;
+        let mut logs = registry().lock().unwrap_or_else(|err| err.into_inner());
+        logs.retain(|log| log.strong_count() > 0);
+        // streams sharing a file must share offsets so they cannot overwrite each other
+        if let Some(log) = logs
+            .iter()
+            .filter_map(Weak::upgrade)
+            .find(|log| log.path == path)
+        {
+            return Ok(log);
+        }
+        let open = Mutex::new(OpenLog::open(&path)?);
+        let log = Arc::new(LogFile { path, open });
+        logs.push(Arc::downgrade(&log));
+        Ok(log)
+    }
+
+    /// Lock the open file, ignoring poisoning since writes are whole chunks
+    fn lock(&self) -> std::sync::MutexGuard<'_, OpenLog> {
+        self.open.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Append data to the log
+    pub fn write(&self, data: &[u8]) -> std::io::Result<()> {
+        let mut open = self.lock();
+        open.file.write_all_at(data, open.offset)?;
+        open.offset += data.len() as u64;
+        Ok(())
+    }
+
+    /// Move up to `len` bytes from a pipe to the end of the log without copying them or
+    /// waiting for the pipe, so the lock is never held while it is empty
+    pub fn splice_from(&self, pipe: &impl AsRawFd, len: usize) -> std::io::Result<usize> {
+        let mut open = self.lock();
+        let mut offset = open.offset as i64;
+        let moved = unix::splice(pipe, &open.file, &mut offset, len)?;
+        open.offset = offset as u64;
+        Ok(moved)
+    }
+
+    /// Reopen the log at its path, such as after it was moved away by logrotate
+    pub fn reopen(&self) -> std::io::Result<()> {
+        *self.lock() = OpenLog::open(&self.path)?;
+        Ok(())
+    }
+}
+
+/// Return every log file which is still in use
+fn registry() -> &'static Mutex<Vec<Weak<LogFile>>> {
+    static LOGS: OnceLock<Mutex<Vec<Weak<LogFile>>>> = OnceLock::new();
+    LOGS.get_or_init(Mutex::default)
+}
+
+/// Reopen every log file in use, returning how many were reopened
+pub fn reopen_all() -> usize {
+    let logs: Vec<Arc<LogFile>> = {
+        let logs = registry().lock().unwrap_or_else(|err| err.into_inner());
+        logs.iter().filter_map(Weak::upgrade).collect()
+    };
+    let mut reopened = 0;
+    for log in logs {
+        match log.reopen() {
+            Ok(()) => reopened += 1,
+            Err(err) => eprintln!("failed to reopen '{}': {}", log.path.display(), err),
+        }
+    }
+    reopened
+}
+
+/// Copy a child stream into a log file until it closes
+pub async fn copy_to_log(
+    mut stream: impl AsyncReadExt + Unpin,
+    log: Arc<LogFile>,
+) -> std::io::Result<()> {
+    let mut buffer = vec![0; CHUNK];
+    loop {
+        let read = stream.read(&mut buffer).await?;
+        if read == 0 {
+            return Ok(());
+        }
+        let chunk = buffer[..read].to_vec();
+        let log = log.clone();
+        smol::unblock(move || log.write(&chunk)).await?;
+    }
+}
+
+/// Size of each chunk moved from a child stream into a log file
+const CHUNK: usize = 64 * 1024;
+
+/// Move a child stream into a log file with `splice` on the blocking pool
+///
+/// The data never passes through the supervisor, though this holds a blocking
+/// thread for as long as the stream is open. Filesystems without `splice`
+/// support fall back to large buffered copies.
+pub async fn splice_to_log(
+    stream: impl AsRawFd + Send + 'static,
+    log: Arc<LogFile>,
+) -> std::io::Result<()> {
+    smol::unblock(move || {
+        unix::set_blocking(&stream)?;
+        loop {
+            // waiting happens without the lock, which other streams of the log share
+            unix::wait_readable(&stream)?;
+            match log.splice_from(&stream, CHUNK) {
+                Ok(0) => return Ok(()),
+                Ok(_) => continue,
+                Err(err) if err.raw_os_error() == Some(unix::EAGAIN) => continue,
+                Err(err) if err.raw_os_error() == Some(unix::EINVAL) => break,
+                Err(err) => return Err(err),
+            }
+        }
+        let mut buffer = vec![0; CHUNK];
+        loop {
+            let read = unix::read(&stream, &mut buffer)?;
+            if read == 0 {
+                return Ok(());
+            }
+            log.write(&buffer[..read])?;
+        }
+    })
+    .await
+}
+
+/// Reopen every log file each time a signal is received
+async fn reopen_on_signal(signal: Signal) -> std::io::Result<()> {
+    let mut signals = Signals::new([signal])?;
+    while let Some(signal) = signals.next().await {
+        signal?;
+        smol::unblock(reopen_all).await;
+    }
+    Ok(())
+}
+
+/// Reopen every log file now from Lua, returning how many were reopened
+async fn reopen(_lua: Lua, _: ()) -> LuaResult<usize> {
+    Ok(smol::unblock(reopen_all).await)
+}
+
+/// Reopen every log file on a signal, `SIGUSR1` by default, from Lua
+async fn reopen_on(_lua: Lua, sig: Option<i32>) -> LuaResult<()> {
+    let signal = match sig {
+        None => Signal::Usr1,
+        Some(sig) => unix::valid_signals()
+            .into_iter()
+            .find(|signal| *signal as i32 == sig)
+            .ok_or_else(|| LuaError::runtime(format!("cannot watch signal {}", sig)))?,
+    };
+    // children would otherwise receive the signal too and usually exit on it
+    unix::reserve_signal(signal as i32);
+    smol::spawn(async move {
+        if let Err(err) = reopen_on_signal(signal).await {
+            eprintln!("error watching for log reopen signals: {}", err);
+        }
+    })
+    .detach();
+    Ok(())
+}
+
+/// Return the `init.logs` Lua table
+pub fn logs_table(lua: &Lua) -> LuaResult<LuaTable> {
+    let table = lua.create_table()?;
+    table.set("reopen", lua.create_async_function(reopen)?)?;
+    table.set("reopen_on", lua.create_async_function(reopen_on)?)?;
+    Ok(table)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("luavisors-log-{}-{}", name, std::process::id()))
+    }
+
+    #[test]
+    fn test_reopen() {
+        let path = test_path("reopen");
+        let rotated = test_path("reopen.1");
+        let log = LogFile::open(&path).unwrap();
+        log.write(b"before\n").unwrap();
+        std::fs::rename(&path, &rotated).unwrap();
+        assert!(reopen_all() >= 1);
+        log.write(b"after\n").unwrap();
+        assert_eq!(std::fs::read_to_string(&rotated).unwrap(), "before\n");
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "after\n");
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&rotated).unwrap();
+    }
+
+    #[test]
+    fn test_copy_to_log() {
+        smol::block_on(async {
+            let path = test_path("copy");
+            let log = LogFile::open(&path).unwrap();
+            copy_to_log(&b"line one\nline two\n"[..], log)
+                .await
+                .unwrap();
+            let contents = std::fs::read_to_string(&path).unwrap();
+            std::fs::remove_file(&path).unwrap();
+            assert_eq!(contents, "line one\nline two\n");
+        });
+    }
+
+    #[test]
+    fn test_shared_log() {
+        let path = test_path("shared");
+        let out = LogFile::open(&path).unwrap();
+        let err = LogFile::open(&path).unwrap();
+        assert!(Arc::ptr_eq(&out, &err));
+        out.write(b"out\n").unwrap();
+        err.write(b"err\n").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "out\nerr\n");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_splice_to_log() {
+        smol::block_on(async {
+            let path = test_path("splice");
+            std::fs::write(&path, "existing\n").unwrap();
+            let log = LogFile::open(&path).unwrap();
+            let (reader, mut writer) = std::io::pipe().unwrap();
+            std::io::Write::write_all(&mut writer, b"spliced\n").unwrap();
+            drop(writer);
+            splice_to_log(reader, log).await.unwrap();
+            let contents = std::fs::read_to_string(&path).unwrap();
+            std::fs::remove_file(&path).unwrap();
+            assert_eq!(contents, "existing\nspliced\n");
+        });
+    }
+
+    #[test]
+    fn test_splice_shared_log() {
+        smol::block_on(async {
+            let path = test_path("splice-shared");
+            let log = LogFile::open(&path).unwrap();
+            let (out, mut out_writer) = std::io::pipe().unwrap();
+            let (err, err_writer) = std::io::pipe().unwrap();
+            let idle = smol::spawn(splice_to_log(err, log.clone()));
+            let busy = smol::spawn(splice_to_log(out, log));
+            // the idle stream must not keep the busy one from reaching the file
+            std::io::Write::write_all(&mut out_writer, b"out\n").unwrap();
+            let mut contents = String::new();
+            for _ in 0..100 {
+                contents = std::fs::read_to_string(&path).unwrap();
+                if !contents.is_empty() {
+                    break;
+                }
+                smol::Timer::after(std::time::Duration::from_millis(10)).await;
+            }
+            drop((out_writer, err_writer));
+            busy.await.unwrap();
+            idle.await.unwrap();
+            std::fs::remove_file(&path).unwrap();
+            assert_eq!(contents, "out\n");
+        });
+    }
+
+    #[test]
+    fn test_reopen_on_err() {
+        smol::block_on(async {
+            let err = reopen_on(Lua::new(), Some(9)).await.unwrap_err();
+            assert!(err.to_string().contains("cannot watch signal"));
+        });
+    }
+}
@@ -4,18 +4,95 @@
 use mlua::{prelude::*, AsChunk};
 
 use crate::{
-    errors::{AppResult, NotFoundExt},
+    cleanup::CLEANUP,
+    errors::{AppResult, NotFoundExt, RuntimeError},
     init::init,
 };
 
+/// Command line parsing for Lua scripts
+mod args;
+/// Behaviour of the supervisor when it runs as pid 1
+mod boot;
+/// Bundling of a script and its local modules into one file
+mod bundle;
+/// Cancellation tokens for asynchronous work
+mod cancel;
+/// Statistics of cgroup v2 control groups
+mod cgroup;
+/// Paths removed on shutdown
+mod cleanup;
+/// SHA-256 digests of files
+mod digest;
+/// Duration parsing functions
+mod duration;
 /// Error handling functions
 mod errors;
+/// Control flow helpers for Lua callbacks
+mod flow;
+/// Filesystem helper functions
+mod fs;
+/// Health check probes of supervised services
+mod health;
 /// Contains the `init` Lua module
 mod init;
+/// Log files which child output is appended to
+mod logfile;
+/// Resource usage of the supervisor itself
+mod metrics;
+/// Canned child processes for testing scripts
+mod mock;
+/// Mount helpers for container init scripts
+mod mount;
+/// Network interface configuration over netlink
+mod net;
+/// Operating system settings
+mod os;
+/// Path manipulation functions
+mod path;
+/// Information about any process from /proc
+mod proc;
 /// Process management functions
 mod process;
+/// Sampling profiler for Lua call stacks
+mod profile;
+/// Random number helpers
+mod random;
+/// Reaping of orphaned processes
+mod reaper;
+/// Buffer of the most recent output of child processes
+mod ring;
+/// Sandbox profiles which remove unsafe globals
+mod sandbox;
+/// Shared timer for scheduled jobs
+mod schedule;
+/// Seccomp filters of spawned processes
+mod seccomp;
+/// Secret values which are masked in logs
+mod secrets;
+/// Shell quoting and splitting functions
+mod shell;
+/// Running a single child like tini
+mod single;
+/// Size parsing functions
+mod size;
+/// Asynchronous standard input functions
+mod stdin;
+/// Supervised services with restart policies
+mod supervise;
+/// Synchronization primitives for Lua tasks
+mod sync;
+/// System configuration and resource limit functions
+mod system;
+/// Background Lua tasks and combinators
+mod task;
+/// Interactive terminal input functions
+mod terminal;
+/// Date and time functions
+mod time;
 /// Unix-specific functions
 mod unix;
+/// Executable integrity verification before spawning
+mod verify;
 
 /// Print usage information
 async fn help() -> AppResult<()> {
@@ -25,7 +102,12 @@ async fn help() -> AppResult<()> {
         .ok_or_not_found("invalid program name")?
         .to_str()
         .ok_or_not_found("invalid program name")?;
-    println!("Usage: {} [script [args...]]", exe);
+    println!(
+        "Usage: {} [--reap] [--rocks-tree path] [script [args...]]",
+        exe
+    );
+    println!("       {} bundle [--deps] [-o output] script", exe);
+    println!("       {} --single [--] command [args...]", exe);
     Ok(())
 }
 
@@ -75,6 +157,31 @@ async fn parse_args(lua: &Lua, args: Vec<String>) -> AppResult<(Chunk, LuaTable)
     Ok((chunk, table))
 }
 
+/// Remove a leading `--reap` option from the arguments and return whether it was given
+fn take_reap(args: &mut Vec<String>) -> bool {
+    if args.get(1).is_none_or(|arg| arg != "--reap") {
+        return false;
+    }
+    args.remove(1);
+    true
+}
+
+/// Remove a leading `--rocks-tree path` option from the arguments and return the tree
+fn take_rocks_tree(args: &mut Vec<String>) -> Option<std::path::PathBuf> {
+    let arg = args.get(1)?;
+    if let Some(tree) = arg.strip_prefix("--rocks-tree=") {
+        let tree = std::path::PathBuf::from(tree);
+        args.remove(1);
+        return Some(tree);
+    }
+    if arg != "--rocks-tree" || args.len() < 3 {
+        return None;
+    }
+    let tree = std::path::PathBuf::from(args.remove(2));
+    args.remove(1);
+    Some(tree)
+}
+
 /// Create a new Lua state which allows unsafe code
 #[allow(unsafe_code)]
 async fn unsafe_lua() -> Lua {
@@ -83,31 +190,90 @@ async fn unsafe_lua() -> Lua {
 }
 
 /// Initialize Lua state with `init` module and `arg` table and run the chunk
-async fn lua(args: Vec<String>) -> AppResult<()> {
+///
+/// Returns the exit code of the main child process if one was started.
+async fn lua(mut args: Vec<String>) -> AppResult<i32> {
     let lua = unsafe_lua().await;
+    let reap = take_reap(&mut args);
+    // find modules of a LuaRocks tree, which must exist if it is given explicitly
+    match take_rocks_tree(&mut args) {
+        Some(tree) if !tree.is_dir() => {
+            return Err(errors::not_found(&format!(
+                "rocks tree '{}' is not a directory",
+                tree.display()
+            ))
+            .into())
+        }
+        Some(tree) => path::add_rocks_tree(&lua, &tree)?,
+        None if std::path::Path::new(path::DEFAULT_ROCKS_TREE).is_dir() => {
+            path::add_rocks_tree(&lua, std::path::Path::new(path::DEFAULT_ROCKS_TREE))?
+        }
+        None => {}
+    }
     // add init table to package preload
     let preload = lua
         .globals()
         .get::<LuaTable>("package")?
         .get::<LuaTable>("preload")?;
     preload.set("init", lua.create_async_function(init)?)?;
+    // remove unsafe globals before any script code runs
+    if let Ok(profile) = std::env::var(sandbox::SANDBOX_ENV) {
+        sandbox::apply(&lua, &profile)?;
+    }
     // parse command line arguments
     let (chunk, arg) = parse_args(&lua, args).await?;
     lua.globals().set("arg", arg)?;
+    // replace spawned processes with canned ones when testing scripts
+    mock::load_env(&lua)?;
+    // find modules next to the script wherever the supervisor was started
+    if let Chunk::Path(script) = &chunk {
+        path::set_script(&lua, script)?;
+    }
+    // turn ctrl-alt-del into a signal when running as pid 1
+    boot::setup();
+    // reap orphans, which nothing else waits for when running as pid 1
+    if reap {
+        unix::set_child_subreaper(true)?;
+    }
+    if reap || boot::is_pid1() {
+        reaper::start(&lua);
+    }
+    // let sleeping tasks and token holders see shutdown requests
+    smol::spawn(async {
+        if let Err(err) = cancel::watch_shutdown().await {
+            eprintln!("error watching for shutdown signals: {}", err);
+        }
+    })
+    .detach();
     // load and execute the lua script
-    lua.load(chunk).exec_async().await?;
-    Ok(())
+    let result = lua.load(chunk).exec_async().await;
+    // stop service groups in reverse order, then wait for or stop other children
+    supervise::finish_services().await;
+    process::finish_children().await;
+    // mirror the main child so wrapped jobs report its result
+    let code = match result {
+        Ok(()) => process::main_exit_code().await.map_err(RuntimeError::from),
+        Err(err) => Err(err.into()),
+    };
+    // remove readiness files and other temporaries
+    CLEANUP.run();
+    boot::finish()?;
+    Ok(code?.unwrap_or(0))
 }
 
-/// Execute the program with command line arguments
-fn run(args: Vec<String>) -> AppResult<()> {
+/// Execute the program with command line arguments and return its exit code
+fn run(args: Vec<String>) -> AppResult<i32> {
     smol::block_on(async {
-        if args.len() > 1 {
-            lua(args).await?;
+        if args.get(1).is_some_and(|arg| arg == "bundle") {
+            bundle::run(&args[2..])?;
+        } else if args.get(1).is_some_and(|arg| arg == "--single") {
+            return single::run(&args[2..]).await;
+        } else if args.len() > 1 {
+            return lua(args).await;
         } else {
             help().await?;
         }
-        Ok(())
+        Ok(0)
     })
 }
 
@@ -115,9 +281,9 @@ fn run(args: Vec<String>) -> AppResult<()> {
 fn main() {
     let args: Vec<String> = std::env::args().collect();
     match run(args) {
-        Ok(()) => std::process::exit(0),
+        Ok(code) => std::process::exit(code),
         Err(err) => {
-            eprintln!("{}", err);
+            eprintln!("{}", secrets::mask(&err.to_string()));
             std::process::exit(1)
         }
     };
@@ -172,6 +338,31 @@ mod tests {
         });
     }
 
+    #[test]
+    fn test_take_reap() {
+        let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+        let mut argv = args(&["test", "--reap", "main.lua"]);
+        assert!(take_reap(&mut argv));
+        assert_eq!(argv, args(&["test", "main.lua"]));
+        assert!(!take_reap(&mut argv));
+        assert!(!take_reap(&mut args(&["test"])));
+    }
+
+    #[test]
+    fn test_take_rocks_tree() {
+        let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+        let mut argv = args(&["test", "--rocks-tree", "/opt/rocks", "main.lua"]);
+        let tree = take_rocks_tree(&mut argv);
+        assert_eq!(tree, Some(std::path::PathBuf::from("/opt/rocks")));
+        assert_eq!(argv, args(&["test", "main.lua"]));
+        let mut argv = args(&["test", "--rocks-tree=rocks", "main.lua"]);
+        assert_eq!(take_rocks_tree(&mut argv), Some("rocks".into()));
+        assert_eq!(argv, args(&["test", "main.lua"]));
+        let mut argv = args(&["test", "main.lua", "--rocks-tree", "x"]);
+        assert!(take_rocks_tree(&mut argv).is_none());
+        assert_eq!(argv.len(), 4);
+    }
+
     #[test]
     fn test_unsafe_lua() {
         smol::block_on(async {
@@ -0,0 +1,103 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use mlua::prelude::*;
+
+use crate::{task, unix};
+
+/// Linux resource number of the open file limit
+const RLIMIT_NOFILE: i32 = 7;
+
+/// Fraction of the open file limit at which a warning is printed
+const FD_WARN_RATIO: f64 = 0.9;
+
+/// Fraction of the open file limit below which the warning is armed again
+const FD_REARM_RATIO: f64 = 0.8;
+
+/// Whether the open file warning has been printed since usage was last low
+static FD_WARNED: AtomicBool = AtomicBool::new(false);
+
+/// Return the number of file descriptors open in the supervisor
+pub fn open_fds() -> std::io::Result<usize> {
+    let entries = std::fs::read_dir("/proc/self/fd")?.count();
+    // reading the directory holds one descriptor of its own
+    Ok(entries.saturating_sub(1))
+}
+
+/// Return the soft limit on open file descriptors, or `None` if unlimited
+pub fn fd_limit() -> Option<u64> {
+    unix::getrlimit(RLIMIT_NOFILE)
+        .ok()
+        .map(|rlim| rlim.soft)
+        .filter(|soft| *soft != unix::RLIM_INFINITY)
+}
+
+/// Return a warning once open descriptors approach the limit, until usage drops again
+fn fd_warning(open: usize, limit: Option<u64>) -> Option<String> {
+    let limit = limit.filter(|limit| *limit > 0)?;
+    let ratio = open as f64 / limit as f64;
+    if ratio < FD_REARM_RATIO {
+        FD_WARNED.store(false, Ordering::Relaxed);
+        return None;
+    }
+    if ratio < FD_WARN_RATIO || FD_WARNED.swap(true, Ordering::Relaxed) {
+        return None;
+    }
+    Some(format!(
+        "warning: {} of {} file descriptors are open, spawns will fail once the limit is reached",
+        open, limit
+    ))
+}
+
+/// Print a warning when the supervisor is close to running out of file descriptors
+pub fn check_fds() {
+    let Ok(open) = open_fds() else {
+        return;
+    };
+    if let Some(warning) = fd_warning(open, fd_limit()) {
+        eprintln!("{}", warning);
+    }
+}
+
+/// Return the supervisor's own resource usage from Lua
+pub async fn metrics(lua: Lua, _: ()) -> LuaResult<LuaTable> {
+    let table = lua.create_table()?;
+    table.set("fds", open_fds()?)?;
+    table.set("fd_limit", fd_limit())?;
+    table.set("tasks", task::running())?;
+    Ok(table)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_open_fds() {
+        let file = std::fs::File::open("/proc/self/status").unwrap();
+        // stdin, stdout, stderr, and the file above
+        assert!(open_fds().unwrap() >= 4);
+        drop(file);
+    }
+
+    #[test]
+    fn test_fd_warning() {
+        assert!(fd_warning(10, None).is_none());
+        assert!(fd_warning(10, Some(100)).is_none());
+        assert!(fd_warning(95, Some(100)).is_some());
+        // the warning is printed once until usage drops below the rearm ratio
+        assert!(fd_warning(96, Some(100)).is_none());
+        assert!(fd_warning(50, Some(100)).is_none());
+        assert!(fd_warning(95, Some(100)).is_some());
+    }
+
+    #[test]
+    fn test_metrics() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            let table = metrics(lua.clone(), ()).await.unwrap();
+            assert!(table.get::<usize>("fds").unwrap() > 0);
+            assert!(table.get::<Option<u64>>("fd_limit").is_ok());
+            assert!(table.get::<usize>("tasks").is_ok());
+        });
+    }
+}
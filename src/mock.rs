@@ -0,0 +1,403 @@
+use std::{
+    collections::VecDeque,
+    os::unix::process::ExitStatusExt,
+    path::Path,
+    process::ExitStatus,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use mlua::prelude::*;
+
+use crate::{duration::Seconds, process, sync::Event};
+
+/// Environment variable naming a Lua file which returns the mocks for `init.exec`
+pub const MOCK_ENV: &str = "LUAVISORS_MOCK_EXEC";
+
+/// Registry key of the mocks used by `init.exec` instead of spawning processes
+const MOCKS: &str = "luavisors.mock_exec";
+
+/// Signal number reported by the status of a killed mock
+const KILLED: i32 = 9;
+
+/// Canned result of a mocked command
+#[derive(Debug, Default, Clone, PartialEq)]
+struct Mock {
+    stdout: String,
+    stderr: String,
+    status: i32,
+    delay: Duration,
+}
+
+/// Read a mock from its table
+fn mock_from_table(table: &LuaTable) -> LuaResult<Mock> {
+    Ok(Mock {
+        stdout: table.get::<Option<String>>("stdout")?.unwrap_or_default(),
+        stderr: table.get::<Option<String>>("stderr")?.unwrap_or_default(),
+        status: table.get::<Option<i32>>("status")?.unwrap_or(0),
+        delay: table
+            .get::<Option<Seconds>>("delay")?
+            .map_or(Duration::ZERO, Seconds::duration),
+    })
+}
+
+/// Set or clear the mocks which `init.exec` uses instead of spawning processes
+///
+/// Each mock is a table whose first value is a Lua pattern matched against the
+/// command line, with canned `stdout`, `stderr`, `status`, and `delay` in seconds.
+pub async fn mock_exec(lua: Lua, mocks: Option<LuaTable>) -> LuaResult<()> {
+    lua.set_named_registry_value(MOCKS, mocks)
+}
+
+/// Load the mocks from the file named by the mock environment variable, if it is set
+pub fn load_env(lua: &Lua) -> LuaResult<()> {
+    let Ok(path) = std::env::var(MOCK_ENV) else {
+        return Ok(());
+    };
+    let mocks = lua.load(Path::new(&path)).eval::<LuaTable>()?;
+    lua.set_named_registry_value(MOCKS, mocks)
+}
+
+/// Return the mocks if `init.exec` is mocked
+pub fn mocks(lua: &Lua) -> LuaResult<Option<LuaTable>> {
+    lua.named_registry_value(MOCKS)
+}
+
+/// Find the first mock whose pattern matches a command line
+fn find_mock(lua: &Lua, mocks: &LuaTable, cmdline: &str) -> LuaResult<Option<Mock>> {
+    let find = lua
+        .globals()
+        .get::<LuaTable>("string")?
+        .get::<LuaFunction>("find")?;
+    for mock in mocks.sequence_values::<LuaTable>() {
+        let mock = mock?;
+        let pattern: String = mock.get(1)?;
+        if find
+            .call::<Option<usize>>((cmdline, pattern.as_str()))?
+            .is_some()
+        {
+            return mock_from_table(&mock).map(Some);
+        }
+    }
+    Ok(None)
+}
+
+/// Return the exit status of a process which exited with a code
+fn exit_status(code: i32) -> ExitStatus {
+    ExitStatus::from_raw((code & 0xff) << 8)
+}
+
+/// Split canned output into the lines returned by `read_line`
+fn output_lines(output: &str) -> VecDeque<String> {
+    output.lines().map(str::to_string).collect()
+}
+
+/// Match a pattern against the unread canned output, returning the match or its
+/// captures, or nil and 'eof' since the canned output never grows
+fn expect(lua: &Lua, unread: &mut Vec<u8>, pattern: &LuaString) -> LuaResult<LuaMultiValue> {
+    let find = lua
+        .globals()
+        .get::<LuaTable>("string")?
+        .get::<LuaFunction>("find")?;
+    let text = lua.create_string(&*unread)?;
+    let found: LuaMultiValue = find.call((&text, pattern))?;
+    let Some(LuaValue::Integer(end)) = found.get(1) else {
+        unread.clear();
+        return Ok(LuaMultiValue::from_iter([
+            LuaValue::Nil,
+            LuaValue::String(lua.create_string("eof")?),
+        ]));
+    };
+    let (start, end) = (found[0].as_integer().unwrap_or(1) as usize, *end as usize);
+    let whole = lua.create_string(&unread[start - 1..end])?;
+    unread.drain(..end);
+    let captures: LuaMultiValue = found.into_iter().skip(2).collect();
+    match captures.is_empty() {
+        true => Ok(LuaMultiValue::from_iter([LuaValue::String(whole)])),
+        false => Ok(captures),
+    }
+}
+
+/// Return the table of a mocked child process, which behaves like a real one without a pid
+pub fn exec(lua: &Lua, mocks: &LuaTable, cmd: &str, args: &[String]) -> LuaResult<LuaTable> {
+    let cmdline = std::iter::once(cmd)
+        .chain(args.iter().map(String::as_str))
+        .collect::<Vec<_>>()
+        .join(" ");
+    let mock = find_mock(lua, mocks, &cmdline)?
+        .ok_or_else(|| LuaError::runtime(format!("no mock for command '{}'", cmdline)))?;
+    let done = Instant::now() + mock.delay;
+    let killed = Event::default();
+    let result = lua.create_table()?;
+
+    // pid, rusage, and stats, which a mock does not have
+    for name in ["pid", "rusage", "stats"] {
+        result.set(
+            name,
+            lua.create_function(|_, _: LuaMultiValue| Ok(LuaValue::Nil))?,
+        )?;
+    }
+
+    // status and status_code, which are nil if the mock is still running after
+    // waiting for as long as given
+    let exited = {
+        let (killed, status) = (killed.clone(), mock.status);
+        move |wait: Option<Seconds>| {
+            let killed = killed.clone();
+            async move {
+                let exited = async {
+                    smol::Timer::at(done).await;
+                    Some(exit_status(status))
+                };
+                let exited = smol::future::or(exited, async {
+                    killed.wait().await;
+                    Some(ExitStatus::from_raw(KILLED))
+                });
+                match wait {
+                    Some(wait) => {
+                        smol::future::or(exited, async {
+                            smol::Timer::after(wait.duration()).await;
+                            None
+                        })
+                        .await
+                    }
+                    None => exited.await,
+                }
+            }
+        }
+    };
+    let clone = exited.clone();
+    result.set(
+        "status",
+        lua.create_async_function(move |lua, (_, wait): (LuaValue, Option<Seconds>)| {
+            let exited = clone(wait);
+            async move {
+                match exited.await {
+                    Some(status) => process::status_table(&lua, status, None).map(Some),
+                    None => Ok(None),
+                }
+            }
+        })?,
+    )?;
+    result.set(
+        "status_code",
+        lua.create_async_function(move |_, (_, wait): (LuaValue, Option<Seconds>)| {
+            let waiting = exited(wait);
+            async move { waiting.await.map(process::status_code).transpose() }
+        })?,
+    )?;
+    let status = mock.status;
+
+    // wait_ready, since a mock is ready as soon as it starts
+    result.set(
+        "wait_ready",
+        lua.create_function(|_, _: LuaMultiValue| Ok(true))?,
+    )?;
+
+    // try_status
+    let clone = killed.clone();
+    result.set(
+        "try_status",
+        lua.create_function(move |_, _: LuaMultiValue| {
+            Ok(match clone.is_set() {
+                true => Some(KILLED),
+                false => (Instant::now() >= done).then_some(status),
+            })
+        })?,
+    )?;
+
+    // read_line, lines, stdout_lines, and stderr_lines
+    let out = Arc::new(Mutex::new(output_lines(&mock.stdout)));
+    let err = Arc::new(Mutex::new(output_lines(&mock.stderr)));
+    let pick = move |name: Option<String>| match name.as_deref() {
+        None | Some("stdout") => Ok(out.clone()),
+        Some("stderr") => Ok(err.clone()),
+        Some(name) => Err(LuaError::runtime(format!("unknown stream '{}'", name))),
+    };
+    let read_pick = pick.clone();
+    result.set(
+        "read_line",
+        lua.create_function(move |_, (_, name): (LuaValue, Option<String>)| {
+            let lines = read_pick(name)?;
+            let line = lines
+                .lock()
+                .unwrap_or_else(|err| err.into_inner())
+                .pop_front();
+            Ok(line)
+        })?,
+    )?;
+    let lines = lua.create_function(move |lua, (_, name): (LuaValue, Option<String>)| {
+        let lines = pick(name)?;
+        lua.create_function(move |_, _: LuaMultiValue| {
+            Ok(lines
+                .lock()
+                .unwrap_or_else(|err| err.into_inner())
+                .pop_front())
+        })
+    })?;
+    result.set("lines", &lines)?;
+    for name in ["stdout", "stderr"] {
+        let lines = lines.clone();
+        result.set(
+            format!("{}_lines", name),
+            lua.create_function(move |_, this: LuaValue| lines.call::<LuaFunction>((this, name)))?,
+        )?;
+    }
+
+    // expect, which matches the canned stdout after the previous match, and send,
+    // which discards its input as a mock has no stdin
+    let unread = Arc::new(Mutex::new(mock.stdout.clone().into_bytes()));
+    result.set(
+        "expect",
+        lua.create_function(
+            move |lua, (_, pattern, _): (LuaValue, LuaString, Option<Seconds>)| {
+                let mut unread = unread.lock().unwrap_or_else(|err| err.into_inner());
+                expect(lua, &mut unread, &pattern)
+            },
+        )?,
+    )?;
+    result.set("send", lua.create_function(|_, _: LuaMultiValue| Ok(()))?)?;
+
+    // stdout and stderr
+    let output = |text: String| move |_: &Lua, ()| Ok((!text.is_empty()).then(|| text.clone()));
+    result.set("stdout", lua.create_function(output(mock.stdout.clone()))?)?;
+    result.set("stderr", lua.create_function(output(mock.stderr.clone()))?)?;
+
+    // logs
+    let recent: Vec<String> = mock
+        .stdout
+        .lines()
+        .chain(mock.stderr.lines())
+        .map(str::to_string)
+        .collect();
+    result.set(
+        "logs",
+        lua.create_function(move |_, (_, count): (LuaValue, Option<usize>)| {
+            let skip = recent.len().saturating_sub(count.unwrap_or(recent.len()));
+            Ok(recent[skip..].to_vec())
+        })?,
+    )?;
+
+    // stop, which returns the canned status once the delay has passed
+    let clone = killed.clone();
+    result.set(
+        "stop",
+        lua.create_function(move |_, _: LuaMultiValue| {
+            if Instant::now() >= done && !clone.is_set() {
+                return Ok(status);
+            }
+            clone.set();
+            Ok(KILLED)
+        })?,
+    )?;
+
+    // kill, kill_group, and kill_tree
+    for name in ["kill", "kill_group", "kill_tree"] {
+        let killed = killed.clone();
+        result.set(
+            name,
+            lua.create_function(move |_, _: LuaMultiValue| {
+                killed.set();
+                Ok(KILLED)
+            })?,
+        )?;
+    }
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mocks(lua: &Lua) -> LuaTable {
+        lua.load(
+            "{
+                { '^curl .*health', stdout = 'ok\\nready\\n', status = 0 },
+                { '^false', stderr = 'failed', status = 1, delay = 0.01 },
+                { '^sleep', delay = 30 },
+            }",
+        )
+        .eval()
+        .unwrap()
+    }
+
+    #[test]
+    fn test_find_mock() {
+        let lua = Lua::new();
+        let mocks = mocks(&lua);
+        let mock = find_mock(&lua, &mocks, "curl -s http://db/health").unwrap();
+        assert_eq!(mock.unwrap().stdout, "ok\nready\n");
+        let mock = find_mock(&lua, &mocks, "false").unwrap().unwrap();
+        assert_eq!((mock.status, mock.delay), (1, Duration::from_millis(10)));
+        assert!(find_mock(&lua, &mocks, "rm -rf /").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_exec() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            let mocks = mocks(&lua);
+            let args = ["-s".to_string(), "http://db/health".to_string()];
+            let child = exec(&lua, &mocks, "curl", &args).unwrap();
+            lua.globals().set("child", child).unwrap();
+            let result: (i32, String, String, Option<String>, String) = lua
+                .load(
+                    "local lines = {}
+                    for line in child:stdout_lines() do lines[#lines + 1] = line end
+                    return child:status_code(), child:stdout(), table.concat(lines, ','),
+                        child:stderr(), table.concat(child:logs(1))",
+                )
+                .eval_async()
+                .await
+                .unwrap();
+            assert_eq!(
+                result,
+                (
+                    0,
+                    "ok\nready\n".into(),
+                    "ok,ready".into(),
+                    None,
+                    "ready".into()
+                )
+            );
+            assert!(exec(&lua, &mocks, "rm", &[]).is_err());
+        });
+    }
+
+    #[test]
+    fn test_exec_expect() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            let mocks = mocks(&lua);
+            let child = exec(&lua, &mocks, "curl", &["health".to_string()]).unwrap();
+            lua.globals().set("child", child).unwrap();
+            let result: (String, String, Option<String>, String) = lua
+                .load(
+                    "child:send('GET /health\\n')
+                    local first = child:expect('%a+')
+                    local second = child:expect('(r%a+)', 1)
+                    return first, second, child:expect('ok')",
+                )
+                .eval_async()
+                .await
+                .unwrap();
+            assert_eq!(result, ("ok".into(), "ready".into(), None, "eof".into()));
+        });
+    }
+
+    #[test]
+    fn test_exec_kill() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            let child = exec(&lua, &mocks(&lua), "sleep", &["30".to_string()]).unwrap();
+            lua.globals().set("child", child).unwrap();
+            let status: i32 = lua
+                .load("assert(child:status(0) == nil) assert(child:try_status() == nil) assert(child:stop(1) == 9) child:kill() assert(child:try_status() == 9) return child:status().signal")
+                .eval_async()
+                .await
+                .unwrap();
+            assert_eq!(status, KILLED);
+        });
+    }
+}
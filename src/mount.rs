@@ -0,0 +1,145 @@
+use mlua::prelude::*;
+
+use crate::{size::Bytes, unix};
+
+/// Mount flag names accepted from Lua
+const FLAGS: [(&str, u64); 8] = [
+    ("ro", unix::MS_RDONLY),
+    ("nosuid", unix::MS_NOSUID),
+    ("nodev", unix::MS_NODEV),
+    ("noexec", unix::MS_NOEXEC),
+    ("remount", unix::MS_REMOUNT),
+    ("noatime", unix::MS_NOATIME),
+    ("bind", unix::MS_BIND),
+    ("rec", unix::MS_REC),
+];
+
+/// Combine a list of flag names into mount flags
+fn flags(names: &[String]) -> LuaResult<u64> {
+    names.iter().try_fold(0, |flags, name| {
+        FLAGS
+            .iter()
+            .find(|(known, _)| known == name)
+            .map(|(_, flag)| flags | flag)
+            .ok_or_else(|| LuaError::runtime(format!("unknown mount flag '{}'", name)))
+    })
+}
+
+/// Turn a failed mount into an error naming its target
+fn mount_error(target: &str, err: std::io::Error) -> LuaError {
+    LuaError::runtime(format!("failed to mount '{}': {}", target, err))
+}
+
+/// Mount a filesystem from an options table with source, target, fstype, flags, and data
+async fn mount(_lua: Lua, options: LuaTable) -> LuaResult<()> {
+    let source: Option<String> = options.get("source")?;
+    let target: String = options.get("target")?;
+    let fstype: Option<String> = options.get("fstype")?;
+    let flags = flags(
+        &options
+            .get::<Option<Vec<String>>>("flags")?
+            .unwrap_or_default(),
+    )?;
+    let data: Option<String> = options.get("data")?;
+    smol::unblock(move || {
+        unix::mount(
+            source.as_deref(),
+            &target,
+            fstype.as_deref(),
+            flags,
+            data.as_deref(),
+        )
+        .map_err(|err| mount_error(&target, err))
+    })
+    .await
+}
+
+/// Mount a directory at another location, optionally read only
+///
+/// A read only bind mount needs a second remount, since the kernel ignores
+/// the read only flag when the bind mount is first created.
+async fn bind(
+    _lua: Lua,
+    (source, target, readonly): (String, String, Option<bool>),
+) -> LuaResult<()> {
+    smol::unblock(move || {
+        let bind = unix::MS_BIND | unix::MS_REC;
+        unix::mount(Some(&source), &target, None, bind, None)
+            .map_err(|err| mount_error(&target, err))?;
+        if readonly.unwrap_or(false) {
+            let flags = bind | unix::MS_REMOUNT | unix::MS_RDONLY;
+            unix::mount(None, &target, None, flags, None)
+                .map_err(|err| mount_error(&target, err))?;
+        }
+        Ok(())
+    })
+    .await
+}
+
+/// Mount a tmpfs without devices or set-user-id programs, optionally limited in size
+async fn tmpfs(_lua: Lua, (target, size): (String, Option<Bytes>)) -> LuaResult<()> {
+    let data = size.map(|size| format!("size={}", size.0));
+    smol::unblock(move || {
+        let flags = unix::MS_NOSUID | unix::MS_NODEV;
+        unix::mount(
+            Some("tmpfs"),
+            &target,
+            Some("tmpfs"),
+            flags,
+            data.as_deref(),
+        )
+        .map_err(|err| mount_error(&target, err))
+    })
+    .await
+}
+
+/// Unmount a filesystem, detaching it lazily if asked
+async fn umount(_lua: Lua, (target, lazy): (String, Option<bool>)) -> LuaResult<()> {
+    let flags = match lazy.unwrap_or(false) {
+        true => unix::MNT_DETACH,
+        false => 0,
+    };
+    smol::unblock(move || {
+        unix::umount(&target, flags)
+            .map_err(|err| LuaError::runtime(format!("failed to unmount '{}': {}", target, err)))
+    })
+    .await
+}
+
+/// Return the `init.mount` Lua table
+pub fn mount_table(lua: &Lua) -> LuaResult<LuaTable> {
+    let table = lua.create_table()?;
+    table.set("mount", lua.create_async_function(mount)?)?;
+    table.set("bind", lua.create_async_function(bind)?)?;
+    table.set("tmpfs", lua.create_async_function(tmpfs)?)?;
+    table.set("umount", lua.create_async_function(umount)?)?;
+    Ok(table)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flags() {
+        let names = vec!["ro".to_string(), "nosuid".to_string()];
+        assert_eq!(flags(&names).unwrap(), unix::MS_RDONLY | unix::MS_NOSUID);
+        assert_eq!(flags(&[]).unwrap(), 0);
+        assert!(flags(&["sync".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_mount_err() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            let missing = "/nonexistent/luavisors".to_string();
+            let err = tmpfs(lua.clone(), (missing.clone(), Some(Bytes(1 << 20))))
+                .await
+                .unwrap_err();
+            assert!(err.to_string().contains("failed to mount"));
+            let bound = bind(lua.clone(), (missing.clone(), missing.clone(), Some(true))).await;
+            assert!(bound.is_err());
+            assert!(umount(lua, (missing, None)).await.is_err());
+        });
+    }
+}
@@ -0,0 +1,222 @@
+use std::net::IpAddr;
+
+use mlua::prelude::*;
+
+use crate::unix;
+
+/// Netlink message reporting an error or acknowledgement
+const NLMSG_ERROR: u16 = 2;
+/// Create or change a network interface
+const RTM_NEWLINK: u16 = 16;
+/// Add an address to a network interface
+const RTM_NEWADDR: u16 = 20;
+/// Netlink request flag
+const NLM_F_REQUEST: u16 = 1;
+/// Ask the kernel to acknowledge the request
+const NLM_F_ACK: u16 = 4;
+/// Fail if the object already exists
+const NLM_F_EXCL: u16 = 0x200;
+/// Create the object if it does not exist
+const NLM_F_CREATE: u16 = 0x400;
+/// Interface is up
+const IFF_UP: u32 = 1;
+/// Local address of an interface
+const IFA_LOCAL: u16 = 2;
+/// Address of an interface, or of the peer for point to point links
+const IFA_ADDRESS: u16 = 1;
+/// IPv4 address family
+const AF_INET: u8 = 2;
+/// IPv6 address family
+const AF_INET6: u8 = 10;
+/// Size of `struct nlmsghdr`
+const NLMSG_HDRLEN: usize = 16;
+
+/// Build a netlink message from its type, flags, and payload
+fn message(kind: u16, flags: u16, payload: &[u8]) -> Vec<u8> {
+    let len = NLMSG_HDRLEN + payload.len();
+    let mut msg = Vec::with_capacity(len);
+    msg.extend_from_slice(&(len as u32).to_ne_bytes());
+    msg.extend_from_slice(&kind.to_ne_bytes());
+    msg.extend_from_slice(&(flags | NLM_F_REQUEST | NLM_F_ACK).to_ne_bytes());
+    // the kernel answers each request on its own socket, so sequence and port are unused
+    msg.extend_from_slice(&1u32.to_ne_bytes());
+    msg.extend_from_slice(&0u32.to_ne_bytes());
+    msg.extend_from_slice(payload);
+    msg
+}
+
+/// Build a request to set an interface up or down
+fn link_message(index: u32, up: bool) -> Vec<u8> {
+    // struct ifinfomsg
+    let mut payload = vec![0u8; 4];
+    payload.extend_from_slice(&(index as i32).to_ne_bytes());
+    payload.extend_from_slice(&(if up { IFF_UP } else { 0 }).to_ne_bytes());
+    payload.extend_from_slice(&IFF_UP.to_ne_bytes());
+    message(RTM_NEWLINK, 0, &payload)
+}
+
+/// Append a route attribute padded to a multiple of four bytes
+fn push_attr(payload: &mut Vec<u8>, kind: u16, data: &[u8]) {
+    let len = 4 + data.len();
+    payload.extend_from_slice(&(len as u16).to_ne_bytes());
+    payload.extend_from_slice(&kind.to_ne_bytes());
+    payload.extend_from_slice(data);
+    payload.resize(payload.len() + (4 - len % 4) % 4, 0);
+}
+
+/// Build a request to add an address with a prefix length to an interface
+fn addr_message(index: u32, addr: IpAddr, prefix: u8) -> Vec<u8> {
+    let (family, bytes) = match addr {
+        IpAddr::V4(addr) => (AF_INET, addr.octets().to_vec()),
+        IpAddr::V6(addr) => (AF_INET6, addr.octets().to_vec()),
+    };
+    // struct ifaddrmsg with universe scope
+    let mut payload = vec![family, prefix, 0, 0];
+    payload.extend_from_slice(&index.to_ne_bytes());
+    push_attr(&mut payload, IFA_LOCAL, &bytes);
+    push_attr(&mut payload, IFA_ADDRESS, &bytes);
+    message(RTM_NEWADDR, NLM_F_CREATE | NLM_F_EXCL, &payload)
+}
+
+/// Check the acknowledgement of a netlink request
+fn parse_ack(buf: &[u8]) -> std::io::Result<()> {
+    let invalid = || std::io::Error::new(std::io::ErrorKind::InvalidData, "invalid netlink reply");
+    let kind = u16::from_ne_bytes(buf.get(4..6).ok_or_else(invalid)?.try_into().unwrap());
+    if kind != NLMSG_ERROR {
+        return Err(invalid());
+    }
+    let errno = i32::from_ne_bytes(buf.get(16..20).ok_or_else(invalid)?.try_into().unwrap());
+    match errno {
+        0 => Ok(()),
+        errno => Err(std::io::Error::from_raw_os_error(-errno)),
+    }
+}
+
+/// Send a request to the kernel and wait for its acknowledgement
+fn request(msg: &[u8]) -> std::io::Result<()> {
+    let socket = unix::netlink_route()?;
+    unix::send(&socket, msg)?;
+    let mut buf = [0u8; 4096];
+    let read = unix::recv(&socket, &mut buf)?;
+    parse_ack(&buf[..read])
+}
+
+/// Split an address such as `10.0.0.2/24`, defaulting to a host prefix
+fn parse_cidr(cidr: &str) -> Result<(IpAddr, u8), String> {
+    let invalid = || format!("invalid address '{}'", cidr);
+    let (addr, prefix) = match cidr.split_once('/') {
+        Some((addr, prefix)) => (addr, Some(prefix)),
+        None => (cidr, None),
+    };
+    let addr: IpAddr = addr.parse().map_err(|_| invalid())?;
+    let max = if addr.is_ipv4() { 32 } else { 128 };
+    let prefix = match prefix {
+        Some(prefix) => prefix.parse().map_err(|_| invalid())?,
+        None => max,
+    };
+    match prefix <= max {
+        true => Ok((addr, prefix)),
+        false => Err(invalid()),
+    }
+}
+
+/// Set a network interface up or down
+fn set_link(name: &str, up: bool) -> LuaResult<()> {
+    let index = unix::if_nametoindex(name)
+        .map_err(|err| LuaError::runtime(format!("unknown interface '{}': {}", name, err)))?;
+    request(&link_message(index, up))
+        .map_err(|err| LuaError::runtime(format!("failed to configure '{}': {}", name, err)))
+}
+
+/// Bring a network interface up from Lua
+async fn link_up(_lua: Lua, name: String) -> LuaResult<()> {
+    smol::unblock(move || set_link(&name, true)).await
+}
+
+/// Take a network interface down from Lua
+async fn link_down(_lua: Lua, name: String) -> LuaResult<()> {
+    smol::unblock(move || set_link(&name, false)).await
+}
+
+/// Add an address such as `10.0.0.2/24` to a network interface from Lua
+async fn addr_add(_lua: Lua, (name, cidr): (String, String)) -> LuaResult<()> {
+    let (addr, prefix) = parse_cidr(&cidr).map_err(LuaError::runtime)?;
+    smol::unblock(move || {
+        let index = unix::if_nametoindex(&name)
+            .map_err(|err| LuaError::runtime(format!("unknown interface '{}': {}", name, err)))?;
+        request(&addr_message(index, addr, prefix)).map_err(|err| {
+            LuaError::runtime(format!("failed to add '{}' to '{}': {}", cidr, name, err))
+        })
+    })
+    .await
+}
+
+/// Return the `init.net` Lua table
+pub fn net_table(lua: &Lua) -> LuaResult<LuaTable> {
+    let table = lua.create_table()?;
+    table.set("link_up", lua.create_async_function(link_up)?)?;
+    table.set("link_down", lua.create_async_function(link_down)?)?;
+    table.set("addr_add", lua.create_async_function(addr_add)?)?;
+    Ok(table)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_link_message() {
+        let msg = link_message(1, true);
+        assert_eq!(msg.len(), NLMSG_HDRLEN + 16);
+        assert_eq!(u32::from_ne_bytes(msg[0..4].try_into().unwrap()), 32);
+        assert_eq!(
+            u16::from_ne_bytes(msg[4..6].try_into().unwrap()),
+            RTM_NEWLINK
+        );
+        assert_eq!(&msg[20..24], &1i32.to_ne_bytes());
+        assert_eq!(&msg[24..28], &IFF_UP.to_ne_bytes());
+    }
+
+    #[test]
+    fn test_addr_message() {
+        let msg = addr_message(2, "10.0.0.2".parse().unwrap(), 24);
+        // header, ifaddrmsg, and two 8 byte attributes
+        assert_eq!(msg.len(), NLMSG_HDRLEN + 8 + 16);
+        assert_eq!(&msg[16..18], &[AF_INET, 24]);
+        assert_eq!(&msg[28..32], &[10, 0, 0, 2]);
+        let msg = addr_message(2, "fd00::2".parse().unwrap(), 64);
+        assert_eq!(msg.len(), NLMSG_HDRLEN + 8 + 40);
+    }
+
+    #[test]
+    fn test_parse_ack() {
+        let mut ack = message(NLMSG_ERROR, 0, &0i32.to_ne_bytes());
+        assert!(parse_ack(&ack).is_ok());
+        ack.truncate(NLMSG_HDRLEN);
+        ack.extend_from_slice(&(-1i32).to_ne_bytes());
+        let err = parse_ack(&ack).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(1));
+        assert!(parse_ack(&[0; 4]).is_err());
+    }
+
+    #[test]
+    fn test_parse_cidr() {
+        assert_eq!(
+            parse_cidr("10.0.0.2/24"),
+            Ok(("10.0.0.2".parse().unwrap(), 24))
+        );
+        assert_eq!(parse_cidr("fd00::2"), Ok(("fd00::2".parse().unwrap(), 128)));
+        assert!(parse_cidr("10.0.0.2/33").is_err());
+        assert!(parse_cidr("host/24").is_err());
+    }
+
+    #[test]
+    fn test_link_up_err() {
+        smol::block_on(async {
+            let err = link_up(Lua::new(), "luavisors0".to_string())
+                .await
+                .unwrap_err();
+            assert!(err.to_string().contains("unknown interface"));
+        });
+    }
+}
@@ -0,0 +1,46 @@
+use mlua::prelude::*;
+
+use crate::unix;
+
+/// Return the hostname of the system from Lua
+async fn hostname(_lua: Lua, _: ()) -> LuaResult<String> {
+    Ok(smol::unblock(unix::gethostname).await?)
+}
+
+/// Set the hostname of the system, or of the current UTS namespace, from Lua
+async fn set_hostname(_lua: Lua, name: String) -> LuaResult<()> {
+    smol::unblock(move || {
+        unix::sethostname(&name)
+            .map_err(|err| LuaError::runtime(format!("failed to set hostname '{}': {}", name, err)))
+    })
+    .await
+}
+
+/// Return the `init.os` Lua table
+pub fn os_table(lua: &Lua) -> LuaResult<LuaTable> {
+    let table = lua.create_table()?;
+    table.set("hostname", lua.create_async_function(hostname)?)?;
+    table.set("set_hostname", lua.create_async_function(set_hostname)?)?;
+    Ok(table)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hostname() {
+        smol::block_on(async {
+            let name = hostname(Lua::new(), ()).await.unwrap();
+            assert_eq!(name, unix::gethostname().unwrap());
+        });
+    }
+
+    #[test]
+    fn test_set_hostname_err() {
+        smol::block_on(async {
+            let err = set_hostname(Lua::new(), "x".repeat(300)).await.unwrap_err();
+            assert!(err.to_string().contains("failed to set hostname"));
+        });
+    }
+}
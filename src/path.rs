@@ -0,0 +1,323 @@
+use std::{
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+};
+
+use mlua::prelude::*;
+
+/// Convert a path into a Lua compatible string
+fn path_string(path: &Path) -> String {
+    path.to_string_lossy().into_owned()
+}
+
+/// Join path components, where an absolute component replaces the path
+async fn join(_lua: Lua, parts: mlua::Variadic<String>) -> LuaResult<String> {
+    let mut path = PathBuf::new();
+    for part in parts {
+        path.push(part);
+    }
+    Ok(path_string(&path))
+}
+
+/// Return the parent directory of a path
+async fn dirname(_lua: Lua, path: String) -> LuaResult<String> {
+    let path = Path::new(&path);
+    let parent = match path.parent() {
+        Some(parent) if parent.as_os_str().is_empty() => Path::new("."),
+        Some(parent) => parent,
+        None if path.has_root() => path,
+        None => Path::new("."),
+    };
+    Ok(path_string(parent))
+}
+
+/// Return the final component of a path
+async fn basename(_lua: Lua, path: String) -> LuaResult<String> {
+    let name = Path::new(&path).file_name().unwrap_or_default();
+    Ok(name.to_string_lossy().into_owned())
+}
+
+/// Make a path absolute relative to the current directory without resolving links
+async fn absolute(_lua: Lua, path: String) -> LuaResult<String> {
+    Ok(path_string(&std::path::absolute(path)?))
+}
+
+/// Check whether a path exists
+async fn exists(_lua: Lua, path: String) -> LuaResult<bool> {
+    Ok(smol::fs::metadata(path).await.is_ok())
+}
+
+/// Resolve a path to an absolute path with all symbolic links resolved
+async fn canonicalize(_lua: Lua, path: String) -> LuaResult<String> {
+    Ok(path_string(&smol::fs::canonicalize(path).await?))
+}
+
+/// Registry key of the directory of the running script
+const SCRIPT_DIR: &str = "luavisors.script_dir";
+
+/// Prepend the module patterns of a directory to `package.path`
+fn prepend_package_path(lua: &Lua, dir: &Path) -> LuaResult<()> {
+    let package = lua.globals().get::<LuaTable>("package")?;
+    let current = package.get::<String>("path")?;
+    let dir = path_string(dir);
+    package.set(
+        "path",
+        format!("{0}/?.lua;{0}/?/init.lua;{1}", dir, current),
+    )
+}
+
+/// Directory of a LuaRocks tree which is used when no tree is given
+pub const DEFAULT_ROCKS_TREE: &str = "lua_modules";
+
+/// Add the Lua and C module directories of a LuaRocks tree to the search paths
+///
+/// LuaJIT implements the Lua 5.1 module ABI, so only the 5.1 directories are used.
+pub fn add_rocks_tree(lua: &Lua, tree: &Path) -> LuaResult<()> {
+    let tree = std::path::absolute(tree)?;
+    prepend_package_path(lua, &tree.join("share/lua/5.1"))?;
+    let package = lua.globals().get::<LuaTable>("package")?;
+    let current = package.get::<String>("cpath")?;
+    let lib = path_string(&tree.join("lib/lua/5.1"));
+    package.set("cpath", format!("{}/?.so;{}", lib, current))
+}
+
+/// Resolve modules relative to the directory of `script` rather than the current directory
+pub fn set_script(lua: &Lua, script: &Path) -> LuaResult<()> {
+    let script = script.canonicalize()?;
+    let dir = script.parent().unwrap_or(Path::new("/"));
+    prepend_package_path(lua, dir)?;
+    lua.set_named_registry_value(SCRIPT_DIR, path_string(dir))
+}
+
+/// Return the directory of the running script, or the current directory for inline code
+pub async fn script_dir(lua: Lua, _: ()) -> LuaResult<String> {
+    match lua.named_registry_value::<Option<String>>(SCRIPT_DIR)? {
+        Some(dir) => Ok(dir),
+        None => Ok(path_string(&std::env::current_dir()?)),
+    }
+}
+
+/// Search path used when `PATH` is not set
+const DEFAULT_PATH: &str = "/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin";
+
+/// Check whether a path is an executable regular file
+fn is_executable(path: &Path) -> bool {
+    std::fs::metadata(path)
+        .map(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+/// Resolve a command against `PATH` like a shell would
+pub fn find_executable(name: &str) -> Option<PathBuf> {
+    if name.is_empty() {
+        return None;
+    }
+    // names with a slash are not looked up in PATH
+    if name.contains('/') {
+        let path = std::path::absolute(name).ok()?;
+        return is_executable(&path).then_some(path);
+    }
+    let search = std::env::var_os("PATH").unwrap_or_else(|| DEFAULT_PATH.into());
+    std::env::split_paths(&search)
+        .map(|dir| match dir.as_os_str().is_empty() {
+            true => PathBuf::from(".").join(name),
+            false => dir.join(name),
+        })
+        .find(|path| is_executable(path))
+        .and_then(|path| std::path::absolute(path).ok())
+}
+
+/// Return the path outside of a chroot of an absolute path inside it
+pub fn host_path(root: &Path, path: &Path) -> PathBuf {
+    root.join(path.strip_prefix("/").unwrap_or(path))
+}
+
+/// Resolve a command against `PATH` inside a chroot, returning its path inside the chroot
+///
+/// Relative paths are relative to `cwd` inside the chroot, or its root.
+pub fn find_executable_in(root: &Path, name: &str, cwd: Option<&str>) -> Option<PathBuf> {
+    if name.is_empty() {
+        return None;
+    }
+    if name.contains('/') {
+        let path = Path::new(cwd.unwrap_or("/")).join(name);
+        return is_executable(&host_path(root, &path)).then_some(path);
+    }
+    let search = std::env::var_os("PATH").unwrap_or_else(|| DEFAULT_PATH.into());
+    std::env::split_paths(&search)
+        .filter(|dir| dir.is_absolute())
+        .map(|dir| dir.join(name))
+        .find(|path| is_executable(&host_path(root, path)))
+}
+
+/// Return the absolute path of a command in `PATH`, or nil if not found
+pub async fn which(_lua: Lua, name: String) -> LuaResult<Option<String>> {
+    let path = smol::unblock(move || find_executable(&name)).await;
+    Ok(path.map(|path| path_string(&path)))
+}
+
+/// Return the `init.path` Lua table
+pub fn path_table(lua: &Lua) -> LuaResult<LuaTable> {
+    let table = lua.create_table()?;
+    table.set("join", lua.create_async_function(join)?)?;
+    table.set("dirname", lua.create_async_function(dirname)?)?;
+    table.set("basename", lua.create_async_function(basename)?)?;
+    table.set("absolute", lua.create_async_function(absolute)?)?;
+    table.set("exists", lua.create_async_function(exists)?)?;
+    table.set("canonicalize", lua.create_async_function(canonicalize)?)?;
+    Ok(table)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call<F, R>(f: impl FnOnce(Lua) -> F) -> R
+    where
+        F: std::future::Future<Output = LuaResult<R>>,
+    {
+        smol::block_on(f(Lua::new())).unwrap()
+    }
+
+    fn parts(parts: &[&str]) -> mlua::Variadic<String> {
+        parts.iter().map(|part| part.to_string()).collect()
+    }
+
+    #[test]
+    fn test_join() {
+        assert_eq!(
+            call(|lua| join(lua, parts(&["a", "b", "c.lua"]))),
+            "a/b/c.lua"
+        );
+        assert_eq!(
+            call(|lua| join(lua, parts(&["a", "/etc", "app"]))),
+            "/etc/app"
+        );
+        assert_eq!(call(|lua| join(lua, parts(&[]))), "");
+    }
+
+    #[test]
+    fn test_dirname() {
+        assert_eq!(call(|lua| dirname(lua, "/etc/app.conf".into())), "/etc");
+        assert_eq!(call(|lua| dirname(lua, "app.conf".into())), ".");
+        assert_eq!(call(|lua| dirname(lua, "/".into())), "/");
+        assert_eq!(call(|lua| dirname(lua, "".into())), ".");
+    }
+
+    #[test]
+    fn test_basename() {
+        assert_eq!(
+            call(|lua| basename(lua, "/etc/app.conf".into())),
+            "app.conf"
+        );
+        assert_eq!(call(|lua| basename(lua, "/etc/".into())), "etc");
+        assert_eq!(call(|lua| basename(lua, "/".into())), "");
+    }
+
+    #[test]
+    fn test_absolute() {
+        let cwd = std::env::current_dir().unwrap();
+        let path = call(|lua| absolute(lua, "test.lua".into()));
+        assert_eq!(PathBuf::from(path), cwd.join("test.lua"));
+    }
+
+    #[test]
+    fn test_exists() {
+        assert!(call(|lua| exists(lua, "/".into())));
+        assert!(!call(|lua| exists(lua, "/does/not/exist".into())));
+    }
+
+    #[test]
+    fn test_canonicalize() {
+        assert_eq!(call(|lua| canonicalize(lua, "/etc/../".into())), "/");
+        let result = smol::block_on(canonicalize(Lua::new(), "/does/not/exist".into()));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_is_executable() {
+        assert!(is_executable(Path::new("/bin/sh")));
+        assert!(!is_executable(Path::new("/etc/passwd")));
+        assert!(!is_executable(Path::new("/bin")));
+    }
+
+    #[test]
+    fn test_find_executable() {
+        let path = find_executable("sh").unwrap();
+        assert!(path.is_absolute());
+        assert!(path.ends_with("sh"));
+        assert_eq!(find_executable("/bin/sh"), Some(PathBuf::from("/bin/sh")));
+        assert!(find_executable("luavisors-does-not-exist").is_none());
+        assert!(find_executable("").is_none());
+    }
+
+    #[test]
+    fn test_find_executable_in() {
+        let sh = find_executable_in(Path::new("/"), "sh", None).unwrap();
+        assert!(sh.is_absolute() && sh.ends_with("sh"));
+        let root = std::env::temp_dir().join(format!("luavisors-jail-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("bin")).unwrap();
+        std::fs::copy(&sh, root.join("bin/tool")).unwrap();
+        let absolute = find_executable_in(&root, "/bin/tool", None);
+        let relative = find_executable_in(&root, "./tool", Some("/bin"));
+        let missing = find_executable_in(&root, "/bin/sh", None);
+        std::fs::remove_dir_all(&root).unwrap();
+        assert_eq!(absolute, Some(PathBuf::from("/bin/tool")));
+        assert_eq!(relative, Some(PathBuf::from("/bin/./tool")));
+        assert_eq!(missing, None);
+        assert_eq!(
+            host_path(&root, Path::new("/bin/tool")),
+            root.join("bin/tool")
+        );
+    }
+
+    #[test]
+    fn test_which() {
+        assert!(call(|lua| which(lua, "sh".into())).is_some());
+        assert!(call(|lua| which(lua, "/does/not/exist".into())).is_none());
+    }
+
+    #[test]
+    fn test_set_script() {
+        let lua = Lua::new();
+        let dir = std::env::temp_dir().join(format!("luavisors-script-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("lib")).unwrap();
+        std::fs::write(dir.join("main.lua"), "").unwrap();
+        std::fs::write(dir.join("lib/services.lua"), "return 'services'").unwrap();
+        set_script(&lua, &dir.join("main.lua")).unwrap();
+        let module: String = lua.load("return require('lib.services')").eval().unwrap();
+        assert_eq!(module, "services");
+        let script = call(|_| script_dir(lua.clone(), ()));
+        assert_eq!(PathBuf::from(script), dir.canonicalize().unwrap());
+        std::fs::remove_dir_all(dir).unwrap();
+        assert!(set_script(&Lua::new(), Path::new("/does/not/exist.lua")).is_err());
+    }
+
+    #[test]
+    fn test_add_rocks_tree() {
+        let lua = Lua::new();
+        let tree = std::env::temp_dir().join(format!("luavisors-rocks-{}", std::process::id()));
+        let share = tree.join("share/lua/5.1/rock");
+        std::fs::create_dir_all(&share).unwrap();
+        std::fs::write(share.join("init.lua"), "return 'rock'").unwrap();
+        add_rocks_tree(&lua, &tree).unwrap();
+        let module: String = lua.load("return require('rock')").eval().unwrap();
+        assert_eq!(module, "rock");
+        let cpath: String = lua.load("return package.cpath").eval().unwrap();
+        assert!(cpath.starts_with(&format!("{}/lib/lua/5.1/?.so;", tree.display())));
+        std::fs::remove_dir_all(tree).unwrap();
+    }
+
+    #[test]
+    fn test_script_dir_code() {
+        let cwd = std::env::current_dir().unwrap();
+        assert_eq!(PathBuf::from(call(|lua| script_dir(lua, ()))), cwd);
+    }
+
+    #[test]
+    fn test_path_table() {
+        let lua = Lua::new();
+        let table = path_table(&lua).unwrap();
+        assert!(table.get::<LuaFunction>("join").is_ok());
+    }
+}
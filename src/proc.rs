@@ -0,0 +1,549 @@
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{Mutex, OnceLock},
+    time::Instant,
+};
+
+use mlua::prelude::*;
+
+use crate::unix;
+
+/// Fields of `/proc/<pid>/stat` used to describe a process
+#[derive(Debug, PartialEq)]
+struct Stat {
+    name: String,
+    state: char,
+    ppid: i32,
+    utime: u64,
+    stime: u64,
+    threads: u32,
+    starttime: u64,
+    vsize: u64,
+    rss: i64,
+}
+
+/// Parse `/proc/<pid>/stat`, whose name field may contain spaces and parentheses
+fn parse_stat(stat: &str) -> Option<Stat> {
+    let open = stat.find('(')?;
+    let close = stat.rfind(')')?;
+    let name = stat.get(open + 1..close)?.to_string();
+    // fields are numbered from 1 in proc(5), starting with the state at 3
+    let fields: Vec<&str> = stat.get(close + 1..)?.split_whitespace().collect();
+    let field = |n: usize| fields.get(n - 3).copied();
+    Some(Stat {
+        name,
+        state: field(3)?.chars().next()?,
+        ppid: field(4)?.parse().ok()?,
+        utime: field(14)?.parse().ok()?,
+        stime: field(15)?.parse().ok()?,
+        threads: field(20)?.parse().ok()?,
+        starttime: field(22)?.parse().ok()?,
+        vsize: field(23)?.parse().ok()?,
+        rss: field(24)?.parse().ok()?,
+    })
+}
+
+/// Return the real user id from the contents of `/proc/<pid>/status`
+fn parse_uid(status: &str) -> Option<u32> {
+    let line = status.lines().find(|line| line.starts_with("Uid:"))?;
+    line.split_whitespace().nth(1)?.parse().ok()
+}
+
+/// Return the boot time in seconds since the epoch from the contents of `/proc/stat`
+fn parse_boot_time(stat: &str) -> Option<u64> {
+    let line = stat.lines().find(|line| line.starts_with("btime "))?;
+    line.split_whitespace().nth(1)?.parse().ok()
+}
+
+/// Return the number of resident pages from the contents of `/proc/<pid>/statm`
+fn parse_statm(statm: &str) -> Option<i64> {
+    statm.split_whitespace().nth(1)?.parse().ok()
+}
+
+/// Return the seconds since boot from the contents of `/proc/uptime`
+fn parse_uptime(uptime: &str) -> Option<f64> {
+    uptime.split_whitespace().next()?.parse().ok()
+}
+
+/// Split the contents of `/proc/<pid>/cmdline` into arguments
+fn parse_cmdline(cmdline: &[u8]) -> Vec<String> {
+    cmdline
+        .split(|byte| *byte == 0)
+        .filter(|arg| !arg.is_empty())
+        .map(|arg| String::from_utf8_lossy(arg).into_owned())
+        .collect()
+}
+
+/// Return the directory of a process in `/proc`
+fn proc_dir(pid: i32) -> PathBuf {
+    PathBuf::from(format!("/proc/{}", pid))
+}
+
+/// Return the name and arguments of a process, or `None` if it does not exist
+fn name_and_cmdline(pid: i32) -> Option<(String, Vec<String>)> {
+    let dir = proc_dir(pid);
+    let stat = parse_stat(&std::fs::read_to_string(dir.join("stat")).ok()?)?;
+    let cmdline = std::fs::read(dir.join("cmdline")).unwrap_or_default();
+    Some((stat.name, parse_cmdline(&cmdline)))
+}
+
+/// Return the ids of every process visible in `/proc`
+fn pids() -> std::io::Result<Vec<i32>> {
+    let mut pids: Vec<i32> = std::fs::read_dir("/proc")?
+        .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse().ok())
+        .collect();
+    pids.sort_unstable();
+    Ok(pids)
+}
+
+/// Process in a tree of descendants
+#[derive(Debug, PartialEq)]
+struct Node {
+    pid: i32,
+    name: String,
+    children: Vec<Node>,
+}
+
+/// Build the tree of descendants of `root` from `(pid, ppid, name)` entries
+fn build_tree(root: i32, processes: &[(i32, i32, String)]) -> Option<Node> {
+    let mut children: HashMap<i32, Vec<(i32, &str)>> = HashMap::new();
+    for (pid, ppid, name) in processes {
+        if pid != ppid {
+            children.entry(*ppid).or_default().push((*pid, name));
+        }
+    }
+    let name = processes.iter().find(|(pid, _, _)| *pid == root)?.2.clone();
+    Some(subtree(root, name, &children))
+}
+
+/// Build a node and its descendants from a map of children by parent pid
+fn subtree(pid: i32, name: String, children: &HashMap<i32, Vec<(i32, &str)>>) -> Node {
+    let children = children
+        .get(&pid)
+        .into_iter()
+        .flatten()
+        .map(|(child, name)| subtree(*child, name.to_string(), children))
+        .collect();
+    Node {
+        pid,
+        name,
+        children,
+    }
+}
+
+/// Convert a process tree into nested Lua tables
+fn node_table(lua: &Lua, node: Node) -> LuaResult<LuaTable> {
+    let table = lua.create_table()?;
+    table.set("pid", node.pid)?;
+    table.set("name", node.name)?;
+    let children = node
+        .children
+        .into_iter()
+        .map(|child| node_table(lua, child))
+        .collect::<LuaResult<Vec<_>>>()?;
+    table.set("children", children)?;
+    Ok(table)
+}
+
+/// Return `(pid, ppid, name)` of every process visible in `/proc`
+fn processes() -> std::io::Result<Vec<(i32, i32, String)>> {
+    let processes = pids()?.into_iter().filter_map(|pid| {
+        let stat = std::fs::read_to_string(proc_dir(pid).join("stat")).ok()?;
+        let stat = parse_stat(&stat)?;
+        Some((pid, stat.ppid, stat.name))
+    });
+    Ok(processes.collect())
+}
+
+/// Return the pids of the direct children of a process
+pub fn children(parent: i32) -> std::io::Result<Vec<i32>> {
+    let processes = processes()?.into_iter();
+    let children = processes.filter(|(_, ppid, _)| *ppid == parent);
+    Ok(children.map(|(pid, _, _)| pid).collect())
+}
+
+/// Collect the pids of a node and its descendants, parents before their children
+fn flatten(node: &Node, pids: &mut Vec<i32>) {
+    pids.push(node.pid);
+    for child in &node.children {
+        flatten(child, pids);
+    }
+}
+
+/// Return the pids of a process and all of its descendants, parents first
+pub fn descendants(root: i32) -> std::io::Result<Vec<i32>> {
+    let mut pids = Vec::new();
+    if let Some(node) = build_tree(root, &processes()?) {
+        flatten(&node, &mut pids);
+    }
+    Ok(pids)
+}
+
+/// Return the tree of descendants of a process from Lua, or nil if it does not exist
+async fn tree(lua: Lua, pid: Option<i32>) -> LuaResult<Option<LuaTable>> {
+    let root = pid.unwrap_or(std::process::id() as i32);
+    let processes = smol::unblock(processes).await?;
+    build_tree(root, &processes)
+        .map(|node| node_table(&lua, node))
+        .transpose()
+}
+
+/// Pattern and options accepted by `init.pgrep` and `init.pkill`
+struct MatchOptions {
+    pattern: String,
+    full: bool,
+}
+
+/// Read a Lua pattern or an options table with a pattern and `full`
+fn match_options(lua: &Lua, value: LuaValue) -> LuaResult<MatchOptions> {
+    match value {
+        LuaValue::Table(options) => Ok(MatchOptions {
+            pattern: options.get(1)?,
+            full: options.get::<Option<bool>>("full")?.unwrap_or(false),
+        }),
+        value => Ok(MatchOptions {
+            pattern: String::from_lua(value, lua)?,
+            full: false,
+        }),
+    }
+}
+
+/// Return the ids of other processes whose name, or full command line, matches a Lua pattern
+async fn matching(lua: &Lua, options: &MatchOptions) -> LuaResult<Vec<i32>> {
+    let find = lua
+        .globals()
+        .get::<LuaTable>("string")?
+        .get::<LuaFunction>("find")?;
+    let own = std::process::id() as i32;
+    let processes = smol::unblock(move || {
+        let pids = pids()?;
+        let found = pids.into_iter().filter(|pid| *pid != own);
+        std::io::Result::Ok(
+            found
+                .filter_map(|pid| Some((pid, name_and_cmdline(pid)?)))
+                .collect::<Vec<_>>(),
+        )
+    })
+    .await?;
+    let mut pids = Vec::new();
+    for (pid, (name, cmdline)) in processes {
+        let text = match options.full && !cmdline.is_empty() {
+            true => cmdline.join(" "),
+            false => name,
+        };
+        if find
+            .call::<Option<usize>>((text, options.pattern.as_str()))?
+            .is_some()
+        {
+            pids.push(pid);
+        }
+    }
+    Ok(pids)
+}
+
+/// Return the ids of processes matching a pattern from Lua
+pub async fn pgrep(lua: Lua, pattern: LuaValue) -> LuaResult<Vec<i32>> {
+    let options = match_options(&lua, pattern)?;
+    matching(&lua, &options).await
+}
+
+/// Send a signal, `SIGTERM` by default, to processes matching a pattern from Lua
+///
+/// Returns the ids of the processes which were signalled, skipping any which
+/// exited or could not be signalled after they were matched.
+pub async fn pkill(lua: Lua, (pattern, sig): (LuaValue, Option<i32>)) -> LuaResult<Vec<i32>> {
+    let options = match_options(&lua, pattern)?;
+    let sig = sig.unwrap_or(async_signal::Signal::Term as i32);
+    let mut killed = Vec::new();
+    for pid in matching(&lua, &options).await? {
+        if unix::kill(pid, sig).await.is_ok() {
+            killed.push(pid);
+        }
+    }
+    Ok(killed)
+}
+
+/// Return details of any process from `/proc`, or nil if it does not exist
+///
+/// Open descriptors can only be counted for processes of the same user unless
+/// the supervisor is privileged, so `fds` is nil when they cannot be read.
+async fn info(lua: Lua, pid: i32) -> LuaResult<Option<LuaTable>> {
+    let dir = proc_dir(pid);
+    let Ok(stat) = smol::fs::read_to_string(dir.join("stat")).await else {
+        return Ok(None);
+    };
+    let stat = parse_stat(&stat)
+        .ok_or_else(|| LuaError::runtime(format!("failed to parse stat of pid {}", pid)))?;
+    let status = smol::fs::read_to_string(dir.join("status")).await?;
+    let cmdline = smol::fs::read(dir.join("cmdline"))
+        .await
+        .unwrap_or_default();
+    let boot_time = parse_boot_time(&smol::fs::read_to_string("/proc/stat").await?);
+    let fds = match smol::fs::read_dir(dir.join("fd")).await {
+        Ok(entries) => Some(smol::stream::StreamExt::count(entries).await),
+        Err(_) => None,
+    };
+    let ticks = unix::sysconf(unix::SC_CLK_TCK).unwrap_or(100) as f64;
+    let page_size = unix::sysconf(unix::SC_PAGESIZE).unwrap_or(4096);
+
+    let table = lua.create_table()?;
+    table.set("pid", pid)?;
+    table.set("name", stat.name)?;
+    table.set("cmdline", parse_cmdline(&cmdline))?;
+    table.set("state", stat.state.to_string())?;
+    table.set("ppid", stat.ppid)?;
+    table.set("uid", parse_uid(&status))?;
+    table.set("rss", stat.rss.max(0) * page_size)?;
+    table.set("vsz", stat.vsize)?;
+    table.set("cpu_time", (stat.utime + stat.stime) as f64 / ticks)?;
+    table.set(
+        "start_time",
+        boot_time.map(|boot| boot as f64 + stat.starttime as f64 / ticks),
+    )?;
+    table.set("fds", fds)?;
+    Ok(Some(table))
+}
+
+/// Return the resident memory of a process in bytes, or `None` if it does not exist
+pub async fn rss(pid: i32) -> Option<u64> {
+    let statm = smol::fs::read_to_string(proc_dir(pid).join("statm"))
+        .await
+        .ok()?;
+    let page_size = unix::sysconf(unix::SC_PAGESIZE).unwrap_or(4096);
+    Some((parse_statm(&statm)?.max(0) * page_size) as u64)
+}
+
+/// CPU time in ticks of processes by pid and start time, and when it was read
+type CpuSamples = HashMap<(i32, u64), (u64, Instant)>;
+
+/// Previous CPU time sample of each process
+fn cpu_samples() -> &'static Mutex<CpuSamples> {
+    static SAMPLES: OnceLock<Mutex<CpuSamples>> = OnceLock::new();
+    SAMPLES.get_or_init(Mutex::default)
+}
+
+/// Return the CPU usage of a process in percent since it was last sampled, or since it started
+fn cpu_percent(pid: i32, stat: &Stat, ticks: f64, uptime: Option<f64>) -> Option<f64> {
+    let now = Instant::now();
+    let total = stat.utime + stat.stime;
+    let mut samples = cpu_samples().lock().unwrap_or_else(|err| err.into_inner());
+    samples.retain(|(known, _), _| *known == pid || proc_dir(*known).exists());
+    let previous = samples.insert((pid, stat.starttime), (total, now));
+    let (used, elapsed) = match previous {
+        Some((before, then)) => (total.saturating_sub(before), (now - then).as_secs_f64()),
+        None => (total, uptime? - stat.starttime as f64 / ticks),
+    };
+    (elapsed > 0.0).then(|| used as f64 / ticks / elapsed * 100.0)
+}
+
+/// Return the current usage of a process from `/proc`, or nil if it does not exist
+///
+/// CPU usage is measured since the previous call for the same process, so
+/// sampling at an interval gives the usage over that interval.
+pub async fn stats(lua: Lua, pid: i32) -> LuaResult<Option<LuaTable>> {
+    let dir = proc_dir(pid);
+    let (Ok(stat), Ok(statm)) = (
+        smol::fs::read_to_string(dir.join("stat")).await,
+        smol::fs::read_to_string(dir.join("statm")).await,
+    ) else {
+        return Ok(None);
+    };
+    let error = || LuaError::runtime(format!("failed to parse stat of pid {}", pid));
+    let stat = parse_stat(&stat).ok_or_else(error)?;
+    let resident = parse_statm(&statm).ok_or_else(error)?;
+    let uptime = parse_uptime(&smol::fs::read_to_string("/proc/uptime").await?);
+    let ticks = unix::sysconf(unix::SC_CLK_TCK).unwrap_or(100) as f64;
+    let page_size = unix::sysconf(unix::SC_PAGESIZE).unwrap_or(4096);
+
+    let table = lua.create_table()?;
+    table.set("pid", pid)?;
+    table.set("state", stat.state.to_string())?;
+    table.set("threads", stat.threads)?;
+    table.set("rss", resident * page_size)?;
+    table.set("cpu_percent", cpu_percent(pid, &stat, ticks, uptime))?;
+    Ok(Some(table))
+}
+
+/// Return the `init.proc` Lua table
+pub fn proc_table(lua: &Lua) -> LuaResult<LuaTable> {
+    let table = lua.create_table()?;
+    table.set("info", lua.create_async_function(info)?)?;
+    table.set("stats", lua.create_async_function(stats)?)?;
+    table.set("tree", lua.create_async_function(tree)?)?;
+    Ok(table)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STAT: &str = "1234 (my (odd) name) S 1 1234 1234 0 -1 4194560 100 0 0 0 \
+        250 50 0 0 20 0 1 0 5000 10485760 256 18446744073709551615";
+
+    #[test]
+    fn test_parse_stat() {
+        let stat = parse_stat(STAT).unwrap();
+        assert_eq!(stat.name, "my (odd) name");
+        assert_eq!(stat.state, 'S');
+        assert_eq!(stat.ppid, 1);
+        assert_eq!((stat.utime, stat.stime), (250, 50));
+        assert_eq!(stat.threads, 1);
+        assert_eq!(stat.starttime, 5000);
+        assert_eq!(stat.vsize, 10485760);
+        assert_eq!(stat.rss, 256);
+        assert!(parse_stat("1234 (truncated").is_none());
+    }
+
+    #[test]
+    fn test_parse_status_and_boot_time() {
+        assert_eq!(
+            parse_uid("Name:\tsh\nUid:\t1000\t1000\t1000\t1000\n"),
+            Some(1000)
+        );
+        assert_eq!(
+            parse_boot_time("cpu 1 2 3\nbtime 1700000000\n"),
+            Some(1700000000)
+        );
+    }
+
+    #[test]
+    fn test_parse_statm_and_uptime() {
+        assert_eq!(parse_statm("2560 256 128 10 0 300 0\n"), Some(256));
+        assert_eq!(parse_uptime("350735.47 234388.90\n"), Some(350735.47));
+        assert!(parse_statm("").is_none());
+    }
+
+    #[test]
+    fn test_parse_cmdline() {
+        assert_eq!(parse_cmdline(b"sleep\x0060\x00"), vec!["sleep", "60"]);
+        assert!(parse_cmdline(b"").is_empty());
+    }
+
+    #[test]
+    fn test_build_tree() {
+        let processes = vec![
+            (1, 0, "init".to_string()),
+            (10, 1, "sh".to_string()),
+            (11, 10, "sleep".to_string()),
+            (12, 1, "cron".to_string()),
+            (20, 2, "kthread".to_string()),
+        ];
+        let tree = build_tree(1, &processes).unwrap();
+        assert_eq!(tree.name, "init");
+        let pids: Vec<i32> = tree.children.iter().map(|child| child.pid).collect();
+        assert_eq!(pids, vec![10, 12]);
+        assert_eq!(tree.children[0].children[0].name, "sleep");
+        assert!(build_tree(99, &processes).is_none());
+    }
+
+    #[test]
+    fn test_tree() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            let mut child = std::process::Command::new("sleep")
+                .arg("60")
+                .spawn()
+                .unwrap();
+            let table = tree(lua.clone(), None).await.unwrap().unwrap();
+            child.kill().unwrap();
+            child.wait().unwrap();
+            assert_eq!(table.get::<i32>("pid").unwrap(), std::process::id() as i32);
+            let children: Vec<LuaTable> = table.get("children").unwrap();
+            let pid = child.id() as i32;
+            assert!(children.iter().any(|c| c.get::<i32>("pid").unwrap() == pid));
+        });
+    }
+
+    #[test]
+    fn test_children() {
+        let mut child = std::process::Command::new("sleep")
+            .arg("30")
+            .spawn()
+            .unwrap();
+        let children = children(std::process::id() as i32).unwrap();
+        child.kill().unwrap();
+        child.wait().unwrap();
+        assert!(children.contains(&(child.id() as i32)));
+    }
+
+    #[test]
+    fn test_flatten() {
+        let processes = vec![
+            (1, 0, "init".to_string()),
+            (2, 1, "a".to_string()),
+            (3, 2, "b".to_string()),
+            (4, 1, "c".to_string()),
+        ];
+        let mut pids = Vec::new();
+        flatten(&build_tree(1, &processes).unwrap(), &mut pids);
+        assert_eq!(pids, vec![1, 2, 3, 4]);
+        assert_eq!(descendants(-1).unwrap(), Vec::<i32>::new());
+    }
+
+    #[test]
+    fn test_pids() {
+        let pids = pids().unwrap();
+        assert!(pids.contains(&(std::process::id() as i32)));
+    }
+
+    #[test]
+    fn test_pgrep_pkill() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            let mut child = std::process::Command::new("sleep")
+                .arg("61.2345")
+                .spawn()
+                .unwrap();
+            let pid = child.id() as i32;
+            let full = || -> LuaValue {
+                lua.load("{ '^sleep 61%.2345$', full = true }")
+                    .eval()
+                    .unwrap()
+            };
+            let found = pgrep(lua.clone(), full()).await.unwrap();
+            assert_eq!(found, vec![pid]);
+            let by_name = LuaValue::String(lua.create_string("^sleep$").unwrap());
+            assert!(pgrep(lua.clone(), by_name).await.unwrap().contains(&pid));
+            let killed = pkill(lua.clone(), (full(), None)).await.unwrap();
+            assert_eq!(killed, vec![pid]);
+            let status = child.wait().unwrap();
+            assert_eq!(
+                std::os::unix::process::ExitStatusExt::signal(&status),
+                Some(15)
+            );
+        });
+    }
+
+    #[test]
+    fn test_info() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            let pid = std::process::id() as i32;
+            let table = info(lua.clone(), pid).await.unwrap().unwrap();
+            assert_eq!(table.get::<i32>("pid").unwrap(), pid);
+            assert_eq!(table.get::<u32>("uid").unwrap(), unix::getuid());
+            assert!(table.get::<i64>("rss").unwrap() > 0);
+            assert!(table.get::<f64>("start_time").unwrap() > 0.0);
+            assert!(table.get::<usize>("fds").unwrap() > 0);
+            assert!(info(lua, i32::MAX).await.unwrap().is_none());
+        });
+    }
+
+    #[test]
+    fn test_stats() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            let pid = std::process::id() as i32;
+            let table = stats(lua.clone(), pid).await.unwrap().unwrap();
+            assert!(table.get::<i64>("rss").unwrap() > 0);
+            assert!(table.get::<u32>("threads").unwrap() >= 1);
+            assert!(!table.get::<String>("state").unwrap().is_empty());
+            let start = Instant::now();
+            while start.elapsed().as_millis() < 50 {}
+            let table = stats(lua.clone(), pid).await.unwrap().unwrap();
+            assert!(table.get::<f64>("cpu_percent").unwrap() > 0.0);
+            assert!(stats(lua, i32::MAX).await.unwrap().is_none());
+        });
+    }
+}
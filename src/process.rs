@@ -1,204 +1,2992 @@
-use std::{ffi::OsStr, os::unix::process::ExitStatusExt, sync::Arc};
+use std::{
+    future::Future,
+    os::{fd::AsRawFd, unix::process::ExitStatusExt},
+    path::Path,
+    process::ExitStatus,
+    sync::{Arc, Mutex as StdMutex},
+    task::Poll,
+    time::Duration,
+};
+
+use async_signal::{Signal, Signals};
+use mlua::prelude::*;
+use smol::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWriteExt, BufReader},
+    lock::{Mutex, RwLock},
+    process::{Child, ChildStdin, Stdio},
+    stream::StreamExt,
+};
+
+use crate::{
+    cancel::{self, CancelToken, Deadline},
+    cgroup::{self, Placement},
+    duration::Seconds,
+    errors::AppResult,
+    logfile::{self, LogFile},
+    metrics, mock, path, proc,
+    ring::{self, Recent, RingBuffer, TeeReader},
+    seccomp::{self, Profile},
+    secrets::Secret,
+    shell,
+    size::Bytes,
+    sync::Event,
+    system, unix, verify,
+};
+
+/// Background task which reads a child stream to the end
+type StreamTask = Arc<Mutex<Option<smol::Task<std::io::Result<Vec<u8>>>>>>;
+
+/// Forward signals to the child process
+///
+/// Takes the pid rather than the child, whose lock is held while another task
+/// waits for its status.
+async fn forward_signals(pid: i32, mut signals: Signals) -> AppResult<()> {
+    while let Some(signal) = signals.next().await {
+        let sig = signal? as i32;
+        if !unix::is_reserved(sig) {
+            unix::kill(pid, sig).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Program, arguments, environment, and user of a process about to be spawned
+#[derive(Debug, Default, PartialEq)]
+struct SpawnSpec {
+    path: String,
+    args: Vec<String>,
+    env: Vec<(String, String)>,
+    clear_env: bool,
+    cwd: Option<String>,
+    new_group: bool,
+    setsid: bool,
+    limits: Vec<(i32, u64)>,
+    nice: Option<i32>,
+    sched_policy: Option<i32>,
+    cpus: Vec<usize>,
+    oom_score_adj: Option<i32>,
+    umask: Option<u32>,
+    root: Option<String>,
+    cgroup: Option<Placement>,
+    namespaces: i32,
+    caps: Option<u64>,
+    seccomp: Option<Profile>,
+    uid: Option<u32>,
+    gid: Option<u32>,
+}
+
+/// Where a child process writes one of its output streams
+#[derive(Debug, Default, Clone, PartialEq)]
+enum StdioMode {
+    #[default]
+    Piped,
+    Inherit,
+    Null,
+    Log(String),
+}
+
+impl StdioMode {
+    /// Parse a `stdout` or `stderr` option, where any other string is a log file path
+    fn parse(value: Option<String>) -> Self {
+        match value.as_deref() {
+            None | Some("piped") => StdioMode::Piped,
+            Some("inherit") => StdioMode::Inherit,
+            Some("null") => StdioMode::Null,
+            Some(path) => StdioMode::Log(path.to_string()),
+        }
+    }
+
+    /// Return how the stream is set up when spawning, where log files are fed through a pipe
+    fn stdio(&self) -> Stdio {
+        match self {
+            StdioMode::Inherit => Stdio::inherit(),
+            StdioMode::Null => Stdio::null(),
+            StdioMode::Piped | StdioMode::Log(_) => Stdio::piped(),
+        }
+    }
+
+    /// Parse a `stdin` option, which is inherited unless it is piped or discarded
+    fn parse_stdin(value: Option<String>) -> LuaResult<Self> {
+        match value.as_deref() {
+            None | Some("inherit") => Ok(StdioMode::Inherit),
+            Some("piped") => Ok(StdioMode::Piped),
+            Some("null") => Ok(StdioMode::Null),
+            Some(value) => Err(LuaError::runtime(format!(
+                "expected stdin to be 'inherit', 'piped', or 'null', got '{}'",
+                value
+            ))),
+        }
+    }
+
+    /// Return the path of the log file the stream is appended to
+    fn log(&self) -> Option<String> {
+        match self {
+            StdioMode::Log(path) => Some(path.clone()),
+            _ => None,
+        }
+    }
+}
+
+/// Spawn a new process asynchronously with its input and output set up
+async fn spawn(
+    spec: &SpawnSpec,
+    stdin: Stdio,
+    stdout: Stdio,
+    stderr: Stdio,
+    ready: Option<(&std::io::PipeWriter, i32)>,
+) -> std::io::Result<Child> {
+    let mut inner = std::process::Command::new(&spec.path);
+    if let Some((writer, target)) = ready {
+        unix::inherit_fd_before_exec(&mut inner, writer.as_raw_fd(), target);
+    }
+    if spec.setsid {
+        unix::setsid_before_exec(&mut inner);
+    } else if spec.new_group {
+        std::os::unix::process::CommandExt::process_group(&mut inner, 0);
+    }
+    if !spec.limits.is_empty() {
+        unix::setrlimits_before_exec(&mut inner, spec.limits.clone());
+    }
+    if spec.nice.is_some() || spec.sched_policy.is_some() {
+        unix::priority_before_exec(&mut inner, spec.sched_policy, spec.nice);
+    }
+    if !spec.cpus.is_empty() {
+        let set = unix::CpuSet::new(&spec.cpus)
+            .ok_or_else(|| std::io::Error::from(std::io::ErrorKind::InvalidInput))?;
+        unix::affinity_before_exec(&mut inner, set);
+    }
+    if let Some(adj) = spec.oom_score_adj {
+        unix::oom_score_adj_before_exec(&mut inner, adj);
+    }
+    if let Some(mask) = spec.umask {
+        unix::umask_before_exec(&mut inner, mask);
+    }
+    if let Some(placement) = &spec.cgroup {
+        let procs = cgroup::prepare(placement)?;
+        unix::cgroup_before_exec(&mut inner, &procs)?;
+    }
+    // namespaces come after joining the cgroup, which becomes the root of a new cgroup namespace
+    if spec.namespaces != 0 {
+        unix::unshare_before_exec(&mut inner, spec.namespaces);
+    }
+    // the chroot comes last so the steps before it can still use /proc
+    if let Some(root) = &spec.root {
+        let dir = spec.cwd.as_deref().unwrap_or("/");
+        unix::chroot_before_exec(&mut inner, root, dir)?;
+    }
+    // the user changes after every privileged step, which the user may not be
+    // allowed to take, since std would change it before any of them run
+    if spec.uid.is_some() || spec.gid.is_some() || spec.caps.is_some() {
+        unix::credentials_before_exec(&mut inner, spec.uid, spec.gid, spec.caps);
+    }
+    // the filter comes after every other step, whose syscalls it may deny
+    if let Some(profile) = &spec.seccomp {
+        unix::seccomp_before_exec(&mut inner, seccomp::filter(profile)?)?;
+    }
+    let mut cmd = smol::process::Command::from(inner);
+    cmd.args(&spec.args)
+        .stdin(stdin)
+        .stdout(stdout)
+        .stderr(stderr);
+    if spec.clear_env {
+        cmd.env_clear();
+    }
+    cmd.envs(spec.env.iter().map(|(key, value)| (key, value)));
+    if let (Some(cwd), None) = (&spec.cwd, &spec.root) {
+        cmd.current_dir(cwd);
+    }
+    // the child is registered under the lock so the orphan reaper never reaps it
+    let mut usage = USAGE.lock().unwrap_or_else(|err| err.into_inner());
+    let child = cmd.spawn()?;
+    usage.retain(|(known, _)| *known != child.id());
+    usage.push((child.id(), UsageSlot::default()));
+    Ok(child)
+}
+
+/// Flatten Lua arguments, where tables are expanded into their values
+fn lua_args(args: LuaMultiValue) -> LuaResult<Vec<String>> {
+    let mut vargs = Vec::new();
+    for arg in args {
+        match arg {
+            LuaValue::Table(t) => vargs.extend(
+                t.sequence_values::<String>()
+                    .collect::<LuaResult<Vec<_>>>()?,
+            ),
+            _ => vargs.push(arg.to_string()?),
+        }
+    }
+    Ok(vargs)
+}
+
+/// Command, arguments, and options accepted by `init.exec`
+#[derive(Default)]
+struct ExecOptions {
+    cmd: String,
+    args: LuaMultiValue,
+    cancel: Option<CancelToken>,
+    env: Vec<(String, String)>,
+    clear_env: bool,
+    cwd: Option<String>,
+    new_group: bool,
+    setsid: bool,
+    limits: Vec<(i32, u64)>,
+    nice: Option<i32>,
+    sched_policy: Option<i32>,
+    cpus: Vec<usize>,
+    oom_score_adj: Option<i32>,
+    umask: Option<u32>,
+    root: Option<String>,
+    cgroup: Option<Placement>,
+    namespaces: i32,
+    caps: Option<u64>,
+    seccomp: Option<Profile>,
+    sha256: Option<String>,
+    uid: Option<u32>,
+    gid: Option<u32>,
+    stdin: Option<StdioMode>,
+    stdout: StdioMode,
+    stderr: StdioMode,
+    pipe_in: Option<std::io::PipeReader>,
+    pipe_out: Option<std::io::PipeWriter>,
+    recent: Option<usize>,
+    stream: bool,
+    main: bool,
+    timeout: Option<Duration>,
+    restart_if: Option<RssLimit>,
+    ready_fd: Option<i32>,
+}
+
+/// Resident memory a child may use before it is stopped, and how often it is checked
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct RssLimit {
+    rss_over: u64,
+    interval: Duration,
+}
+
+/// Read the `ready_fd` option, which must not replace the standard streams
+fn ready_fd_option(options: &LuaTable) -> LuaResult<Option<i32>> {
+    match options.get::<Option<i32>>("ready_fd")? {
+        Some(fd) if fd < 3 => Err(LuaError::runtime(format!(
+            "ready_fd {} would replace a standard stream",
+            fd
+        ))),
+        fd => Ok(fd),
+    }
+}
+
+/// Read the `restart_if` option, a table with `rss_over` and the seconds between checks
+fn restart_if_option(options: &LuaTable) -> LuaResult<Option<RssLimit>> {
+    let Some(restart_if) = options.get::<Option<LuaTable>>("restart_if")? else {
+        return Ok(None);
+    };
+    let rss_over = restart_if
+        .get::<Option<Bytes>>("rss_over")?
+        .ok_or_else(|| LuaError::runtime("restart_if needs rss_over"))?;
+    Ok(Some(RssLimit {
+        rss_over: rss_over.0,
+        interval: restart_if
+            .get::<Option<Seconds>>("interval")?
+            .map_or(Duration::from_secs(1), Seconds::duration),
+    }))
+}
+
+/// Read extra environment variables whose values are strings or secrets
+///
+/// Variables are expanded in string values, but never in secrets.
+fn env_option(options: &LuaTable, expand: &Expand) -> LuaResult<Vec<(String, String)>> {
+    let Some(env) = options.get::<Option<LuaTable>>("env")? else {
+        return Ok(Vec::new());
+    };
+    env.pairs::<String, LuaValue>()
+        .map(|pair| {
+            let (key, value) = pair?;
+            let value = match value {
+                LuaValue::UserData(ud) if ud.is::<Secret>() => {
+                    ud.borrow::<Secret>()?.reveal().to_string()
+                }
+                value => expand(value.to_string()?)?,
+            };
+            Ok((key, value))
+        })
+        .collect()
+}
+
+/// Expansion applied to the command, arguments, and environment of a child process
+type Expand = Box<dyn Fn(String) -> LuaResult<String>>;
+
+/// Read the `expand` option, which is `true` or a table of variables used before the environment
+fn expand_option(options: &LuaTable) -> LuaResult<Expand> {
+    let vars = match options.get::<LuaValue>("expand")? {
+        LuaValue::Nil | LuaValue::Boolean(false) => return Ok(Box::new(Ok)),
+        LuaValue::Boolean(true) => None,
+        LuaValue::Table(vars) => Some(vars),
+        value => {
+            return Err(LuaError::runtime(format!(
+                "expected expand to be a boolean or table, got a value of type '{}'",
+                value.type_name()
+            )))
+        }
+    };
+    Ok(Box::new(move |s| shell::expand_with(&s, vars.as_ref())))
+}
+
+/// Read a file mode creation mask, where strings such as `'027'` are octal
+fn umask_option(options: &LuaTable) -> LuaResult<Option<u32>> {
+    let mask = match options.get::<LuaValue>("umask")? {
+        LuaValue::Nil => return Ok(None),
+        LuaValue::String(s) => u32::from_str_radix(&s.to_str()?, 8)
+            .map_err(|_| LuaError::runtime(format!("invalid octal umask '{}'", s.display())))?,
+        LuaValue::Integer(mask) => u32::try_from(mask).unwrap_or(u32::MAX),
+        LuaValue::Number(mask) if mask.fract() == 0.0 && mask >= 0.0 => mask as u32,
+        value => {
+            return Err(LuaError::runtime(format!(
+                "expected umask to be a number or octal string, got a value of type '{}'",
+                value.type_name()
+            )))
+        }
+    };
+    match mask <= 0o777 {
+        true => Ok(Some(mask)),
+        false => Err(LuaError::runtime(format!(
+            "umask {:o} is out of range",
+            mask
+        ))),
+    }
+}
+
+/// Split a command name or options table into the command, arguments, and options
+fn exec_options(lua: &Lua, cmd: LuaValue, args: LuaMultiValue) -> LuaResult<ExecOptions> {
+    let LuaValue::Table(options) = cmd else {
+        return Ok(ExecOptions {
+            cmd: String::from_lua(cmd, lua)?,
+            args,
+            ..Default::default()
+        });
+    };
+    let expand = expand_option(&options)?;
+    let mut values = options.sequence_values::<LuaValue>();
+    let cmd = match values.next() {
+        Some(cmd) => expand(String::from_lua(cmd?, lua)?)?,
+        None => return Err(LuaError::runtime("missing command to execute")),
+    };
+    let mut vargs = values.collect::<LuaResult<Vec<_>>>()?;
+    vargs.extend(args);
+    let vargs = vargs
+        .into_iter()
+        .map(|arg| match arg {
+            LuaValue::String(s) => Ok(LuaValue::String(
+                lua.create_string(expand(s.to_str()?.to_string())?)?,
+            )),
+            arg => Ok(arg),
+        })
+        .collect::<LuaResult<Vec<_>>>()?;
+    Ok(ExecOptions {
+        cmd,
+        args: LuaMultiValue::from(vargs),
+        cancel: cancel::cancel_option(&options)?,
+        env: env_option(&options, &expand)?,
+        clear_env: options.get::<Option<bool>>("clear_env")?.unwrap_or(false),
+        cwd: options
+            .get::<Option<String>>("cwd")?
+            .map(&expand)
+            .transpose()?,
+        new_group: options.get::<Option<bool>>("new_group")?.unwrap_or(false),
+        setsid: options.get::<Option<bool>>("setsid")?.unwrap_or(false),
+        limits: match options.get::<Option<LuaTable>>("limits")? {
+            Some(limits) => system::limits_from_table(&limits)?,
+            None => Vec::new(),
+        },
+        nice: options.get("nice")?,
+        sched_policy: options
+            .get::<Option<String>>("sched_policy")?
+            .map(|name| system::sched_policy(&name))
+            .transpose()?,
+        cpus: system::cpus_option(&options)?,
+        oom_score_adj: options
+            .get::<Option<i32>>("oom_score_adj")?
+            .map(system::check_oom_score_adj)
+            .transpose()?,
+        umask: umask_option(&options)?,
+        root: options
+            .get::<Option<String>>("root")?
+            .map(&expand)
+            .transpose()?,
+        cgroup: options
+            .get::<Option<LuaTable>>("cgroup")?
+            .map(|placement| cgroup::placement_from_table(&placement))
+            .transpose()?,
+        namespaces: system::namespaces_option(&options)?,
+        caps: system::caps_option(&options)?,
+        seccomp: seccomp::seccomp_option(lua, &options)?,
+        sha256: match options.get::<Option<LuaTable>>("verify")? {
+            Some(verify) => verify.get("sha256")?,
+            None => None,
+        },
+        uid: options.get("uid")?,
+        gid: options.get("gid")?,
+        stdin: Some(StdioMode::parse_stdin(options.get("stdin")?)?),
+        stdout: StdioMode::parse(options.get("stdout")?),
+        stderr: StdioMode::parse(options.get("stderr")?),
+        pipe_in: None,
+        pipe_out: None,
+        recent: options
+            .get::<Option<Bytes>>("recent")?
+            .map(|size| size.0 as usize),
+        stream: options.get::<Option<bool>>("stream")?.unwrap_or(false),
+        main: options.get::<Option<bool>>("main")?.unwrap_or(false),
+        timeout: options
+            .get::<Option<Seconds>>("timeout")?
+            .map(Seconds::duration),
+        restart_if: restart_if_option(&options)?,
+        ready_fd: ready_fd_option(&options)?,
+    })
+}
+
+/// Registry key of the hook set by `init.on_spawn`
+const SPAWN_HOOK: &str = "luavisors.on_spawn";
+
+/// Set or clear the hook called before every child process is spawned
+pub async fn on_spawn(lua: Lua, hook: Option<LuaFunction>) -> LuaResult<()> {
+    lua.set_named_registry_value(SPAWN_HOOK, hook)
+}
+
+/// Convert a spawn spec into the table passed to the spawn hook
+fn spec_table(lua: &Lua, spec: &SpawnSpec) -> LuaResult<LuaTable> {
+    let table = lua.create_table()?;
+    table.set("path", spec.path.as_str())?;
+    table.set(
+        "args",
+        lua.create_sequence_from(spec.args.iter().map(String::as_str))?,
+    )?;
+    table.set(
+        "env",
+        lua.create_table_from(spec.env.iter().map(|(k, v)| (k.as_str(), v.as_str())))?,
+    )?;
+    table.set("clear_env", spec.clear_env)?;
+    table.set("cwd", spec.cwd.as_deref())?;
+    table.set("new_group", spec.new_group)?;
+    table.set("setsid", spec.setsid)?;
+    table.set("limits", system::limits_table(lua, &spec.limits)?)?;
+    table.set("nice", spec.nice)?;
+    table.set(
+        "sched_policy",
+        spec.sched_policy.and_then(system::sched_policy_name),
+    )?;
+    table.set("cpus", spec.cpus.as_slice())?;
+    table.set("oom_score_adj", spec.oom_score_adj)?;
+    table.set("umask", spec.umask)?;
+    table.set("root", spec.root.as_deref())?;
+    if let Some(placement) = &spec.cgroup {
+        table.set("cgroup", cgroup::placement_table(lua, placement)?)?;
+    }
+    table.set("namespaces", system::namespace_names(spec.namespaces))?;
+    if let Some(keep) = spec.caps {
+        let caps = lua.create_table()?;
+        caps.set("keep", system::cap_names(keep))?;
+        table.set("caps", caps)?;
+    }
+    if let Some(profile) = &spec.seccomp {
+        table.set("seccomp", seccomp::profile_table(lua, profile)?)?;
+    }
+    table.set("uid", spec.uid.unwrap_or_else(unix::getuid))?;
+    table.set("gid", spec.gid)?;
+    Ok(table)
+}
+
+/// Read a spawn spec back from a table returned by the spawn hook
+fn spec_from_table(table: &LuaTable) -> LuaResult<SpawnSpec> {
+    let env = match table.get::<Option<LuaTable>>("env")? {
+        Some(env) => env.pairs::<String, String>().collect::<LuaResult<_>>()?,
+        None => Vec::new(),
+    };
+    let args = match table.get::<Option<LuaTable>>("args")? {
+        Some(args) => args.sequence_values::<String>().collect::<LuaResult<_>>()?,
+        None => Vec::new(),
+    };
+    Ok(SpawnSpec {
+        path: table.get("path")?,
+        args,
+        env,
+        clear_env: table.get::<Option<bool>>("clear_env")?.unwrap_or(false),
+        cwd: table.get("cwd")?,
+        new_group: table.get::<Option<bool>>("new_group")?.unwrap_or(false),
+        setsid: table.get::<Option<bool>>("setsid")?.unwrap_or(false),
+        limits: match table.get::<Option<LuaTable>>("limits")? {
+            Some(limits) => system::limits_from_table(&limits)?,
+            None => Vec::new(),
+        },
+        nice: table.get("nice")?,
+        sched_policy: table
+            .get::<Option<String>>("sched_policy")?
+            .map(|name| system::sched_policy(&name))
+            .transpose()?,
+        cpus: system::cpus_option(table)?,
+        oom_score_adj: table
+            .get::<Option<i32>>("oom_score_adj")?
+            .map(system::check_oom_score_adj)
+            .transpose()?,
+        umask: umask_option(table)?,
+        root: table.get("root")?,
+        cgroup: table
+            .get::<Option<LuaTable>>("cgroup")?
+            .map(|placement| cgroup::placement_from_table(&placement))
+            .transpose()?,
+        namespaces: system::namespaces_option(table)?,
+        caps: system::caps_option(table)?,
+        seccomp: table
+            .get::<Option<LuaTable>>("seccomp")?
+            .map(|profile| seccomp::profile_from_table(&profile))
+            .transpose()?,
+        uid: table.get("uid")?,
+        gid: table.get("gid")?,
+    })
+}
+
+/// Pass a spawn spec to the spawn hook, which can allow, deny, or replace it
+///
+/// The hook allows the spawn by returning nothing or true, denies it by returning
+/// false and an optional reason, and modifies it by returning a new spec table.
+async fn apply_spawn_hook(lua: &Lua, spec: SpawnSpec) -> LuaResult<SpawnSpec> {
+    let Some(hook) = lua.named_registry_value::<Option<LuaFunction>>(SPAWN_HOOK)? else {
+        return Ok(spec);
+    };
+    let (verdict, reason) = hook
+        .call_async::<(LuaValue, Option<String>)>(spec_table(lua, &spec)?)
+        .await?;
+    match verdict {
+        LuaValue::Nil | LuaValue::Boolean(true) => Ok(spec),
+        LuaValue::Boolean(false) => Err(LuaError::runtime(format!(
+            "spawn of '{}' denied: {}",
+            spec.path,
+            reason.as_deref().unwrap_or("denied by spawn hook")
+        ))),
+        LuaValue::Table(table) => spec_from_table(&table),
+        value => Err(LuaError::runtime(format!(
+            "spawn hook returned a value of type '{}'",
+            value.type_name()
+        ))),
+    }
+}
+
+/// Child whose exit status becomes the exit code of the supervisor
+static MAIN_CHILD: StdMutex<Option<Arc<RwLock<Child>>>> = StdMutex::new(None);
+
+/// Convert an exit status into a shell style exit code, where a signal is 128 plus its number
+fn exit_code(status: ExitStatus) -> i32 {
+    match status.signal() {
+        Some(signal) => 128 + signal,
+        None => status.code().unwrap_or(1),
+    }
+}
+
+/// Return the exit code of a status, or the signal which killed the process
+pub fn status_code(status: ExitStatus) -> LuaResult<i32> {
+    status
+        .signal()
+        .or_else(|| status.code())
+        .ok_or(LuaError::runtime("failed to get status code"))
+}
+
+/// Why the supervisor killed a child, which is `'timeout'` or `'rss_over'`
+type KillReason = Arc<StdMutex<Option<&'static str>>>;
+
+/// Return the reason the supervisor killed a child, if it did
+fn kill_reason(reason: &KillReason) -> Option<&'static str> {
+    *reason.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+/// Return the table of an exit status, with its `code` or `signal`, `core_dumped`, `success`,
+/// `timeout`, and the `reason` the supervisor killed it
+pub fn status_table(lua: &Lua, status: ExitStatus, reason: Option<&str>) -> LuaResult<LuaTable> {
+    let table = lua.create_table()?;
+    table.set("code", status.code())?;
+    table.set("signal", status.signal())?;
+    table.set("core_dumped", status.core_dumped())?;
+    table.set("success", status.success())?;
+    table.set("timeout", reason == Some("timeout"))?;
+    table.set("reason", reason)?;
+    Ok(table)
+}
+
+/// Return the table of the resource usage of a child, with sizes in bytes and times in seconds
+pub fn usage_table(lua: &Lua, rusage: &unix::Rusage) -> LuaResult<LuaTable> {
+    let table = lua.create_table()?;
+    table.set("max_rss", rusage.maxrss * 1024)?;
+    table.set("user_time", rusage.utime.seconds())?;
+    table.set("system_time", rusage.stime.seconds())?;
+    table.set("minor_faults", rusage.minflt)?;
+    table.set("major_faults", rusage.majflt)?;
+    Ok(table)
+}
+
+/// Wait for a child to exit, for at most `wait` if given, returning nil if it is still running
+async fn wait_child(child: &RwLock<Child>, wait: Option<Seconds>) -> LuaResult<Option<ExitStatus>> {
+    let exited = async { Some(reap(&mut *child.write().await).await) };
+    let status = match wait {
+        Some(wait) => {
+            let expired = async {
+                smol::Timer::after(wait.duration()).await;
+                None
+            };
+            smol::future::or(exited, expired).await
+        }
+        None => exited.await,
+    };
+    Ok(status.transpose()?)
+}
+
+/// Wait for the main child and return its exit code, or `None` if there is no main child
+pub async fn main_exit_code() -> std::io::Result<Option<i32>> {
+    let child = MAIN_CHILD
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .clone();
+    let Some(child) = child else {
+        return Ok(None);
+    };
+    let status = reap(&mut *child.write().await).await?;
+    Ok(Some(exit_code(status)))
+}
+
+/// Resource usage of a child, filled in just before it is reaped
+type UsageSlot = Arc<StdMutex<Option<unix::Rusage>>>;
+
+/// Usage slots of children started by `init.exec` which have not been reaped, by pid
+static USAGE: StdMutex<Vec<(u32, UsageSlot)>> = StdMutex::new(Vec::new());
+
+/// Return the usage slot of a child which was just spawned
+fn usage_slot(pid: u32) -> UsageSlot {
+    let usage = USAGE.lock().unwrap_or_else(|err| err.into_inner());
+    let slot = usage.iter().find(|(known, _)| *known == pid);
+    slot.map(|(_, slot)| slot.clone()).unwrap_or_default()
+}
+
+/// Check whether a child has a usage slot, meaning it has not been reaped so its pid is its own
+fn has_usage(pid: u32) -> bool {
+    let usage = USAGE.lock().unwrap_or_else(|err| err.into_inner());
+    usage.iter().any(|(known, _)| *known == pid)
+}
+
+/// Forget the usage slot of a child once it has been reaped
+fn untrack_usage(pid: u32) {
+    let mut usage = USAGE.lock().unwrap_or_else(|err| err.into_inner());
+    usage.retain(|(known, _)| *known != pid);
+}
+
+/// Fill in the usage slot of a child which exited, returning whether it is still running
+fn record_usage(pid: u32) -> bool {
+    let mut usage = USAGE.lock().unwrap_or_else(|err| err.into_inner());
+    let Some(at) = usage.iter().position(|(known, _)| *known == pid) else {
+        return false;
+    };
+    match unix::exited_usage(pid as i32) {
+        Ok(None) => true,
+        Ok(Some(rusage)) => {
+            *usage[at].1.lock().unwrap_or_else(|err| err.into_inner()) = Some(rusage);
+            false
+        }
+        // the child was reaped elsewhere, so its pid is no longer its own
+        Err(_) => {
+            usage.swap_remove(at);
+            false
+        }
+    }
+}
+
+/// Wait for a child to exit and reap it, recording its resource usage first
+async fn reap(child: &mut Child) -> std::io::Result<ExitStatus> {
+    let pid = child.id();
+    if has_usage(pid) {
+        // the pidfd becomes readable once the child exits, which leaves it to be reaped
+        if let Ok(pidfd) = unix::pidfd_open(pid as i32).and_then(smol::Async::new) {
+            let _ = pidfd.readable().await;
+        }
+        record_usage(pid);
+    }
+    let status = child.status().await;
+    untrack_usage(pid);
+    status
+}
+
+/// Reap a child if it exited, recording its resource usage first, or return `None`
+fn try_reap(child: &mut Child) -> std::io::Result<Option<ExitStatus>> {
+    if record_usage(child.id()) {
+        return Ok(None);
+    }
+    let status = child.try_status()?;
+    if status.is_some() {
+        untrack_usage(child.id());
+    }
+    Ok(status)
+}
+
+/// Reap children which exited but were not started by `init.exec`, returning their statuses
+///
+/// These are orphans whose parents exited, which the supervisor adopts as pid 1
+/// or as a subreaper. Children started by `init.exec` are left to their handles.
+pub fn reap_orphans(pids: &[i32]) -> Vec<(i32, ExitStatus)> {
+    // holding the lock keeps a child which is being spawned from being reaped here
+    let usage = USAGE.lock().unwrap_or_else(|err| err.into_inner());
+    pids.iter()
+        .filter(|pid| !usage.iter().any(|(known, _)| *known as i32 == **pid))
+        .filter_map(|pid| Some((*pid, unix::waitpid_nohang(*pid).ok()??)))
+        .collect()
+}
+
+/// Children started by `init.exec` which have not been seen to exit
+static CHILDREN: StdMutex<Vec<Arc<RwLock<Child>>>> = StdMutex::new(Vec::new());
+
+/// Track a child so it can be waited for when the script finishes
+fn track(child: &Arc<RwLock<Child>>) {
+    running_children();
+    let mut children = CHILDREN.lock().unwrap_or_else(|err| err.into_inner());
+    children.push(child.clone());
+}
+
+/// Return the number of children started by `init.exec` which may still be running
+pub fn running_children() -> usize {
+    let mut children = CHILDREN.lock().unwrap_or_else(|err| err.into_inner());
+    // a child with a running `status` call is still running
+    children.retain(|child| {
+        child
+            .try_write()
+            .is_none_or(|mut child| matches!(try_reap(&mut child), Ok(None)))
+    });
+    children.len()
+}
+
+/// How children which are still running when the script finishes are handled
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct ExitPolicy {
+    timeout: Duration,
+    signal: Option<i32>,
+}
+
+/// Handling of children when the script finishes, which leaves them running by default
+static EXIT_POLICY: StdMutex<Option<ExitPolicy>> = StdMutex::new(None);
+
+/// Wait for every child to exit, returning false if `timeout` passes first
+async fn wait_all(children: &[Arc<RwLock<Child>>], timeout: Duration) -> bool {
+    let wait = async {
+        for child in children {
+            let _ = reap(&mut *child.write().await).await;
+        }
+        true
+    };
+    smol::future::or(wait, async {
+        smol::Timer::after(timeout).await;
+        false
+    })
+    .await
+}
+
+/// Wait for children to exit, then signal and finally kill and reap those still running
+async fn finish(children: &[Arc<RwLock<Child>>], policy: ExitPolicy) {
+    if wait_all(children, policy.timeout).await {
+        return;
+    }
+    let Some(signal) = policy.signal else {
+        return;
+    };
+    for child in children {
+        if let Some(mut child) = child.try_write() {
+            if matches!(try_reap(&mut child), Ok(None)) {
+                let _ = unix::kill(child.id() as i32, signal).await;
+            }
+        }
+    }
+    if wait_all(children, policy.timeout).await {
+        return;
+    }
+    for child in children {
+        let mut child = child.write().await;
+        if child.kill().is_ok() {
+            let _ = reap(&mut child).await;
+        }
+    }
+}
+
+/// Handle the children which are still running once the script has finished
+pub async fn finish_children() {
+    let Some(policy) = *EXIT_POLICY.lock().unwrap_or_else(|err| err.into_inner()) else {
+        return;
+    };
+    let children = std::mem::take(&mut *CHILDREN.lock().unwrap_or_else(|err| err.into_inner()));
+    finish(&children, policy).await;
+}
+
+/// Read an exit policy from an options table
+fn exit_policy(options: &LuaTable) -> LuaResult<ExitPolicy> {
+    Ok(ExitPolicy {
+        timeout: options
+            .get::<Option<Seconds>>("timeout")?
+            .map_or(Duration::from_secs(10), Seconds::duration),
+        signal: options.get("signal")?,
+    })
+}
+
+/// Wait for children when the script finishes, optionally signalling those still running
+///
+/// Children are waited for up to `timeout` seconds. With a signal, children still
+/// running are then signalled, waited for again, and killed if they remain.
+pub async fn on_exit(_lua: Lua, options: Option<LuaTable>) -> LuaResult<()> {
+    let policy = options.as_ref().map(exit_policy).transpose()?;
+    *EXIT_POLICY.lock().unwrap_or_else(|err| err.into_inner()) = policy;
+    Ok(())
+}
+
+/// Send a signal to a child process unless it was already reaped, returning whether it was sent
+async fn signal_child(child: &RwLock<Child>, pid: i32, signal: i32) -> bool {
+    // only signal a child which has not been reaped so its pid cannot be reused
+    if reaped(child) {
+        return false;
+    }
+    unix::kill(pid, signal).await.is_ok()
+}
+
+/// Check whether a child exited and was reaped, reaping it if it just exited
+fn reaped(child: &RwLock<Child>) -> bool {
+    // a running `status` call holds the lock until the child is reaped
+    child
+        .try_write()
+        .is_some_and(|mut child| !matches!(try_reap(&mut child), Ok(None)))
+}
+
+/// Whether a child reported it is ready on its `ready_fd`, and whether it closed it
+#[derive(Clone, Default)]
+struct Readiness {
+    ready: Event,
+    closed: Event,
+}
+
+/// Wait in the background for a child to write a line to its end of the ready pipe
+fn watch_ready(reader: std::io::PipeReader) -> Readiness {
+    let readiness = Readiness::default();
+    let clone = readiness.clone();
+    smol::spawn(async move {
+        if let Ok(reader) = smol::Async::new(reader) {
+            let mut buf = [0; 64];
+            loop {
+                let read =
+                    reader.read_with(|mut reader| std::io::Read::read(&mut reader, &mut buf));
+                match read.await {
+                    Ok(0) | Err(_) => break,
+                    Ok(len) if buf[..len].contains(&b'\n') => {
+                        clone.ready.set();
+                        break;
+                    }
+                    Ok(_) => {}
+                }
+            }
+        }
+        clone.closed.set();
+    })
+    .detach();
+    readiness
+}
+
+/// Terminate a child process when its token is cancelled
+async fn cancel_child(child: std::sync::Weak<RwLock<Child>>, pid: i32, token: CancelToken) {
+    token.wait().await;
+    if let Some(child) = child.upgrade() {
+        signal_child(&child, pid, Signal::Term as i32).await;
+    }
+}
+
+/// Terminate a child process started within `init.deadline` once the deadline passes
+async fn expire_child(child: std::sync::Weak<RwLock<Child>>, pid: i32, deadline: Deadline) {
+    if !deadline.expired().await {
+        return;
+    }
+    if let Some(child) = child.upgrade() {
+        signal_child(&child, pid, Signal::Term as i32).await;
+    }
+}
+
+/// Kill a child process which is still running after `timeout`, recording that it timed out
+async fn timeout_child(
+    child: std::sync::Weak<RwLock<Child>>,
+    pid: i32,
+    timeout: Duration,
+    reason: KillReason,
+) {
+    smol::Timer::after(timeout).await;
+    let Some(child) = child.upgrade() else {
+        return;
+    };
+    if signal_child(&child, pid, Signal::Kill as i32).await {
+        reason
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .get_or_insert("timeout");
+    }
+}
+
+/// Stop a child process whose resident memory grows over a limit, recording why it was stopped
+///
+/// The reason is recorded before the child is signalled, so its status always reports it.
+async fn watch_rss(
+    child: std::sync::Weak<RwLock<Child>>,
+    pid: i32,
+    limit: RssLimit,
+    reason: KillReason,
+) {
+    loop {
+        smol::Timer::after(limit.interval).await;
+        let Some(child) = child.upgrade() else {
+            return;
+        };
+        if reaped(&child) {
+            return;
+        }
+        let Some(rss) = proc::rss(pid).await else {
+            return;
+        };
+        if rss > limit.rss_over {
+            reason
+                .lock()
+                .unwrap_or_else(|err| err.into_inner())
+                .get_or_insert("rss_over");
+            let _ = stop_child(&child, pid, DEFAULT_GRACE).await;
+            return;
+        }
+    }
+}
+
+/// Time a process is given to exit after SIGTERM before it is killed by default
+const DEFAULT_GRACE: Duration = Duration::from_secs(10);
+
+/// Send SIGTERM to a child, then SIGKILL if it is still running after `grace`
+///
+/// Returns how the child exited and whether it did so within the grace period.
+async fn stop_child(
+    child: &RwLock<Child>,
+    pid: i32,
+    grace: Duration,
+) -> LuaResult<(ExitStatus, bool)> {
+    signal_child(child, pid, Signal::Term as i32).await;
+    let exited = async { Some(reap(&mut *child.write().await).await) };
+    let status = smol::future::or(exited, async {
+        smol::Timer::after(grace).await;
+        None
+    })
+    .await;
+    if let Some(status) = status {
+        return Ok((status?, true));
+    }
+    signal_child(child, pid, Signal::Kill as i32).await;
+    Ok((reap(&mut *child.write().await).await?, false))
+}
+
+/// Signal the process group which a child leads, refusing the group of the supervisor itself
+async fn signal_group(child: &RwLock<Child>, pid: i32, signal: i32) -> LuaResult<()> {
+    let pgid = unix::getpgid(pid)?;
+    if pgid == unix::getpgid(0)? {
+        return Err(LuaError::runtime(
+            "child shares the process group of the supervisor",
+        ));
+    }
+    // the group outlives its leader, but a reaped leader's pgid may be reused
+    if signal_child(child, pid, 0).await {
+        unix::kill(-pgid, signal).await.map_err(LuaError::runtime)?;
+    }
+    Ok(())
+}
+
+/// Signal a child and every process it started, returning how many were signalled
+///
+/// Descendants are found before any are signalled, so processes forked while
+/// the tree is being signalled are missed, as are those reparented to init.
+async fn signal_tree(child: &RwLock<Child>, pid: i32, signal: i32) -> LuaResult<usize> {
+    let pids = smol::unblock(move || proc::descendants(pid)).await?;
+    let mut signalled = 0;
+    for descendant in pids {
+        let sent = match descendant == pid {
+            true => signal_child(child, pid, signal).await,
+            false => unix::kill(descendant, signal).await.is_ok(),
+        };
+        signalled += sent as usize;
+    }
+    Ok(signalled)
+}
+
+/// Check whether a process exists, which includes children which have not been reaped
+async fn exists(pid: i32) -> bool {
+    unix::kill(pid, 0).await.is_ok()
+}
+
+/// Send SIGTERM to any process, then SIGKILL if it still exists after `grace`
+///
+/// Returns whether the process exited within the grace period.
+async fn stop_pid(pid: i32, grace: Duration) -> LuaResult<bool> {
+    unix::kill(pid, Signal::Term as i32)
+        .await
+        .map_err(LuaError::runtime)?;
+    let deadline = std::time::Instant::now() + grace;
+    while std::time::Instant::now() < deadline {
+        if !exists(pid).await {
+            return Ok(true);
+        }
+        smol::Timer::after(Duration::from_millis(50)).await;
+    }
+    let _ = unix::kill(pid, Signal::Kill as i32).await;
+    Ok(false)
+}
+
+/// Stop a process from Lua with SIGTERM, then SIGKILL after a grace period in seconds
+///
+/// Children started by `init.exec` are reaped so their status is kept; any other
+/// process is polled until it is gone. Returns whether it exited within the grace period.
+pub async fn stop(_lua: Lua, (pid, grace): (i32, Option<Seconds>)) -> LuaResult<bool> {
+    // kill treats these as process groups or every process, and the supervisor
+    // cannot wait for itself to exit
+    if pid <= 0 || pid as u32 == std::process::id() {
+        return Err(LuaError::runtime(format!("cannot stop process {}", pid)));
+    }
+    let grace = grace.map_or(DEFAULT_GRACE, Seconds::duration);
+    // a child whose lock is held is being reaped by a `status` call, so polling works
+    let child = CHILDREN
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .iter()
+        .find(|child| {
+            child
+                .try_read()
+                .is_some_and(|child| child.id() as i32 == pid)
+        })
+        .cloned();
+    let Some(child) = child else {
+        return stop_pid(pid, grace).await;
+    };
+    let (_, graceful) = stop_child(&child, pid, grace).await?;
+    Ok(graceful)
+}
+
+/// Spawn a task to read from a stream
+async fn spawn_stream_task(
+    stream: Option<impl AsyncReadExt + Unpin + Send + 'static>,
+) -> StreamTask {
+    let task = stream.map(|mut stream| {
+        smol::spawn(async move {
+            let mut data = Vec::new();
+            stream.read_to_end(&mut data).await?;
+            Ok(data)
+        })
+    });
+    Arc::new(Mutex::new(task))
+}
+
+/// Spawn a task to append a stream to a log file, leaving no output for Lua
+fn log_stream_task(
+    stream: Option<impl AsyncReadExt + Unpin + Send + 'static>,
+    log: Arc<LogFile>,
+) -> StreamTask {
+    let task = stream.map(|stream| {
+        smol::spawn(async move {
+            logfile::copy_to_log(stream, log).await?;
+            Ok(Vec::new())
+        })
+    });
+    Arc::new(Mutex::new(task))
+}
+
+/// Spawn a task to move a stream into a log file without reading it
+fn splice_stream_task(stream: impl AsRawFd + Send + 'static, log: Arc<LogFile>) -> StreamTask {
+    let task = smol::spawn(async move {
+        logfile::splice_to_log(stream, log).await?;
+        Ok(Vec::new())
+    });
+    Arc::new(Mutex::new(Some(task)))
+}
+
+/// Buffered reader of a child stream which Lua reads from on demand
+type LineReader = Arc<Mutex<BufReader<Box<dyn AsyncRead + Unpin + Send>>>>;
+
+/// Output of a child stream, either collected in the background or read on demand
+#[derive(Clone)]
+enum Output {
+    Collected(StreamTask),
+    Streamed(LineReader),
+}
+
+/// Set up how a child stream is read, redirected, or streamed
+///
+/// A stream going to a log file with no recent output kept is never read by the
+/// supervisor, and is spliced straight into the file instead. A streamed stream
+/// is only read when Lua asks for more, so a child writing faster than Lua
+/// reads blocks on its full pipe instead of growing the supervisor's memory.
+async fn stream_output<S>(
+    stream: Option<S>,
+    log: Option<Arc<LogFile>>,
+    recent: &Recent,
+    streamed: bool,
+) -> Output
+where
+    S: AsyncReadExt + AsRawFd + Unpin + Send + 'static,
+{
+    let keep = recent
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .capacity()
+        > 0;
+    match (stream, log) {
+        (Some(stream), Some(log)) if !keep => Output::Collected(splice_stream_task(stream, log)),
+        (stream, log) => {
+            let stream = stream.map(|stream| TeeReader::new(stream, recent.clone()));
+            match (stream, log) {
+                (stream, Some(log)) => Output::Collected(log_stream_task(stream, log)),
+                (Some(stream), None) if streamed => {
+                    let reader: Box<dyn AsyncRead + Unpin + Send> = Box::new(stream);
+                    Output::Streamed(Arc::new(Mutex::new(BufReader::new(reader))))
+                }
+                (Some(stream), None) => Output::Collected(spawn_stream_task(Some(stream)).await),
+                // streams which are inherited or discarded have no output to read
+                (None, None) => Output::Collected(spawn_stream_task(Some(smol::io::empty())).await),
+            }
+        }
+    }
+}
+
+/// Read the rest of a child stream into a Lua string
+async fn read_output(lua: Lua, output: Output) -> LuaResult<LuaValue> {
+    let reader = match output {
+        Output::Collected(task) => return read_stream_task(lua, task).await,
+        Output::Streamed(reader) => reader,
+    };
+    let mut data = Vec::new();
+    reader.lock().await.read_to_end(&mut data).await?;
+    if data.is_empty() {
+        return Ok(LuaValue::Nil);
+    }
+    Ok(LuaValue::String(lua.create_string(&data)?))
+}
+
+/// Read the next line of a streamed child stream without its newline, or nil at the end
+async fn read_line(lua: Lua, output: Output) -> LuaResult<Option<LuaString>> {
+    let Output::Streamed(reader) = output else {
+        return Err(LuaError::runtime(
+            "reading lines needs init.exec({ ..., stream = true })",
+        ));
+    };
+    let mut line = Vec::new();
+    reader.lock().await.read_until(b'\n', &mut line).await?;
+    if line.is_empty() {
+        return Ok(None);
+    }
+    if line.ends_with(b"\n") {
+        line.pop();
+    }
+    Ok(Some(lua.create_string(&line)?))
+}
+
+/// Most recent output searched by `expect`, so output which never matches costs
+/// bounded memory and time
+const EXPECT_WINDOW: usize = 64 * 1024;
+
+/// Wait until the output of a streamed child stream matches a Lua pattern, or nil and why not
+///
+/// Output up to the end of the match is consumed and the rest is left for later
+/// reads, while output read before a timeout or the end of the stream is lost.
+/// Only the last `EXPECT_WINDOW` bytes are searched, so a match cannot span more.
+/// The match is returned like `string.match` returns it.
+async fn expect(
+    lua: Lua,
+    output: Output,
+    pattern: LuaString,
+    timeout: Option<Duration>,
+) -> LuaResult<LuaMultiValue> {
+    let Output::Streamed(reader) = output else {
+        return Err(LuaError::runtime(
+            "expecting output needs init.exec({ ..., stream = true })",
+        ));
+    };
+    let find = lua
+        .globals()
+        .get::<LuaTable>("string")?
+        .get::<LuaFunction>("find")?;
+    let matched = async {
+        let mut reader = reader.lock().await;
+        let mut seen = Vec::new();
+        loop {
+            let available = reader.fill_buf().await?;
+            if available.is_empty() {
+                return LuaResult::Ok(Err("eof"));
+            }
+            let text = lua.create_string([seen.as_slice(), available].concat())?;
+            let found: LuaMultiValue = find.call((&text, &pattern))?;
+            if let Some(LuaValue::Integer(end)) = found.get(1) {
+                let (start, end) = (found[0].as_integer().unwrap_or(1), *end as usize);
+                let used = end.saturating_sub(seen.len());
+                reader.consume(used);
+                let captures: LuaMultiValue = found.into_iter().skip(2).collect();
+                if !captures.is_empty() {
+                    return Ok(Ok(captures));
+                }
+                let whole = &text.as_bytes()[start as usize - 1..end];
+                let whole = LuaValue::String(lua.create_string(whole)?);
+                return Ok(Ok(LuaMultiValue::from_iter([whole])));
+            }
+            let used = available.len();
+            seen.extend_from_slice(available);
+            reader.consume(used);
+            let excess = seen.len().saturating_sub(EXPECT_WINDOW);
+            seen.drain(..excess);
+        }
+    };
+    let timed_out = async {
+        match timeout {
+            Some(timeout) => smol::Timer::after(timeout).await,
+            None => smol::future::pending().await,
+        };
+        LuaResult::Ok(Err("timeout"))
+    };
+    let reason = match smol::future::or(matched, timed_out).await? {
+        Ok(values) => return Ok(values),
+        Err(reason) => reason,
+    };
+    Ok(LuaMultiValue::from_iter([
+        LuaValue::Nil,
+        LuaValue::String(lua.create_string(reason)?),
+    ]))
+}
+
+/// Write to the piped stdin of a child
+async fn send(stdin: &Mutex<Option<ChildStdin>>, data: &[u8]) -> LuaResult<()> {
+    let mut stdin = stdin.lock().await;
+    let stdin = stdin.as_mut().ok_or_else(|| {
+        LuaError::runtime("sending input needs init.exec({ ..., stdin = 'piped' })")
+    })?;
+    stdin.write_all(data).await?;
+    stdin.flush().await?;
+    Ok(())
+}
+
+/// Open the log file a stream is redirected to, if any
+fn open_log(path: Option<String>) -> LuaResult<Option<Arc<LogFile>>> {
+    path.map(|path| {
+        LogFile::open(&path)
+            .map_err(|err| LuaError::runtime(format!("failed to open log '{}': {}", path, err)))
+    })
+    .transpose()
+}
+
+/// Read a stream into a Lua string
+async fn read_stream_task(lua: Lua, task: StreamTask) -> LuaResult<LuaValue> {
+    let task = task.lock().await.take().ok_or_else(|| {
+        std::io::Error::new(std::io::ErrorKind::InvalidInput, "stream already consumed")
+    })?;
+    let data = task.await?;
+    if data.is_empty() {
+        return Ok(LuaValue::Nil);
+    }
+    Ok(LuaValue::String(lua.create_string(&data)?))
+}
+
+/// Asynchronously execute a command in Lua
+pub async fn exec(lua: Lua, (cmd, args): (LuaValue, LuaMultiValue)) -> LuaResult<LuaTable> {
+    let options = exec_options(&lua, cmd, args)?;
+    exec_with(lua, options).await
+}
+
+/// Execute a command from its parsed options
+async fn exec_with(lua: Lua, options: ExecOptions) -> LuaResult<LuaTable> {
+    let ExecOptions {
+        cmd,
+        args,
+        cancel: token,
+        env,
+        clear_env,
+        cwd,
+        new_group,
+        setsid,
+        limits,
+        nice,
+        sched_policy,
+        cpus,
+        oom_score_adj,
+        umask,
+        root,
+        cgroup,
+        namespaces,
+        caps,
+        seccomp,
+        sha256,
+        uid,
+        gid,
+        stdin,
+        stdout,
+        stderr,
+        pipe_in,
+        pipe_out,
+        recent,
+        stream: streamed,
+        main,
+        timeout,
+        restart_if,
+        ready_fd,
+    } = options;
+    // a command started after the deadline passed could never be waited for
+    let deadline = cancel::current_deadline();
+    if let Some(deadline) = deadline.as_ref().filter(|deadline| deadline.is_expired()) {
+        return Err(deadline.error());
+    }
+    if let Some(mocks) = mock::mocks(&lua)? {
+        return mock::exec(&lua, &mocks, &cmd, &lua_args(args)?);
+    }
+    if main
+        && MAIN_CHILD
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .is_some()
+    {
+        return Err(LuaError::runtime(
+            "a main child process was already started",
+        ));
+    }
+    let (stdout_log, stderr_log) = (open_log(stdout.log())?, open_log(stderr.log())?);
+    // relative paths to a command are relative to its working directory
+    let resolved = smol::unblock({
+        let (cmd, cwd, root) = (cmd.clone(), cwd.clone(), root.clone());
+        move || match (root, cwd) {
+            (Some(root), cwd) => path::find_executable_in(Path::new(&root), &cmd, cwd.as_deref()),
+            (None, Some(cwd)) if cmd.contains('/') => {
+                path::find_executable(&Path::new(&cwd).join(&cmd).display().to_string())
+            }
+            (None, _) => path::find_executable(&cmd),
+        }
+    });
+    let spec = SpawnSpec {
+        path: match resolved.await {
+            Some(path) => path.to_string_lossy().into_owned(),
+            None => cmd,
+        },
+        args: lua_args(args)?,
+        env,
+        clear_env,
+        cwd,
+        new_group,
+        setsid,
+        limits,
+        nice,
+        sched_policy,
+        cpus,
+        oom_score_adj,
+        umask,
+        root,
+        cgroup,
+        namespaces,
+        caps,
+        seccomp,
+        uid,
+        gid,
+    };
+    let mut spec = apply_spawn_hook(&lua, spec).await?;
+    // spawn the verified file so a different one earlier in PATH cannot be run
+    // a chrooted command is checked outside the chroot, but run by its path inside it
+    let checked = smol::unblock({
+        let path = match &spec.root {
+            Some(root) => path::host_path(Path::new(root), Path::new(&spec.path))
+                .display()
+                .to_string(),
+            None => spec.path.clone(),
+        };
+        move || verify::check(&path, sha256.as_deref())
+    });
+    let checked = checked.await.map_err(LuaError::runtime)?;
+    if let (Some(path), None) = (checked, &spec.root) {
+        spec.path = path.to_string_lossy().into_owned();
+    }
+    metrics::check_fds();
+    // the ends of a pipeline replace the streams they connect
+    let stdin = match pipe_in {
+        Some(pipe) => Stdio::from(pipe),
+        None => stdin.unwrap_or(StdioMode::Inherit).stdio(),
+    };
+    let stdout_stdio = match pipe_out {
+        Some(pipe) => Stdio::from(pipe),
+        None => stdout.stdio(),
+    };
+    // the child writes a line to its end of the pipe once it is ready
+    let ready = ready_fd
+        .map(|fd| std::io::pipe().map(|pipe| (pipe, fd)))
+        .transpose()?;
+    let writer = ready.as_ref().map(|((_, writer), fd)| (writer, *fd));
+    // handle signals before the child starts so none sent in between is missed
+    let signals = unix::signal_wait().await?;
+    let mut child = spawn(&spec, stdin, stdout_stdio, stderr.stdio(), writer).await?;
+    let ready = ready.map(|((reader, _), _)| watch_ready(reader));
+    let pid = child.id() as i32;
+    let usage = usage_slot(child.id());
+    let stdin = Arc::new(Mutex::new(child.stdin.take()));
+
+    // both streams feed one buffer so recent lines stay in the order they arrived
+    let capacity = recent.unwrap_or(ring::DEFAULT_CAPACITY);
+    let recent: Recent = Arc::new(StdMutex::new(RingBuffer::new(capacity)));
+    let stdout = stream_output(child.stdout.take(), stdout_log, &recent, streamed).await;
+    let stderr = stream_output(child.stderr.take(), stderr_log, &recent, streamed).await;
+
+    let child = Arc::new(RwLock::new(child));
+    if main {
+        *MAIN_CHILD.lock().unwrap_or_else(|err| err.into_inner()) = Some(child.clone());
+    }
+    track(&child);
+
+    smol::spawn(forward_signals(pid, signals)).detach();
+    if let Some(token) = token {
+        smol::spawn(cancel_child(Arc::downgrade(&child), pid, token)).detach();
+    }
+    if let Some(deadline) = deadline {
+        smol::spawn(expire_child(Arc::downgrade(&child), pid, deadline)).detach();
+    }
+    let reason = KillReason::default();
+    if let Some(timeout) = timeout {
+        let task = timeout_child(Arc::downgrade(&child), pid, timeout, reason.clone());
+        smol::spawn(task).detach();
+    }
+    if let Some(limit) = restart_if {
+        let task = watch_rss(Arc::downgrade(&child), pid, limit, reason.clone());
+        smol::spawn(task).detach();
+    }
+
+    let result = lua.create_table()?;
+
+    // pid
+    let clone = child.clone();
+    result.set(
+        "pid",
+        lua.create_async_function(move |_, ()| {
+            let child = clone.clone();
+            async move { Ok(child.read().await.id()) }
+        })?,
+    )?;
+
+    // status, or nil when the child is still running after waiting for as long as given
+    let (clone, killed) = (child.clone(), reason.clone());
+    result.set(
+        "status",
+        lua.create_async_function(move |lua, (_, wait): (LuaValue, Option<Seconds>)| {
+            let (child, reason) = (clone.clone(), killed.clone());
+            async move {
+                let Some(status) = cancel::until_deadline(wait_child(&child, wait)).await? else {
+                    return Ok(None);
+                };
+                status_table(&lua, status, kill_reason(&reason)).map(Some)
+            }
+        })?,
+    )?;
+
+    // status_code, which returns the code or signal followed by 'timeout' when
+    // the child was killed for running too long, or 'rss_over' for using too much memory
+    let (clone, killed) = (child.clone(), reason.clone());
+    result.set(
+        "status_code",
+        lua.create_async_function(move |_, (_, wait): (LuaValue, Option<Seconds>)| {
+            let (child, reason) = (clone.clone(), killed.clone());
+            async move {
+                let Some(status) = cancel::until_deadline(wait_child(&child, wait)).await? else {
+                    return Ok((None, None));
+                };
+                Ok((Some(status_code(status)?), kill_reason(&reason)))
+            }
+        })?,
+    )?;
+
+    // wait_ready, which is false if the child closes its ready_fd or the timeout passes first
+    result.set(
+        "wait_ready",
+        lua.create_async_function(move |_, (_, wait): (LuaValue, Option<Seconds>)| {
+            let ready = ready.clone();
+            async move {
+                let ready =
+                    ready.ok_or_else(|| LuaError::runtime("child was started without ready_fd"))?;
+                let waiting = smol::future::or(ready.ready.wait(), ready.closed.wait());
+                match wait {
+                    Some(wait) => {
+                        smol::future::or(waiting, async {
+                            smol::Timer::after(wait.duration()).await;
+                        })
+                        .await
+                    }
+                    None => waiting.await,
+                }
+                Ok(ready.ready.is_set())
+            }
+        })?,
+    )?;
+
+    // try_status, which returns nil while the child is running or another call waits for it
+    let clone = child.clone();
+    result.set(
+        "try_status",
+        lua.create_function(move |_, _: LuaMultiValue| {
+            let Some(mut child) = clone.try_write() else {
+                return Ok((None, None));
+            };
+            let code = try_reap(&mut child)?.map(status_code).transpose()?;
+            Ok((code, code.and(kill_reason(&reason))))
+        })?,
+    )?;
+
+    // rusage, which is nil until the child exits
+    result.set(
+        "rusage",
+        lua.create_function(move |lua, _: LuaMultiValue| {
+            record_usage(pid as u32);
+            let rusage = *usage.lock().unwrap_or_else(|err| err.into_inner());
+            rusage.map(|rusage| usage_table(lua, &rusage)).transpose()
+        })?,
+    )?;
+
+    // stats, which is nil once the child exits
+    let clone = child.clone();
+    result.set(
+        "stats",
+        lua.create_async_function(move |lua, _: LuaMultiValue| {
+            let child = clone.clone();
+            async move {
+                // only read a child which has not been reaped so its pid cannot be reused
+                if reaped(&child) {
+                    return Ok(None);
+                }
+                proc::stats(lua, pid).await
+            }
+        })?,
+    )?;
+
+    // read_line, lines, stdout_lines, and stderr_lines
+    let (out, err) = (stdout.clone(), stderr.clone());
+    let pick = move |name: Option<String>| match name.as_deref() {
+        None | Some("stdout") => Ok(out.clone()),
+        Some("stderr") => Ok(err.clone()),
+        Some(name) => Err(LuaError::runtime(format!("unknown stream '{}'", name))),
+    };
+    let read_pick = pick.clone();
+    result.set(
+        "read_line",
+        lua.create_async_function(move |lua, (_, name): (LuaValue, Option<String>)| {
+            let output = read_pick(name);
+            async move { read_line(lua, output?).await }
+        })?,
+    )?;
+    let lines = lua.create_function(move |lua, (_, name): (LuaValue, Option<String>)| {
+        let output = pick(name)?;
+        lua.create_async_function(move |lua, _: LuaMultiValue| read_line(lua, output.clone()))
+    })?;
+    result.set("lines", &lines)?;
+    for name in ["stdout", "stderr"] {
+        let lines = lines.clone();
+        result.set(
+            format!("{}_lines", name),
+            lua.create_function(move |_, this: LuaValue| lines.call::<LuaFunction>((this, name)))?,
+        )?;
+    }
+
+    // expect, which waits for stdout to match a pattern, and send
+    let output = stdout.clone();
+    result.set(
+        "expect",
+        lua.create_async_function(
+            move |lua, (_, pattern, timeout): (LuaValue, LuaString, Option<Seconds>)| {
+                let output = output.clone();
+                expect(lua, output, pattern, timeout.map(Seconds::duration))
+            },
+        )?,
+    )?;
+    result.set(
+        "send",
+        lua.create_async_function(move |_, (_, data): (LuaValue, LuaString)| {
+            let stdin = stdin.clone();
+            async move { send(&stdin, &data.as_bytes()).await }
+        })?,
+    )?;
+
+    // stdout
+    result.set(
+        "stdout",
+        lua.create_async_function(move |lua, ()| read_output(lua, stdout.clone()))?,
+    )?;
+
+    // stderr
+    result.set(
+        "stderr",
+        lua.create_async_function(move |lua, ()| read_output(lua, stderr.clone()))?,
+    )?;
+
+    // logs
+    result.set(
+        "logs",
+        lua.create_function(move |_, count: Option<usize>| {
+            let recent = recent.lock().unwrap_or_else(|err| err.into_inner());
+            Ok(recent.lines(count))
+        })?,
+    )?;
+
+    // stop
+    let clone = child.clone();
+    result.set(
+        "stop",
+        lua.create_async_function(move |_, (_, grace): (LuaValue, Option<Seconds>)| {
+            let child = clone.clone();
+            async move {
+                let grace = grace.map_or(DEFAULT_GRACE, Seconds::duration);
+                let (status, _) = stop_child(&child, pid, grace).await?;
+                let code = status_code(status)?;
+                Ok(code)
+            }
+        })?,
+    )?;
+
+    // kill_group and kill_tree, which send SIGKILL by default
+    let clone = child.clone();
+    result.set(
+        "kill_group",
+        lua.create_async_function(move |_, (_, sig): (LuaValue, Option<i32>)| {
+            let child = clone.clone();
+            async move {
+                let sig = sig.unwrap_or(Signal::Kill as i32);
+                signal_group(&child, pid, sig).await?;
+                Ok(sig)
+            }
+        })?,
+    )?;
+    let clone = child.clone();
+    result.set(
+        "kill_tree",
+        lua.create_async_function(move |_, (_, sig): (LuaValue, Option<i32>)| {
+            let child = clone.clone();
+            async move { signal_tree(&child, pid, sig.unwrap_or(Signal::Kill as i32)).await }
+        })?,
+    )?;
+
+    // kill
+    let clone = child.clone();
+    result.set(
+        "kill",
+        lua.create_async_function(move |_, ()| {
+            let child = clone.clone();
+            async move {
+                child.write().await.kill()?;
+                Ok(Signal::Kill as i32)
+            }
+        })?,
+    )?;
+
+    Ok(result)
+}
+
+/// Shell which runs the command lines passed to `init.shell` by default
+const DEFAULT_SHELL: &str = "/bin/sh";
+
+/// Convert a command line or options table into the options which run it with `shell -c`
+fn shell_options(lua: &Lua, command: LuaValue) -> LuaResult<LuaTable> {
+    let exec_options = lua.create_table()?;
+    let (script, shell) = match command {
+        LuaValue::Table(options) => {
+            for pair in options.pairs::<LuaValue, LuaValue>() {
+                let (key, value) = pair?;
+                match &key {
+                    LuaValue::Integer(_) => {}
+                    LuaValue::String(name) if name == "shell" => {}
+                    _ => exec_options.set(key, value)?,
+                }
+            }
+            (options.get::<Option<String>>(1)?, options.get("shell")?)
+        }
+        command => (Option::<String>::from_lua(command, lua)?, None),
+    };
+    let script = script.ok_or_else(|| LuaError::runtime("missing command line to run"))?;
+    exec_options.push(shell.unwrap_or_else(|| DEFAULT_SHELL.to_string()))?;
+    exec_options.push("-c")?;
+    exec_options.push(script)?;
+    Ok(exec_options)
+}
+
+/// Asynchronously run a command line through a shell in Lua, like `exec`
+pub async fn shell(lua: Lua, command: LuaValue) -> LuaResult<LuaTable> {
+    let options = shell_options(&lua, command)?;
+    exec(lua, (LuaValue::Table(options), LuaMultiValue::new())).await
+}
+
+/// Execute commands with the stdout of each connected to the stdin of the next in Lua
+///
+/// Each command is a command name or an options table like `init.exec` takes,
+/// and the handles of the children are returned in order.
+pub async fn pipeline(lua: Lua, commands: LuaMultiValue) -> LuaResult<Vec<LuaTable>> {
+    if commands.is_empty() {
+        return Err(LuaError::runtime("a pipeline needs at least one command"));
+    }
+    let mut options = commands
+        .into_iter()
+        .map(|command| exec_options(&lua, command, LuaMultiValue::new()))
+        .collect::<LuaResult<Vec<_>>>()?;
+    for i in 1..options.len() {
+        let (reader, writer) = std::io::pipe()?;
+        options[i - 1].pipe_out = Some(writer);
+        options[i].pipe_in = Some(reader);
+    }
+    let mut children = Vec::new();
+    for options in options {
+        children.push(exec_with(lua.clone(), options).await?);
+    }
+    Ok(children)
+}
+
+/// Execute commands concurrently in Lua and wait for all of them to exit
+///
+/// All commands are started before any is waited for, and a command which fails
+/// to start kills the ones started before it. Each result holds the `status` of
+/// a command, `reason` if it timed out, and its `stdout` and `stderr`.
+pub async fn exec_all(lua: Lua, commands: LuaTable) -> LuaResult<Vec<LuaTable>> {
+    let commands = commands
+        .sequence_values::<LuaValue>()
+        .collect::<LuaResult<Vec<_>>>()?;
+    let mut children = Vec::new();
+    for command in commands {
+        let started = match exec_options(&lua, command, LuaMultiValue::new()) {
+            Ok(options) => exec_with(lua.clone(), options).await,
+            Err(err) => Err(err),
+        };
+        match started {
+            Ok(child) => children.push(child),
+            Err(err) => {
+                for child in &children {
+                    let kill = child.get::<LuaFunction>("kill")?;
+                    kill.call_async::<()>(()).await.ok();
+                }
+                return Err(err);
+            }
+        }
+    }
+    // output is collected in the background, so waiting in order waits as long as the slowest
+    let mut results = Vec::new();
+    for child in children {
+        let result = lua.create_table()?;
+        let stdout = child.get::<LuaFunction>("stdout")?;
+        result.set("stdout", stdout.call_async::<LuaValue>(()).await?)?;
+        let stderr = child.get::<LuaFunction>("stderr")?;
+        result.set("stderr", stderr.call_async::<LuaValue>(()).await?)?;
+        let status = child.get::<LuaFunction>("status_code")?;
+        let (code, reason) = status.call_async::<(i32, Option<String>)>(()).await?;
+        result.set("status", code)?;
+        result.set("reason", reason)?;
+        results.push(result);
+    }
+    Ok(results)
+}
+
+/// Wait until the first of several children exits in Lua, returning its handle and status
+///
+/// The statuses of the other children are still returned by their `status`.
+pub async fn wait_any(_lua: Lua, handles: LuaVariadic<LuaTable>) -> LuaResult<LuaMultiValue> {
+    if handles.is_empty() {
+        return Err(LuaError::runtime("wait_any needs at least one child"));
+    }
+    let mut waits = handles
+        .iter()
+        .map(|handle| handle.get::<LuaFunction>("status"))
+        .collect::<LuaResult<Vec<_>>>()?
+        .into_iter()
+        .map(|status| Box::pin(async move { status.call_async::<LuaMultiValue>(()).await }))
+        .collect::<Vec<_>>();
+    let (index, status) = smol::future::poll_fn(|cx| {
+        for (index, wait) in waits.iter_mut().enumerate() {
+            if let Poll::Ready(status) = wait.as_mut().poll(cx) {
+                return Poll::Ready((index, status));
+            }
+        }
+        Poll::Pending
+    })
+    .await;
+    let mut values = status?;
+    values.push_front(LuaValue::Table(handles[index].clone()));
+    Ok(values)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+
+    use super::*;
+
+    async fn test_setup_spawn() -> std::io::Result<Child> {
+        let spec = SpawnSpec {
+            path: "rustc".to_string(),
+            args: vec!["--version".to_string()],
+            ..Default::default()
+        };
+        spawn(
+            &spec,
+            Stdio::inherit(),
+            Stdio::piped(),
+            Stdio::piped(),
+            None,
+        )
+        .await
+    }
+
+    async fn test_setup_exec(lua: &Lua) -> LuaResult<LuaTable> {
+        let cmd = LuaValue::String(lua.create_string("rustc")?);
+        let args = LuaMultiValue::new();
+        exec(lua.clone(), (cmd, args)).await
+    }
+
+    #[test]
+    fn test_spawn() {
+        smol::block_on(async {
+            let mut child = test_setup_spawn().await.unwrap();
+            let status = child.status().await.unwrap();
+            assert!(status.success());
+        });
+    }
+
+    #[test]
+    fn test_lua_args() {
+        let lua = Lua::new();
+        let table = lua.create_table().unwrap();
+        table.set(1, "--version").unwrap();
+        let args = LuaMultiValue::from(vec![
+            LuaValue::String(lua.create_string("-v").unwrap()),
+            LuaValue::Table(table),
+            LuaValue::Integer(1),
+        ]);
+        assert_eq!(lua_args(args).unwrap(), vec!["-v", "--version", "1"]);
+    }
+
+    #[test]
+    fn test_spec_table_roundtrip() {
+        let lua = Lua::new();
+        let spec = SpawnSpec {
+            path: "/bin/echo".to_string(),
+            args: vec!["a".to_string()],
+            env: vec![("KEY".to_string(), "value".to_string())],
+            clear_env: true,
+            cwd: Some("/tmp".to_string()),
+            new_group: true,
+            setsid: false,
+            limits: vec![(4, 0), (7, 1024)],
+            nice: Some(5),
+            sched_policy: Some(3),
+            cpus: vec![0],
+            oom_score_adj: Some(100),
+            umask: Some(0o027),
+            root: Some("/srv/jail".to_string()),
+            cgroup: Some(
+                cgroup::placement_from_table(
+                    &lua.load("{ name = 'web', memory_max = '256M' }")
+                        .eval()
+                        .unwrap(),
+                )
+                .unwrap(),
+            ),
+            namespaces: unix::CLONE_NEWNS | unix::CLONE_NEWUTS,
+            caps: Some(1 << 10),
+            seccomp: seccomp::seccomp_option(
+                &lua,
+                &lua.load("{ seccomp = 'default' }").eval().unwrap(),
+            )
+            .unwrap(),
+            uid: Some(unix::getuid()),
+            gid: None,
+        };
+        let table = spec_table(&lua, &spec).unwrap();
+        assert_eq!(spec_from_table(&table).unwrap(), spec);
+    }
+
+    #[test]
+    fn test_spawn_hook() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            let spec = || SpawnSpec {
+                path: "/bin/echo".to_string(),
+                args: vec!["a".to_string()],
+                ..Default::default()
+            };
+            assert_eq!(apply_spawn_hook(&lua, spec()).await.unwrap(), spec());
+            let hook: LuaFunction = lua
+                .load(
+                    "return function(spec)
+                        if spec.args[1] == 'deny' then return false, 'not allowed' end
+                        spec.args = { 'b' }
+                        return spec
+                    end",
+                )
+                .eval()
+                .unwrap();
+            on_spawn(lua.clone(), Some(hook)).await.unwrap();
+            let modified = apply_spawn_hook(&lua, spec()).await.unwrap();
+            assert_eq!(modified.args, vec!["b"]);
+            let mut denied = spec();
+            denied.args = vec!["deny".to_string()];
+            let err = apply_spawn_hook(&lua, denied).await.unwrap_err();
+            assert!(err.to_string().contains("not allowed"));
+        });
+    }
+
+    #[test]
+    fn test_exec_options() {
+        let lua = Lua::new();
+        let options: LuaTable = lua.load("{ 'echo', 'a', { 'b' } }").eval().unwrap();
+        let extra = LuaMultiValue::from(vec![LuaValue::Integer(1)]);
+        let options = exec_options(&lua, LuaValue::Table(options), extra).unwrap();
+        assert_eq!(options.cmd, "echo");
+        assert_eq!(options.args.len(), 3);
+        assert!(options.cancel.is_none());
+        let empty = LuaValue::Table(lua.create_table().unwrap());
+        assert!(exec_options(&lua, empty, LuaMultiValue::new()).is_err());
+    }
+
+    #[test]
+    fn test_stdio_mode() {
+        assert_eq!(StdioMode::parse(None), StdioMode::Piped);
+        assert_eq!(StdioMode::parse(Some("piped".into())), StdioMode::Piped);
+        assert_eq!(StdioMode::parse(Some("inherit".into())), StdioMode::Inherit);
+        assert_eq!(StdioMode::parse(Some("null".into())), StdioMode::Null);
+        let log = StdioMode::parse(Some("/var/log/app.log".into()));
+        assert_eq!(log.log().as_deref(), Some("/var/log/app.log"));
+        assert_eq!(StdioMode::Null.log(), None);
+    }
+
+    #[test]
+    fn test_exec_stdio_null() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            let options: LuaTable = lua
+                .load("{ 'sh', '-c', 'echo out; echo err >&2', stdout = 'null' }")
+                .eval()
+                .unwrap();
+            let child = exec(
+                lua.clone(),
+                (LuaValue::Table(options), LuaMultiValue::new()),
+            )
+            .await
+            .unwrap();
+            let stdout = child.get::<LuaFunction>("stdout").unwrap();
+            let stderr = child.get::<LuaFunction>("stderr").unwrap();
+            assert_eq!(stdout.call_async::<Option<String>>(()).await.unwrap(), None);
+            assert_eq!(
+                stderr.call_async::<Option<String>>(()).await.unwrap(),
+                Some("err\n".to_string())
+            );
+        });
+    }
+
+    #[test]
+    fn test_exec_options_expand() {
+        let lua = Lua::new();
+        let options: LuaTable = lua
+            .load(
+                "{ '${BIN}', '${ARG:-x}', '${BIN}', env = { A = '${BIN}/a' },
+                   expand = { BIN = '/bin' } }",
+            )
+            .eval()
+            .unwrap();
+        let extra = LuaMultiValue::from(vec![LuaValue::Integer(1)]);
+        let options = exec_options(&lua, LuaValue::Table(options), extra).unwrap();
+        assert_eq!(options.cmd, "/bin");
+        assert_eq!(lua_args(options.args).unwrap(), vec!["x", "/bin", "1"]);
+        assert_eq!(options.env, vec![("A".to_string(), "/bin/a".to_string())]);
+        let options: LuaTable = lua.load("{ '${BIN}', expand = 1 }").eval().unwrap();
+        assert!(exec_options(&lua, LuaValue::Table(options), LuaMultiValue::new()).is_err());
+    }
+
+    #[test]
+    fn test_exec_env() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            let options: LuaTable = lua
+                .load("{ 'sh', '-c', 'echo $PLAIN $TOKEN', env = { PLAIN = 'a' } }")
+                .eval()
+                .unwrap();
+            let env: LuaTable = options.get("env").unwrap();
+            env.set("TOKEN", Secret::new("exec-env-secret")).unwrap();
+            let child = exec(
+                lua.clone(),
+                (LuaValue::Table(options), LuaMultiValue::new()),
+            )
+            .await
+            .unwrap();
+            let stdout = child.get::<LuaFunction>("stdout").unwrap();
+            let output = stdout.call_async::<String>(()).await.unwrap();
+            assert_eq!(output, "a exec-env-secret\n");
+        });
+    }
+
+    #[test]
+    fn test_exec_timeout() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            let init = lua.create_table().unwrap();
+            init.set("exec", lua.create_async_function(exec).unwrap())
+                .unwrap();
+            lua.globals().set("init", init).unwrap();
+            let result: (i32, Option<String>, i32, Option<String>) = lua
+                .load(
+                    "local slow = init.exec({ 'sleep', '30', timeout = 0.05 })
+                    local fast = init.exec({ 'true', timeout = 30 })
+                    local code, reason = slow:status_code()
+                    return code, reason, fast:status_code()",
+                )
+                .eval_async()
+                .await
+                .unwrap();
+            assert_eq!(result, (9, Some("timeout".to_string()), 0, None));
+        });
+    }
+
+    #[test]
+    fn test_exec_restart_if() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            let init = lua.create_table().unwrap();
+            init.set("exec", lua.create_async_function(exec).unwrap())
+                .unwrap();
+            lua.globals().set("init", init).unwrap();
+            let result: (i32, Option<String>, String, i32, Option<String>) = lua
+                .load(
+                    "local limit = { rss_over = '8M', interval = 0.02 }
+                    local leaky = init.exec({ 'sh', '-c', 'x=$(head -c 20000000 /dev/zero | tr \"\\\\0\" a); sleep 30', restart_if = limit })
+                    local small = init.exec({ 'sleep', '0.1', restart_if = limit })
+                    local code, reason = leaky:status_code()
+                    return code, reason, leaky:status().reason, small:status_code()",
+                )
+                .eval_async()
+                .await
+                .unwrap();
+            assert_eq!(
+                result,
+                (15, Some("rss_over".into()), "rss_over".into(), 0, None)
+            );
+            let options = lua
+                .load("{ restart_if = { interval = 1 } }")
+                .eval()
+                .unwrap();
+            assert!(restart_if_option(&options).is_err());
+        });
+    }
+
+    #[test]
+    fn test_exec_ready_fd() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            let init = lua.create_table().unwrap();
+            init.set("exec", lua.create_async_function(exec).unwrap())
+                .unwrap();
+            lua.globals().set("init", init).unwrap();
+            let result: (bool, bool, bool, bool) = lua
+                .load(
+                    "local ready = init.exec({ 'sh', '-c', 'sleep 0.05; echo >&3; sleep 30', ready_fd = 3 })
+                    local silent = init.exec({ 'sh', '-c', 'exec 3>&-; sleep 30', ready_fd = 3 })
+                    local exited = init.exec({ 'true', ready_fd = 4 })
+                    local results = { ready:wait_ready(), silent:wait_ready(0.05), exited:wait_ready(),
+                        pcall(init.exec({ 'true' }).wait_ready) }
+                    ready:kill() silent:kill()
+                    return unpack(results, 1, 4)",
+                )
+                .eval_async()
+                .await
+                .unwrap();
+            assert_eq!(result, (true, false, false, false));
+            let options = lua.load("{ ready_fd = 1 }").eval().unwrap();
+            assert!(ready_fd_option(&options).is_err());
+        });
+    }
+
+    #[test]
+    fn test_exec_stop() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            let init = lua.create_table().unwrap();
+            init.set("exec", lua.create_async_function(exec).unwrap())
+                .unwrap();
+            lua.globals().set("init", init).unwrap();
+            let codes: (i32, i32) = lua
+                .load(
+                    "local polite = init.exec({ 'sleep', '30' })
+                    local stubborn = init.exec({
+                        'sh', '-c', 'trap \"\" TERM; echo ready; sleep 30 & wait', stream = true
+                    })
+                    -- only stop the shell once it ignores SIGTERM
+                    assert(stubborn:read_line() == 'ready')
+                    return polite:stop(1), stubborn:stop(0.1)",
+                )
+                .eval_async()
+                .await
+                .unwrap();
+            assert_eq!(codes, (15, 9));
+        });
+    }
+
+    #[test]
+    fn test_exec_kill_tree() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            let init = lua.create_table().unwrap();
+            init.set("exec", lua.create_async_function(exec).unwrap())
+                .unwrap();
+            lua.globals().set("init", init).unwrap();
+            let (signalled, code, err): (usize, i32, String) = lua
+                .load(
+                    "local child = init.exec({
+                        'sh', '-c', 'sleep 30 & echo $!; wait', stream = true
+                    })
+                    local worker = tonumber(child:read_line())
+                    local ok, err = pcall(child.kill_group, child)
+                    local signalled = child:kill_tree()
+                    assert(worker > 0)
+                    return signalled, child:status_code(), tostring(err)",
+                )
+                .eval_async()
+                .await
+                .unwrap();
+            assert_eq!((signalled, code), (2, 9));
+            assert!(err.contains("process group of the supervisor"), "{}", err);
+        });
+    }
+
+    #[test]
+    fn test_exec_new_group() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            let init = lua.create_table().unwrap();
+            init.set("exec", lua.create_async_function(exec).unwrap())
+                .unwrap();
+            lua.globals().set("init", init).unwrap();
+            lua.globals()
+                .set(
+                    "getpgid",
+                    lua.create_function(|_, pid: i32| Ok(unix::getpgid(pid)?))
+                        .unwrap(),
+                )
+                .unwrap();
+            let result: (bool, bool, i32, i32) = lua
+                .load(
+                    "local group = init.exec({ 'sh', '-c', 'sleep 30 & wait', new_group = true })
+                    local session = init.exec({ 'sleep', '30', setsid = true })
+                    local grouped = getpgid(group:pid()) == group:pid()
+                    local led = getpgid(session:pid()) == session:pid()
+                    group:kill_group()
+                    session:kill_group(15)
+                    return grouped, led, group:status_code(), session:status_code()",
+                )
+                .eval_async()
+                .await
+                .unwrap();
+            assert_eq!(result, (true, true, 9, 15));
+        });
+    }
+
+    #[test]
+    fn test_exec_limits() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            let options: LuaTable = lua
+                .load("{ 'sh', '-c', 'ulimit -n; ulimit -c', limits = { nofile = 64, core = 0 } }")
+                .eval()
+                .unwrap();
+            let child = exec(
+                lua.clone(),
+                (LuaValue::Table(options), LuaMultiValue::new()),
+            )
+            .await
+            .unwrap();
+            let stdout = child.get::<LuaFunction>("stdout").unwrap();
+            let output = stdout.call_async::<String>(()).await.unwrap();
+            assert_eq!(output, "64\n0\n");
+        });
+    }
+
+    #[test]
+    fn test_exec_seccomp() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            let dir =
+                std::env::temp_dir().join(format!("luavisors-seccomp-{}", std::process::id()));
+            let options: LuaTable = lua
+                .load(format!(
+                    "{{ 'sh', '-c', 'ls / >/dev/null && echo allowed; mkdir {} 2>/dev/null || echo denied', seccomp = {{ syscalls = {{ 'mkdir', 'mkdirat' }} }} }}",
+                    dir.display()
+                ))
+                .eval()
+                .unwrap();
+            let child = exec(
+                lua.clone(),
+                (LuaValue::Table(options), LuaMultiValue::new()),
+            )
+            .await
+            .unwrap();
+            let stdout = child.get::<LuaFunction>("stdout").unwrap();
+            let output = stdout.call_async::<String>(()).await.unwrap();
+            assert!(!dir.exists());
+            assert_eq!(output, "allowed\ndenied\n");
+        });
+    }
+
+    #[test]
+    fn test_exec_nice() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            let options: LuaTable = lua
+                .load("{ 'sh', '-c', 'cut -d \" \" -f 19 /proc/self/stat', nice = 10, sched_policy = 'batch' }")
+                .eval()
+                .unwrap();
+            let child = exec(
+                lua.clone(),
+                (LuaValue::Table(options), LuaMultiValue::new()),
+            )
+            .await
+            .unwrap();
+            let stdout = child.get::<LuaFunction>("stdout").unwrap();
+            let output = stdout.call_async::<String>(()).await.unwrap();
+            assert_eq!(output, "10\n");
+        });
+    }
+
+    #[test]
+    fn test_exec_cpus() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            let options: LuaTable = lua
+                .load("{ 'sh', '-c', 'grep Cpus_allowed_list /proc/self/status', cpus = { 0 } }")
+                .eval()
+                .unwrap();
+            let child = exec(
+                lua.clone(),
+                (LuaValue::Table(options), LuaMultiValue::new()),
+            )
+            .await
+            .unwrap();
+            let stdout = child.get::<LuaFunction>("stdout").unwrap();
+            let output = stdout.call_async::<String>(()).await.unwrap();
+            assert_eq!(output.split_whitespace().last(), Some("0"));
+        });
+    }
+
+    #[test]
+    fn test_exec_oom_score_adj() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            let options: LuaTable = lua
+                .load("{ 'cat', '/proc/self/oom_score_adj', oom_score_adj = 900 }")
+                .eval()
+                .unwrap();
+            let child = exec(
+                lua.clone(),
+                (LuaValue::Table(options), LuaMultiValue::new()),
+            )
+            .await
+            .unwrap();
+            let stdout = child.get::<LuaFunction>("stdout").unwrap();
+            let output = stdout.call_async::<String>(()).await.unwrap();
+            assert_eq!(output, "900\n");
+        });
+    }
+
+    #[test]
+    fn test_exec_uid_with_privileged_options() {
+        // dropping privileges before the other steps only shows up as root
+        if unix::getuid() != 0 {
+            return;
+        }
+        smol::block_on(async {
+            let lua = Lua::new();
+            let options: LuaTable = lua
+                .load("{ 'sh', '-c', 'id -u; cut -d \" \" -f 19 /proc/self/stat', uid = 65534, gid = 65534, nice = -5 }")
+                .eval()
+                .unwrap();
+            let child = exec(
+                lua.clone(),
+                (LuaValue::Table(options), LuaMultiValue::new()),
+            )
+            .await
+            .unwrap();
+            let stdout = child.get::<LuaFunction>("stdout").unwrap();
+            let output = stdout.call_async::<String>(()).await.unwrap();
+            assert_eq!(output, "65534\n-5\n");
+        });
+    }
+
+    #[test]
+    fn test_umask_option() {
+        let lua = Lua::new();
+        let options: LuaTable = lua.load("{ umask = '027' }").eval().unwrap();
+        assert_eq!(umask_option(&options).unwrap(), Some(0o027));
+        let options: LuaTable = lua.load("{ umask = 18 }").eval().unwrap();
+        assert_eq!(umask_option(&options).unwrap(), Some(0o022));
+        assert_eq!(umask_option(&lua.create_table().unwrap()).unwrap(), None);
+        let options: LuaTable = lua.load("{ umask = '089' }").eval().unwrap();
+        assert!(umask_option(&options).is_err());
+        let options: LuaTable = lua.load("{ umask = 4096 }").eval().unwrap();
+        assert!(umask_option(&options).is_err());
+    }
 
-use async_signal::Signal;
-use mlua::prelude::*;
-use smol::{
-    io::AsyncReadExt,
-    lock::{Mutex, RwLock},
-    process::{Child, Stdio},
-    stream::StreamExt,
-};
+    #[test]
+    fn test_exec_umask() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            let options: LuaTable = lua
+                .load("{ 'sh', '-c', 'umask', umask = '027' }")
+                .eval()
+                .unwrap();
+            let child = exec(
+                lua.clone(),
+                (LuaValue::Table(options), LuaMultiValue::new()),
+            )
+            .await
+            .unwrap();
+            let stdout = child.get::<LuaFunction>("stdout").unwrap();
+            let output = stdout.call_async::<String>(()).await.unwrap();
+            assert_eq!(output, "0027\n");
+        });
+    }
 
-use crate::{errors::AppResult, unix};
+    #[test]
+    fn test_stop_pid() {
+        smol::block_on(async {
+            let child = std::process::Command::new("sleep")
+                .arg("30")
+                .spawn()
+                .unwrap();
+            let pid = child.id() as i32;
+            // reap in another thread so the pid disappears once it exits
+            let reaper = std::thread::spawn(move || {
+                let mut child = child;
+                child.wait().unwrap()
+            });
+            assert!(stop_pid(pid, Duration::from_secs(1)).await.unwrap());
+            assert_eq!(reaper.join().unwrap().signal(), Some(15));
+            assert!(stop_pid(pid, Duration::ZERO).await.is_err());
+        });
+    }
 
-/// Forward signals to the child process
-async fn forward_signals(child: Arc<RwLock<Child>>) -> AppResult<()> {
-    let pid = child.read().await.id() as i32;
-    let mut signals = unix::signal_wait().await?;
-    while let Some(signal) = signals.next().await {
-        let sig = signal? as i32;
-        unix::kill(pid, sig).await?;
+    #[test]
+    fn test_stop_invalid_pid() {
+        smol::block_on(async {
+            let own = std::process::id() as i32;
+            for pid in [-1, 0, own] {
+                let result = stop(Lua::new(), (pid, Some(Seconds(0.0)))).await;
+                assert!(result.is_err(), "{}", pid);
+            }
+        });
     }
-    Ok(())
-}
 
-/// Spawn a new process asynchronously
-async fn spawn<S, I>(program: S, args: I) -> std::io::Result<Child>
-where
-    S: AsRef<OsStr>,
-    I: IntoIterator<Item = S>,
-{
-    let mut cmd = smol::process::Command::new(&program);
-    cmd.args(args).stdout(Stdio::piped()).stderr(Stdio::piped());
-    cmd.spawn()
-}
+    #[test]
+    fn test_shell_options() {
+        let lua = Lua::new();
+        let command = LuaValue::String(lua.create_string("echo hi | wc -c").unwrap());
+        let options = shell_options(&lua, command).unwrap();
+        let values: Vec<String> = options.sequence_values().collect::<LuaResult<_>>().unwrap();
+        assert_eq!(values, vec!["/bin/sh", "-c", "echo hi | wc -c"]);
+        let table: LuaTable = lua
+            .load("{ 'echo $0', shell = 'bash', stream = true }")
+            .eval()
+            .unwrap();
+        let options = shell_options(&lua, LuaValue::Table(table)).unwrap();
+        let values: Vec<String> = options.sequence_values().collect::<LuaResult<_>>().unwrap();
+        assert_eq!(values, vec!["bash", "-c", "echo $0"]);
+        assert!(options.get::<bool>("stream").unwrap());
+        assert!(options.get::<Option<String>>("shell").unwrap().is_none());
+        let empty = LuaValue::Table(lua.create_table().unwrap());
+        assert!(shell_options(&lua, empty).is_err());
+    }
 
-/// Spawn a new process from Lua
-async fn lua_spawn(_lua: &Lua, cmd: String, args: LuaMultiValue) -> LuaResult<Child> {
-    let mut vargs = Vec::new();
-    for arg in args {
-        match arg {
-            LuaValue::Table(t) => vargs.extend(
-                t.sequence_values::<String>()
-                    .collect::<LuaResult<Vec<_>>>()?,
-            ),
-            _ => vargs.push(arg.to_string()?),
-        }
+    #[test]
+    fn test_shell() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            let command = LuaValue::String(lua.create_string("echo a b | tr ' ' -").unwrap());
+            let child = shell(lua.clone(), command).await.unwrap();
+            let stdout = child.get::<LuaFunction>("stdout").unwrap();
+            let output = stdout.call_async::<String>(()).await.unwrap();
+            assert_eq!(output, "a-b\n");
+        });
     }
-    Ok(spawn(cmd, vargs).await?)
-}
 
-/// Spawn a task to read from a stream
-async fn spawn_stream_task(
-    stream: Option<impl AsyncReadExt + Unpin + Send + 'static>,
-) -> Arc<Mutex<Option<smol::Task<std::io::Result<Vec<u8>>>>>> {
-    let task = stream.map(|mut stream| {
-        smol::spawn(async move {
-            let mut data = Vec::new();
-            stream.read_to_end(&mut data).await?;
-            Ok(data)
-        })
-    });
-    Arc::new(Mutex::new(task))
-}
+    #[test]
+    fn test_exec_cwd() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            let dir = std::env::temp_dir().join(format!("luavisors-cwd-{}", std::process::id()));
+            std::fs::create_dir_all(dir.join("bin")).unwrap();
+            let script = dir.join("bin/where");
+            std::fs::write(&script, "#!/bin/sh\npwd\n").unwrap();
+            std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+            let options: LuaTable = lua.load("{ './bin/where' }").eval().unwrap();
+            options.set("cwd", dir.display().to_string()).unwrap();
+            let child = exec(
+                lua.clone(),
+                (LuaValue::Table(options), LuaMultiValue::new()),
+            )
+            .await
+            .unwrap();
+            let stdout = child.get::<LuaFunction>("stdout").unwrap();
+            let output = stdout.call_async::<String>(()).await.unwrap();
+            let expected = dir.canonicalize().unwrap();
+            std::fs::remove_dir_all(&dir).unwrap();
+            assert_eq!(Path::new(output.trim()), expected);
+        });
+    }
 
-/// Read a stream into a Lua string
-async fn read_stream_task(
-    lua: Lua,
-    task: Arc<Mutex<Option<smol::Task<std::io::Result<Vec<u8>>>>>>,
-) -> LuaResult<LuaValue> {
-    let task = task.lock().await.take().ok_or_else(|| {
-        std::io::Error::new(std::io::ErrorKind::InvalidInput, "stream already consumed")
-    })?;
-    let data = task.await?;
-    if data.is_empty() {
-        return Ok(LuaValue::Nil);
+    #[test]
+    fn test_exec_clear_env() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            let options: LuaTable = lua
+                .load("{ 'sh', '-c', 'echo \"$HOME,$PLAIN\"', env = { PLAIN = 'a' }, clear_env = true }")
+                .eval()
+                .unwrap();
+            let child = exec(
+                lua.clone(),
+                (LuaValue::Table(options), LuaMultiValue::new()),
+            )
+            .await
+            .unwrap();
+            let stdout = child.get::<LuaFunction>("stdout").unwrap();
+            let output = stdout.call_async::<String>(()).await.unwrap();
+            assert_eq!(output, ",a\n");
+        });
     }
-    Ok(LuaValue::String(lua.create_string(&data)?))
-}
 
-/// Asynchronously execute a command in Lua
-pub async fn exec(lua: Lua, (cmd, args): (String, LuaMultiValue)) -> LuaResult<LuaTable> {
-    let mut child = lua_spawn(&lua, cmd, args).await?;
+    #[test]
+    fn test_exec_log() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            let path = std::env::temp_dir().join(format!("luavisors-exec-{}", std::process::id()));
+            let options: LuaTable = lua.load("{ 'echo', 'logged' }").eval().unwrap();
+            options.set("stdout", path.display().to_string()).unwrap();
+            let child = exec(
+                lua.clone(),
+                (LuaValue::Table(options), LuaMultiValue::new()),
+            )
+            .await
+            .unwrap();
+            // the redirected output is fully written once stdout returns
+            let stdout = child.get::<LuaFunction>("stdout").unwrap();
+            assert!(stdout
+                .call_async::<Option<String>>(())
+                .await
+                .unwrap()
+                .is_none());
+            let contents = std::fs::read_to_string(&path).unwrap();
+            std::fs::remove_file(&path).unwrap();
+            assert_eq!(contents, "logged\n");
+        });
+    }
 
-    let stdout = spawn_stream_task(child.stdout.take()).await;
-    let stderr = spawn_stream_task(child.stderr.take()).await;
+    #[test]
+    fn test_exec_logs() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            let options: LuaTable = lua
+                .load("{ 'sh', '-c', 'echo one; echo two; echo three', recent = 10 }")
+                .eval()
+                .unwrap();
+            let child = exec(
+                lua.clone(),
+                (LuaValue::Table(options), LuaMultiValue::new()),
+            )
+            .await
+            .unwrap();
+            let stdout = child.get::<LuaFunction>("stdout").unwrap();
+            stdout.call_async::<String>(()).await.unwrap();
+            let logs = child.get::<LuaFunction>("logs").unwrap();
+            // only the last 10 bytes are kept
+            assert_eq!(logs.call::<Vec<String>>(()).unwrap(), vec!["two", "three"]);
+            assert_eq!(logs.call::<Vec<String>>(1).unwrap(), vec!["three"]);
+        });
+    }
 
-    let child = Arc::new(RwLock::new(child));
+    #[test]
+    fn test_exec_log_splice() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            let path =
+                std::env::temp_dir().join(format!("luavisors-splice-{}", std::process::id()));
+            let options: LuaTable = lua
+                .load("{ 'sh', '-c', 'echo out; echo err >&2', recent = 0 }")
+                .eval()
+                .unwrap();
+            options.set("stdout", path.display().to_string()).unwrap();
+            options.set("stderr", path.display().to_string()).unwrap();
+            let child = exec(
+                lua.clone(),
+                (LuaValue::Table(options), LuaMultiValue::new()),
+            )
+            .await
+            .unwrap();
+            for stream in ["stdout", "stderr"] {
+                let read = child.get::<LuaFunction>(stream).unwrap();
+                assert!(read
+                    .call_async::<Option<String>>(())
+                    .await
+                    .unwrap()
+                    .is_none());
+            }
+            let logs = child.get::<LuaFunction>("logs").unwrap();
+            assert!(logs.call::<Vec<String>>(()).unwrap().is_empty());
+            let mut lines: Vec<String> = std::fs::read_to_string(&path)
+                .unwrap()
+                .lines()
+                .map(str::to_string)
+                .collect();
+            std::fs::remove_file(&path).unwrap();
+            lines.sort();
+            assert_eq!(lines, vec!["err", "out"]);
+        });
+    }
 
-    smol::spawn(forward_signals(child.clone())).detach();
+    #[test]
+    fn test_exec_stream() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            let init = lua.create_table().unwrap();
+            init.set("exec", lua.create_async_function(exec).unwrap())
+                .unwrap();
+            lua.globals().set("init", init).unwrap();
+            let lines: Vec<String> = lua
+                .load(
+                    "local child = init.exec({ 'sh', '-c', 'seq 3; echo err >&2; echo more >&2', stream = true })
+                    local lines = {}
+                    for line in child:lines() do lines[#lines + 1] = line end
+                    lines[#lines + 1] = child:read_line('stderr')
+                    for line in child:stderr_lines() do lines[#lines + 1] = line end
+                    return lines",
+                )
+                .eval_async()
+                .await
+                .unwrap();
+            assert_eq!(lines, vec!["1", "2", "3", "err", "more"]);
+        });
+    }
 
-    let result = lua.create_table()?;
+    #[test]
+    fn test_exec_expect() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            let init = lua.create_table().unwrap();
+            init.set("exec", lua.create_async_function(exec).unwrap())
+                .unwrap();
+            lua.globals().set("init", init).unwrap();
+            let results: Vec<String> = lua
+                .load(
+                    "local script = 'printf \"Password: \"; read pw; echo \"got $pw\"; printf \"Continue? [y/n] \"; read a; echo \"answer $a\"'
+                    local child = init.exec({ 'sh', '-c', script, stdin = 'piped', stream = true })
+                    local results = { child:expect('Password: ', 5) }
+                    child:send('secret\\n')
+                    results[#results + 1] = child:expect('got (%w+)', 5)
+                    child:expect('%[y/n%] ', 5)
+                    child:send('y\\n')
+                    results[#results + 1] = child:read_line()
+                    results[#results + 1] = select(2, child:expect('never', 5))
+                    local sleeper = init.exec({ 'sleep', '5', stream = true })
+                    results[#results + 1] = select(2, sleeper:expect('never', 0.05))
+                    sleeper:kill()
+                    results[#results + 1] = tostring(select(2, pcall(sleeper.send, sleeper, 'x')))
+                    return results",
+                )
+                .eval_async()
+                .await
+                .unwrap();
+            assert_eq!(
+                results[..5],
+                ["Password: ", "secret", "answer y", "eof", "timeout"]
+            );
+            assert!(results[5].contains("stdin = 'piped'"), "{}", results[5]);
+        });
+    }
 
-    // pid
-    let clone = child.clone();
-    result.set(
-        "pid",
-        lua.create_async_function(move |_, ()| {
-            let child = clone.clone();
-            async move { Ok(child.read().await.id()) }
-        })?,
-    )?;
+    #[test]
+    fn test_exec_expect_window() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            let init = lua.create_table().unwrap();
+            init.set("exec", lua.create_async_function(exec).unwrap())
+                .unwrap();
+            lua.globals().set("init", init).unwrap();
+            let results: (String, String) = lua
+                .load(
+                    "local script = 'printf A; head -c 200000 /dev/zero | tr -c x x; printf B; echo DONE'
+                    local spanning = init.exec({ 'sh', '-c', script, stream = true })
+                    local _, reason = spanning:expect('Ax+B', 5)
+                    local tail = init.exec({ 'sh', '-c', script, stream = true })
+                    return reason, tail:expect('B(%u+)', 5)",
+                )
+                .eval_async()
+                .await
+                .unwrap();
+            // a match which spans more than the window is not found
+            assert_eq!(results, ("eof".into(), "DONE".into()));
+        });
+    }
 
-    // status
-    let clone = child.clone();
-    result.set(
-        "status",
-        lua.create_async_function(move |_, ()| {
-            let child = clone.clone();
-            async move {
-                let status = child.write().await.status().await?;
-                let code = status
-                    .signal()
-                    .or_else(|| status.code())
-                    .ok_or(LuaError::runtime("failed to get status code"))?;
-                Ok(code)
-            }
-        })?,
-    )?;
+    #[test]
+    fn test_pipeline() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            let commands = lua
+                .load("{ 'seq', '1000' }, { 'sh', '-c', 'grep 7; echo err >&2' }, { 'wc', '-l' }")
+                .eval::<LuaMultiValue>()
+                .unwrap();
+            let children = pipeline(lua.clone(), commands).await.unwrap();
+            let stdout = |child: &LuaTable| child.get::<LuaFunction>("stdout").unwrap();
+            let output = stdout(&children[2]).call_async::<String>(()).await.unwrap();
+            assert_eq!(output.trim(), "271");
+            let output = stdout(&children[1]).call_async::<Option<String>>(()).await;
+            assert_eq!(output.unwrap(), None);
+            let stderr = children[1].get::<LuaFunction>("stderr").unwrap();
+            assert_eq!(stderr.call_async::<String>(()).await.unwrap(), "err\n");
+            assert!(pipeline(lua, LuaMultiValue::new()).await.is_err());
+        });
+    }
 
-    // stdout
-    result.set(
-        "stdout",
-        lua.create_async_function(move |lua, ()| {
-            let task = stdout.clone();
-            async move { read_stream_task(lua, task).await }
-        })?,
-    )?;
+    #[test]
+    fn test_exec_all() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            let commands = lua
+                .load("{ { 'sh', '-c', 'sleep 0.2; echo slow' }, { 'sh', '-c', 'echo fast >&2; exit 3' }, { 'sleep', '5', timeout = 0.1 } }")
+                .eval::<LuaTable>()
+                .unwrap();
+            let started = std::time::Instant::now();
+            let results = exec_all(lua.clone(), commands).await.unwrap();
+            assert!(started.elapsed() < Duration::from_secs(2));
+            let get = |i: usize, key: &str| results[i].get::<Option<String>>(key).unwrap();
+            assert_eq!(
+                (get(0, "status"), get(0, "stdout")),
+                (Some("0".into()), Some("slow\n".into()))
+            );
+            assert_eq!(
+                (get(1, "status"), get(1, "stderr")),
+                (Some("3".into()), Some("fast\n".into()))
+            );
+            assert_eq!(get(2, "reason"), Some("timeout".into()));
+            let commands = lua
+                .load("{ { 'sleep', '5' }, { 'luavisors-missing-command' } }")
+                .eval::<LuaTable>()
+                .unwrap();
+            assert!(exec_all(lua, commands).await.is_err());
+        });
+    }
 
-    // stderr
-    result.set(
-        "stderr",
-        lua.create_async_function(move |lua, ()| {
-            let task = stderr.clone();
-            async move { read_stream_task(lua, task).await }
-        })?,
-    )?;
+    #[test]
+    fn test_wait_any() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            let init = lua.create_table().unwrap();
+            init.set("exec", lua.create_async_function(exec).unwrap())
+                .unwrap();
+            init.set("wait_any", lua.create_async_function(wait_any).unwrap())
+                .unwrap();
+            lua.globals().set("init", init).unwrap();
+            let (first, code, second): (bool, i32, i32) = lua
+                .load(
+                    "local slow = init.exec({ 'sh', '-c', 'sleep 0.3; exit 1' })
+                    local fast = init.exec({ 'sh', '-c', 'sleep 0.05; exit 2' })
+                    local done, status = init.wait_any(slow, fast)
+                    return done == fast, status.code, slow:status_code()",
+                )
+                .eval_async()
+                .await
+                .unwrap();
+            assert_eq!((first, code, second), (true, 2, 1));
+            assert!(wait_any(lua, LuaVariadic::new()).await.is_err());
+        });
+    }
 
-    // kill
-    let clone = child.clone();
-    result.set(
-        "kill",
-        lua.create_async_function(move |_, ()| {
-            let child = clone.clone();
-            async move {
-                child.write().await.kill()?;
-                Ok(Signal::Kill as i32)
+    #[test]
+    fn test_exec_try_status() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            let options: LuaTable = lua
+                .load("{ 'sh', '-c', 'sleep 0.1; exit 4' }")
+                .eval()
+                .unwrap();
+            let child = exec(
+                lua.clone(),
+                (LuaValue::Table(options), LuaMultiValue::new()),
+            )
+            .await
+            .unwrap();
+            let try_status = child.get::<LuaFunction>("try_status").unwrap();
+            assert_eq!(try_status.call::<Option<i32>>(&child).unwrap(), None);
+            let status = child.get::<LuaFunction>("status_code").unwrap();
+            assert_eq!(status.call_async::<i32>(()).await.unwrap(), 4);
+            assert_eq!(try_status.call::<Option<i32>>(&child).unwrap(), Some(4));
+        });
+    }
+
+    #[test]
+    fn test_exec_rusage() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            let init = lua.create_table().unwrap();
+            init.set("exec", lua.create_async_function(exec).unwrap())
+                .unwrap();
+            lua.globals().set("init", init).unwrap();
+            let (running, max_rss, user_time): (bool, i64, f64) = lua
+                .load(
+                    "local child = init.exec({ 'sh', '-c', 'i=0; while [ $i -lt 20000 ]; do i=$((i+1)); done' })
+                    local running = child:rusage() == nil
+                    child:status()
+                    local usage = child:rusage()
+                    return running, usage.max_rss, usage.user_time + usage.system_time",
+                )
+                .eval_async()
+                .await
+                .unwrap();
+            assert!(running);
+            assert!(max_rss > 0);
+            assert!(user_time > 0.0);
+        });
+    }
+
+    #[test]
+    fn test_exec_stats() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            let init = lua.create_table().unwrap();
+            init.set("exec", lua.create_async_function(exec).unwrap())
+                .unwrap();
+            lua.globals().set("init", init).unwrap();
+            let (rss, threads, exited): (i64, u32, bool) = lua
+                .load(
+                    "local child = init.exec({ 'sleep', '30' })
+                    local stats = child:stats()
+                    child:kill()
+                    child:status()
+                    return stats.rss, stats.threads, child:stats() == nil",
+                )
+                .eval_async()
+                .await
+                .unwrap();
+            assert!(rss > 0);
+            assert_eq!(threads, 1);
+            assert!(exited);
+        });
+    }
+
+    #[test]
+    fn test_reap_orphans() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            let child = test_setup_exec(&lua).await.unwrap();
+            let pid = child.get::<LuaFunction>("pid").unwrap();
+            let pid: i32 = pid.call_async(()).await.unwrap();
+            smol::Timer::after(Duration::from_millis(100)).await;
+            assert!(reap_orphans(&[pid]).is_empty());
+            let status = child.get::<LuaFunction>("status_code").unwrap();
+            assert_eq!(status.call_async::<i32>(()).await.unwrap(), 0);
+        });
+    }
+
+    #[test]
+    fn test_exec_status_wait() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            let options: LuaTable = lua
+                .load("{ 'sh', '-c', 'sleep 0.2; exit 5' }")
+                .eval()
+                .unwrap();
+            let child = exec(
+                lua.clone(),
+                (LuaValue::Table(options), LuaMultiValue::new()),
+            )
+            .await
+            .unwrap();
+            let status = child.get::<LuaFunction>("status_code").unwrap();
+            let code = status
+                .call_async::<Option<i32>>((&child, 0.01))
+                .await
+                .unwrap();
+            assert_eq!(code, None);
+            let code = status.call_async::<Option<i32>>((&child, 5)).await.unwrap();
+            assert_eq!(code, Some(5));
+        });
+    }
+
+    #[test]
+    fn test_stdin_mode() {
+        assert_eq!(StdioMode::parse_stdin(None).unwrap(), StdioMode::Inherit);
+        assert_eq!(
+            StdioMode::parse_stdin(Some("piped".into())).unwrap(),
+            StdioMode::Piped
+        );
+        assert!(StdioMode::parse_stdin(Some("/var/log/in".into())).is_err());
+    }
+
+    #[test]
+    fn test_exec_read_line_err() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            let child = test_setup_exec(&lua).await.unwrap();
+            let read_line = child.get::<LuaFunction>("read_line").unwrap();
+            let err = read_line
+                .call_async::<Option<String>>(&child)
+                .await
+                .unwrap_err();
+            assert!(err.to_string().contains("stream = true"));
+        });
+    }
+
+    async fn test_sleeper(script: &str) -> Arc<RwLock<Child>> {
+        let spec = SpawnSpec {
+            path: "sh".to_string(),
+            args: vec!["-c".to_string(), script.to_string()],
+            ..Default::default()
+        };
+        Arc::new(RwLock::new(
+            spawn(
+                &spec,
+                Stdio::inherit(),
+                Stdio::piped(),
+                Stdio::piped(),
+                None,
+            )
+            .await
+            .unwrap(),
+        ))
+    }
+
+    #[test]
+    fn test_exit_policy() {
+        let lua = Lua::new();
+        let options: LuaTable = lua.load("{ timeout = 2, signal = 15 }").eval().unwrap();
+        let policy = exit_policy(&options).unwrap();
+        assert_eq!(
+            policy,
+            ExitPolicy {
+                timeout: Duration::from_secs(2),
+                signal: Some(15),
             }
-        })?,
-    )?;
+        );
+        let policy = exit_policy(&lua.create_table().unwrap()).unwrap();
+        assert_eq!(policy.timeout, Duration::from_secs(10));
+        assert!(policy.signal.is_none());
+    }
 
-    Ok(result)
-}
+    #[test]
+    fn test_finish() {
+        smol::block_on(async {
+            let children = vec![
+                test_sleeper("exit 0").await,
+                test_sleeper("sleep 30").await,
+                test_sleeper("trap '' TERM; sleep 30").await,
+            ];
+            let policy = ExitPolicy {
+                timeout: Duration::from_millis(200),
+                signal: Some(Signal::Term as i32),
+            };
+            let start = std::time::Instant::now();
+            finish(&children, policy).await;
+            assert!(start.elapsed() < Duration::from_secs(10));
+            for child in &children {
+                assert!(matches!(child.write().await.try_status(), Ok(Some(_))));
+            }
+        });
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[test]
+    fn test_finish_without_signal() {
+        smol::block_on(async {
+            let children = vec![test_sleeper("sleep 30").await];
+            let policy = ExitPolicy {
+                timeout: Duration::from_millis(50),
+                signal: None,
+            };
+            finish(&children, policy).await;
+            let mut child = children[0].write().await;
+            assert!(matches!(child.try_status(), Ok(None)));
+            child.kill().unwrap();
+        });
+    }
 
-    async fn test_setup_spawn() -> std::io::Result<Child> {
-        spawn("rustc", ["--version"]).await
+    #[test]
+    fn test_exit_code() {
+        assert_eq!(exit_code(ExitStatus::from_raw(0)), 0);
+        assert_eq!(exit_code(ExitStatus::from_raw(3 << 8)), 3);
+        assert_eq!(exit_code(ExitStatus::from_raw(Signal::Term as i32)), 143);
     }
 
-    async fn test_setup_exec(lua: &Lua) -> LuaResult<LuaTable> {
-        let cmd = "rustc".to_string();
-        let args = LuaMultiValue::new();
-        exec(lua.clone(), (cmd, args)).await
+    #[test]
+    fn test_exec_main() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            let options: LuaTable = lua
+                .load("{ 'sh', '-c', 'exit 3', main = true }")
+                .eval()
+                .unwrap();
+            let value = LuaValue::Table(options.clone());
+            exec(lua.clone(), (value, LuaMultiValue::new()))
+                .await
+                .unwrap();
+            let value = LuaValue::Table(options);
+            let err = exec(lua.clone(), (value, LuaMultiValue::new())).await;
+            assert!(err.is_err());
+            assert_eq!(main_exit_code().await.unwrap(), Some(3));
+        });
     }
 
     #[test]
-    fn test_spawn() {
+    fn test_exec_verify() {
         smol::block_on(async {
-            let mut child = test_setup_spawn().await.unwrap();
-            let status = child.status().await.unwrap();
-            assert!(status.success());
+            let lua = Lua::new();
+            let options: LuaTable = lua
+                .load("{ 'true', verify = { sha256 = '00' } }")
+                .eval()
+                .unwrap();
+            let result = exec(
+                lua.clone(),
+                (LuaValue::Table(options), LuaMultiValue::new()),
+            )
+            .await;
+            let err = result.unwrap_err();
+            assert!(err.to_string().contains("refusing to run"));
         });
     }
 
     #[test]
-    fn test_lua_spawn() {
+    fn test_exec_cancelled() {
         smol::block_on(async {
             let lua = Lua::new();
-            let cmd = "rustc".to_string();
-            let args = LuaMultiValue::from(vec![LuaValue::String(
-                lua.create_string("--version").unwrap(),
-            )]);
-            let mut child = lua_spawn(&lua, cmd, args).await.unwrap();
-            let status = child.status().await.unwrap();
-            assert!(status.success());
+            let token = CancelToken::default();
+            let options: LuaTable = lua.load("{ 'sleep', '60' }").eval().unwrap();
+            options.set("cancel", token.clone()).unwrap();
+            let table = exec(
+                lua.clone(),
+                (LuaValue::Table(options), LuaMultiValue::new()),
+            )
+            .await
+            .unwrap();
+            token.cancel();
+            let status = table.get::<LuaFunction>("status_code").unwrap();
+            assert_eq!(
+                status.call_async::<i32>(()).await.unwrap(),
+                Signal::Term as i32
+            );
         });
     }
 
     #[test]
-    fn test_lua_spawn_with_table() {
+    fn test_exec_deadline() {
         smol::block_on(async {
             let lua = Lua::new();
-            let cmd = "rustc".to_string();
-            let table = lua.create_table().unwrap();
-            table.set(1, "--version").unwrap();
-            let args = LuaMultiValue::from(vec![LuaValue::Table(table)]);
-            let mut child = lua_spawn(&lua, cmd, args).await.unwrap();
-            let status = child.status().await.unwrap();
-            assert!(status.success());
+            let exec = lua.create_async_function(exec).unwrap();
+            lua.globals().set("exec", exec).unwrap();
+            let func: LuaFunction = lua
+                .load(
+                    "return function() child = exec({ 'sleep', '60' }); return child:status() end",
+                )
+                .eval()
+                .unwrap();
+            let args = (Seconds(0.05), func, LuaMultiValue::new());
+            let result = crate::flow::deadline(lua.clone(), args).await;
+            assert!(result.unwrap_err().to_string().contains("DeadlineExceeded"));
+            // the child is terminated along with the call
+            let child: LuaTable = lua.globals().get("child").unwrap();
+            let status = child.get::<LuaFunction>("status_code").unwrap();
+            assert_eq!(
+                status.call_async::<i32>(()).await.unwrap(),
+                Signal::Term as i32
+            );
         });
     }
 
@@ -289,10 +3077,29 @@ mod tests {
             let lua = Lua::new();
             let table = test_setup_exec(&lua).await.unwrap();
             let status = table.get::<LuaFunction>("status").unwrap();
-            assert!(status.call_async::<i32>(()).await.is_ok());
+            let status = status.call_async::<LuaTable>(()).await.unwrap();
+            assert_eq!(status.get::<Option<i32>>("code").unwrap(), Some(0));
+            assert_eq!(status.get::<Option<i32>>("signal").unwrap(), None);
+            assert!(status.get::<bool>("success").unwrap());
+            assert!(!status.get::<bool>("core_dumped").unwrap());
+            let status_code = table.get::<LuaFunction>("status_code").unwrap();
+            assert_eq!(status_code.call_async::<i32>(()).await.unwrap(), 0);
         });
     }
 
+    #[test]
+    fn test_status_table() {
+        let lua = Lua::new();
+        let status = status_table(&lua, ExitStatus::from_raw(9), Some("timeout")).unwrap();
+        assert_eq!(status.get::<Option<i32>>("code").unwrap(), None);
+        assert_eq!(status.get::<Option<i32>>("signal").unwrap(), Some(9));
+        assert!(!status.get::<bool>("success").unwrap());
+        assert!(status.get::<bool>("timeout").unwrap());
+        let status = status_table(&lua, ExitStatus::from_raw(3 << 8), None).unwrap();
+        assert_eq!(status.get::<Option<i32>>("code").unwrap(), Some(3));
+        assert_eq!(status.get::<Option<String>>("reason").unwrap(), None);
+    }
+
     #[test]
     fn test_exec_stdout() {
         smol::block_on(async {
@@ -0,0 +1,165 @@
+use std::{
+    collections::HashMap,
+    sync::{Mutex, OnceLock},
+};
+
+use mlua::prelude::*;
+
+/// Lua instructions run between samples by default
+const DEFAULT_INTERVAL: u32 = 1000;
+
+/// Deepest call stack recorded by a sample
+const MAX_DEPTH: usize = 64;
+
+/// Number of samples taken of each folded call stack, while profiling
+fn samples() -> &'static Mutex<Option<HashMap<String, u64>>> {
+    static SAMPLES: OnceLock<Mutex<Option<HashMap<String, u64>>>> = OnceLock::new();
+    SAMPLES.get_or_init(Mutex::default)
+}
+
+/// Describe a stack frame as its function name and where it is defined
+fn frame_name(debug: &mlua::Debug) -> String {
+    let source = debug.source();
+    let name = debug.names().name.map(|name| name.into_owned());
+    let name = name.as_deref().unwrap_or("?");
+    match source.what {
+        "main" => format!("main ({})", source.short_src.unwrap_or_default()),
+        "C" => name.to_string(),
+        _ => format!(
+            "{} ({}:{})",
+            name,
+            source.short_src.unwrap_or_default(),
+            source.line_defined.unwrap_or(0)
+        ),
+    }
+}
+
+/// Return the current call stack folded into `outer;inner` form
+fn folded_stack(lua: &Lua) -> String {
+    let mut frames = Vec::new();
+    for level in 0..MAX_DEPTH {
+        match lua.inspect_stack(level, frame_name) {
+            Some(frame) => frames.push(frame),
+            None => break,
+        }
+    }
+    frames.reverse();
+    frames.join(";")
+}
+
+/// Format samples as folded stacks which flamegraph tools read, one stack per line
+fn report(samples: &HashMap<String, u64>) -> String {
+    let mut lines: Vec<_> = samples
+        .iter()
+        .map(|(stack, count)| format!("{} {}\n", stack, count))
+        .collect();
+    lines.sort();
+    lines.concat()
+}
+
+/// Turn the JIT compiler on or off, since compiled traces skip instruction hooks
+fn set_jit(lua: &Lua, on: bool) -> LuaResult<()> {
+    let loaded = lua
+        .globals()
+        .get::<LuaTable>("package")?
+        .get::<LuaTable>("loaded")?;
+    if let Some(jit) = loaded.get::<Option<LuaTable>>("jit")? {
+        jit.get::<LuaFunction>("flush")?.call::<()>(())?;
+        let name = if on { "on" } else { "off" };
+        jit.get::<LuaFunction>(name)?.call::<()>(())?;
+    }
+    Ok(())
+}
+
+/// Start sampling Lua call stacks every `interval` instructions
+///
+/// Code runs without the JIT compiler while profiling so every instruction is counted.
+async fn start(lua: Lua, options: Option<LuaTable>) -> LuaResult<()> {
+    let interval = match options {
+        Some(options) => options.get::<Option<u32>>("interval")?,
+        None => None,
+    }
+    .unwrap_or(DEFAULT_INTERVAL)
+    .max(1);
+    {
+        let mut samples = samples().lock().unwrap_or_else(|err| err.into_inner());
+        if samples.is_some() {
+            return Err(LuaError::runtime("profiler is already running"));
+        }
+        *samples = Some(HashMap::new());
+    }
+    set_jit(&lua, false)?;
+    let triggers = LuaHookTriggers::new().every_nth_instruction(interval);
+    lua.set_global_hook(triggers, |lua, _| {
+        let stack = folded_stack(lua);
+        let mut samples = samples().lock().unwrap_or_else(|err| err.into_inner());
+        if let Some(samples) = samples.as_mut() {
+            *samples.entry(stack).or_default() += 1;
+        }
+        Ok(LuaVmState::Continue)
+    })
+}
+
+/// Stop profiling and return the folded stack report, also writing it to `path` if given
+async fn stop(lua: Lua, path: Option<String>) -> LuaResult<String> {
+    let samples = samples()
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .take()
+        .ok_or_else(|| LuaError::runtime("profiler is not running"))?;
+    lua.remove_global_hook();
+    lua.remove_hook();
+    set_jit(&lua, true)?;
+    let report = report(&samples);
+    if let Some(path) = path {
+        smol::fs::write(path, &report).await?;
+    }
+    Ok(report)
+}
+
+/// Return the `init.profile` Lua table
+pub fn profile_table(lua: &Lua) -> LuaResult<LuaTable> {
+    let table = lua.create_table()?;
+    table.set("start", lua.create_async_function(start)?)?;
+    table.set("stop", lua.create_async_function(stop)?)?;
+    Ok(table)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report() {
+        let samples = HashMap::from([("main;b".to_string(), 2), ("main;a".to_string(), 5)]);
+        assert_eq!(report(&samples), "main;a 5\nmain;b 2\n");
+    }
+
+    #[test]
+    fn test_profile() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            lua.globals()
+                .set("profile", profile_table(&lua).unwrap())
+                .unwrap();
+            let report: String = lua
+                .load(
+                    "local function busy()
+                        local x = 0
+                        for i = 1, 200000 do x = x + i % 7 end
+                        return x
+                    end
+                    profile.start({ interval = 100 })
+                    assert(not pcall(profile.start))
+                    busy()
+                    return profile.stop()",
+                )
+                .eval_async()
+                .await
+                .unwrap();
+            assert!(report.contains("busy ("), "{}", report);
+            assert!(report.lines().all(|line| line.rsplit_once(' ').is_some()));
+            assert!(stop(lua, None).await.is_err());
+        });
+    }
+}
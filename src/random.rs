@@ -0,0 +1,52 @@
+use std::hash::{BuildHasher, Hasher};
+
+/// Return a random 64-bit number from the standard library hasher seed
+pub fn random_u64() -> u64 {
+    let mut hasher = std::collections::hash_map::RandomState::new().build_hasher();
+    hasher.write_u128(
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos(),
+    );
+    hasher.finish()
+}
+
+/// Return a random number in the range `[0, 1)`
+pub fn random_f64() -> f64 {
+    (random_u64() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// Return a random alphanumeric string of `len` characters
+pub fn random_string(len: usize) -> String {
+    const CHARS: &[u8] = b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789";
+    (0..len)
+        .map(|_| CHARS[(random_u64() % CHARS.len() as u64) as usize] as char)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_random_u64() {
+        assert_ne!(random_u64(), random_u64());
+    }
+
+    #[test]
+    fn test_random_f64() {
+        for _ in 0..100 {
+            let n = random_f64();
+            assert!((0.0..1.0).contains(&n));
+        }
+    }
+
+    #[test]
+    fn test_random_string() {
+        let s = random_string(12);
+        assert_eq!(s.len(), 12);
+        assert!(s.chars().all(|c| c.is_ascii_alphanumeric()));
+        assert_ne!(s, random_string(12));
+    }
+}
@@ -0,0 +1,107 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use async_signal::{Signal, Signals};
+use mlua::prelude::*;
+use smol::stream::StreamExt;
+
+use crate::{errors::AppResult, proc, process, task, unix};
+
+/// Registry key of the hook set by `init.on_reap`
+const REAP_HOOK: &str = "luavisors.on_reap";
+
+/// Set or clear the hook called with the pid and status of each orphan which is reaped
+pub async fn on_reap(lua: Lua, hook: Option<LuaFunction>) -> LuaResult<()> {
+    lua.set_named_registry_value(REAP_HOOK, hook)
+}
+
+/// Reap the orphans among `pids` which exited, calling the hook for each of them
+async fn sweep(lua: &Lua, pids: &[i32]) {
+    for (pid, status) in process::reap_orphans(pids) {
+        let hook = match lua.named_registry_value::<Option<LuaFunction>>(REAP_HOOK) {
+            Ok(Some(hook)) => hook,
+            _ => continue,
+        };
+        let status = match process::status_table(lua, status, None) {
+            Ok(status) => status,
+            Err(err) => return task::report_error(lua, "init.on_reap", err, None).await,
+        };
+        if let Err(err) = hook.call_async::<()>((pid, status)).await {
+            task::report_error(lua, "init.on_reap", err, None).await;
+        }
+    }
+}
+
+/// Reap orphaned descendants whenever a child exits, as init is expected to
+///
+/// Children started by `init.exec` are left for their handles to reap.
+async fn run(lua: Lua) -> AppResult<()> {
+    let mut signals = Signals::new([Signal::Child])?;
+    let parent = std::process::id() as i32;
+    loop {
+        // orphans which exited before the first signal are reaped as well
+        let pids = smol::unblock(move || proc::children(parent)).await?;
+        sweep(&lua, &pids).await;
+        match signals.next().await {
+            Some(signal) => signal?,
+            None => return Ok(()),
+        };
+    }
+}
+
+/// Whether orphans are being reaped
+static STARTED: AtomicBool = AtomicBool::new(false);
+
+/// Start reaping orphans in the background unless they already are
+pub fn start(lua: &Lua) {
+    if STARTED.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    let lua = lua.clone();
+    smol::spawn(async move {
+        if let Err(err) = run(lua).await {
+            eprintln!("error reaping orphans: {}", err);
+        }
+    })
+    .detach();
+}
+
+/// Adopt orphaned descendants and reap them from Lua, or stop adopting them, and
+/// return whether they are adopted
+///
+/// Orphans adopted before turning it off are still reaped.
+pub async fn subreaper(lua: Lua, on: Option<bool>) -> LuaResult<bool> {
+    if let Some(on) = on {
+        unix::set_child_subreaper(on)?;
+        if on {
+            start(&lua);
+        }
+    }
+    Ok(unix::is_child_subreaper()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sweep() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            let child = std::process::Command::new("sh")
+                .args(["-c", "exit 3"])
+                .spawn()
+                .unwrap();
+            let pid = child.id() as i32;
+            drop(child);
+            let hook = lua
+                .load("function(pid, status) reaped = { pid, status.code } end")
+                .eval()
+                .unwrap();
+            on_reap(lua.clone(), Some(hook)).await.unwrap();
+            smol::Timer::after(std::time::Duration::from_millis(100)).await;
+            sweep(&lua, &[pid]).await;
+            let reaped: (i32, i32) = lua.load("return reaped[1], reaped[2]").eval().unwrap();
+            assert_eq!(reaped, (pid, 3));
+        });
+    }
+}
@@ -0,0 +1,115 @@
+use std::{
+    collections::VecDeque,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+
+use smol::io::AsyncRead;
+
+/// Default number of bytes of recent output kept for each child process
+pub const DEFAULT_CAPACITY: usize = 64 * 1024;
+
+/// Bounded buffer which keeps the most recent bytes written to it
+pub struct RingBuffer {
+    data: VecDeque<u8>,
+    capacity: usize,
+}
+
+impl RingBuffer {
+    /// Create an empty buffer which keeps at most `capacity` bytes
+    pub fn new(capacity: usize) -> Self {
+        RingBuffer {
+            data: VecDeque::with_capacity(capacity.min(DEFAULT_CAPACITY)),
+            capacity,
+        }
+    }
+
+    /// Return the most bytes the buffer keeps
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Append data, dropping the oldest bytes once the buffer is full
+    pub fn push(&mut self, data: &[u8]) {
+        let data = &data[data.len().saturating_sub(self.capacity)..];
+        let overflow = (self.data.len() + data.len()).saturating_sub(self.capacity);
+        self.data.drain(..overflow);
+        self.data.extend(data);
+    }
+
+    /// Return up to the last `count` lines, or every line if `count` is `None`
+    ///
+    /// The first line may be partial when older output has been dropped.
+    pub fn lines(&self, count: Option<usize>) -> Vec<String> {
+        let (front, back) = self.data.as_slices();
+        let text = String::from_utf8_lossy(&[front, back].concat()).into_owned();
+        let lines: Vec<String> = text.lines().map(str::to_string).collect();
+        let skip = count.map_or(0, |count| lines.len().saturating_sub(count));
+        lines.into_iter().skip(skip).collect()
+    }
+}
+
+/// Ring buffer shared by the readers of a child's streams
+pub type Recent = Arc<Mutex<RingBuffer>>;
+
+/// Reader which copies everything read into a ring buffer
+pub struct TeeReader<R> {
+    inner: R,
+    recent: Recent,
+}
+
+impl<R> TeeReader<R> {
+    /// Wrap a reader so its data is also kept in `recent`
+    pub fn new(inner: R, recent: Recent) -> Self {
+        TeeReader { inner, recent }
+    }
+}
+
+/// Read from the inner reader and record the bytes read
+impl<R: AsyncRead + Unpin> AsyncRead for TeeReader<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let poll = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(read)) = poll {
+            let mut recent = self.recent.lock().unwrap_or_else(|err| err.into_inner());
+            recent.push(&buf[..read]);
+        }
+        poll
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use smol::io::AsyncReadExt;
+
+    use super::*;
+
+    #[test]
+    fn test_ring_buffer() {
+        let mut ring = RingBuffer::new(14);
+        ring.push(b"one\ntwo\n");
+        assert_eq!(ring.lines(None), vec!["one", "two"]);
+        ring.push(b"three\nfour\n");
+        // "one\n" and part of "two" were dropped
+        assert_eq!(ring.lines(None), vec!["wo", "three", "four"]);
+        assert_eq!(ring.lines(Some(1)), vec!["four"]);
+        ring.push(&[b'x'; 40]);
+        assert_eq!(ring.lines(None), vec!["x".repeat(14)]);
+    }
+
+    #[test]
+    fn test_tee_reader() {
+        smol::block_on(async {
+            let recent = Arc::new(Mutex::new(RingBuffer::new(DEFAULT_CAPACITY)));
+            let mut reader = TeeReader::new(&b"a\nb\n"[..], recent.clone());
+            let mut data = Vec::new();
+            reader.read_to_end(&mut data).await.unwrap();
+            assert_eq!(data, b"a\nb\n");
+            assert_eq!(recent.lock().unwrap().lines(None), vec!["a", "b"]);
+        });
+    }
+}
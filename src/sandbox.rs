@@ -0,0 +1,160 @@
+use mlua::prelude::*;
+
+/// Environment variable naming the sandbox profile applied at startup
+pub const SANDBOX_ENV: &str = "LUAVISORS_SANDBOX";
+
+/// Globals removed by the `restricted` profile
+const RESTRICTED: &[&str] = &["ffi", "debug", "os.execute", "io.popen", "package.loadlib"];
+
+/// Globals removed by the `strict` profile in addition to `restricted`
+const STRICT: &[&str] = &[
+    "io",
+    "os.exit",
+    "os.remove",
+    "os.rename",
+    "os.tmpname",
+    "os.setlocale",
+    "dofile",
+    "loadfile",
+    "load",
+    "loadstring",
+];
+
+/// Replace the loaders kept by `restricted` with ones which refuse binary chunks,
+/// whose malformed bytecode can corrupt the LuaJIT state
+const TEXT_ONLY: &str = r#"
+    local load, loadfile = load, loadfile
+    _G.load = function(chunk, name, _, env) return load(chunk, name, 't', env) end
+    _G.loadstring = function(s, name) return load(s, name, 't') end
+    _G.loadfile = function(path, _, env) return loadfile(path, 't', env) end
+    _G.dofile = function(path) return assert(loadfile(path, 't'))() end
+"#;
+
+/// Names of the globals removed by a sandbox profile
+fn profile(name: &str) -> Option<Vec<&'static str>> {
+    match name {
+        "none" => Some(Vec::new()),
+        "restricted" => Some(RESTRICTED.to_vec()),
+        "strict" => Some(RESTRICTED.iter().chain(STRICT).copied().collect()),
+        _ => None,
+    }
+}
+
+/// Remove a global or a field of a global table such as `os.execute`
+fn remove(lua: &Lua, name: &str) -> LuaResult<()> {
+    let globals = lua.globals();
+    match name.split_once('.') {
+        Some((table, field)) => {
+            if let Some(table) = globals.get::<Option<LuaTable>>(table)? {
+                table.set(field, LuaValue::Nil)?;
+            }
+        }
+        None => {
+            globals.set(name, LuaValue::Nil)?;
+            // keep `require` from handing back a removed module
+            let package = globals.get::<LuaTable>("package")?;
+            package
+                .get::<LuaTable>("loaded")?
+                .set(name, LuaValue::Nil)?;
+            package
+                .get::<LuaTable>("preload")?
+                .set(name, LuaValue::Nil)?;
+        }
+    }
+    Ok(())
+}
+
+/// Remove the globals of a sandbox profile, which cannot be undone
+pub fn apply(lua: &Lua, name: &str) -> LuaResult<()> {
+    let names =
+        profile(name).ok_or_else(|| LuaError::runtime(format!("unknown sandbox '{}'", name)))?;
+    if names.is_empty() {
+        return Ok(());
+    }
+    lua.load(TEXT_ONLY).set_name("=sandbox").exec()?;
+    for name in names {
+        remove(lua, name)?;
+    }
+    // native modules could restore anything that was removed
+    lua.globals().get::<LuaTable>("package")?.set("cpath", "")?;
+    Ok(())
+}
+
+/// Apply a sandbox profile to the running script from Lua
+pub async fn sandbox(lua: Lua, name: String) -> LuaResult<()> {
+    apply(&lua, &name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn is_nil(lua: &Lua, expr: &str) -> bool {
+        lua.load(format!("return {} == nil", expr)).eval().unwrap()
+    }
+
+    #[test]
+    fn test_profile() {
+        assert!(profile("none").unwrap().is_empty());
+        let strict = profile("strict").unwrap();
+        assert!(strict.contains(&"ffi") && strict.contains(&"io"));
+        assert!(profile("unknown").is_none());
+    }
+
+    #[test]
+    fn test_apply_restricted() {
+        let lua = Lua::new();
+        apply(&lua, "restricted").unwrap();
+        assert!(is_nil(&lua, "debug"));
+        assert!(is_nil(&lua, "package.loaded.debug"));
+        assert!(is_nil(&lua, "os.execute"));
+        assert!(!is_nil(&lua, "os.time"));
+        assert!(!is_nil(&lua, "io.open"));
+        assert!(lua.load("require('debug')").exec().is_err());
+        assert!(is_nil(&lua, "package.cpath:match('so')"));
+    }
+
+    #[test]
+    fn test_apply_restricted_text_only() {
+        let lua = Lua::new();
+        apply(&lua, "restricted").unwrap();
+        let loaded: (bool, bool, bool) = lua
+            .load(
+                r#"
+                local bytecode = string.dump(function() return 1 end)
+                return load(bytecode) == nil, loadstring(bytecode) == nil,
+                    load('return 1')() == 1
+                "#,
+            )
+            .eval()
+            .unwrap();
+        assert_eq!(loaded, (true, true, true));
+        let path = std::env::temp_dir().join(format!("luavisors-sandbox-{}", std::process::id()));
+        let bytecode: LuaString = lua
+            .load("return string.dump(function() return 1 end)")
+            .eval()
+            .unwrap();
+        std::fs::write(&path, bytecode.as_bytes()).unwrap();
+        lua.globals().set("path", path.to_str().unwrap()).unwrap();
+        assert!(is_nil(&lua, "loadfile(path)"));
+        assert!(lua.load("dofile(path)").exec().is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_apply_strict() {
+        let lua = Lua::new();
+        apply(&lua, "strict").unwrap();
+        assert!(is_nil(&lua, "io"));
+        assert!(is_nil(&lua, "loadstring"));
+        assert!(!is_nil(&lua, "string.format"));
+    }
+
+    #[test]
+    fn test_apply_err() {
+        let lua = Lua::new();
+        assert!(apply(&lua, "unknown").is_err());
+        apply(&lua, "none").unwrap();
+        assert!(!is_nil(&lua, "os.execute"));
+    }
+}
@@ -0,0 +1,244 @@
+use std::{
+    cmp::Ordering,
+    collections::BinaryHeap,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex, OnceLock},
+    time::{Duration, Instant, SystemTime},
+};
+
+use smol::channel::{Receiver, Sender};
+
+/// Future which runs a scheduled job and returns when it is next due, if ever
+pub type JobFuture = Pin<Box<dyn Future<Output = Option<Instant>> + Send>>;
+
+/// Scheduled job, called with the instant it was due
+pub type Job = Arc<dyn Fn(Instant) -> JobFuture + Send + Sync>;
+
+/// Job waiting in the queue for its due time
+struct Entry {
+    due: Instant,
+    id: u64,
+    job: Job,
+}
+
+/// Order entries so the earliest due time is at the top of the heap
+impl Ord for Entry {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (other.due, other.id).cmp(&(self.due, self.id))
+    }
+}
+
+impl PartialOrd for Entry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Entry {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Entry {}
+
+/// Pending jobs ordered by due time
+#[derive(Default)]
+struct Queue {
+    entries: BinaryHeap<Entry>,
+    next_id: u64,
+}
+
+impl Queue {
+    /// Add a job to the queue
+    fn push(&mut self, due: Instant, job: Job) {
+        self.next_id += 1;
+        let id = self.next_id;
+        self.entries.push(Entry { due, id, job });
+    }
+
+    /// Remove every job due at `now` and return when the next one is due
+    fn take_due(&mut self, now: Instant) -> (Vec<Entry>, Option<Instant>) {
+        let mut due = Vec::new();
+        while self.entries.peek().is_some_and(|entry| entry.due <= now) {
+            due.extend(self.entries.pop());
+        }
+        (due, self.entries.peek().map(|entry| entry.due))
+    }
+}
+
+/// Single timer task which runs every scheduled job
+pub struct Scheduler {
+    queue: Mutex<Queue>,
+    wake: Sender<()>,
+}
+
+impl Scheduler {
+    /// Create a scheduler and the receiver which wakes its timer task
+    fn new() -> (Self, Receiver<()>) {
+        let (wake, woken) = smol::channel::bounded(1);
+        let scheduler = Scheduler {
+            queue: Mutex::new(Queue::default()),
+            wake,
+        };
+        (scheduler, woken)
+    }
+
+    /// Lock the queue, ignoring poisoning since it is always consistent
+    fn queue(&self) -> std::sync::MutexGuard<'_, Queue> {
+        self.queue.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Schedule a job to run at `due`
+    pub fn at(&'static self, due: Instant, job: Job) {
+        self.queue().push(due, job);
+        // a full channel means the timer task is already going to wake
+        let _ = self.wake.try_send(());
+    }
+
+    /// Wait for due jobs and run each one as a task, rescheduling it if asked
+    async fn run(&'static self, woken: Receiver<()>) {
+        loop {
+            let (due, next) = self.queue().take_due(Instant::now());
+            for entry in due {
+                let future = (entry.job)(entry.due);
+                smol::spawn(async move {
+                    if let Some(next) = future.await {
+                        self.at(next, entry.job);
+                    }
+                })
+                .detach();
+            }
+            let timer = match next {
+                Some(next) => smol::Timer::at(next),
+                None => smol::Timer::never(),
+            };
+            smol::future::or(
+                async {
+                    timer.await;
+                },
+                async {
+                    let _ = woken.recv().await;
+                },
+            )
+            .await;
+        }
+    }
+}
+
+/// Return the shared scheduler, starting its timer task on first use
+pub fn scheduler() -> &'static Scheduler {
+    static SCHEDULER: OnceLock<Scheduler> = OnceLock::new();
+    let mut woken = None;
+    let scheduler = SCHEDULER.get_or_init(|| {
+        let (scheduler, receiver) = Scheduler::new();
+        woken = Some(receiver);
+        scheduler
+    });
+    if let Some(woken) = woken {
+        smol::spawn(scheduler.run(woken)).detach();
+    }
+    scheduler
+}
+
+/// Return the next due time of an interval, skipping ticks missed while running
+pub fn next_interval(due: Instant, interval: Duration, now: Instant) -> Instant {
+    let next = due + interval;
+    if next > now || interval.is_zero() {
+        return next.max(now);
+    }
+    let missed = (now - due).as_secs_f64() / interval.as_secs_f64();
+    due + interval.mul_f64(missed.floor() + 1.0)
+}
+
+/// Return the wall clock time since the Unix epoch
+pub fn wall_clock() -> Duration {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+}
+
+/// Return the delay until the next wall clock multiple of `interval`
+///
+/// After a job has just run, boundaries less than half an interval away are
+/// skipped so a timer which fires slightly early cannot run the job twice.
+pub fn aligned_delay(interval: Duration, wall: Duration, after_run: bool) -> Duration {
+    let interval_nanos = interval.as_nanos().max(1);
+    let from = match after_run {
+        true => wall + interval / 2,
+        false => wall,
+    };
+    let remainder = from.as_nanos() % interval_nanos;
+    let boundary = from + Duration::from_nanos((interval_nanos - remainder) as u64);
+    boundary - wall
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn job() -> Job {
+        Arc::new(|_| Box::pin(async { None }))
+    }
+
+    #[test]
+    fn test_queue_order() {
+        let now = Instant::now();
+        let mut queue = Queue::default();
+        queue.push(now + Duration::from_secs(2), job());
+        queue.push(now, job());
+        queue.push(now + Duration::from_secs(1), job());
+        let (due, next) = queue.take_due(now + Duration::from_millis(1500));
+        let due: Vec<Instant> = due.iter().map(|entry| entry.due).collect();
+        assert_eq!(due, vec![now, now + Duration::from_secs(1)]);
+        assert_eq!(next, Some(now + Duration::from_secs(2)));
+    }
+
+    #[test]
+    fn test_next_interval() {
+        let now = Instant::now();
+        let second = Duration::from_secs(1);
+        assert_eq!(next_interval(now, second, now), now + second);
+        // ticks missed while a job ran late are skipped
+        let late = now + Duration::from_millis(2500);
+        assert_eq!(next_interval(now, second, late), now + second * 3);
+        assert_eq!(next_interval(now, Duration::ZERO, late), late);
+    }
+
+    #[test]
+    fn test_aligned_delay() {
+        let minute = Duration::from_secs(60);
+        let wall = Duration::from_secs(3600 + 45);
+        assert_eq!(aligned_delay(minute, wall, false), Duration::from_secs(15));
+        // a boundary exactly now waits a full interval
+        let wall = Duration::from_secs(3600);
+        assert_eq!(aligned_delay(minute, wall, false), minute);
+        // a timer which fired just before the boundary skips it
+        let early = Duration::from_millis(3600 * 1000 - 5);
+        let delay = aligned_delay(minute, early, true);
+        assert_eq!(delay, minute + Duration::from_millis(5));
+    }
+
+    #[test]
+    fn test_scheduler() {
+        smol::block_on(async {
+            let (sender, receiver) = smol::channel::unbounded();
+            let count = Arc::new(Mutex::new(0));
+            let job: Job = Arc::new(move |_| {
+                let sender = sender.clone();
+                let count = count.clone();
+                Box::pin(async move {
+                    let mut count = count.lock().unwrap();
+                    *count += 1;
+                    sender.try_send(*count).unwrap();
+                    (*count < 3).then(Instant::now)
+                })
+            });
+            scheduler().at(Instant::now(), job);
+            for expected in 1..=3 {
+                assert_eq!(receiver.recv().await.unwrap(), expected);
+            }
+        });
+    }
+}
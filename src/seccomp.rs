@@ -0,0 +1,1277 @@
+use std::path::Path;
+
+use mlua::prelude::*;
+
+use crate::unix::SockFilter;
+
+/// Audit architecture which syscalls checked by a filter must come from
+#[cfg(target_arch = "x86_64")]
+const AUDIT_ARCH: Option<u32> = Some(0xc000003e);
+/// Audit architecture which syscalls checked by a filter must come from
+#[cfg(target_arch = "aarch64")]
+const AUDIT_ARCH: Option<u32> = Some(0xc00000b7);
+/// Audit architecture which syscalls checked by a filter must come from
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+const AUDIT_ARCH: Option<u32> = None;
+
+/// Syscall names indexed by their numbers, where unused numbers are empty
+#[cfg(target_arch = "x86_64")]
+const SYSCALLS: [&str; 451] = [
+    "read",
+    "write",
+    "open",
+    "close",
+    "stat",
+    "fstat",
+    "lstat",
+    "poll",
+    "lseek",
+    "mmap",
+    "mprotect",
+    "munmap",
+    "brk",
+    "rt_sigaction",
+    "rt_sigprocmask",
+    "rt_sigreturn",
+    "ioctl",
+    "pread64",
+    "pwrite64",
+    "readv",
+    "writev",
+    "access",
+    "pipe",
+    "select",
+    "sched_yield",
+    "mremap",
+    "msync",
+    "mincore",
+    "madvise",
+    "shmget",
+    "shmat",
+    "shmctl",
+    "dup",
+    "dup2",
+    "pause",
+    "nanosleep",
+    "getitimer",
+    "alarm",
+    "setitimer",
+    "getpid",
+    "sendfile",
+    "socket",
+    "connect",
+    "accept",
+    "sendto",
+    "recvfrom",
+    "sendmsg",
+    "recvmsg",
+    "shutdown",
+    "bind",
+    "listen",
+    "getsockname",
+    "getpeername",
+    "socketpair",
+    "setsockopt",
+    "getsockopt",
+    "clone",
+    "fork",
+    "vfork",
+    "execve",
+    "exit",
+    "wait4",
+    "kill",
+    "uname",
+    "semget",
+    "semop",
+    "semctl",
+    "shmdt",
+    "msgget",
+    "msgsnd",
+    "msgrcv",
+    "msgctl",
+    "fcntl",
+    "flock",
+    "fsync",
+    "fdatasync",
+    "truncate",
+    "ftruncate",
+    "getdents",
+    "getcwd",
+    "chdir",
+    "fchdir",
+    "rename",
+    "mkdir",
+    "rmdir",
+    "creat",
+    "link",
+    "unlink",
+    "symlink",
+    "readlink",
+    "chmod",
+    "fchmod",
+    "chown",
+    "fchown",
+    "lchown",
+    "umask",
+    "gettimeofday",
+    "getrlimit",
+    "getrusage",
+    "sysinfo",
+    "times",
+    "ptrace",
+    "getuid",
+    "syslog",
+    "getgid",
+    "setuid",
+    "setgid",
+    "geteuid",
+    "getegid",
+    "setpgid",
+    "getppid",
+    "getpgrp",
+    "setsid",
+    "setreuid",
+    "setregid",
+    "getgroups",
+    "setgroups",
+    "setresuid",
+    "getresuid",
+    "setresgid",
+    "getresgid",
+    "getpgid",
+    "setfsuid",
+    "setfsgid",
+    "getsid",
+    "capget",
+    "capset",
+    "rt_sigpending",
+    "rt_sigtimedwait",
+    "rt_sigqueueinfo",
+    "rt_sigsuspend",
+    "sigaltstack",
+    "utime",
+    "mknod",
+    "uselib",
+    "personality",
+    "ustat",
+    "statfs",
+    "fstatfs",
+    "sysfs",
+    "getpriority",
+    "setpriority",
+    "sched_setparam",
+    "sched_getparam",
+    "sched_setscheduler",
+    "sched_getscheduler",
+    "sched_get_priority_max",
+    "sched_get_priority_min",
+    "sched_rr_get_interval",
+    "mlock",
+    "munlock",
+    "mlockall",
+    "munlockall",
+    "vhangup",
+    "modify_ldt",
+    "pivot_root",
+    "_sysctl",
+    "prctl",
+    "arch_prctl",
+    "adjtimex",
+    "setrlimit",
+    "chroot",
+    "sync",
+    "acct",
+    "settimeofday",
+    "mount",
+    "umount2",
+    "swapon",
+    "swapoff",
+    "reboot",
+    "sethostname",
+    "setdomainname",
+    "iopl",
+    "ioperm",
+    "create_module",
+    "init_module",
+    "delete_module",
+    "get_kernel_syms",
+    "query_module",
+    "quotactl",
+    "nfsservctl",
+    "getpmsg",
+    "putpmsg",
+    "afs_syscall",
+    "tuxcall",
+    "security",
+    "gettid",
+    "readahead",
+    "setxattr",
+    "lsetxattr",
+    "fsetxattr",
+    "getxattr",
+    "lgetxattr",
+    "fgetxattr",
+    "listxattr",
+    "llistxattr",
+    "flistxattr",
+    "removexattr",
+    "lremovexattr",
+    "fremovexattr",
+    "tkill",
+    "time",
+    "futex",
+    "sched_setaffinity",
+    "sched_getaffinity",
+    "set_thread_area",
+    "io_setup",
+    "io_destroy",
+    "io_getevents",
+    "io_submit",
+    "io_cancel",
+    "get_thread_area",
+    "lookup_dcookie",
+    "epoll_create",
+    "epoll_ctl_old",
+    "epoll_wait_old",
+    "remap_file_pages",
+    "getdents64",
+    "set_tid_address",
+    "restart_syscall",
+    "semtimedop",
+    "fadvise64",
+    "timer_create",
+    "timer_settime",
+    "timer_gettime",
+    "timer_getoverrun",
+    "timer_delete",
+    "clock_settime",
+    "clock_gettime",
+    "clock_getres",
+    "clock_nanosleep",
+    "exit_group",
+    "epoll_wait",
+    "epoll_ctl",
+    "tgkill",
+    "utimes",
+    "vserver",
+    "mbind",
+    "set_mempolicy",
+    "get_mempolicy",
+    "mq_open",
+    "mq_unlink",
+    "mq_timedsend",
+    "mq_timedreceive",
+    "mq_notify",
+    "mq_getsetattr",
+    "kexec_load",
+    "waitid",
+    "add_key",
+    "request_key",
+    "keyctl",
+    "ioprio_set",
+    "ioprio_get",
+    "inotify_init",
+    "inotify_add_watch",
+    "inotify_rm_watch",
+    "migrate_pages",
+    "openat",
+    "mkdirat",
+    "mknodat",
+    "fchownat",
+    "futimesat",
+    "newfstatat",
+    "unlinkat",
+    "renameat",
+    "linkat",
+    "symlinkat",
+    "readlinkat",
+    "fchmodat",
+    "faccessat",
+    "pselect6",
+    "ppoll",
+    "unshare",
+    "set_robust_list",
+    "get_robust_list",
+    "splice",
+    "tee",
+    "sync_file_range",
+    "vmsplice",
+    "move_pages",
+    "utimensat",
+    "epoll_pwait",
+    "signalfd",
+    "timerfd_create",
+    "eventfd",
+    "fallocate",
+    "timerfd_settime",
+    "timerfd_gettime",
+    "accept4",
+    "signalfd4",
+    "eventfd2",
+    "epoll_create1",
+    "dup3",
+    "pipe2",
+    "inotify_init1",
+    "preadv",
+    "pwritev",
+    "rt_tgsigqueueinfo",
+    "perf_event_open",
+    "recvmmsg",
+    "fanotify_init",
+    "fanotify_mark",
+    "prlimit64",
+    "name_to_handle_at",
+    "open_by_handle_at",
+    "clock_adjtime",
+    "syncfs",
+    "sendmmsg",
+    "setns",
+    "getcpu",
+    "process_vm_readv",
+    "process_vm_writev",
+    "kcmp",
+    "finit_module",
+    "sched_setattr",
+    "sched_getattr",
+    "renameat2",
+    "seccomp",
+    "getrandom",
+    "memfd_create",
+    "kexec_file_load",
+    "bpf",
+    "execveat",
+    "userfaultfd",
+    "membarrier",
+    "mlock2",
+    "copy_file_range",
+    "preadv2",
+    "pwritev2",
+    "pkey_mprotect",
+    "pkey_alloc",
+    "pkey_free",
+    "statx",
+    "io_pgetevents",
+    "rseq",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "pidfd_send_signal",
+    "io_uring_setup",
+    "io_uring_enter",
+    "io_uring_register",
+    "open_tree",
+    "move_mount",
+    "fsopen",
+    "fsconfig",
+    "fsmount",
+    "fspick",
+    "pidfd_open",
+    "clone3",
+    "close_range",
+    "openat2",
+    "pidfd_getfd",
+    "faccessat2",
+    "process_madvise",
+    "epoll_pwait2",
+    "mount_setattr",
+    "quotactl_fd",
+    "landlock_create_ruleset",
+    "landlock_add_rule",
+    "landlock_restrict_self",
+    "memfd_secret",
+    "process_mrelease",
+    "futex_waitv",
+    "set_mempolicy_home_node",
+];
+
+/// Syscall names indexed by their numbers, where unused numbers are empty
+#[cfg(target_arch = "aarch64")]
+const SYSCALLS: [&str; 451] = [
+    "io_setup",
+    "io_destroy",
+    "io_submit",
+    "io_cancel",
+    "io_getevents",
+    "setxattr",
+    "lsetxattr",
+    "fsetxattr",
+    "getxattr",
+    "lgetxattr",
+    "fgetxattr",
+    "listxattr",
+    "llistxattr",
+    "flistxattr",
+    "removexattr",
+    "lremovexattr",
+    "fremovexattr",
+    "getcwd",
+    "lookup_dcookie",
+    "eventfd2",
+    "epoll_create1",
+    "epoll_ctl",
+    "epoll_pwait",
+    "dup",
+    "dup3",
+    "fcntl",
+    "inotify_init1",
+    "inotify_add_watch",
+    "inotify_rm_watch",
+    "ioctl",
+    "ioprio_set",
+    "ioprio_get",
+    "flock",
+    "mknodat",
+    "mkdirat",
+    "unlinkat",
+    "symlinkat",
+    "linkat",
+    "renameat",
+    "umount2",
+    "mount",
+    "pivot_root",
+    "nfsservctl",
+    "statfs",
+    "fstatfs",
+    "truncate",
+    "ftruncate",
+    "fallocate",
+    "faccessat",
+    "chdir",
+    "fchdir",
+    "chroot",
+    "fchmod",
+    "fchmodat",
+    "fchownat",
+    "fchown",
+    "openat",
+    "close",
+    "vhangup",
+    "pipe2",
+    "quotactl",
+    "getdents64",
+    "lseek",
+    "read",
+    "write",
+    "readv",
+    "writev",
+    "pread64",
+    "pwrite64",
+    "preadv",
+    "pwritev",
+    "sendfile",
+    "pselect6",
+    "ppoll",
+    "signalfd4",
+    "vmsplice",
+    "splice",
+    "tee",
+    "readlinkat",
+    "newfstatat",
+    "fstat",
+    "sync",
+    "fsync",
+    "fdatasync",
+    "sync_file_range",
+    "timerfd_create",
+    "timerfd_settime",
+    "timerfd_gettime",
+    "utimensat",
+    "acct",
+    "capget",
+    "capset",
+    "personality",
+    "exit",
+    "exit_group",
+    "waitid",
+    "set_tid_address",
+    "unshare",
+    "futex",
+    "set_robust_list",
+    "get_robust_list",
+    "nanosleep",
+    "getitimer",
+    "setitimer",
+    "kexec_load",
+    "init_module",
+    "delete_module",
+    "timer_create",
+    "timer_gettime",
+    "timer_getoverrun",
+    "timer_settime",
+    "timer_delete",
+    "clock_settime",
+    "clock_gettime",
+    "clock_getres",
+    "clock_nanosleep",
+    "syslog",
+    "ptrace",
+    "sched_setparam",
+    "sched_setscheduler",
+    "sched_getscheduler",
+    "sched_getparam",
+    "sched_setaffinity",
+    "sched_getaffinity",
+    "sched_yield",
+    "sched_get_priority_max",
+    "sched_get_priority_min",
+    "sched_rr_get_interval",
+    "restart_syscall",
+    "kill",
+    "tkill",
+    "tgkill",
+    "sigaltstack",
+    "rt_sigsuspend",
+    "rt_sigaction",
+    "rt_sigprocmask",
+    "rt_sigpending",
+    "rt_sigtimedwait",
+    "rt_sigqueueinfo",
+    "rt_sigreturn",
+    "setpriority",
+    "getpriority",
+    "reboot",
+    "setregid",
+    "setgid",
+    "setreuid",
+    "setuid",
+    "setresuid",
+    "getresuid",
+    "setresgid",
+    "getresgid",
+    "setfsuid",
+    "setfsgid",
+    "times",
+    "setpgid",
+    "getpgid",
+    "getsid",
+    "setsid",
+    "getgroups",
+    "setgroups",
+    "uname",
+    "sethostname",
+    "setdomainname",
+    "getrlimit",
+    "setrlimit",
+    "getrusage",
+    "umask",
+    "prctl",
+    "getcpu",
+    "gettimeofday",
+    "settimeofday",
+    "adjtimex",
+    "getpid",
+    "getppid",
+    "getuid",
+    "geteuid",
+    "getgid",
+    "getegid",
+    "gettid",
+    "sysinfo",
+    "mq_open",
+    "mq_unlink",
+    "mq_timedsend",
+    "mq_timedreceive",
+    "mq_notify",
+    "mq_getsetattr",
+    "msgget",
+    "msgctl",
+    "msgrcv",
+    "msgsnd",
+    "semget",
+    "semctl",
+    "semtimedop",
+    "semop",
+    "shmget",
+    "shmctl",
+    "shmat",
+    "shmdt",
+    "socket",
+    "socketpair",
+    "bind",
+    "listen",
+    "accept",
+    "connect",
+    "getsockname",
+    "getpeername",
+    "sendto",
+    "recvfrom",
+    "setsockopt",
+    "getsockopt",
+    "shutdown",
+    "sendmsg",
+    "recvmsg",
+    "readahead",
+    "brk",
+    "munmap",
+    "mremap",
+    "add_key",
+    "request_key",
+    "keyctl",
+    "clone",
+    "execve",
+    "mmap",
+    "fadvise64",
+    "swapon",
+    "swapoff",
+    "mprotect",
+    "msync",
+    "mlock",
+    "munlock",
+    "mlockall",
+    "munlockall",
+    "mincore",
+    "madvise",
+    "remap_file_pages",
+    "mbind",
+    "get_mempolicy",
+    "set_mempolicy",
+    "migrate_pages",
+    "move_pages",
+    "rt_tgsigqueueinfo",
+    "perf_event_open",
+    "accept4",
+    "recvmmsg",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "wait4",
+    "prlimit64",
+    "fanotify_init",
+    "fanotify_mark",
+    "name_to_handle_at",
+    "open_by_handle_at",
+    "clock_adjtime",
+    "syncfs",
+    "setns",
+    "sendmmsg",
+    "process_vm_readv",
+    "process_vm_writev",
+    "kcmp",
+    "finit_module",
+    "sched_setattr",
+    "sched_getattr",
+    "renameat2",
+    "seccomp",
+    "getrandom",
+    "memfd_create",
+    "bpf",
+    "execveat",
+    "userfaultfd",
+    "membarrier",
+    "mlock2",
+    "copy_file_range",
+    "preadv2",
+    "pwritev2",
+    "pkey_mprotect",
+    "pkey_alloc",
+    "pkey_free",
+    "statx",
+    "io_pgetevents",
+    "rseq",
+    "kexec_file_load",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "pidfd_send_signal",
+    "io_uring_setup",
+    "io_uring_enter",
+    "io_uring_register",
+    "open_tree",
+    "move_mount",
+    "fsopen",
+    "fsconfig",
+    "fsmount",
+    "fspick",
+    "pidfd_open",
+    "clone3",
+    "close_range",
+    "openat2",
+    "pidfd_getfd",
+    "faccessat2",
+    "process_madvise",
+    "epoll_pwait2",
+    "mount_setattr",
+    "quotactl_fd",
+    "landlock_create_ruleset",
+    "landlock_add_rule",
+    "landlock_restrict_self",
+    "memfd_secret",
+    "process_mrelease",
+    "futex_waitv",
+    "set_mempolicy_home_node",
+];
+
+/// Syscall names indexed by their numbers, where unused numbers are empty
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+const SYSCALLS: [&str; 0] = [];
+
+/// First syscall number of the x32 ABI, which shares the x86_64 architecture
+const X32_SYSCALL_BIT: u32 = 0x40000000;
+
+/// Address family of unix sockets
+const AF_UNIX: u32 = 1;
+
+/// Load a word of the syscall data into the accumulator
+const BPF_LD_W_ABS: u16 = 0x20;
+/// Jump if the accumulator equals a constant
+const BPF_JEQ_K: u16 = 0x15;
+/// Jump if the accumulator is greater than or equal to a constant
+const BPF_JGE_K: u16 = 0x35;
+/// Return a constant action
+const BPF_RET_K: u16 = 0x06;
+
+/// Offset of the syscall number in the syscall data
+const OFFSET_NR: u32 = 0;
+/// Offset of the architecture in the syscall data
+const OFFSET_ARCH: u32 = 4;
+/// Offset of the lower half of the first argument in the syscall data
+const OFFSET_ARG0: u32 = 16;
+
+/// What a filter does with a syscall
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+    Allow,
+    Errno,
+    Log,
+    Kill,
+}
+
+/// Action names accepted in profiles
+const ACTIONS: [(&str, Action); 4] = [
+    ("allow", Action::Allow),
+    ("errno", Action::Errno),
+    ("log", Action::Log),
+    ("kill", Action::Kill),
+];
+
+impl Action {
+    /// Return the filter return value of the action, where denied syscalls fail with `EPERM`
+    fn ret(self) -> u32 {
+        match self {
+            Action::Allow => 0x7fff0000,
+            Action::Errno => 0x00050000 | 1,
+            Action::Log => 0x7ffc0000,
+            Action::Kill => 0x80000000,
+        }
+    }
+
+    /// Look up an action by its name
+    fn parse(name: &str) -> LuaResult<Self> {
+        ACTIONS
+            .iter()
+            .find(|(known, _)| *known == name)
+            .map(|(_, action)| *action)
+            .ok_or_else(|| LuaError::runtime(format!("unknown seccomp action '{}'", name)))
+    }
+
+    /// Return the name of the action
+    fn name(self) -> &'static str {
+        ACTIONS
+            .iter()
+            .find(|(_, action)| *action == self)
+            .map_or("", |(name, _)| name)
+    }
+}
+
+/// Syscalls denied by the `default` preset, which administer the system rather than use it
+const ADMIN: &[&str] = &[
+    "acct",
+    "add_key",
+    "adjtimex",
+    "bpf",
+    "clock_adjtime",
+    "clock_settime",
+    "delete_module",
+    "finit_module",
+    "fsconfig",
+    "fsmount",
+    "fsopen",
+    "init_module",
+    "ioperm",
+    "iopl",
+    "kexec_file_load",
+    "kexec_load",
+    "keyctl",
+    "mount",
+    "move_mount",
+    "open_by_handle_at",
+    "open_tree",
+    "perf_event_open",
+    "pivot_root",
+    "process_vm_readv",
+    "process_vm_writev",
+    "ptrace",
+    "quotactl",
+    "reboot",
+    "request_key",
+    "setdomainname",
+    "sethostname",
+    "setns",
+    "settimeofday",
+    "swapoff",
+    "swapon",
+    "syslog",
+    "umount2",
+    "unshare",
+    "userfaultfd",
+];
+
+/// Syscalls which a filter applies its action to, and what it does with the others
+#[derive(Debug, Clone, PartialEq)]
+pub struct Profile {
+    default: Action,
+    action: Action,
+    syscalls: Vec<u32>,
+    allow_unix: bool,
+}
+
+/// Look up the number of a syscall by its name
+fn syscall(name: &str) -> Option<u32> {
+    match name {
+        "" => None,
+        name => SYSCALLS
+            .iter()
+            .position(|known| *known == name)
+            .map(|nr| nr as u32),
+    }
+}
+
+/// Return the profile of a named preset, skipping syscalls which this architecture does not have
+fn preset(name: &str) -> Option<Profile> {
+    let admin = || ADMIN.iter().filter_map(|name| syscall(name));
+    let syscalls = match name {
+        "default" => admin().collect(),
+        "no_network" => admin().chain(syscall("socket")).collect(),
+        _ => return None,
+    };
+    Some(Profile {
+        default: Action::Allow,
+        action: Action::Errno,
+        syscalls,
+        allow_unix: name == "no_network",
+    })
+}
+
+/// Read a profile from a table of `default` and `action` names, `syscalls`, and `allow_unix`
+///
+/// Listed syscalls fail with `EPERM` when every other syscall is allowed, and are
+/// allowed otherwise, unless `action` says what to do with them.
+pub fn profile_from_table(table: &LuaTable) -> LuaResult<Profile> {
+    let default = match table.get::<Option<String>>("default")? {
+        Some(name) => Action::parse(&name)?,
+        None => Action::Allow,
+    };
+    let action = match table.get::<Option<String>>("action")? {
+        Some(name) => Action::parse(&name)?,
+        None if default == Action::Allow => Action::Errno,
+        None => Action::Allow,
+    };
+    let syscalls = table
+        .get::<Option<LuaTable>>("syscalls")?
+        .map(|syscalls| {
+            syscalls
+                .sequence_values::<LuaValue>()
+                .map(|value| match value? {
+                    LuaValue::Integer(nr) => Ok(nr as u32),
+                    value => {
+                        let name = value.to_string()?;
+                        syscall(&name)
+                            .ok_or_else(|| LuaError::runtime(format!("unknown syscall '{}'", name)))
+                    }
+                })
+                .collect::<LuaResult<Vec<_>>>()
+        })
+        .transpose()?
+        .unwrap_or_default();
+    Ok(Profile {
+        default,
+        action,
+        syscalls,
+        allow_unix: table.get::<Option<bool>>("allow_unix")?.unwrap_or(false),
+    })
+}
+
+/// Convert a profile back into its table, naming the syscalls which have a name
+pub fn profile_table(lua: &Lua, profile: &Profile) -> LuaResult<LuaTable> {
+    let table = lua.create_table()?;
+    table.set("default", profile.default.name())?;
+    table.set("action", profile.action.name())?;
+    let syscalls = lua.create_table()?;
+    for nr in &profile.syscalls {
+        match SYSCALLS.get(*nr as usize).filter(|name| !name.is_empty()) {
+            Some(name) => syscalls.push(*name)?,
+            None => syscalls.push(*nr)?,
+        }
+    }
+    table.set("syscalls", syscalls)?;
+    table.set("allow_unix", profile.allow_unix)?;
+    Ok(table)
+}
+
+/// Read the `seccomp` option of `init.exec`, a preset name, a Lua profile file, or a profile table
+pub fn seccomp_option(lua: &Lua, options: &LuaTable) -> LuaResult<Option<Profile>> {
+    match options.get::<LuaValue>("seccomp")? {
+        LuaValue::Nil => Ok(None),
+        LuaValue::Table(table) => profile_from_table(&table).map(Some),
+        value => {
+            let name = value.to_string()?;
+            if let Some(profile) = preset(&name) {
+                return Ok(Some(profile));
+            }
+            if !name.contains('/') {
+                return Err(LuaError::runtime(format!(
+                    "unknown seccomp preset '{}'",
+                    name
+                )));
+            }
+            let table = lua.load(Path::new(&name)).eval::<LuaTable>()?;
+            profile_from_table(&table).map(Some)
+        }
+    }
+}
+
+/// Return a filter instruction which loads a word or returns
+fn statement(code: u16, k: u32) -> SockFilter {
+    SockFilter::new(code, 0, 0, k)
+}
+
+/// Return a filter instruction which skips `jt` instructions if a jump is taken, else `jf`
+fn jump(code: u16, k: u32, jt: u8, jf: u8) -> SockFilter {
+    SockFilter::new(code, jt, jf, k)
+}
+
+/// Compile a profile into a filter program for this architecture
+///
+/// Syscalls from other architectures, such as the x32 ABI, kill the process so
+/// they cannot be used to get around the filter.
+pub fn filter(profile: &Profile) -> std::io::Result<Vec<SockFilter>> {
+    let arch = AUDIT_ARCH.ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "seccomp filters are not supported on this architecture",
+        )
+    })?;
+    let kill = Action::Kill.ret();
+    let mut program = vec![
+        statement(BPF_LD_W_ABS, OFFSET_ARCH),
+        jump(BPF_JEQ_K, arch, 1, 0),
+        statement(BPF_RET_K, kill),
+        statement(BPF_LD_W_ABS, OFFSET_NR),
+    ];
+    if cfg!(target_arch = "x86_64") {
+        program.push(jump(BPF_JGE_K, X32_SYSCALL_BIT, 0, 1));
+        program.push(statement(BPF_RET_K, kill));
+    }
+    let socket = syscall("socket");
+    for nr in &profile.syscalls {
+        if profile.allow_unix && Some(*nr) == socket {
+            program.push(jump(BPF_JEQ_K, *nr, 0, 4));
+            program.push(statement(BPF_LD_W_ABS, OFFSET_ARG0));
+            program.push(jump(BPF_JEQ_K, AF_UNIX, 0, 1));
+            program.push(statement(BPF_RET_K, Action::Allow.ret()));
+        } else {
+            program.push(jump(BPF_JEQ_K, *nr, 0, 1));
+        }
+        program.push(statement(BPF_RET_K, profile.action.ret()));
+    }
+    program.push(statement(BPF_RET_K, profile.default.ret()));
+    Ok(program)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_syscall() {
+        assert!(syscall("read").is_some());
+        assert_eq!(syscall("luavisors"), None);
+        assert_eq!(syscall(""), None);
+        let preset = preset("no_network").unwrap();
+        assert!(preset.syscalls.contains(&syscall("socket").unwrap()));
+        assert!(!super::preset("default").unwrap().allow_unix);
+        assert!(super::preset("paranoid").is_none());
+    }
+
+    #[test]
+    fn test_profile_from_table() {
+        let lua = Lua::new();
+        let table = lua
+            .load("{ default = 'kill', syscalls = { 'read', 'write', 'exit_group' } }")
+            .eval::<LuaTable>()
+            .unwrap();
+        let profile = profile_from_table(&table).unwrap();
+        assert_eq!(
+            (profile.default, profile.action),
+            (Action::Kill, Action::Allow)
+        );
+        assert_eq!(profile.syscalls.len(), 3);
+        let table = profile_table(&lua, &profile).unwrap();
+        assert_eq!(profile_from_table(&table).unwrap(), profile);
+        let table = lua.load("{ syscalls = { 'fly' } }").eval().unwrap();
+        assert!(profile_from_table(&table).is_err());
+        let table = lua.load("{ default = 'maybe' }").eval().unwrap();
+        assert!(profile_from_table(&table).is_err());
+    }
+
+    #[test]
+    fn test_seccomp_option() {
+        let lua = Lua::new();
+        let path =
+            std::env::temp_dir().join(format!("luavisors-seccomp-{}.lua", std::process::id()));
+        std::fs::write(&path, "return { syscalls = { 'ptrace' } }").unwrap();
+        let options = lua.create_table().unwrap();
+        options.set("seccomp", path.display().to_string()).unwrap();
+        let profile = seccomp_option(&lua, &options);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            profile.unwrap().unwrap().syscalls,
+            [syscall("ptrace").unwrap()]
+        );
+        options.set("seccomp", "default").unwrap();
+        assert_eq!(seccomp_option(&lua, &options).unwrap(), preset("default"));
+        options.set("seccomp", "paranoid").unwrap();
+        assert!(seccomp_option(&lua, &options).is_err());
+        assert_eq!(
+            seccomp_option(&lua, &lua.create_table().unwrap()).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_filter() {
+        let profile = preset("no_network").unwrap();
+        let program = filter(&profile).unwrap();
+        let header = if cfg!(target_arch = "x86_64") { 6 } else { 4 };
+        assert_eq!(program.len(), header + 2 * profile.syscalls.len() + 3 + 1);
+        assert_eq!(
+            program.last(),
+            Some(&statement(BPF_RET_K, Action::Allow.ret()))
+        );
+    }
+}
@@ -0,0 +1,170 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, OnceLock},
+};
+
+use mlua::prelude::*;
+
+/// Directory searched for secrets given by name, as used by Docker and Kubernetes
+const SECRETS_DIR: &str = "/run/secrets";
+
+/// Text which replaces secret values in logs
+const MASK: &str = "******";
+
+/// Secret value which prints masked and is only revealed on request
+#[derive(Clone)]
+pub struct Secret(Arc<str>);
+
+impl Secret {
+    /// Create a secret and register its value for masking
+    pub fn new(value: &str) -> Self {
+        if !value.is_empty() {
+            let mut values = registry().lock().unwrap_or_else(|err| err.into_inner());
+            if !values.iter().any(|known| known == value) {
+                values.push(value.to_string());
+                // mask longer values first so a secret containing another is fully hidden
+                values.sort_by_key(|known| std::cmp::Reverse(known.len()));
+            }
+        }
+        Secret(Arc::from(value))
+    }
+
+    /// Return the secret value
+    pub fn reveal(&self) -> &str {
+        &self.0
+    }
+}
+
+/// Lua methods for secrets
+impl LuaUserData for Secret {
+    fn add_methods<M: LuaUserDataMethods<Self>>(methods: &mut M) {
+        methods.add_method("reveal", |_, this, ()| Ok(this.reveal().to_string()));
+        methods.add_meta_method(LuaMetaMethod::ToString, |_, _, ()| Ok(MASK));
+    }
+}
+
+/// Convert a Lua value into a secret
+impl FromLua for Secret {
+    fn from_lua(value: LuaValue, _lua: &Lua) -> LuaResult<Self> {
+        match value {
+            LuaValue::UserData(ud) if ud.is::<Secret>() => Ok(ud.borrow::<Self>()?.clone()),
+            value => Err(LuaError::runtime(format!(
+                "expected a secret, got a value of type '{}'",
+                value.type_name()
+            ))),
+        }
+    }
+}
+
+/// Return the values of every secret created so far
+fn registry() -> &'static Mutex<Vec<String>> {
+    static SECRETS: OnceLock<Mutex<Vec<String>>> = OnceLock::new();
+    SECRETS.get_or_init(Mutex::default)
+}
+
+/// Replace every registered secret value in `text`
+pub fn mask(text: &str) -> String {
+    let values = registry().lock().unwrap_or_else(|err| err.into_inner());
+    values
+        .iter()
+        .fold(text.to_string(), |text, value| text.replace(value, MASK))
+}
+
+/// Resolve a secret name to a file in the secrets directory, leaving paths as is
+fn secret_path(name: &str) -> PathBuf {
+    match name.contains('/') {
+        true => PathBuf::from(name),
+        false => Path::new(SECRETS_DIR).join(name),
+    }
+}
+
+/// Read a secret from a file, without its trailing newline
+async fn file(_lua: Lua, name: String) -> LuaResult<Secret> {
+    let path = secret_path(&name);
+    let value = smol::fs::read_to_string(&path).await.map_err(|err| {
+        LuaError::runtime(format!(
+            "failed to read secret '{}': {}",
+            path.display(),
+            err
+        ))
+    })?;
+    Ok(Secret::new(value.trim_end_matches(['\r', '\n'])))
+}
+
+/// Read a secret from an environment variable and remove it so children do not inherit it
+async fn env(_lua: Lua, name: String) -> LuaResult<Option<Secret>> {
+    let Ok(value) = std::env::var(&name) else {
+        return Ok(None);
+    };
+    std::env::remove_var(&name);
+    Ok(Some(Secret::new(&value)))
+}
+
+/// Register a value as a secret from Lua
+async fn wrap(_lua: Lua, value: String) -> LuaResult<Secret> {
+    Ok(Secret::new(&value))
+}
+
+/// Mask registered secrets in a string from Lua
+async fn lua_mask(_lua: Lua, text: String) -> LuaResult<String> {
+    Ok(mask(&text))
+}
+
+/// Return the `init.secrets` Lua table
+pub fn secrets_table(lua: &Lua) -> LuaResult<LuaTable> {
+    let table = lua.create_table()?;
+    table.set("file", lua.create_async_function(file)?)?;
+    table.set("env", lua.create_async_function(env)?)?;
+    table.set("wrap", lua.create_async_function(wrap)?)?;
+    table.set("mask", lua.create_async_function(lua_mask)?)?;
+    Ok(table)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mask() {
+        Secret::new("hunter2");
+        Secret::new("hunter2-long");
+        assert_eq!(mask("password is hunter2"), "password is ******");
+        assert_eq!(mask("token hunter2-long"), "token ******");
+        assert_eq!(mask("nothing here"), "nothing here");
+    }
+
+    #[test]
+    fn test_secret_path() {
+        assert_eq!(secret_path("db"), PathBuf::from("/run/secrets/db"));
+        assert_eq!(secret_path("./db"), PathBuf::from("./db"));
+    }
+
+    #[test]
+    fn test_file() {
+        smol::block_on(async {
+            let path =
+                std::env::temp_dir().join(format!("luavisors-secret-{}", std::process::id()));
+            std::fs::write(&path, "s3cr3t-file\n").unwrap();
+            let lua = Lua::new();
+            let secret = file(lua.clone(), path.display().to_string()).await.unwrap();
+            std::fs::remove_file(&path).unwrap();
+            assert_eq!(secret.reveal(), "s3cr3t-file");
+            lua.globals().set("secret", secret).unwrap();
+            let shown: String = lua.load("return tostring(secret)").eval().unwrap();
+            assert_eq!(shown, MASK);
+            assert!(file(lua, "/nonexistent/secret".to_string()).await.is_err());
+        });
+    }
+
+    #[test]
+    fn test_env() {
+        smol::block_on(async {
+            let name = "LUAVISORS_TEST_SECRET";
+            std::env::set_var(name, "s3cr3t-env");
+            let secret = env(Lua::new(), name.to_string()).await.unwrap().unwrap();
+            assert_eq!(secret.reveal(), "s3cr3t-env");
+            assert!(std::env::var(name).is_err());
+            assert!(env(Lua::new(), name.to_string()).await.unwrap().is_none());
+        });
+    }
+}
@@ -0,0 +1,242 @@
+use mlua::prelude::*;
+
+/// Check whether a character never needs quoting in a POSIX shell
+fn is_safe(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "@%+=:,./_-".contains(c)
+}
+
+/// Quote a string so a POSIX shell treats it as a single word
+pub fn quote(s: &str) -> String {
+    if !s.is_empty() && s.chars().all(is_safe) {
+        return s.to_string();
+    }
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+/// Split a string into words using POSIX shell quoting rules
+pub fn split(s: &str) -> Result<Vec<String>, String> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            ' ' | '\t' | '\n' => {
+                if let Some(word) = word.take() {
+                    words.push(word);
+                }
+            }
+            '#' if word.is_none() => {
+                // comments run to the end of the line
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+            }
+            '\\' => match chars.next() {
+                Some('\n') => {}
+                Some(c) => word.get_or_insert_with(String::new).push(c),
+                None => return Err("trailing backslash".to_string()),
+            },
+            '\'' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => word.push(c),
+                        None => return Err("unterminated single quote".to_string()),
+                    }
+                }
+            }
+            '"' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('$' | '`' | '"' | '\\')) => word.push(c),
+                            Some('\n') => {}
+                            Some(c) => {
+                                word.push('\\');
+                                word.push(c);
+                            }
+                            None => return Err("unterminated double quote".to_string()),
+                        },
+                        Some(c) => word.push(c),
+                        None => return Err("unterminated double quote".to_string()),
+                    }
+                }
+            }
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    if let Some(word) = word {
+        words.push(word);
+    }
+    Ok(words)
+}
+
+/// Expand `${VAR}` and `${VAR:-default}` in a string using `lookup`
+///
+/// Unset variables expand to nothing, a default is used when the variable is
+/// unset or empty and is expanded itself, and `$${` produces a literal `${`.
+pub fn expand(s: &str, lookup: &dyn Fn(&str) -> Option<String>) -> Result<String, String> {
+    let mut out = String::new();
+    let mut rest = s;
+    while let Some(start) = rest.find('$') {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        if let Some(after) = after.strip_prefix("${") {
+            out.push_str("${");
+            rest = after;
+            continue;
+        }
+        let Some(body) = after.strip_prefix('{') else {
+            out.push('$');
+            rest = after;
+            continue;
+        };
+        let mut depth = 1;
+        let end = body
+            .char_indices()
+            .find(|&(_, c)| {
+                match c {
+                    '{' => depth += 1,
+                    '}' => depth -= 1,
+                    _ => {}
+                }
+                depth == 0
+            })
+            .map(|(i, _)| i)
+            .ok_or_else(|| format!("unterminated variable in '{}'", s))?;
+        let (name, default) = match body[..end].split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (&body[..end], None),
+        };
+        let valid = name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if name.is_empty() || !valid {
+            return Err(format!("invalid variable name '{}' in '{}'", name, s));
+        }
+        match (lookup(name), default) {
+            (Some(value), Some(default)) if value.is_empty() => {
+                out.push_str(&expand(default, lookup)?)
+            }
+            (Some(value), _) => out.push_str(&value),
+            (None, Some(default)) => out.push_str(&expand(default, lookup)?),
+            (None, None) => {}
+        }
+        rest = &body[end + 1..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// Expand variables from a table of values, falling back to the environment
+pub fn expand_with(s: &str, vars: Option<&LuaTable>) -> LuaResult<String> {
+    let lookup = |name: &str| {
+        vars.and_then(|vars| vars.get::<Option<String>>(name).ok().flatten())
+            .or_else(|| std::env::var(name).ok())
+    };
+    expand(s, &lookup).map_err(LuaError::runtime)
+}
+
+/// Expand variables in a string from Lua
+pub async fn lua_expand(_lua: Lua, (s, vars): (String, Option<LuaTable>)) -> LuaResult<String> {
+    expand_with(&s, vars.as_ref())
+}
+
+/// Quote a string for safe use in a shell command from Lua
+pub async fn shellquote(_lua: Lua, s: String) -> LuaResult<String> {
+    Ok(quote(&s))
+}
+
+/// Split a shell command into an array of arguments from Lua
+pub async fn shellsplit(_lua: Lua, s: String) -> LuaResult<Vec<String>> {
+    split(&s).map_err(LuaError::runtime)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quote() {
+        assert_eq!(quote("simple-word_1.0"), "simple-word_1.0");
+        assert_eq!(quote(""), "''");
+        assert_eq!(quote("hello world"), "'hello world'");
+        assert_eq!(quote("it's"), "'it'\\''s'");
+        assert_eq!(quote("$(rm -rf /)"), "'$(rm -rf /)'");
+    }
+
+    #[test]
+    fn test_split() {
+        let words = split(r#"cmd -a 'b c' "d \"e\"" f\ g "" # comment"#).unwrap();
+        assert_eq!(words, vec!["cmd", "-a", "b c", "d \"e\"", "f g", ""]);
+    }
+
+    #[test]
+    fn test_split_joined() {
+        let words = split(r#"a'b'"c"\d e#f"#).unwrap();
+        assert_eq!(words, vec!["abcd", "e#f"]);
+    }
+
+    #[test]
+    fn test_expand() {
+        let lookup = |name: &str| match name {
+            "HOME" => Some("/root".to_string()),
+            "EMPTY" => Some(String::new()),
+            _ => None,
+        };
+        let expand = |s| expand(s, &lookup);
+        assert_eq!(expand("${HOME}/app").unwrap(), "/root/app");
+        assert_eq!(expand("${MISSING}x").unwrap(), "x");
+        assert_eq!(expand("${EMPTY:-none}").unwrap(), "none");
+        assert_eq!(expand("${PORT:-${HOME}:80}").unwrap(), "/root:80");
+        assert_eq!(expand("$HOME $$ $${HOME}").unwrap(), "$HOME $$ ${HOME}");
+        assert!(expand("${HOME").is_err());
+        assert!(expand("${}").is_err());
+        assert!(expand("${A B}").is_err());
+    }
+
+    #[test]
+    fn test_expand_with() {
+        let lua = Lua::new();
+        let vars = lua.create_table().unwrap();
+        vars.set("PATH", "/opt/bin").unwrap();
+        assert_eq!(expand_with("${PATH}", Some(&vars)).unwrap(), "/opt/bin");
+        let path = std::env::var("PATH").unwrap();
+        assert_eq!(expand_with("${PATH}", None).unwrap(), path);
+    }
+
+    #[test]
+    fn test_split_err() {
+        assert!(split("'unterminated").is_err());
+        assert!(split("\"unterminated").is_err());
+        assert!(split("trailing\\").is_err());
+    }
+
+    #[test]
+    fn test_quote_split_roundtrip() {
+        let args = ["a b", "it's", "", "$HOME", "\"quoted\"", "back\\slash"];
+        let line = args
+            .iter()
+            .map(|arg| quote(arg))
+            .collect::<Vec<_>>()
+            .join(" ");
+        assert_eq!(split(&line).unwrap(), args);
+    }
+
+    #[test]
+    fn test_shellsplit() {
+        let lua = Lua::new();
+        let words = smol::block_on(shellsplit(lua, "a 'b c'".to_string())).unwrap();
+        assert_eq!(words, vec!["a", "b c"]);
+    }
+
+    #[test]
+    fn test_shellquote() {
+        let lua = Lua::new();
+        let quoted = smol::block_on(shellquote(lua, "a b".to_string())).unwrap();
+        assert_eq!(quoted, "'a b'");
+    }
+}
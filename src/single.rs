@@ -0,0 +1,88 @@
+use mlua::prelude::*;
+
+use crate::{
+    boot,
+    errors::{not_found, AppResult},
+    process, reaper, unix,
+};
+
+/// Execute a command with the supervisor's terminal and return its shell style exit code
+async fn exec_single(lua: &Lua, args: &[String]) -> LuaResult<i32> {
+    let options = lua.create_sequence_from(args.iter().map(String::as_str))?;
+    options.set("stdout", "inherit")?;
+    options.set("stderr", "inherit")?;
+    let value = LuaValue::Table(options);
+    let child = process::exec(lua.clone(), (value, LuaMultiValue::new())).await?;
+    let status: LuaTable = child
+        .get::<LuaFunction>("status")?
+        .call_async(&child)
+        .await?;
+    Ok(match status.get::<Option<i32>>("signal")? {
+        Some(signal) => 128 + signal,
+        None => status.get::<Option<i32>>("code")?.unwrap_or(1),
+    })
+}
+
+/// Run `luavisors --single` with the command following it, like tini
+///
+/// Every signal is forwarded to the command and orphans are reaped, and the
+/// exit code is that of the command, or 128 plus the signal which killed it.
+pub async fn run(args: &[String]) -> AppResult<i32> {
+    let args = args.strip_prefix(&["--".to_string()]).unwrap_or(args);
+    if args.is_empty() {
+        return Err(not_found("missing command to run").into());
+    }
+    let lua = Lua::new();
+    if !boot::is_pid1() {
+        unix::set_child_subreaper(true)?;
+    }
+    reaper::start(&lua);
+    Ok(exec_single(&lua, args).await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exec_single() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+            let code = exec_single(&lua, &args(&["sh", "-c", "exit 3"])).await;
+            assert_eq!(code.unwrap(), 3);
+            let code = exec_single(&lua, &args(&["sh", "-c", "kill -TERM $$"])).await;
+            assert_eq!(code.unwrap(), 143);
+            assert!(exec_single(&lua, &args(&["luavisors-missing-command"]))
+                .await
+                .is_err());
+        });
+    }
+
+    #[test]
+    fn test_exec_single_forwards_signals() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            // SIGWINCH is ignored by default, so children of other tests are unaffected
+            let script = "trap 'exit 7' WINCH; while :; do sleep 0.01; done";
+            let args = ["sh", "-c", script].map(str::to_string);
+            let signal = async {
+                smol::Timer::after(std::time::Duration::from_millis(200)).await;
+                let pid = std::process::id() as i32;
+                unix::kill(pid, async_signal::Signal::Winch as i32)
+                    .await
+                    .unwrap();
+                std::future::pending().await
+            };
+            let code = smol::future::or(exec_single(&lua, &args), signal).await;
+            assert_eq!(code.unwrap(), 7);
+        });
+    }
+
+    #[test]
+    fn test_run_without_command() {
+        smol::block_on(async {
+            assert!(run(&["--".to_string()]).await.is_err());
+        });
+    }
+}
@@ -0,0 +1,82 @@
+use mlua::prelude::*;
+
+/// Bytes in each supported size suffix, using binary multiples
+const SUFFIXES: [(&str, u64); 11] = [
+    ("", 1),
+    ("B", 1),
+    ("K", 1 << 10),
+    ("KB", 1 << 10),
+    ("KIB", 1 << 10),
+    ("M", 1 << 20),
+    ("MB", 1 << 20),
+    ("MIB", 1 << 20),
+    ("G", 1 << 30),
+    ("GB", 1 << 30),
+    ("GIB", 1 << 30),
+];
+
+/// Parse a size such as `512`, `16K`, or `1.5M` into bytes
+pub fn parse(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let split = s
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(s.len());
+    let (number, suffix) = s.split_at(split);
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("invalid size '{}'", s))?;
+    let suffix = suffix.trim().to_ascii_uppercase();
+    let scale = SUFFIXES
+        .iter()
+        .find(|(name, _)| *name == suffix)
+        .map(|(_, scale)| *scale)
+        .ok_or_else(|| format!("unknown unit '{}' in size '{}'", suffix, s))?;
+    Ok((number * scale as f64) as u64)
+}
+
+/// Number of bytes given as a number or size string
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bytes(pub u64);
+
+/// Convert a Lua number or size string into bytes
+impl FromLua for Bytes {
+    fn from_lua(value: LuaValue, lua: &Lua) -> LuaResult<Self> {
+        match value {
+            LuaValue::String(s) => parse(&s.to_str()?).map(Bytes).map_err(LuaError::runtime),
+            value => Ok(Bytes(u64::from_lua(value, lua)?)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse("512"), Ok(512));
+        assert_eq!(parse("16K"), Ok(16384));
+        assert_eq!(parse("16M"), Ok(16 << 20));
+        assert_eq!(parse("1.5 MiB"), Ok(3 << 19));
+        assert_eq!(parse("2gb"), Ok(2 << 30));
+    }
+
+    #[test]
+    fn test_parse_err() {
+        assert!(parse("").is_err());
+        assert!(parse("M").is_err());
+        assert!(parse("10X").is_err());
+    }
+
+    #[test]
+    fn test_bytes_from_lua() {
+        let lua = Lua::new();
+        let value = LuaValue::String(lua.create_string("1K").unwrap());
+        assert_eq!(Bytes::from_lua(value, &lua).unwrap(), Bytes(1024));
+        assert_eq!(
+            Bytes::from_lua(LuaValue::Integer(7), &lua).unwrap(),
+            Bytes(7)
+        );
+        assert!(Bytes::from_lua(LuaValue::Integer(-1), &lua).is_err());
+    }
+}
@@ -0,0 +1,111 @@
+use std::sync::OnceLock;
+
+use mlua::prelude::*;
+use smol::{
+    io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, BufReader},
+    lock::Mutex,
+    Unblock,
+};
+
+/// Buffered standard input shared by all Lua tasks
+type Stdin = Mutex<BufReader<Unblock<std::io::Stdin>>>;
+
+/// Return the shared standard input reader
+pub fn stdin() -> &'static Stdin {
+    static STDIN: OnceLock<Stdin> = OnceLock::new();
+    STDIN.get_or_init(|| Mutex::new(BufReader::new(Unblock::new(std::io::stdin()))))
+}
+
+/// Read a single line without the trailing newline, or `None` at end of input
+pub async fn next_line<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+) -> std::io::Result<Option<Vec<u8>>> {
+    let mut line = Vec::new();
+    if reader.read_until(b'\n', &mut line).await? == 0 {
+        return Ok(None);
+    }
+    if line.ends_with(b"\n") {
+        line.pop();
+        if line.ends_with(b"\r") {
+            line.pop();
+        }
+    }
+    Ok(Some(line))
+}
+
+/// Read everything until the end of input
+async fn remaining<R: AsyncBufRead + Unpin>(reader: &mut R) -> std::io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data).await?;
+    Ok(data)
+}
+
+/// Read a line from standard input into a Lua string
+async fn read_line(lua: Lua, _: ()) -> LuaResult<LuaValue> {
+    let mut reader = stdin().lock().await;
+    match next_line(&mut *reader).await? {
+        Some(line) => Ok(LuaValue::String(lua.create_string(&line)?)),
+        None => Ok(LuaValue::Nil),
+    }
+}
+
+/// Read the rest of standard input into a Lua string
+async fn read_all(lua: Lua, _: ()) -> LuaResult<LuaValue> {
+    let mut reader = stdin().lock().await;
+    let data = remaining(&mut *reader).await?;
+    if data.is_empty() {
+        return Ok(LuaValue::Nil);
+    }
+    Ok(LuaValue::String(lua.create_string(&data)?))
+}
+
+/// Return the `init.stdin` Lua table
+pub fn stdin_table(lua: &Lua) -> LuaResult<LuaTable> {
+    let table = lua.create_table()?;
+    table.set("read_line", lua.create_async_function(read_line)?)?;
+    table.set("read_all", lua.create_async_function(read_all)?)?;
+    Ok(table)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_next_line() {
+        smol::block_on(async {
+            let mut reader = smol::io::Cursor::new(b"one\ntwo\r\nthree".to_vec());
+            assert_eq!(next_line(&mut reader).await.unwrap().unwrap(), b"one");
+            assert_eq!(next_line(&mut reader).await.unwrap().unwrap(), b"two");
+            assert_eq!(next_line(&mut reader).await.unwrap().unwrap(), b"three");
+            assert!(next_line(&mut reader).await.unwrap().is_none());
+        });
+    }
+
+    #[test]
+    fn test_next_line_empty() {
+        smol::block_on(async {
+            let mut reader = smol::io::Cursor::new(b"\n".to_vec());
+            assert!(next_line(&mut reader).await.unwrap().unwrap().is_empty());
+            assert!(next_line(&mut reader).await.unwrap().is_none());
+        });
+    }
+
+    #[test]
+    fn test_remaining() {
+        smol::block_on(async {
+            let mut reader = smol::io::Cursor::new(b"one\ntwo\n".to_vec());
+            next_line(&mut reader).await.unwrap();
+            assert_eq!(remaining(&mut reader).await.unwrap(), b"two\n");
+            assert!(remaining(&mut reader).await.unwrap().is_empty());
+        });
+    }
+
+    #[test]
+    fn test_stdin_table() {
+        let lua = Lua::new();
+        let table = stdin_table(&lua).unwrap();
+        assert!(table.get::<LuaFunction>("read_line").is_ok());
+        assert!(table.get::<LuaFunction>("read_all").is_ok());
+    }
+}
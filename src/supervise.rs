@@ -0,0 +1,1259 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex as StdMutex},
+    time::{Duration, Instant},
+};
+
+use mlua::prelude::*;
+
+use crate::{
+    cancel::{self, CancelToken},
+    duration::Seconds,
+    health::{self, HealthCheck},
+    process, random,
+    sync::{self, Event},
+    task,
+};
+
+/// Fields of `init.supervise` options which are not passed on to `init.exec`
+const SERVICE_KEYS: [&str; 13] = [
+    "name",
+    "cmd",
+    "args",
+    "restart",
+    "max_restarts",
+    "delay",
+    "backoff",
+    "crash_loop",
+    "on_crash_loop",
+    "healthcheck",
+    "on_unhealthy",
+    "ready",
+    "after",
+];
+
+/// How often a readiness file is checked for
+const READY_POLL: Duration = Duration::from_millis(50);
+
+/// When a service is started again after its process exits
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Restart {
+    Always,
+    OnFailure,
+    Never,
+}
+
+impl Restart {
+    /// Parse a restart policy from its name
+    fn parse(name: &str) -> LuaResult<Self> {
+        match name {
+            "always" => Ok(Restart::Always),
+            "on-failure" => Ok(Restart::OnFailure),
+            "never" => Ok(Restart::Never),
+            _ => Err(LuaError::runtime(format!(
+                "unknown restart policy '{}'",
+                name
+            ))),
+        }
+    }
+
+    /// Check whether a process which exited, successfully or not, is started again
+    fn restarts(self, success: bool) -> bool {
+        match self {
+            Restart::Always => true,
+            Restart::OnFailure => !success,
+            Restart::Never => false,
+        }
+    }
+}
+
+/// How the delay before starting a service again grows while it keeps exiting
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Backoff {
+    initial: f64,
+    max: f64,
+    multiplier: f64,
+    jitter: f64,
+}
+
+impl Backoff {
+    /// Backoff which always waits the same delay
+    fn constant(delay: f64) -> Self {
+        Backoff {
+            initial: delay,
+            max: delay,
+            multiplier: 1.0,
+            jitter: 0.0,
+        }
+    }
+
+    /// Read a backoff table, whose initial delay defaults to `delay`
+    fn from_table(table: &LuaTable, delay: f64) -> LuaResult<Self> {
+        let jitter: f64 = table.get::<Option<f64>>("jitter")?.unwrap_or(0.0);
+        if !(0.0..=1.0).contains(&jitter) {
+            return Err(LuaError::runtime("backoff jitter must be between 0 and 1"));
+        }
+        let multiplier: f64 = table.get::<Option<f64>>("multiplier")?.unwrap_or(2.0);
+        if multiplier < 1.0 {
+            return Err(LuaError::runtime("backoff multiplier must be at least 1"));
+        }
+        let initial = table
+            .get::<Option<Seconds>>("initial")?
+            .map_or(delay, |s| s.0);
+        Ok(Backoff {
+            initial,
+            max: table
+                .get::<Option<Seconds>>("max")?
+                .map_or(60f64.max(initial), |s| s.0),
+            multiplier,
+            jitter,
+        })
+    }
+
+    /// Delay before the `failures`-th consecutive restart, shortened by up to `jitter` of it
+    fn delay(&self, failures: u32) -> Duration {
+        let exponent = failures.saturating_sub(1).min(i32::MAX as u32) as i32;
+        let delay = (self.initial * self.multiplier.powi(exponent)).min(self.max);
+        Duration::from_secs_f64(delay * (1.0 - self.jitter * random::random_f64()))
+    }
+}
+
+/// Number of exits within a period after which a service is failed as crash looping
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct CrashLoop {
+    crashes: usize,
+    within: Duration,
+}
+
+/// Read the `crash_loop` option, which needs a number of crashes
+fn crash_loop_option(options: &LuaTable) -> LuaResult<Option<CrashLoop>> {
+    let Some(table) = options.get::<Option<LuaTable>>("crash_loop")? else {
+        return Ok(None);
+    };
+    let crashes = table
+        .get::<Option<usize>>("crashes")?
+        .ok_or_else(|| LuaError::runtime("crash_loop needs a number of crashes"))?;
+    Ok(Some(CrashLoop {
+        crashes: crashes.max(1),
+        within: table
+            .get::<Option<Seconds>>("within")?
+            .map_or(Duration::from_secs(60), Seconds::duration),
+    }))
+}
+
+/// How a service reports that it is ready, rather than as soon as it starts
+#[derive(Debug, Clone, PartialEq)]
+enum Ready {
+    Started,
+    Fd,
+    File(String),
+}
+
+/// Read the `ready` option, passing a readiness fd on to `init.exec`
+fn ready_option(options: &LuaTable, exec: &LuaTable) -> LuaResult<Ready> {
+    let Some(table) = options.get::<Option<LuaTable>>("ready")? else {
+        return Ok(Ready::Started);
+    };
+    if let Some(fd) = table.get::<Option<i32>>("fd")? {
+        exec.set("ready_fd", fd)?;
+        return Ok(Ready::Fd);
+    }
+    match table.get::<Option<String>>("file")? {
+        Some(path) => Ok(Ready::File(path)),
+        None => Err(LuaError::runtime("ready needs an fd or a file")),
+    }
+}
+
+/// Options of a service, with the options table of `init.exec` which starts it
+struct ServiceOptions {
+    name: Option<String>,
+    exec: LuaTable,
+    restart: Restart,
+    max_restarts: Option<u32>,
+    backoff: Backoff,
+    crash_loop: Option<CrashLoop>,
+    on_crash_loop: Option<LuaFunction>,
+    healthcheck: Option<HealthCheck>,
+    on_unhealthy: Option<LuaFunction>,
+    ready: Ready,
+}
+
+/// Read the options of `init.supervise`, passing the fields it does not know to `init.exec`
+fn service_options(
+    lua: &Lua,
+    options: &LuaTable,
+    name: Option<String>,
+) -> LuaResult<ServiceOptions> {
+    let cmd: String = options
+        .get::<Option<String>>("cmd")?
+        .ok_or_else(|| LuaError::runtime("supervise needs a cmd"))?;
+    let args = options.get::<Option<Vec<String>>>("args")?;
+    let exec = lua.create_sequence_from(std::iter::once(cmd).chain(args.into_iter().flatten()))?;
+    for pair in options.pairs::<LuaValue, LuaValue>() {
+        let (key, value) = pair?;
+        let known = matches!(&key, LuaValue::String(key)
+            if key.to_str().is_ok_and(|key| SERVICE_KEYS.contains(&&*key)));
+        if !known {
+            exec.set(key, value)?;
+        }
+    }
+    let restart = match options.get::<Option<String>>("restart")? {
+        Some(name) => Restart::parse(&name)?,
+        None => Restart::OnFailure,
+    };
+    let delay = options
+        .get::<Option<Seconds>>("delay")?
+        .map_or(1.0, |s| s.0);
+    let backoff = match options.get::<Option<LuaTable>>("backoff")? {
+        Some(table) => Backoff::from_table(&table, delay)?,
+        None => Backoff::constant(delay),
+    };
+    Ok(ServiceOptions {
+        name,
+        restart,
+        max_restarts: options.get("max_restarts")?,
+        backoff,
+        crash_loop: crash_loop_option(options)?,
+        on_crash_loop: options.get("on_crash_loop")?,
+        healthcheck: health::healthcheck_option(options)?,
+        on_unhealthy: options.get("on_unhealthy")?,
+        ready: ready_option(options, &exec)?,
+        exec,
+    })
+}
+
+/// What a service is doing, as reported by its `status`
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+enum Phase {
+    Running,
+    Restarting,
+    #[default]
+    Stopped,
+    Exited,
+    Failed,
+}
+
+impl Phase {
+    /// Return the name of a phase
+    fn name(self) -> &'static str {
+        match self {
+            Phase::Running => "running",
+            Phase::Restarting => "restarting",
+            Phase::Stopped => "stopped",
+            Phase::Exited => "exited",
+            Phase::Failed => "failed",
+        }
+    }
+}
+
+/// Mutable state of a service, shared with the task which runs it
+#[derive(Default)]
+struct State {
+    phase: Phase,
+    child: Option<LuaTable>,
+    pid: Option<u32>,
+    runs: u64,
+    healthy: Option<bool>,
+    last: Option<LuaTable>,
+    restarts: u32,
+    failures: u32,
+    crashes: VecDeque<Instant>,
+    looping: bool,
+    wanted: bool,
+    restart_now: bool,
+}
+
+/// Service which runs a command and starts it again according to its restart policy
+#[derive(Clone)]
+pub struct Service {
+    options: Arc<ServiceOptions>,
+    state: Arc<StdMutex<State>>,
+    ready: Event,
+}
+
+impl Service {
+    /// Create a stopped service from its options, publishing its states under `name`
+    fn new(lua: &Lua, options: &LuaTable, name: Option<String>) -> LuaResult<Self> {
+        Ok(Service {
+            options: Arc::new(service_options(lua, options, name)?),
+            state: Arc::default(),
+            ready: Event::default(),
+        })
+    }
+
+    /// Lock the state of the service
+    fn state(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Publish the state of a named service to `init.state`, which is `healthy`
+    /// or `unhealthy` for a running service once it has been probed
+    fn publish(&self, state: &State) {
+        let Some(name) = &self.options.name else {
+            return;
+        };
+        let value = match (state.phase, state.healthy) {
+            (Phase::Running, Some(true)) => "healthy",
+            (Phase::Running, Some(false)) => "unhealthy",
+            (phase, _) => phase.name(),
+        };
+        sync::set_state(name, value);
+    }
+
+    /// Start the service unless it is already running
+    fn start(&self, lua: &Lua) {
+        let mut state = self.state();
+        state.wanted = true;
+        if state.looping {
+            return;
+        }
+        state.looping = true;
+        state.restarts = 0;
+        state.failures = 0;
+        state.crashes.clear();
+        state.phase = Phase::Running;
+        self.publish(&state);
+        smol::spawn(self.clone().run(lua.clone())).detach();
+    }
+
+    /// Start the command and wait for it to exit, returning its status if it could be started
+    async fn run_once(&self, lua: &Lua) -> LuaResult<LuaTable> {
+        self.ready.clear();
+        if let Ready::File(path) = &self.options.ready {
+            // a file left by an earlier run must not report this one as ready
+            match smol::fs::remove_file(path).await {
+                Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err.into()),
+                _ => {}
+            }
+        }
+        let value = LuaValue::Table(self.options.exec.clone());
+        let child = process::exec(lua.clone(), (value, LuaMultiValue::new())).await?;
+        // `pid` waits for a running `status` call, so it is read before waiting
+        let pid = child.get::<LuaFunction>("pid")?.call_async(&child).await?;
+        let (run, wanted) = {
+            let mut state = self.state();
+            state.child = Some(child.clone());
+            state.pid = pid;
+            state.runs += 1;
+            state.healthy = None;
+            state.phase = Phase::Running;
+            self.publish(&state);
+            (state.runs, state.wanted)
+        };
+        if !wanted {
+            // the service was stopped while the process was starting
+            let stop = child.get::<LuaFunction>("stop")?;
+            stop.call_async::<()>(&child).await?;
+            return child.get::<LuaFunction>("status")?.call_async(&child).await;
+        }
+        match &self.options.ready {
+            Ready::Started => self.ready.set(),
+            _ => smol::spawn(self.clone().watch_ready(run, child.clone())).detach(),
+        }
+        if self.options.healthcheck.is_some() {
+            smol::spawn(self.clone().watch_health(lua.clone(), run, child.clone())).detach();
+        }
+        child.get::<LuaFunction>("status")?.call_async(&child).await
+    }
+
+    /// Wait for a run of the process to report that it is ready, setting the ready event if it does
+    async fn watch_ready(self, run: u64, child: LuaTable) {
+        let current = || {
+            let state = self.state();
+            state.runs == run && state.child.is_some()
+        };
+        let ready = match &self.options.ready {
+            Ready::Started => true,
+            Ready::Fd => match child.get::<LuaFunction>("wait_ready") {
+                Ok(wait_ready) => wait_ready.call_async(&child).await.unwrap_or(false),
+                Err(_) => false,
+            },
+            Ready::File(path) => loop {
+                if smol::fs::metadata(path).await.is_ok() {
+                    break true;
+                }
+                if !current() {
+                    break false;
+                }
+                smol::Timer::after(READY_POLL).await;
+            },
+        };
+        if ready && current() {
+            self.ready.set();
+        }
+    }
+
+    /// Wait until the running process is ready, returning false if the timeout passes first
+    async fn wait_ready(&self, timeout: Option<Seconds>) -> bool {
+        match timeout {
+            Some(timeout) => {
+                smol::future::or(
+                    async {
+                        self.ready.wait().await;
+                        true
+                    },
+                    async {
+                        smol::Timer::after(timeout.duration()).await;
+                        false
+                    },
+                )
+                .await
+            }
+            None => {
+                self.ready.wait().await;
+                true
+            }
+        }
+    }
+
+    /// Probe a running process until it exits, restarting it once it fails too many probes in a row
+    async fn watch_health(self, lua: Lua, run: u64, child: LuaTable) {
+        let Some(check) = &self.options.healthcheck else {
+            return;
+        };
+        let current = |state: &State| state.runs == run && state.child.is_some();
+        let mut failures = 0;
+        let reason = loop {
+            smol::Timer::after(check.interval).await;
+            if !current(&self.state()) {
+                return;
+            }
+            let probed = health::probe(&lua, check)
+                .await
+                .unwrap_or_else(|err| Err(err.to_string()));
+            let mut state = self.state();
+            if !current(&state) {
+                return;
+            }
+            match probed {
+                Ok(()) => {
+                    failures = 0;
+                    state.healthy = Some(true);
+                    self.publish(&state);
+                }
+                Err(reason) => {
+                    failures += 1;
+                    if failures >= check.failures {
+                        state.healthy = Some(false);
+                        self.publish(&state);
+                        state.restart_now = true;
+                        state.restarts += 1;
+                        break reason;
+                    }
+                }
+            }
+        };
+        if let Some(on_unhealthy) = &self.options.on_unhealthy {
+            if let Err(err) = on_unhealthy.call_async::<()>(reason.as_str()).await {
+                task::report_error(&lua, "init.supervise", err, None).await;
+            }
+        }
+        let stopped = match child.get::<LuaFunction>("stop") {
+            Ok(stop) => stop.call_async::<()>(&child).await,
+            Err(err) => Err(err),
+        };
+        if let Err(err) = stopped {
+            task::report_error(&lua, "init.supervise", err, None).await;
+        }
+    }
+
+    /// Record an exit which is restarted, returning whether the service is crash looping
+    fn crashed(&self, state: &mut State, ran: Duration) -> bool {
+        // a process which stayed up longer than the longest delay resets the backoff
+        if ran.as_secs_f64() >= self.options.backoff.max {
+            state.failures = 0;
+        }
+        state.failures += 1;
+        let Some(crash_loop) = self.options.crash_loop else {
+            return false;
+        };
+        let now = Instant::now();
+        state.crashes.push_back(now);
+        while state
+            .crashes
+            .front()
+            .is_some_and(|crash| now.duration_since(*crash) > crash_loop.within)
+        {
+            state.crashes.pop_front();
+        }
+        state.crashes.len() >= crash_loop.crashes
+    }
+
+    /// Run the command until it should no longer be restarted or the service is stopped
+    async fn run(self, lua: Lua) {
+        loop {
+            let started = Instant::now();
+            let status = match self.run_once(&lua).await {
+                Ok(status) => Some(status),
+                Err(err) => {
+                    task::report_error(&lua, "init.supervise", err, None).await;
+                    None
+                }
+            };
+            let success = status
+                .as_ref()
+                .is_some_and(|status| status.get("success").unwrap_or(false));
+            let mut crash_looping = false;
+            let (phase, delay) = {
+                let mut state = self.state();
+                self.ready.clear();
+                state.child = None;
+                state.pid = None;
+                state.last = status.clone();
+                let restart_now = std::mem::take(&mut state.restart_now);
+                let phase = if !state.wanted {
+                    Phase::Stopped
+                } else if restart_now {
+                    Phase::Running
+                } else if !self.options.restart.restarts(success) {
+                    Phase::Exited
+                } else if self
+                    .options
+                    .max_restarts
+                    .is_some_and(|max| state.restarts >= max)
+                {
+                    Phase::Failed
+                } else if self.crashed(&mut state, started.elapsed()) {
+                    crash_looping = true;
+                    Phase::Failed
+                } else {
+                    state.restarts += 1;
+                    Phase::Restarting
+                };
+                state.phase = phase;
+                self.publish(&state);
+                if !matches!(phase, Phase::Running | Phase::Restarting) {
+                    state.looping = false;
+                }
+                (phase, self.options.backoff.delay(state.failures))
+            };
+            match phase {
+                Phase::Running => continue,
+                Phase::Restarting => {}
+                _ => {
+                    if let Some(on_crash_loop) = self
+                        .options
+                        .on_crash_loop
+                        .as_ref()
+                        .filter(|_| crash_looping)
+                    {
+                        if let Err(err) = on_crash_loop.call_async::<()>(status).await {
+                            task::report_error(&lua, "init.supervise", err, None).await;
+                        }
+                    }
+                    return;
+                }
+            }
+            smol::Timer::after(delay).await;
+            let mut state = self.state();
+            if !state.wanted {
+                state.phase = Phase::Stopped;
+                self.publish(&state);
+                state.looping = false;
+                return;
+            }
+        }
+    }
+
+    /// Stop the running process, returning its exit code or signal, or nil if none was running
+    async fn stop(&self, grace: Option<Seconds>) -> LuaResult<Option<i32>> {
+        let child = {
+            let mut state = self.state();
+            state.wanted = false;
+            if state.child.is_none() && state.phase == Phase::Restarting {
+                state.phase = Phase::Stopped;
+                self.publish(&state);
+            }
+            state.child.clone()
+        };
+        let Some(child) = child else {
+            return Ok(None);
+        };
+        let stop = child.get::<LuaFunction>("stop")?;
+        stop.call_async((&child, grace.map(|grace| grace.0))).await
+    }
+
+    /// Stop the running process and start it again right away, or start the service if it is not running
+    async fn restart(&self, lua: &Lua, grace: Option<Seconds>) -> LuaResult<()> {
+        let child = {
+            let mut state = self.state();
+            state.restart_now = state.child.is_some();
+            state.child.clone()
+        };
+        match child {
+            Some(child) => {
+                let stop = child.get::<LuaFunction>("stop")?;
+                stop.call_async::<()>((&child, grace.map(|grace| grace.0)))
+                    .await?;
+                self.start(lua);
+            }
+            None => self.start(lua),
+        }
+        Ok(())
+    }
+
+    /// Return the table of the phase, pid, restarts, and last exit status of the service
+    fn status(&self, lua: &Lua) -> LuaResult<LuaTable> {
+        let state = self.state();
+        let table = lua.create_table()?;
+        table.set("state", state.phase.name())?;
+        table.set("pid", state.pid)?;
+        table.set("restarts", state.restarts)?;
+        table.set("healthy", state.healthy)?;
+        table.set("ready", self.ready.is_set())?;
+        table.set("last", state.last.clone())?;
+        Ok(table)
+    }
+}
+
+/// Lua methods for services
+impl LuaUserData for Service {
+    fn add_methods<M: LuaUserDataMethods<Self>>(methods: &mut M) {
+        methods.add_method("start", |lua, this, ()| {
+            this.start(lua);
+            Ok(())
+        });
+        methods.add_async_method("stop", |_, this, grace: Option<Seconds>| {
+            let this = this.clone();
+            async move { this.stop(grace).await }
+        });
+        methods.add_async_method("restart", |lua, this, grace: Option<Seconds>| {
+            let this = this.clone();
+            async move { this.restart(&lua, grace).await }
+        });
+        methods.add_method("status", |lua, this, ()| this.status(lua));
+        methods.add_async_method("wait_ready", |_, this, timeout: Option<Seconds>| {
+            let this = this.clone();
+            async move { Ok(this.wait_ready(timeout).await) }
+        });
+    }
+}
+
+/// Start a service from Lua which restarts its command according to a policy
+///
+/// The command is `cmd` with `args`, and the other fields are passed to `init.exec`.
+/// A process which exits is started again after `delay` seconds if `restart` is
+/// `'always'`, or `'on-failure'` by default when it failed, at most `max_restarts` times.
+/// The delay grows with `backoff`, and `crash_loop` fails a service which exits too often.
+/// A `healthcheck` probe which fails `failures` times in a row restarts the process, and
+/// `ready` names an fd the process writes a line to, or a file it creates, once ready.
+/// A service with a `name` publishes its state under that name to `init.state`.
+pub async fn supervise(lua: Lua, options: LuaTable) -> LuaResult<Service> {
+    let service = Service::new(&lua, &options, options.get("name")?)?;
+    service.start(&lua);
+    Ok(service)
+}
+
+/// Order services so each comes after those it depends on, failing on unknown names and cycles
+fn start_order(services: &[(String, Vec<String>)]) -> Result<Vec<usize>, String> {
+    /// Depth first visit which appends a service once everything it depends on is ordered
+    fn visit(
+        index: usize,
+        services: &[(String, Vec<String>)],
+        path: &mut Vec<usize>,
+        order: &mut Vec<usize>,
+    ) -> Result<(), String> {
+        if order.contains(&index) {
+            return Ok(());
+        }
+        if let Some(start) = path.iter().position(|&known| known == index) {
+            let cycle: Vec<_> = path[start..]
+                .iter()
+                .chain(std::iter::once(&index))
+                .map(|&known| services[known].0.as_str())
+                .collect();
+            return Err(format!("service dependency cycle: {}", cycle.join(" -> ")));
+        }
+        path.push(index);
+        let (name, after) = &services[index];
+        for dependency in after {
+            let found = services
+                .iter()
+                .position(|(known, _)| known == dependency)
+                .ok_or_else(|| {
+                    format!(
+                        "service '{}' depends on unknown service '{}'",
+                        name, dependency
+                    )
+                })?;
+            visit(found, services, path, order)?;
+        }
+        path.pop();
+        order.push(index);
+        Ok(())
+    }
+
+    let mut order = Vec::with_capacity(services.len());
+    for index in 0..services.len() {
+        visit(index, services, &mut Vec::new(), &mut order)?;
+    }
+    Ok(order)
+}
+
+/// Named service of a group with the names of the services it starts after
+struct Member {
+    name: String,
+    after: Vec<String>,
+    service: Service,
+}
+
+/// Services which start in dependency order and stop in reverse
+#[derive(Clone)]
+pub struct ServiceGroup {
+    members: Arc<Vec<Member>>,
+    timeout: Option<Seconds>,
+}
+
+impl ServiceGroup {
+    /// Find a service of the group by name
+    fn service(&self, name: &str) -> Option<&Service> {
+        self.members
+            .iter()
+            .find(|member| member.name == name)
+            .map(|member| &member.service)
+    }
+
+    /// Wait for the services which a member starts after to be ready
+    async fn dependencies_ready(&self, member: &Member) -> LuaResult<()> {
+        for dependency in &member.after {
+            let Some(service) = self.service(dependency) else {
+                continue;
+            };
+            if !service.wait_ready(self.timeout).await {
+                return Err(LuaError::runtime(format!(
+                    "service '{}' was not ready to start '{}'",
+                    dependency, member.name
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Start each service once the services it starts after are ready
+    ///
+    /// The services already started are stopped again if one is not ready in time.
+    async fn start(&self, lua: &Lua) -> LuaResult<()> {
+        for (index, member) in self.members.iter().enumerate() {
+            if let Err(err) = self.dependencies_ready(member).await {
+                for started in self.members[..index].iter().rev() {
+                    let _ = started.service.stop(None).await;
+                }
+                return Err(err);
+            }
+            member.service.start(lua);
+        }
+        Ok(())
+    }
+
+    /// Stop each service after the services which start after it have stopped
+    async fn stop(&self, grace: Option<Seconds>) -> LuaResult<()> {
+        for member in self.members.iter().rev() {
+            member.service.stop(grace).await?;
+        }
+        Ok(())
+    }
+
+    /// Return the status table of each service by name
+    fn status(&self, lua: &Lua) -> LuaResult<LuaTable> {
+        let table = lua.create_table()?;
+        for member in self.members.iter() {
+            table.set(member.name.as_str(), member.service.status(lua)?)?;
+        }
+        Ok(table)
+    }
+}
+
+/// Lua methods for service groups
+impl LuaUserData for ServiceGroup {
+    fn add_methods<M: LuaUserDataMethods<Self>>(methods: &mut M) {
+        methods.add_async_method("start", |lua, this, ()| {
+            let this = this.clone();
+            async move { this.start(&lua).await }
+        });
+        methods.add_async_method("stop", |_, this, grace: Option<Seconds>| {
+            let this = this.clone();
+            async move { this.stop(grace).await }
+        });
+        methods.add_method("service", |_, this, name: String| {
+            Ok(this.service(&name).cloned())
+        });
+        methods.add_method("status", |lua, this, ()| this.status(lua));
+    }
+}
+
+/// Start a table of named services from Lua in the order given by their `after` fields
+///
+/// Each service waits up to `timeout` seconds for those it starts after to be ready,
+/// and the services are stopped in reverse order on shutdown.
+pub async fn services(
+    lua: Lua,
+    (specs, options): (LuaTable, Option<LuaTable>),
+) -> LuaResult<ServiceGroup> {
+    let mut named = Vec::new();
+    for pair in specs.pairs::<String, LuaTable>() {
+        let (name, spec) = pair?;
+        let after = spec
+            .get::<Option<Vec<String>>>("after")?
+            .unwrap_or_default();
+        named.push((name, after, spec));
+    }
+    // a stable order of names keeps the start order the same between runs
+    named.sort_by(|(a, _, _), (b, _, _)| a.cmp(b));
+    let graph: Vec<_> = named
+        .iter()
+        .map(|(name, after, _)| (name.clone(), after.clone()))
+        .collect();
+    let order = start_order(&graph).map_err(LuaError::runtime)?;
+    let mut members = Vec::with_capacity(order.len());
+    for index in order {
+        let (name, after, spec) = &named[index];
+        members.push(Member {
+            name: name.clone(),
+            after: after.clone(),
+            service: Service::new(&lua, spec, Some(name.clone()))?,
+        });
+    }
+    let group = ServiceGroup {
+        members: Arc::new(members),
+        timeout: match options {
+            Some(options) => options.get("timeout")?,
+            None => None,
+        },
+    };
+    group.start(&lua).await?;
+    let stopping = stop_on(cancel::shutdown().clone(), group.clone());
+    STOPPING
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .push(stopping);
+    Ok(group)
+}
+
+/// Tasks which stop service groups on shutdown, waited for before the supervisor exits
+static STOPPING: StdMutex<Vec<smol::Task<()>>> = StdMutex::new(Vec::new());
+
+/// Stop the services of a group in reverse order once `token` is cancelled
+fn stop_on(token: CancelToken, group: ServiceGroup) -> smol::Task<()> {
+    smol::spawn(async move {
+        token.wait().await;
+        let _ = group.stop(None).await;
+    })
+}
+
+/// Wait for service groups to stop when shutting down, since the script may
+/// finish before they do
+pub async fn finish_services() {
+    if !cancel::shutdown().is_cancelled() {
+        return;
+    }
+    let stopping = std::mem::take(&mut *STOPPING.lock().unwrap_or_else(|err| err.into_inner()));
+    // later groups may depend on earlier ones, so they finish stopping first
+    for task in stopping.into_iter().rev() {
+        task.await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lua() -> Lua {
+        let lua = Lua::new();
+        let init = lua.create_table().unwrap();
+        init.set("supervise", lua.create_async_function(supervise).unwrap())
+            .unwrap();
+        init.set("services", lua.create_async_function(services).unwrap())
+            .unwrap();
+        init.set("sleep", lua.create_async_function(sleep).unwrap())
+            .unwrap();
+        lua.globals().set("init", init).unwrap();
+        lua
+    }
+
+    async fn sleep(_lua: Lua, seconds: f64) -> LuaResult<()> {
+        smol::Timer::after(Duration::from_secs_f64(seconds)).await;
+        Ok(())
+    }
+
+    #[test]
+    fn test_restart_policy() {
+        assert!(Restart::Always.restarts(true));
+        assert!(Restart::OnFailure.restarts(false));
+        assert!(!Restart::OnFailure.restarts(true));
+        assert!(!Restart::Never.restarts(false));
+        assert!(Restart::parse("sometimes").is_err());
+    }
+
+    #[test]
+    fn test_backoff_delay() {
+        let backoff = Backoff {
+            initial: 1.0,
+            max: 5.0,
+            multiplier: 2.0,
+            jitter: 0.0,
+        };
+        let delays: Vec<_> = (1..=4).map(|failures| backoff.delay(failures)).collect();
+        assert_eq!(delays, [1.0, 2.0, 4.0, 5.0].map(Duration::from_secs_f64));
+        let backoff = Backoff {
+            jitter: 0.5,
+            ..backoff
+        };
+        let delay = backoff.delay(3);
+        assert!(delay >= Duration::from_secs(2) && delay <= Duration::from_secs(4));
+        assert_eq!(Backoff::constant(3.0).delay(10), Duration::from_secs(3));
+    }
+
+    #[test]
+    fn test_backoff_options() {
+        let lua = Lua::new();
+        let options = lua
+            .load("{ cmd = 'true', delay = 2, backoff = { max = '1m', jitter = 0.1 }, crash_loop = { crashes = 3 } }")
+            .eval()
+            .unwrap();
+        let options = service_options(&lua, &options, None).unwrap();
+        assert_eq!(
+            options.backoff,
+            Backoff {
+                initial: 2.0,
+                max: 60.0,
+                multiplier: 2.0,
+                jitter: 0.1,
+            }
+        );
+        assert_eq!(
+            options.crash_loop,
+            Some(CrashLoop {
+                crashes: 3,
+                within: Duration::from_secs(60),
+            })
+        );
+        for bad in [
+            "{ cmd = 'true', backoff = { jitter = 2 } }",
+            "{ cmd = 'true', backoff = { multiplier = 0.5 } }",
+            "{ cmd = 'true', crash_loop = { within = 10 } }",
+        ] {
+            let options = lua.load(bad).eval().unwrap();
+            assert!(service_options(&lua, &options, None).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_service_options() {
+        let lua = Lua::new();
+        let options = lua
+            .load("{ cmd = 'sleep', args = { '1' }, restart = 'always', cwd = '/tmp' }")
+            .eval()
+            .unwrap();
+        let options = service_options(&lua, &options, None).unwrap();
+        assert_eq!(options.restart, Restart::Always);
+        assert_eq!(options.exec.get::<String>(1).unwrap(), "sleep");
+        assert_eq!(options.exec.get::<String>(2).unwrap(), "1");
+        assert_eq!(options.exec.get::<String>("cwd").unwrap(), "/tmp");
+        assert!(options
+            .exec
+            .get::<Option<String>>("restart")
+            .unwrap()
+            .is_none());
+        let options = lua.load("{ args = { '1' } }").eval().unwrap();
+        assert!(service_options(&lua, &options, None).is_err());
+    }
+
+    #[test]
+    fn test_supervise_max_restarts() {
+        smol::block_on(async {
+            let lua = lua();
+            let (state, restarts, code): (String, u32, i32) = lua
+                .load(
+                    "local service = init.supervise({ cmd = 'sh', args = { '-c', 'exit 2' },
+                        max_restarts = 2, delay = 0.01 })
+                    local status = service:status()
+                    while status.state ~= 'failed' do
+                        init.sleep(0.01)
+                        status = service:status()
+                    end
+                    return status.state, status.restarts, status.last.code",
+                )
+                .eval_async()
+                .await
+                .unwrap();
+            assert_eq!((state.as_str(), restarts, code), ("failed", 2, 2));
+        });
+    }
+
+    #[test]
+    fn test_supervise_crash_loop() {
+        smol::block_on(async {
+            let lua = lua();
+            let (state, restarts, code): (String, u32, i32) = lua
+                .load(
+                    "local crashed
+                    local service = init.supervise({ cmd = 'sh', args = { '-c', 'exit 3' },
+                        backoff = { initial = 0.01, max = 0.05 }, crash_loop = { crashes = 3, within = 10 },
+                        on_crash_loop = function(status) crashed = status end })
+                    while not crashed do init.sleep(0.01) end
+                    local status = service:status()
+                    assert(status.state == 'failed' and status.last == crashed)
+                    return status.state, status.restarts, crashed.code",
+                )
+                .eval_async()
+                .await
+                .unwrap();
+            assert_eq!((state.as_str(), restarts, code), ("failed", 2, 3));
+        });
+    }
+
+    #[test]
+    fn test_supervise_unhealthy() {
+        smol::block_on(async {
+            let lua = lua();
+            let (reason, restarted, healthy): (String, bool, Option<bool>) = lua
+                .load(
+                    "local reason
+                    local service = init.supervise({ cmd = 'sleep', args = { '30' },
+                        healthcheck = { exec = { 'false' }, interval = 0.05, failures = 2 },
+                        on_unhealthy = function(why) reason = why end })
+                    init.sleep(0.01)
+                    local first = service:status().pid
+                    while not reason do init.sleep(0.01) end
+                    init.sleep(0.03)
+                    local status = service:status()
+                    service:stop()
+                    return reason, status.pid ~= first and status.restarts == 1, status.healthy",
+                )
+                .eval_async()
+                .await
+                .unwrap();
+            assert_eq!(
+                (reason.as_str(), restarted, healthy),
+                ("probe command exited with code 1", true, None)
+            );
+        });
+    }
+
+    #[test]
+    fn test_supervise_ready() {
+        smol::block_on(async {
+            let lua = lua();
+            let file = std::env::temp_dir().join(format!("luavisors-ready-{}", std::process::id()));
+            std::fs::write(&file, "").unwrap();
+            lua.globals()
+                .set("file", file.display().to_string())
+                .unwrap();
+            let result: (bool, bool, bool, bool, bool) = lua
+                .load(
+                    "local by_fd = init.supervise({ cmd = 'sh', args = { '-c', 'sleep 0.05; echo >&3; sleep 30' },
+                        ready = { fd = 3 } })
+                    local by_file = init.supervise({ cmd = 'sh', args = { '-c', 'sleep 0.05; touch \"$0\"; sleep 30', file },
+                        ready = { file = file } })
+                    local started = init.supervise({ cmd = 'sleep', args = { '30' } })
+                    local early = by_file:wait_ready(0.01)
+                    local results = { early, by_fd:wait_ready(5), by_file:wait_ready(5), started:wait_ready(),
+                        by_fd:status().ready }
+                    by_fd:stop() by_file:stop() started:stop()
+                    return unpack(results, 1, 5)",
+                )
+                .eval_async()
+                .await
+                .unwrap();
+            std::fs::remove_file(&file).unwrap();
+            assert_eq!(result, (false, true, true, true, true));
+        });
+    }
+
+    #[test]
+    fn test_start_order() {
+        let graph = |edges: &[(&str, &[&str])]| -> Vec<(String, Vec<String>)> {
+            edges
+                .iter()
+                .map(|(name, after)| {
+                    let after = after.iter().map(|name| name.to_string()).collect();
+                    (name.to_string(), after)
+                })
+                .collect()
+        };
+        let services = graph(&[("cache", &[]), ("db", &[]), ("web", &["db", "cache"])]);
+        assert_eq!(start_order(&services).unwrap(), [0, 1, 2]);
+        let services = graph(&[("app", &["db"]), ("db", &["disk"]), ("disk", &[])]);
+        assert_eq!(start_order(&services).unwrap(), [2, 1, 0]);
+        let services = graph(&[("a", &["b"]), ("b", &["c"]), ("c", &["a"])]);
+        assert_eq!(
+            start_order(&services),
+            Err("service dependency cycle: a -> b -> c -> a".to_string())
+        );
+        let services = graph(&[("web", &["db"])]);
+        assert_eq!(
+            start_order(&services),
+            Err("service 'web' depends on unknown service 'db'".to_string())
+        );
+    }
+
+    #[test]
+    fn test_services() {
+        smol::block_on(async {
+            let lua = lua();
+            let file =
+                std::env::temp_dir().join(format!("luavisors-services-{}", std::process::id()));
+            lua.globals()
+                .set("file", file.display().to_string())
+                .unwrap();
+            let result: (String, String, bool, String) = lua
+                .load(
+                    "local script = 'echo start $1 >> $0; trap \"echo stop $1 >> $0; exit 0\" TERM; sleep 0.05; echo >&3; while :; do sleep 0.01; done'
+                    local function service(name, after)
+                        return { cmd = 'sh', args = { '-c', script, file, name }, ready = { fd = 3 }, after = after }
+                    end
+                    local group = init.services({ web = service('web', { 'db', 'cache' }),
+                        db = service('db'), cache = service('cache', { 'db' }) }, { timeout = 5 })
+                    group:service('web'):wait_ready(5)
+                    local running = group:status().web.state
+                    group:stop()
+                    local cycle = select(2, pcall(init.services, { a = { cmd = 'true', after = { 'b' } },
+                        b = { cmd = 'true', after = { 'a' } } }))
+                    local f = io.open(file) local lines = f:read('*a') f:close()
+                    return lines, running, group:service('nope') == nil, tostring(cycle)",
+                )
+                .eval_async()
+                .await
+                .unwrap();
+            std::fs::remove_file(&file).unwrap();
+            assert_eq!(
+                (result.0.as_str(), result.1.as_str(), result.2),
+                (
+                    "start db\nstart cache\nstart web\nstop web\nstop cache\nstop db\n",
+                    "running",
+                    true
+                )
+            );
+            assert!(
+                result.3.contains("service dependency cycle: a -> b -> a"),
+                "{}",
+                result.3
+            );
+        });
+    }
+
+    #[test]
+    fn test_services_not_ready() {
+        smol::block_on(async {
+            let lua = lua();
+            let file =
+                std::env::temp_dir().join(format!("luavisors-not-ready-{}", std::process::id()));
+            lua.globals()
+                .set("file", file.display().to_string())
+                .unwrap();
+            let (started, stopped): (bool, String) = lua
+                .load(
+                    "local script = 'trap \"echo stopped > $0; exit 0\" TERM; while :; do sleep 0.01; done'
+                    local ok, err = pcall(init.services, {
+                        a = { cmd = 'sh', args = { '-c', script, file }, ready = { file = file .. '.ready' } },
+                        b = { cmd = 'true', after = { 'a' } },
+                    }, { timeout = 0.1 })
+                    assert(tostring(err):find(\"service 'a' was not ready to start 'b'\"), tostring(err))
+                    local f = io.open(file) local stopped = f and f:read('*a') if f then f:close() end
+                    return ok, stopped",
+                )
+                .eval_async()
+                .await
+                .unwrap();
+            std::fs::remove_file(&file).unwrap();
+            assert!(!started);
+            assert_eq!(stopped, "stopped\n");
+        });
+    }
+
+    #[test]
+    fn test_services_stop_on() {
+        smol::block_on(async {
+            let lua = lua();
+            let file =
+                std::env::temp_dir().join(format!("luavisors-stop-on-{}", std::process::id()));
+            let specs: LuaTable = lua
+                .load(
+                    "local script = 'trap \"echo $1 >> $0; exit 0\" TERM; while :; do sleep 0.01; done'
+                    local file = ...
+                    return {
+                        db = { cmd = 'sh', args = { '-c', script, file, 'db' } },
+                        web = { cmd = 'sh', args = { '-c', script, file, 'web' }, after = { 'db' } },
+                    }",
+                )
+                .call(file.display().to_string())
+                .unwrap();
+            let group = services(lua.clone(), (specs, None)).await.unwrap();
+            smol::Timer::after(Duration::from_millis(100)).await;
+            let token = CancelToken::default();
+            let stopping = stop_on(token.clone(), group);
+            token.cancel();
+            stopping.await;
+            let stopped = std::fs::read_to_string(&file).unwrap();
+            std::fs::remove_file(&file).unwrap();
+            assert_eq!(stopped, "web\ndb\n");
+        });
+    }
+
+    #[test]
+    fn test_supervise_publishes_state() {
+        smol::block_on(async {
+            let lua = lua();
+            let init: LuaTable = lua.globals().get("init").unwrap();
+            init.set("state", sync::state_table(&lua).unwrap()).unwrap();
+            let result: (bool, bool, bool) = lua
+                .load(
+                    "local service = init.supervise({
+                        name = 'published', cmd = 'sleep', args = { '30' },
+                        healthcheck = { exec = { 'true' }, interval = 0.05 },
+                    })
+                    local running = init.state.wait_for('published', 'running', 1)
+                    local healthy = init.state.wait_for('published', 'healthy', 2)
+                    service:stop()
+                    return running, healthy, init.state.wait_for('published', 'stopped', 2)",
+                )
+                .eval_async()
+                .await
+                .unwrap();
+            assert_eq!(result, (true, true, true));
+        });
+    }
+
+    #[test]
+    fn test_supervise_stop_starting() {
+        smol::block_on(async {
+            let lua = lua();
+            let result: (Option<i32>, String, bool) = lua
+                .load(
+                    "local service = init.supervise({ cmd = 'sleep', args = { '30' } })
+                    local code = service:stop()
+                    init.sleep(0.1)
+                    local status = service:status()
+                    return code, status.state, status.pid == nil",
+                )
+                .eval_async()
+                .await
+                .unwrap();
+            assert_eq!(result, (None, "stopped".into(), true));
+        });
+    }
+
+    #[test]
+    fn test_supervise_stop_restart() {
+        smol::block_on(async {
+            let lua = lua();
+            let result: (bool, String, bool, String) = lua
+                .load(
+                    "local service = init.supervise({ cmd = 'sleep', args = { '30' }, restart = 'always' })
+                    init.sleep(0.05)
+                    local first = service:status().pid
+                    service:restart()
+                    init.sleep(0.05)
+                    local second = service:status()
+                    service:stop()
+                    init.sleep(0.05)
+                    local stopped = service:status()
+                    return first ~= nil, second.state, second.pid ~= first, stopped.state",
+                )
+                .eval_async()
+                .await
+                .unwrap();
+            assert_eq!(result, (true, "running".into(), true, "stopped".into()));
+        });
+    }
+}
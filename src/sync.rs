@@ -0,0 +1,594 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex as StdMutex, OnceLock,
+    },
+    time::{Duration, Instant},
+};
+
+use mlua::prelude::*;
+use smol::lock::{Mutex, Semaphore as AsyncSemaphore};
+
+use crate::duration::Seconds;
+
+/// Token bucket state
+#[derive(Debug)]
+struct Bucket {
+    rate: f64,
+    burst: f64,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    /// Create a full bucket refilled at `rate` tokens per second
+    fn new(rate: f64, burst: f64, now: Instant) -> Self {
+        Bucket {
+            rate,
+            burst,
+            tokens: burst,
+            updated: now,
+        }
+    }
+
+    /// Add the tokens accumulated since the last update
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.burst);
+        self.updated = now;
+    }
+
+    /// Take `n` tokens, or return how long to wait until they are available
+    fn take(&mut self, n: f64, now: Instant) -> Result<(), Duration> {
+        self.refill(now);
+        if self.tokens >= n {
+            self.tokens -= n;
+            return Ok(());
+        }
+        // a wait too long to represent is as good as forever
+        let wait = (n - self.tokens) / self.rate;
+        Err(Duration::try_from_secs_f64(wait).unwrap_or(Duration::MAX))
+    }
+}
+
+/// Token bucket rate limiter shared between Lua tasks
+#[derive(Clone)]
+pub struct RateLimit {
+    bucket: Arc<Mutex<Bucket>>,
+}
+
+/// Reject a number of tokens which is negative or not a number
+fn check_tokens(n: f64) -> LuaResult<f64> {
+    match n.is_finite() && n >= 0.0 {
+        true => Ok(n),
+        false => Err(LuaError::runtime(
+            "number of tokens must be a non-negative number",
+        )),
+    }
+}
+
+impl RateLimit {
+    /// Wait until `n` tokens are available and take them
+    async fn acquire(&self, n: f64) -> LuaResult<()> {
+        let n = check_tokens(n)?;
+        // holding the lock while waiting serves callers in order
+        let mut bucket = self.bucket.lock().await;
+        if n > bucket.burst {
+            return Err(LuaError::runtime(
+                "cannot acquire more tokens than the burst size",
+            ));
+        }
+        while let Err(wait) = bucket.take(n, Instant::now()) {
+            smol::Timer::after(wait).await;
+        }
+        Ok(())
+    }
+
+    /// Take `n` tokens if they are available without waiting
+    async fn try_acquire(&self, n: f64) -> LuaResult<bool> {
+        let n = check_tokens(n)?;
+        Ok(match self.bucket.try_lock() {
+            Some(mut bucket) => bucket.take(n, Instant::now()).is_ok(),
+            None => false,
+        })
+    }
+}
+
+/// Lua methods for rate limiters
+impl LuaUserData for RateLimit {
+    fn add_methods<M: LuaUserDataMethods<Self>>(methods: &mut M) {
+        methods.add_async_method("acquire", |_, this, n: Option<f64>| {
+            let this = this.clone();
+            async move { this.acquire(n.unwrap_or(1.0)).await }
+        });
+        methods.add_async_method("try_acquire", |_, this, n: Option<f64>| {
+            let this = this.clone();
+            async move { this.try_acquire(n.unwrap_or(1.0)).await }
+        });
+    }
+}
+
+/// Create a rate limiter allowing `rate` operations per second with bursts of `burst`
+pub async fn ratelimit(_lua: Lua, (rate, burst): (f64, Option<f64>)) -> LuaResult<RateLimit> {
+    if rate <= 0.0 || !rate.is_finite() {
+        return Err(LuaError::runtime("rate must be a positive number"));
+    }
+    let burst = burst.unwrap_or(1.0).max(1.0);
+    let bucket = Bucket::new(rate, burst, Instant::now());
+    Ok(RateLimit {
+        bucket: Arc::new(Mutex::new(bucket)),
+    })
+}
+
+/// Counting semaphore shared between Lua tasks, a mutex has one permit
+#[derive(Clone)]
+pub struct Semaphore {
+    permits: Arc<AsyncSemaphore>,
+    held: Arc<AtomicUsize>,
+}
+
+impl Semaphore {
+    /// Create a semaphore with `n` permits
+    fn new(n: usize) -> Self {
+        Semaphore {
+            permits: Arc::new(AsyncSemaphore::new(n)),
+            held: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Wait for a permit
+    async fn acquire(&self) {
+        self.permits.acquire().await.forget();
+        self.held.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Take a permit if one is available without waiting
+    fn try_acquire(&self) -> bool {
+        match self.permits.try_acquire() {
+            Some(guard) => {
+                guard.forget();
+                self.held.fetch_add(1, Ordering::SeqCst);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Return a permit
+    fn release(&self) -> LuaResult<()> {
+        self.held
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |held| {
+                held.checked_sub(1)
+            })
+            .map_err(|_| LuaError::runtime("released without a matching acquire"))?;
+        self.permits.add_permits(1);
+        Ok(())
+    }
+
+    /// Run a function while holding a permit, releasing it even on error or cancellation
+    async fn with(&self, func: LuaFunction, args: LuaMultiValue) -> LuaResult<LuaMultiValue> {
+        self.acquire().await;
+        let _held = HeldPermit(self);
+        func.call_async::<LuaMultiValue>(args).await
+    }
+}
+
+/// Permit taken by `with`, returned when dropped so an abandoned call cannot leak it
+struct HeldPermit<'a>(&'a Semaphore);
+
+impl Drop for HeldPermit<'_> {
+    fn drop(&mut self) {
+        // the permit was acquired by `with`, so it is always held here
+        let _ = self.0.release();
+    }
+}
+
+/// Lua methods for semaphores and mutexes
+impl LuaUserData for Semaphore {
+    fn add_methods<M: LuaUserDataMethods<Self>>(methods: &mut M) {
+        for name in ["acquire", "lock"] {
+            methods.add_async_method(name, |_, this, ()| {
+                let this = this.clone();
+                async move {
+                    this.acquire().await;
+                    Ok(())
+                }
+            });
+        }
+        for name in ["try_acquire", "try_lock"] {
+            methods.add_method(name, |_, this, ()| Ok(this.try_acquire()));
+        }
+        for name in ["release", "unlock"] {
+            methods.add_method(name, |_, this, ()| this.release());
+        }
+        methods.add_async_method(
+            "with",
+            |_, this, (func, args): (LuaFunction, LuaMultiValue)| {
+                let this = this.clone();
+                async move { this.with(func, args).await }
+            },
+        );
+    }
+}
+
+/// Create a mutex which allows one task at a time
+pub async fn mutex(_lua: Lua, _: ()) -> LuaResult<Semaphore> {
+    Ok(Semaphore::new(1))
+}
+
+/// Create a semaphore which allows `n` tasks at a time
+pub async fn semaphore(_lua: Lua, n: usize) -> LuaResult<Semaphore> {
+    if n == 0 {
+        return Err(LuaError::runtime("semaphore requires at least one permit"));
+    }
+    Ok(Semaphore::new(n))
+}
+
+/// Whether an event is set and the tasks waiting for it
+#[derive(Default)]
+struct EventState {
+    set: bool,
+    waiters: Vec<smol::channel::Sender<()>>,
+}
+
+/// Flag which wakes every waiting task when set
+#[derive(Clone, Default)]
+pub struct Event {
+    state: Arc<StdMutex<EventState>>,
+}
+
+impl Event {
+    /// Lock the event state, ignoring poisoning since it is always consistent
+    fn state(&self) -> std::sync::MutexGuard<'_, EventState> {
+        self.state.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Set the event and wake all waiters
+    pub fn set(&self) {
+        let mut state = self.state();
+        state.set = true;
+        // dropping the senders closes the channels which wakes the receivers
+        state.waiters.clear();
+    }
+
+    /// Clear the event so later waiters block until it is set again
+    pub fn clear(&self) {
+        self.state().set = false;
+    }
+
+    /// Check whether the event is set
+    pub fn is_set(&self) -> bool {
+        self.state().set
+    }
+
+    /// Wait until the event is set
+    pub async fn wait(&self) {
+        let receiver = {
+            let mut state = self.state();
+            if state.set {
+                return;
+            }
+            let (sender, receiver) = smol::channel::bounded(1);
+            state.waiters.push(sender);
+            receiver
+        };
+        let _ = receiver.recv().await;
+    }
+}
+
+/// Lua methods for events
+impl LuaUserData for Event {
+    fn add_methods<M: LuaUserDataMethods<Self>>(methods: &mut M) {
+        methods.add_method("set", |_, this, ()| {
+            this.set();
+            Ok(())
+        });
+        methods.add_method("clear", |_, this, ()| {
+            this.clear();
+            Ok(())
+        });
+        methods.add_method("is_set", |_, this, ()| Ok(this.is_set()));
+        methods.add_async_method("wait", |_, this, ()| {
+            let this = this.clone();
+            async move {
+                this.wait().await;
+                Ok(())
+            }
+        });
+    }
+}
+
+/// Create an event which tasks can wait on until another task sets it
+pub async fn event(_lua: Lua, _: ()) -> LuaResult<Event> {
+    Ok(Event::default())
+}
+
+/// Named states and the tasks waiting for any of them to change
+#[derive(Default)]
+struct States {
+    values: HashMap<String, String>,
+    waiters: Vec<smol::channel::Sender<()>>,
+}
+
+/// Return the states shared by every task of the script
+fn states() -> &'static StdMutex<States> {
+    static STATES: OnceLock<StdMutex<States>> = OnceLock::new();
+    STATES.get_or_init(StdMutex::default)
+}
+
+/// Set the state of a name, waking the tasks waiting on states
+pub fn set_state(name: &str, state: &str) {
+    let mut states = states().lock().unwrap_or_else(|err| err.into_inner());
+    states.values.insert(name.to_string(), state.to_string());
+    states.waiters.clear();
+}
+
+/// Return the state of a name
+fn get_state(name: &str) -> Option<String> {
+    let states = states().lock().unwrap_or_else(|err| err.into_inner());
+    states.values.get(name).cloned()
+}
+
+/// Wait until a name reaches a state
+async fn wait_for_state(name: &str, state: &str) {
+    loop {
+        let receiver = {
+            let mut states = states().lock().unwrap_or_else(|err| err.into_inner());
+            if states.values.get(name).is_some_and(|value| value == state) {
+                return;
+            }
+            let (sender, receiver) = smol::channel::bounded(1);
+            states.waiters.push(sender);
+            receiver
+        };
+        let _ = receiver.recv().await;
+    }
+}
+
+/// Set the state of a name such as a service from Lua
+async fn lua_set_state(_lua: Lua, (name, state): (String, String)) -> LuaResult<()> {
+    set_state(&name, &state);
+    Ok(())
+}
+
+/// Return the state of a name from Lua, or nil if it was never set
+async fn lua_get_state(_lua: Lua, name: String) -> LuaResult<Option<String>> {
+    Ok(get_state(&name))
+}
+
+/// Wait until a name reaches a state from Lua, returning false if the timeout passes first
+async fn wait_for(
+    _lua: Lua,
+    (name, state, timeout): (String, String, Option<Seconds>),
+) -> LuaResult<bool> {
+    let wait = async {
+        wait_for_state(&name, &state).await;
+        true
+    };
+    let Some(timeout) = timeout else {
+        return Ok(wait.await);
+    };
+    Ok(smol::future::or(wait, async {
+        smol::Timer::after(timeout.duration()).await;
+        false
+    })
+    .await)
+}
+
+/// Return the `init.state` Lua table
+pub fn state_table(lua: &Lua) -> LuaResult<LuaTable> {
+    let table = lua.create_table()?;
+    table.set("set", lua.create_async_function(lua_set_state)?)?;
+    table.set("get", lua.create_async_function(lua_get_state)?)?;
+    table.set("wait_for", lua.create_async_function(wait_for)?)?;
+    Ok(table)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bucket_take() {
+        let now = Instant::now();
+        let mut bucket = Bucket::new(2.0, 2.0, now);
+        assert!(bucket.take(1.0, now).is_ok());
+        assert!(bucket.take(1.0, now).is_ok());
+        assert_eq!(bucket.take(1.0, now), Err(Duration::from_millis(500)));
+        let mut slow = Bucket::new(1e-300, 1.0, now);
+        assert!(slow.take(1.0, now).is_ok());
+        assert_eq!(slow.take(1.0, now), Err(Duration::MAX));
+    }
+
+    #[test]
+    fn test_bucket_refill() {
+        let now = Instant::now();
+        let mut bucket = Bucket::new(2.0, 2.0, now);
+        bucket.tokens = 0.0;
+        bucket.refill(now + Duration::from_millis(500));
+        assert_eq!(bucket.tokens, 1.0);
+        bucket.refill(now + Duration::from_secs(10));
+        assert_eq!(bucket.tokens, 2.0);
+    }
+
+    #[test]
+    fn test_ratelimit() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            let limit = ratelimit(lua, (100.0, Some(2.0))).await.unwrap();
+            let start = Instant::now();
+            for _ in 0..3 {
+                limit.acquire(1.0).await.unwrap();
+            }
+            assert!(start.elapsed() >= Duration::from_millis(9));
+            assert!(limit.acquire(3.0).await.is_err());
+        });
+    }
+
+    #[test]
+    fn test_ratelimit_try_acquire() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            let limit = ratelimit(lua, (0.001, None)).await.unwrap();
+            assert!(limit.try_acquire(1.0).await.unwrap());
+            assert!(!limit.try_acquire(1.0).await.unwrap());
+        });
+    }
+
+    #[test]
+    fn test_ratelimit_err() {
+        smol::block_on(async {
+            assert!(ratelimit(Lua::new(), (0.0, None)).await.is_err());
+            assert!(ratelimit(Lua::new(), (f64::NAN, None)).await.is_err());
+            let limit = ratelimit(Lua::new(), (1.0, None)).await.unwrap();
+            assert!(limit.acquire(f64::NAN).await.is_err());
+            assert!(limit.acquire(-1.0).await.is_err());
+            assert!(limit.try_acquire(f64::INFINITY).await.is_err());
+            assert!(!limit.try_acquire(1e300).await.unwrap());
+        });
+    }
+
+    #[test]
+    fn test_semaphore() {
+        smol::block_on(async {
+            let sem = semaphore(Lua::new(), 2).await.unwrap();
+            sem.acquire().await;
+            assert!(sem.try_acquire());
+            assert!(!sem.try_acquire());
+            sem.release().unwrap();
+            assert!(sem.try_acquire());
+            sem.release().unwrap();
+            sem.release().unwrap();
+            assert!(sem.release().is_err());
+            assert!(semaphore(Lua::new(), 0).await.is_err());
+        });
+    }
+
+    #[test]
+    fn test_mutex_with() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            let lock = mutex(lua.clone(), ()).await.unwrap();
+            let func = lua.load("error('boom')").into_function().unwrap();
+            assert!(lock.with(func, LuaMultiValue::new()).await.is_err());
+            // the lock is released after an error
+            assert!(lock.try_acquire());
+        });
+    }
+
+    #[test]
+    fn test_mutex_with_dropped() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            let lock = mutex(lua.clone(), ()).await.unwrap();
+            let func = lua.load("coroutine.yield()").into_function().unwrap();
+            // a call abandoned like one past its deadline still returns the permit
+            let abandoned = smol::future::or(
+                async { lock.with(func, LuaMultiValue::new()).await.map(drop) },
+                async { Ok(()) },
+            );
+            abandoned.await.unwrap();
+            assert!(lock.try_acquire());
+        });
+    }
+
+    #[test]
+    fn test_mutex_lua() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            let lock = mutex(lua.clone(), ()).await.unwrap();
+            lua.globals().set("lock", lock).unwrap();
+            let chunk = lua.load(
+                r#"
+                lock:lock()
+                local busy = lock:try_lock()
+                lock:unlock()
+                local value = lock:with(function(n) return n * 2 end, 21)
+                return busy, value, lock:try_lock()
+                "#,
+            );
+            let result = chunk.eval_async::<(bool, i32, bool)>().await.unwrap();
+            assert_eq!(result, (false, 42, true));
+        });
+    }
+
+    #[test]
+    fn test_event() {
+        smol::block_on(async {
+            let event = Event::default();
+            let waiters: Vec<_> = (0..3)
+                .map(|_| {
+                    let event = event.clone();
+                    smol::spawn(async move { event.wait().await })
+                })
+                .collect();
+            smol::future::yield_now().await;
+            assert!(!event.is_set());
+            event.set();
+            for waiter in waiters {
+                waiter.await;
+            }
+            // a set event does not block
+            event.wait().await;
+            event.clear();
+            assert!(!event.is_set());
+            assert!(event.state().waiters.is_empty());
+        });
+    }
+
+    #[test]
+    fn test_event_lua() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            lua.globals().set("ready", Event::default()).unwrap();
+            let chunk = lua.load(
+                r#"
+                local before = ready:is_set()
+                ready:set()
+                ready:wait()
+                return before, ready:is_set()
+                "#,
+            );
+            let result = chunk.eval_async::<(bool, bool)>().await.unwrap();
+            assert_eq!(result, (false, true));
+        });
+    }
+
+    #[test]
+    fn test_ratelimit_lua() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            let limit = ratelimit(lua.clone(), (1000.0, None)).await.unwrap();
+            lua.globals().set("limit", limit).unwrap();
+            let chunk = lua.load("limit:acquire(); return limit:try_acquire()");
+            assert!(!chunk.eval_async::<bool>().await.unwrap());
+        });
+    }
+
+    #[test]
+    fn test_states() {
+        assert_eq!(get_state("test-states"), None);
+        set_state("test-states", "starting");
+        assert_eq!(get_state("test-states").as_deref(), Some("starting"));
+    }
+
+    #[test]
+    fn test_wait_for() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            let name = "test-wait-for".to_string();
+            let waiter = smol::spawn(wait_for(
+                lua.clone(),
+                (name.clone(), "healthy".to_string(), None),
+            ));
+            smol::Timer::after(Duration::from_millis(10)).await;
+            set_state(&name, "starting");
+            set_state(&name, "healthy");
+            assert!(waiter.await.unwrap());
+            let timeout = Some(Seconds(0.01));
+            let result = wait_for(lua, (name, "stopped".to_string(), timeout)).await;
+            assert!(!result.unwrap());
+        });
+    }
+}
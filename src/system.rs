@@ -0,0 +1,497 @@
+use mlua::prelude::*;
+
+use crate::{
+    size,
+    unix::{self, Rlimit, RLIM_INFINITY},
+};
+
+/// Resource limit names and their Linux resource numbers
+const RESOURCES: [(&str, i32); 10] = [
+    ("cpu", 0),
+    ("fsize", 1),
+    ("data", 2),
+    ("stack", 3),
+    ("core", 4),
+    ("rss", 5),
+    ("nproc", 6),
+    ("nofile", 7),
+    ("memlock", 8),
+    ("as", 9),
+];
+
+/// Scheduling policy names and their Linux policy numbers, which need no static priority
+const SCHED_POLICIES: [(&str, i32); 3] = [("other", 0), ("batch", 3), ("idle", 5)];
+
+/// Look up the policy number of a scheduling policy name such as `batch`
+pub fn sched_policy(name: &str) -> LuaResult<i32> {
+    SCHED_POLICIES
+        .iter()
+        .find(|(known, _)| *known == name)
+        .map(|(_, policy)| *policy)
+        .ok_or_else(|| LuaError::runtime(format!("unknown scheduling policy '{}'", name)))
+}
+
+/// Return the name of a scheduling policy number
+pub fn sched_policy_name(policy: i32) -> Option<&'static str> {
+    SCHED_POLICIES
+        .iter()
+        .find(|(_, known)| *known == policy)
+        .map(|(name, _)| *name)
+}
+
+/// Namespace names accepted by the `namespaces` option of `init.exec`
+const NAMESPACES: [(&str, i32); 7] = [
+    ("mount", unix::CLONE_NEWNS),
+    ("cgroup", unix::CLONE_NEWCGROUP),
+    ("uts", unix::CLONE_NEWUTS),
+    ("ipc", unix::CLONE_NEWIPC),
+    ("user", unix::CLONE_NEWUSER),
+    ("pid", unix::CLONE_NEWPID),
+    ("net", unix::CLONE_NEWNET),
+];
+
+/// Combine the `namespaces` option of a table into unshare flags
+pub fn namespaces_option(options: &LuaTable) -> LuaResult<i32> {
+    let names = options
+        .get::<Option<Vec<String>>>("namespaces")?
+        .unwrap_or_default();
+    names.iter().try_fold(0, |flags, name| {
+        NAMESPACES
+            .iter()
+            .find(|(known, _)| known == name)
+            .map(|(_, flag)| flags | flag)
+            .ok_or_else(|| LuaError::runtime(format!("unknown namespace '{}'", name)))
+    })
+}
+
+/// Return the names of the namespaces in unshare flags
+pub fn namespace_names(flags: i32) -> Vec<&'static str> {
+    NAMESPACES
+        .iter()
+        .filter(|(_, flag)| flags & flag != 0)
+        .map(|(name, _)| *name)
+        .collect()
+}
+
+/// Capability names in the order of their numbers
+const CAPABILITIES: [&str; 41] = [
+    "CAP_CHOWN",
+    "CAP_DAC_OVERRIDE",
+    "CAP_DAC_READ_SEARCH",
+    "CAP_FOWNER",
+    "CAP_FSETID",
+    "CAP_KILL",
+    "CAP_SETGID",
+    "CAP_SETUID",
+    "CAP_SETPCAP",
+    "CAP_LINUX_IMMUTABLE",
+    "CAP_NET_BIND_SERVICE",
+    "CAP_NET_BROADCAST",
+    "CAP_NET_ADMIN",
+    "CAP_NET_RAW",
+    "CAP_IPC_LOCK",
+    "CAP_IPC_OWNER",
+    "CAP_SYS_MODULE",
+    "CAP_SYS_RAWIO",
+    "CAP_SYS_CHROOT",
+    "CAP_SYS_PTRACE",
+    "CAP_SYS_PACCT",
+    "CAP_SYS_ADMIN",
+    "CAP_SYS_BOOT",
+    "CAP_SYS_NICE",
+    "CAP_SYS_RESOURCE",
+    "CAP_SYS_TIME",
+    "CAP_SYS_TTY_CONFIG",
+    "CAP_MKNOD",
+    "CAP_LEASE",
+    "CAP_AUDIT_WRITE",
+    "CAP_AUDIT_CONTROL",
+    "CAP_SETFCAP",
+    "CAP_MAC_OVERRIDE",
+    "CAP_MAC_ADMIN",
+    "CAP_SYSLOG",
+    "CAP_WAKE_ALARM",
+    "CAP_BLOCK_SUSPEND",
+    "CAP_AUDIT_READ",
+    "CAP_PERFMON",
+    "CAP_BPF",
+    "CAP_CHECKPOINT_RESTORE",
+];
+
+/// Look up the number of a capability name such as `CAP_NET_BIND_SERVICE` or `net_bind_service`
+fn capability(name: &str) -> LuaResult<u32> {
+    let upper = name.to_ascii_uppercase();
+    let full = match upper.starts_with("CAP_") {
+        true => upper,
+        false => format!("CAP_{}", upper),
+    };
+    CAPABILITIES
+        .iter()
+        .position(|known| *known == full)
+        .map(|cap| cap as u32)
+        .ok_or_else(|| LuaError::runtime(format!("unknown capability '{}'", name)))
+}
+
+/// Read the capabilities a child keeps from the `caps` option of a table, as a mask
+pub fn caps_option(options: &LuaTable) -> LuaResult<Option<u64>> {
+    let Some(caps) = options.get::<Option<LuaTable>>("caps")? else {
+        return Ok(None);
+    };
+    let keep = caps.get::<Option<Vec<String>>>("keep")?.unwrap_or_default();
+    keep.iter()
+        .try_fold(0, |mask, name| Ok(mask | 1 << capability(name)?))
+        .map(Some)
+}
+
+/// Return the names of the capabilities in a mask
+pub fn cap_names(mask: u64) -> Vec<&'static str> {
+    CAPABILITIES
+        .iter()
+        .enumerate()
+        .filter(|(cap, _)| mask & (1 << cap) != 0)
+        .map(|(_, name)| *name)
+        .collect()
+}
+
+/// Set the nice value of a process from Lua, where pid 0 is the supervisor
+///
+/// Lowering the nice value below its current value needs `CAP_SYS_NICE`.
+pub async fn renice(_lua: Lua, (pid, nice): (i32, i32)) -> LuaResult<()> {
+    unix::setpriority(pid, nice)
+        .map_err(|err| LuaError::runtime(format!("failed to renice {}: {}", pid, err)))
+}
+
+/// Lowest and highest OOM score adjustments, which never and always choose a process
+const OOM_SCORE_ADJ: std::ops::RangeInclusive<i32> = -1000..=1000;
+
+/// Check that an OOM score adjustment is in range
+pub fn check_oom_score_adj(adj: i32) -> LuaResult<i32> {
+    match OOM_SCORE_ADJ.contains(&adj) {
+        true => Ok(adj),
+        false => Err(LuaError::runtime(format!(
+            "oom_score_adj {} is not between -1000 and 1000",
+            adj
+        ))),
+    }
+}
+
+/// Get or set how likely the OOM killer chooses a process from Lua, where pid 0 is the supervisor
+///
+/// Lowering the adjustment below its current value needs `CAP_SYS_RESOURCE`.
+pub async fn oom_score_adj(_lua: Lua, (pid, adj): (i32, Option<i32>)) -> LuaResult<i32> {
+    let path = match pid {
+        0 => "/proc/self/oom_score_adj".to_string(),
+        pid => format!("/proc/{}/oom_score_adj", pid),
+    };
+    if let Some(adj) = adj {
+        smol::fs::write(&path, check_oom_score_adj(adj)?.to_string())
+            .await
+            .map_err(|err| LuaError::runtime(format!("failed to set {}: {}", path, err)))?;
+    }
+    let current = smol::fs::read_to_string(&path)
+        .await
+        .map_err(|err| LuaError::runtime(format!("failed to read {}: {}", path, err)))?;
+    current.trim().parse().map_err(LuaError::runtime)
+}
+
+/// Look up the resource number of a limit name such as `nofile`
+fn resource(name: &str) -> LuaResult<i32> {
+    let name = name.trim_start_matches("RLIMIT_").to_ascii_lowercase();
+    RESOURCES
+        .iter()
+        .find(|(known, _)| *known == name)
+        .map(|(_, resource)| *resource)
+        .ok_or_else(|| LuaError::runtime(format!("unknown resource limit '{}'", name)))
+}
+
+/// Convert a limit into a Lua number, where unlimited is `math.huge`
+fn limit_to_lua(limit: u64) -> f64 {
+    match limit {
+        RLIM_INFINITY => f64::INFINITY,
+        limit => limit as f64,
+    }
+}
+
+/// Convert a Lua number into a limit, where `math.huge` is unlimited
+fn limit_from_lua(limit: f64) -> LuaResult<u64> {
+    match limit {
+        limit if limit == f64::INFINITY => Ok(RLIM_INFINITY),
+        limit if limit >= 0.0 && limit.fract() == 0.0 => Ok(limit as u64),
+        limit => Err(LuaError::runtime(format!(
+            "invalid resource limit {}",
+            limit
+        ))),
+    }
+}
+
+/// Convert a Lua number, size string such as `512M`, or `unlimited` into a limit
+fn limit_value(value: LuaValue) -> LuaResult<u64> {
+    match value {
+        LuaValue::String(s) => match &*s.to_str()? {
+            "unlimited" | "infinity" => Ok(RLIM_INFINITY),
+            s => size::parse(s).map_err(LuaError::runtime),
+        },
+        LuaValue::Integer(limit) => limit_from_lua(limit as f64),
+        LuaValue::Number(limit) => limit_from_lua(limit),
+        value => Err(LuaError::runtime(format!(
+            "expected resource limit to be a number or string, got a value of type '{}'",
+            value.type_name()
+        ))),
+    }
+}
+
+/// Read the limits which a child sets for itself before exec, as soft and hard limits alike
+pub fn limits_from_table(table: &LuaTable) -> LuaResult<Vec<(i32, u64)>> {
+    let mut limits = Vec::new();
+    for pair in table.pairs::<String, LuaValue>() {
+        let (name, value) = pair?;
+        limits.push((resource(&name)?, limit_value(value)?));
+    }
+    limits.sort_unstable();
+    Ok(limits)
+}
+
+/// Convert the limits of a child into a table of limit names and numbers
+pub fn limits_table(lua: &Lua, limits: &[(i32, u64)]) -> LuaResult<LuaTable> {
+    let table = lua.create_table()?;
+    for (resource, limit) in limits {
+        if let Some((name, _)) = RESOURCES.iter().find(|(_, known)| known == resource) {
+            table.set(*name, limit_to_lua(*limit))?;
+        }
+    }
+    Ok(table)
+}
+
+/// Return the system configuration values useful to boot scripts
+pub async fn sysconf(lua: Lua, _: ()) -> LuaResult<LuaTable> {
+    let table = lua.create_table()?;
+    table.set("page_size", unix::sysconf(unix::SC_PAGESIZE))?;
+    table.set("clock_ticks", unix::sysconf(unix::SC_CLK_TCK))?;
+    table.set("open_max", unix::sysconf(unix::SC_OPEN_MAX))?;
+    table.set("child_max", unix::sysconf(unix::SC_CHILD_MAX))?;
+    table.set("processors", unix::sysconf(unix::SC_NPROCESSORS_ONLN))?;
+    Ok(table)
+}
+
+/// Return the number of online CPUs, which `cpus` options of `init.exec` count from 0
+pub async fn cpu_count(_lua: Lua, _: ()) -> LuaResult<i64> {
+    unix::sysconf(unix::SC_NPROCESSORS_ONLN)
+        .ok_or_else(|| LuaError::runtime("failed to count online CPUs"))
+}
+
+/// Read the CPUs a child is restricted to from the `cpus` option
+pub fn cpus_option(options: &LuaTable) -> LuaResult<Vec<usize>> {
+    let cpus = options
+        .get::<Option<Vec<usize>>>("cpus")?
+        .unwrap_or_default();
+    match cpus.iter().find(|cpu| **cpu >= unix::CPU_SETSIZE) {
+        Some(cpu) => Err(LuaError::runtime(format!("cpu {} is out of range", cpu))),
+        None => Ok(cpus),
+    }
+}
+
+/// Return the soft and hard limits of a resource of the supervisor
+async fn rlimit_get(_lua: Lua, name: String) -> LuaResult<(f64, f64)> {
+    let rlim = unix::getrlimit(resource(&name)?)?;
+    Ok((limit_to_lua(rlim.soft), limit_to_lua(rlim.hard)))
+}
+
+/// Set the soft and optionally hard limits of a resource of the supervisor
+///
+/// Children inherit these limits, so raising `nofile` before starting services
+/// lets each of them open more files.
+async fn rlimit_set(_lua: Lua, (name, soft, hard): (String, f64, Option<f64>)) -> LuaResult<()> {
+    let resource = resource(&name)?;
+    let hard = match hard {
+        Some(hard) => limit_from_lua(hard)?,
+        None => unix::getrlimit(resource)?.hard,
+    };
+    let soft = limit_from_lua(soft)?;
+    unix::setrlimit(resource, Rlimit { soft, hard })
+        .map_err(|err| LuaError::runtime(format!("failed to set limit '{}': {}", name, err)))
+}
+
+/// Return the `init.rlimit` Lua table
+pub fn rlimit_table(lua: &Lua) -> LuaResult<LuaTable> {
+    let table = lua.create_table()?;
+    table.set("get", lua.create_async_function(rlimit_get)?)?;
+    table.set("set", lua.create_async_function(rlimit_set)?)?;
+    table.set("infinity", f64::INFINITY)?;
+    Ok(table)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resource() {
+        assert_eq!(resource("nofile").unwrap(), 7);
+        assert_eq!(resource("RLIMIT_NOFILE").unwrap(), 7);
+        assert!(resource("unknown").is_err());
+    }
+
+    #[test]
+    fn test_sched_policy() {
+        assert_eq!(sched_policy("batch").unwrap(), 3);
+        assert_eq!(sched_policy_name(5), Some("idle"));
+        assert!(sched_policy("fifo").is_err());
+        assert_eq!(sched_policy_name(1), None);
+    }
+
+    #[test]
+    fn test_namespaces_option() {
+        let lua = Lua::new();
+        let options = lua
+            .load("{ namespaces = { 'pid', 'mount', 'net', 'uts' } }")
+            .eval::<LuaTable>()
+            .unwrap();
+        let flags = namespaces_option(&options).unwrap();
+        assert_eq!(
+            flags,
+            unix::CLONE_NEWPID | unix::CLONE_NEWNS | unix::CLONE_NEWNET | unix::CLONE_NEWUTS
+        );
+        assert_eq!(namespace_names(flags), ["mount", "uts", "pid", "net"]);
+        assert_eq!(namespaces_option(&lua.create_table().unwrap()).unwrap(), 0);
+        let options = lua.load("{ namespaces = { 'time' } }").eval().unwrap();
+        assert!(namespaces_option(&options).is_err());
+    }
+
+    #[test]
+    fn test_caps_option() {
+        let lua = Lua::new();
+        let options = lua
+            .load("{ caps = { keep = { 'CAP_NET_BIND_SERVICE', 'kill' } } }")
+            .eval::<LuaTable>()
+            .unwrap();
+        let keep = caps_option(&options).unwrap().unwrap();
+        assert_eq!(keep, 1 << 10 | 1 << 5);
+        assert_eq!(cap_names(keep), ["CAP_KILL", "CAP_NET_BIND_SERVICE"]);
+        let options = lua.load("{ caps = {} }").eval::<LuaTable>().unwrap();
+        assert_eq!(caps_option(&options).unwrap(), Some(0));
+        assert_eq!(caps_option(&lua.create_table().unwrap()).unwrap(), None);
+        let options = lua
+            .load("{ caps = { keep = { 'CAP_FLY' } } }")
+            .eval()
+            .unwrap();
+        assert!(caps_option(&options).is_err());
+    }
+
+    #[test]
+    fn test_renice() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            let mut child = std::process::Command::new("sleep")
+                .arg("30")
+                .spawn()
+                .unwrap();
+            let pid = child.id() as i32;
+            renice(lua.clone(), (pid, 19)).await.unwrap();
+            let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).unwrap();
+            child.kill().unwrap();
+            child.wait().unwrap();
+            // nice is the 19th field, and the name field before it has no spaces
+            assert_eq!(stat.split_whitespace().nth(18), Some("19"));
+            assert!(renice(lua, (-1, 0)).await.is_err());
+        });
+    }
+
+    #[test]
+    fn test_limit_conversion() {
+        assert_eq!(limit_to_lua(RLIM_INFINITY), f64::INFINITY);
+        assert_eq!(limit_from_lua(f64::INFINITY).unwrap(), RLIM_INFINITY);
+        assert_eq!(limit_from_lua(1024.0).unwrap(), 1024);
+        assert!(limit_from_lua(-1.0).is_err());
+        assert!(limit_from_lua(1.5).is_err());
+    }
+
+    #[test]
+    fn test_limits_table() {
+        let lua = Lua::new();
+        let table: LuaTable = lua
+            .load("{ nofile = 4096, core = 0, as = '512M', stack = 'unlimited' }")
+            .eval()
+            .unwrap();
+        let limits = limits_from_table(&table).unwrap();
+        assert_eq!(
+            limits,
+            vec![
+                (3, RLIM_INFINITY),
+                (4, 0),
+                (7, 4096),
+                (9, 512 * 1024 * 1024)
+            ]
+        );
+        let table = limits_table(&lua, &limits).unwrap();
+        assert_eq!(limits_from_table(&table).unwrap(), limits);
+        let table: LuaTable = lua.load("{ nofile = true }").eval().unwrap();
+        assert!(limits_from_table(&table).is_err());
+        let table: LuaTable = lua.load("{ files = 1 }").eval().unwrap();
+        assert!(limits_from_table(&table).is_err());
+    }
+
+    #[test]
+    fn test_sysconf() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            let table = sysconf(lua.clone(), ()).await.unwrap();
+            assert!(table.get::<i64>("page_size").unwrap() >= 4096);
+            assert!(table.get::<i64>("clock_ticks").unwrap() > 0);
+        });
+    }
+
+    #[test]
+    fn test_oom_score_adj() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            let mut child = std::process::Command::new("sleep")
+                .arg("30")
+                .spawn()
+                .unwrap();
+            let pid = child.id() as i32;
+            let raised = oom_score_adj(lua.clone(), (pid, Some(500))).await;
+            let current = oom_score_adj(lua.clone(), (pid, None)).await;
+            child.kill().unwrap();
+            child.wait().unwrap();
+            assert_eq!((raised.unwrap(), current.unwrap()), (500, 500));
+            assert!(oom_score_adj(lua.clone(), (0, None)).await.is_ok());
+            assert!(oom_score_adj(lua, (0, Some(1001))).await.is_err());
+        });
+    }
+
+    #[test]
+    fn test_cpu_count() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            assert!(cpu_count(lua, ()).await.unwrap() >= 1);
+        });
+    }
+
+    #[test]
+    fn test_cpus_option() {
+        let lua = Lua::new();
+        let options: LuaTable = lua.load("{ cpus = { 0, 2 } }").eval().unwrap();
+        assert_eq!(cpus_option(&options).unwrap(), vec![0, 2]);
+        assert!(cpus_option(&lua.create_table().unwrap())
+            .unwrap()
+            .is_empty());
+        let options: LuaTable = lua.load("{ cpus = { 4096 } }").eval().unwrap();
+        assert!(cpus_option(&options).is_err());
+    }
+
+    #[test]
+    fn test_rlimit_get_set() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            let (soft, hard) = rlimit_get(lua.clone(), "nofile".to_string()).await.unwrap();
+            assert!(soft <= hard);
+            rlimit_set(lua.clone(), ("nofile".to_string(), soft, None))
+                .await
+                .unwrap();
+            let err = rlimit_set(lua, ("nofile".to_string(), soft, Some(-1.0)))
+                .await
+                .unwrap_err();
+            assert!(err.to_string().contains("invalid resource limit"));
+        });
+    }
+}
@@ -0,0 +1,859 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex as StdMutex,
+    },
+    task::Poll,
+    time::{Duration, Instant},
+};
+
+use mlua::prelude::*;
+use smol::lock::{Mutex, OnceCell, Semaphore};
+
+use crate::{
+    cancel::{self, CancelToken},
+    secrets,
+    size::Bytes,
+};
+
+/// Boxed future which resolves to the values returned by a Lua call
+type LuaFuture = Pin<Box<dyn Future<Output = LuaResult<LuaMultiValue>> + Send>>;
+
+/// Registry key of the handler set by `init.on_task_error`
+const TASK_ERROR_HANDLER: &str = "luavisors.on_task_error";
+
+/// Set or clear the global handler for errors raised by background callbacks
+pub async fn on_task_error(lua: Lua, handler: Option<LuaFunction>) -> LuaResult<()> {
+    lua.set_named_registry_value(TASK_ERROR_HANDLER, handler)
+}
+
+/// Deliver an error from a background callback to `handler`, the global handler, or stderr
+pub async fn report_error(lua: &Lua, source: &str, err: LuaError, handler: Option<LuaFunction>) {
+    let handler = match handler {
+        Some(handler) => Some(handler),
+        None => lua
+            .named_registry_value::<Option<LuaFunction>>(TASK_ERROR_HANDLER)
+            .ok()
+            .flatten(),
+    };
+    let err = secrets::mask(&err.to_string());
+    let Some(handler) = handler else {
+        eprintln!("error in '{}' callback: {}", source, err);
+        return;
+    };
+    if let Err(handler_err) = handler.call_async::<()>((err.as_str(), source)).await {
+        eprintln!("error in '{}' callback: {}", source, err);
+        let handler_err = secrets::mask(&handler_err.to_string());
+        eprintln!("error in '{}' error handler: {}", source, handler_err);
+    }
+}
+
+/// Number of instructions between checks of a task's limits
+const BUDGET_STEP: u32 = 1000;
+
+/// Resource limits applied to a single call of a Lua function
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Limits {
+    /// Total number of instructions the call may execute
+    pub instructions: Option<u64>,
+    /// Bytes the call may allocate beyond what it frees, across all of its polls
+    pub memory: Option<u64>,
+    /// Time the call may run before it next waits on something
+    pub cpu_slice: Option<Duration>,
+}
+
+impl Limits {
+    /// Read the `instructions`, `memory`, and `cpu_slice_ms` fields of an options table
+    pub fn from_options(options: &LuaTable) -> LuaResult<Self> {
+        let cpu_slice = match options.get::<Option<f64>>("cpu_slice_ms")? {
+            Some(ms) => Some(Duration::try_from_secs_f64(ms / 1000.0).map_err(|_| {
+                LuaError::runtime("cpu_slice_ms must be a non-negative number of milliseconds")
+            })?),
+            None => None,
+        };
+        Ok(Limits {
+            instructions: options.get("instructions")?,
+            memory: options.get::<Option<Bytes>>("memory")?.map(|bytes| bytes.0),
+            cpu_slice,
+        })
+    }
+
+    /// Check if no limit is set
+    pub fn is_empty(&self) -> bool {
+        *self == Limits::default()
+    }
+}
+
+/// Resources used so far by a limited call
+struct Usage {
+    instructions: u64,
+    slice_start: Instant,
+    memory_start: usize,
+    /// Net growth of the Lua state over the finished polls, which frees can lower
+    memory: i64,
+}
+
+impl Usage {
+    /// Return an error describing the first limit the call has exceeded
+    fn exceeded(&self, limits: &Limits, used_memory: usize) -> Option<String> {
+        if let Some(budget) = limits
+            .instructions
+            .filter(|budget| self.instructions > *budget)
+        {
+            return Some(format!(
+                "callback exceeded its budget of {} instructions",
+                budget
+            ));
+        }
+        if let Some(slice) = limits
+            .cpu_slice
+            .filter(|slice| self.slice_start.elapsed() > *slice)
+        {
+            return Some(format!(
+                "callback exceeded its cpu slice of {} ms without yielding",
+                slice.as_secs_f64() * 1000.0
+            ));
+        }
+        let memory = self.memory + growth(self.memory_start, used_memory);
+        if let Some(quota) = limits.memory.filter(|quota| memory > *quota as i64) {
+            return Some(format!(
+                "callback exceeded its memory quota of {} bytes",
+                quota
+            ));
+        }
+        None
+    }
+}
+
+/// Signed change in memory use from `start` to `used`
+fn growth(start: usize, used: usize) -> i64 {
+    used as i64 - start as i64
+}
+
+/// Call a Lua function, raising an error once it exceeds any of its `limits`
+///
+/// Memory is counted as the net growth of the shared Lua state while the call
+/// is running, so it is approximate when other tasks allocate or collect
+/// garbage at the same time.
+pub async fn call_with_limits<R: FromLuaMulti>(
+    lua: &Lua,
+    func: &LuaFunction,
+    args: LuaMultiValue,
+    limits: Limits,
+) -> LuaResult<R> {
+    if limits.is_empty() {
+        return func.call_async(args).await;
+    }
+    // compiled traces skip instruction hooks, so only interpret the function
+    let loaded = lua
+        .globals()
+        .get::<LuaTable>("package")?
+        .get::<LuaTable>("loaded")?;
+    if let Some(jit) = loaded.get::<Option<LuaTable>>("jit")? {
+        jit.get::<LuaFunction>("off")?.call::<()>((func, true))?;
+    }
+    let usage = Arc::new(StdMutex::new(Usage {
+        instructions: 0,
+        slice_start: Instant::now(),
+        memory_start: lua.used_memory(),
+        memory: 0,
+    }));
+    // hooks are per thread, so the limits only cover this call
+    let thread = lua.create_thread(func.clone())?;
+    let triggers = LuaHookTriggers::new().every_nth_instruction(BUDGET_STEP);
+    let hook_usage = usage.clone();
+    thread.set_hook(triggers, move |lua, _| {
+        let mut usage = hook_usage.lock().unwrap_or_else(|err| err.into_inner());
+        usage.instructions += BUDGET_STEP as u64;
+        match usage.exceeded(&limits, lua.used_memory()) {
+            Some(err) => Err(LuaError::runtime(err)),
+            None => Ok(LuaVmState::Continue),
+        }
+    })?;
+    let mut call = thread.into_async::<R>(args)?;
+    smol::future::poll_fn(|cx| {
+        // each poll starts a new cpu slice and carries over the memory used so far
+        {
+            let mut usage = usage.lock().unwrap_or_else(|err| err.into_inner());
+            usage.slice_start = Instant::now();
+            usage.memory_start = lua.used_memory();
+        }
+        let poll = Pin::new(&mut call).poll(cx);
+        let mut usage = usage.lock().unwrap_or_else(|err| err.into_inner());
+        usage.memory += growth(usage.memory_start, lua.used_memory());
+        poll
+    })
+    .await
+}
+
+/// Wrap a Lua function so each call is subject to `limits`
+pub fn with_limits(lua: &Lua, func: LuaFunction, limits: Limits) -> LuaResult<LuaFunction> {
+    lua.create_async_function(move |lua, args: LuaMultiValue| {
+        let func = func.clone();
+        async move { call_with_limits::<LuaMultiValue>(&lua, &func, args, limits).await }
+    })
+}
+
+/// Handle to a Lua function running as a background task
+#[derive(Clone)]
+pub struct TaskHandle {
+    result: Arc<OnceCell<LuaResult<LuaMultiValue>>>,
+}
+
+impl TaskHandle {
+    /// Wait for the task to finish and return its values
+    pub async fn wait(&self) -> LuaResult<LuaMultiValue> {
+        self.result.wait().await.clone()
+    }
+
+    /// Check whether the task has finished
+    pub fn done(&self) -> bool {
+        self.result.is_initialized()
+    }
+}
+
+/// Lua methods for task handles
+impl LuaUserData for TaskHandle {
+    fn add_methods<M: LuaUserDataMethods<Self>>(methods: &mut M) {
+        methods.add_async_method("wait", |_, this, ()| {
+            let this = this.clone();
+            async move { cancel::until_deadline(this.wait()).await }
+        });
+        methods.add_method("done", |_, this, ()| Ok(this.done()));
+    }
+}
+
+/// Number of background tasks which have not finished yet
+static RUNNING: AtomicUsize = AtomicUsize::new(0);
+
+/// Guard counting a background task as running until it is dropped
+struct Running;
+
+impl Running {
+    /// Count a new running task
+    fn start() -> Self {
+        RUNNING.fetch_add(1, Ordering::Relaxed);
+        Running
+    }
+}
+
+/// Stop counting the task as running
+impl Drop for Running {
+    fn drop(&mut self) {
+        RUNNING.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Return the number of background tasks which have not finished yet
+pub fn running() -> usize {
+    RUNNING.load(Ordering::Relaxed)
+}
+
+/// Run a Lua function as a background task, first waiting for a permit if given
+fn spawn_limited(
+    func: LuaFunction,
+    args: LuaMultiValue,
+    permits: Option<Arc<Semaphore>>,
+    token: Option<CancelToken>,
+) -> TaskHandle {
+    let handle = TaskHandle {
+        result: Arc::new(OnceCell::new()),
+    };
+    let result = handle.result.clone();
+    let running = Running::start();
+    smol::spawn(async move {
+        let _running = running;
+        let _permit = match &permits {
+            Some(permits) => {
+                match cancel::until_cancelled(token.as_ref(), permits.acquire_arc()).await {
+                    Some(permit) => Some(permit),
+                    None => {
+                        let _ = result.set(Err(LuaError::runtime("task cancelled"))).await;
+                        return;
+                    }
+                }
+            }
+            None => None,
+        };
+        let value = cancel::until_cancelled(token.as_ref(), func.call_async(args))
+            .await
+            .unwrap_or_else(|| Err(LuaError::runtime("task cancelled")));
+        let _ = result.set(value).await;
+    })
+    .detach();
+    handle
+}
+
+/// Run a Lua function with arguments as a background task
+pub fn spawn_task(func: LuaFunction, args: LuaMultiValue) -> TaskHandle {
+    spawn_limited(func, args, None, None)
+}
+
+/// Asynchronously run a Lua function and return a handle to wait on it
+pub async fn spawn(lua: Lua, (func, args): (LuaValue, LuaMultiValue)) -> LuaResult<TaskHandle> {
+    match func {
+        LuaValue::Table(options) => {
+            let token = cancel::cancel_option(&options)?;
+            let mut func = options.get(1)?;
+            let limits = Limits::from_options(&options)?;
+            if !limits.is_empty() {
+                func = with_limits(&lua, func, limits)?;
+            }
+            Ok(spawn_limited(func, args, None, token))
+        }
+        LuaValue::Function(func) => Ok(spawn_task(func, args)),
+        value => Err(LuaError::runtime(format!(
+            "cannot spawn a value of type '{}'",
+            value.type_name()
+        ))),
+    }
+}
+
+/// Queue of Lua jobs which run with bounded concurrency
+#[derive(Clone)]
+pub struct Pool {
+    permits: Arc<Semaphore>,
+    jobs: Arc<Mutex<Vec<TaskHandle>>>,
+}
+
+impl Pool {
+    /// Queue a job and return a handle to its result
+    async fn submit(&self, func: LuaFunction, args: LuaMultiValue) -> TaskHandle {
+        let handle = spawn_limited(func, args, Some(self.permits.clone()), None);
+        self.jobs.lock().await.push(handle.clone());
+        handle
+    }
+
+    /// Wait for every queued job to finish, returning the number that failed
+    async fn wait(&self) -> usize {
+        let jobs = std::mem::take(&mut *self.jobs.lock().await);
+        let mut failed = 0;
+        for job in jobs {
+            if job.wait().await.is_err() {
+                failed += 1;
+            }
+        }
+        failed
+    }
+}
+
+/// Lua methods for worker pools
+impl LuaUserData for Pool {
+    fn add_methods<M: LuaUserDataMethods<Self>>(methods: &mut M) {
+        methods.add_async_method(
+            "submit",
+            |_, this, (func, args): (LuaFunction, LuaMultiValue)| {
+                let this = this.clone();
+                async move { Ok(this.submit(func, args).await) }
+            },
+        );
+        methods.add_async_method("wait", |_, this, ()| {
+            let this = this.clone();
+            async move { Ok(this.wait().await) }
+        });
+    }
+}
+
+/// Create a worker pool which runs at most `n` jobs at a time
+pub async fn pool(_lua: Lua, n: usize) -> LuaResult<Pool> {
+    if n == 0 {
+        return Err(LuaError::runtime("pool requires at least one worker"));
+    }
+    Ok(Pool {
+        permits: Arc::new(Semaphore::new(n)),
+        jobs: Arc::new(Mutex::new(Vec::new())),
+    })
+}
+
+/// Convert a task handle, exec handle, or function into a future
+fn awaitable(value: LuaValue) -> LuaResult<LuaFuture> {
+    match value {
+        LuaValue::UserData(ud) if ud.is::<TaskHandle>() => {
+            let handle = ud.borrow::<TaskHandle>()?.clone();
+            Ok(Box::pin(async move { handle.wait().await }))
+        }
+        // exec handles complete when their process exits
+        LuaValue::Table(table) => {
+            let status = table.get::<LuaFunction>("status")?;
+            Ok(Box::pin(async move { status.call_async(table).await }))
+        }
+        LuaValue::Function(func) => {
+            let handle = spawn_task(func, LuaMultiValue::new());
+            Ok(Box::pin(async move { handle.wait().await }))
+        }
+        value => Err(LuaError::runtime(format!(
+            "cannot wait on a value of type '{}'",
+            value.type_name()
+        ))),
+    }
+}
+
+/// Convert a Lua array of awaitable values into futures
+fn awaitables(items: LuaTable) -> LuaResult<Vec<LuaFuture>> {
+    items
+        .sequence_values::<LuaValue>()
+        .map(|value| awaitable(value?))
+        .collect()
+}
+
+/// Poll all futures, stopping early when `stop` returns true for a result
+async fn poll_until(
+    futures: Vec<LuaFuture>,
+    stop: impl Fn(&LuaResult<LuaMultiValue>) -> bool,
+) -> Vec<Option<LuaResult<LuaMultiValue>>> {
+    let mut futures: Vec<Option<LuaFuture>> = futures.into_iter().map(Some).collect();
+    let mut results: Vec<Option<LuaResult<LuaMultiValue>>> = futures.iter().map(|_| None).collect();
+    std::future::poll_fn(|cx| {
+        for (i, slot) in futures.iter_mut().enumerate() {
+            let Some(future) = slot else {
+                continue;
+            };
+            if let Poll::Ready(result) = future.as_mut().poll(cx) {
+                let done = stop(&result);
+                results[i] = Some(result);
+                *slot = None;
+                if done {
+                    return Poll::Ready(());
+                }
+            }
+        }
+        match futures.iter().all(Option::is_none) {
+            true => Poll::Ready(()),
+            false => Poll::Pending,
+        }
+    })
+    .await;
+    results
+}
+
+/// First value returned by a Lua call
+fn first(values: LuaMultiValue) -> LuaValue {
+    values.into_iter().next().unwrap_or(LuaValue::Nil)
+}
+
+/// Wait for all items and return their first values, raising the first error
+pub async fn all(lua: Lua, items: LuaTable) -> LuaResult<LuaTable> {
+    let results = poll_until(awaitables(items)?, |result| result.is_err()).await;
+    let table = lua.create_table()?;
+    for (i, result) in results.into_iter().enumerate() {
+        match result {
+            Some(result) => table.raw_set(i + 1, first(result?))?,
+            None => continue,
+        }
+    }
+    Ok(table)
+}
+
+/// Wait for the first item to finish and return its index and first value
+pub async fn any(_lua: Lua, items: LuaTable) -> LuaResult<(usize, LuaValue)> {
+    let futures = awaitables(items)?;
+    if futures.is_empty() {
+        return Err(LuaError::runtime("no items to wait on"));
+    }
+    let results = poll_until(futures, |_| true).await;
+    let (i, result) = results
+        .into_iter()
+        .enumerate()
+        .find_map(|(i, result)| result.map(|result| (i, result)))
+        .ok_or_else(|| LuaError::runtime("no items finished"))?;
+    Ok((i + 1, first(result?)))
+}
+
+/// Wait for all items and return a table describing how each one finished
+pub async fn join(lua: Lua, items: LuaTable) -> LuaResult<LuaTable> {
+    let results = poll_until(awaitables(items)?, |_| false).await;
+    let table = lua.create_table()?;
+    for result in results.into_iter().flatten() {
+        let entry = lua.create_table()?;
+        match result {
+            Ok(values) => {
+                entry.set("ok", true)?;
+                entry.set("value", first(values))?;
+            }
+            Err(err) => {
+                entry.set("ok", false)?;
+                entry.set("error", err.to_string())?;
+            }
+        }
+        table.push(entry)?;
+    }
+    Ok(table)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_function(lua: &Lua, code: &str) -> LuaFunction {
+        lua.load(code).into_function().unwrap()
+    }
+
+    #[test]
+    fn test_spawn_wait() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            let func = test_function(&lua, "local a, b = ...; return a + b");
+            let args = (1, 2).into_lua_multi(&lua).unwrap();
+            let handle = spawn(lua.clone(), (LuaValue::Function(func), args))
+                .await
+                .unwrap();
+            let values = handle.wait().await.unwrap();
+            assert_eq!(values[0].as_i32(), Some(3));
+            assert!(handle.done());
+        });
+    }
+
+    #[test]
+    fn test_spawn_error() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            let func = test_function(&lua, "error('boom')");
+            let handle = spawn_task(func, LuaMultiValue::new());
+            assert!(handle.wait().await.is_err());
+        });
+    }
+
+    #[test]
+    fn test_spawn_cancelled() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            let token = CancelToken::default();
+            let options = lua.create_table().unwrap();
+            options
+                .set(
+                    1,
+                    lua.create_async_function(|_, ()| std::future::pending::<LuaResult<()>>())
+                        .unwrap(),
+                )
+                .unwrap();
+            options.set("cancel", token.child()).unwrap();
+            let handle = spawn(
+                lua.clone(),
+                (LuaValue::Table(options), LuaMultiValue::new()),
+            )
+            .await
+            .unwrap();
+            token.cancel();
+            let err = handle.wait().await.unwrap_err();
+            assert!(err.to_string().contains("cancelled"));
+        });
+    }
+
+    #[test]
+    fn test_spawn_err() {
+        smol::block_on(async {
+            let result = spawn(Lua::new(), (LuaValue::Integer(1), LuaMultiValue::new())).await;
+            assert!(result.is_err());
+        });
+    }
+
+    #[test]
+    fn test_call_with_limits_instructions() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            let endless = test_function(&lua, "while true do end");
+            let limits = Limits {
+                instructions: Some(10_000),
+                ..Default::default()
+            };
+            let result = call_with_limits::<()>(&lua, &endless, LuaMultiValue::new(), limits);
+            let err = result.await.unwrap_err();
+            assert!(err.to_string().contains("budget"));
+            let quick = test_function(
+                &lua,
+                "local n = 0; for i = 1, 10 do n = n + i end; return n",
+            );
+            let n = call_with_limits::<i32>(&lua, &quick, LuaMultiValue::new(), limits);
+            assert_eq!(n.await.unwrap(), 55);
+        });
+    }
+
+    #[test]
+    fn test_limits_from_options() {
+        let lua = Lua::new();
+        let options: LuaTable = lua
+            .load("{ memory = '16M', cpu_slice_ms = 5, instructions = 100 }")
+            .eval()
+            .unwrap();
+        let limits = Limits::from_options(&options).unwrap();
+        assert_eq!(limits.memory, Some(16 << 20));
+        assert_eq!(limits.cpu_slice, Some(Duration::from_millis(5)));
+        assert_eq!(limits.instructions, Some(100));
+        let options = lua.load("{ cpu_slice_ms = -1 }").eval().unwrap();
+        assert!(Limits::from_options(&options).is_err());
+        assert!(Limits::from_options(&lua.create_table().unwrap())
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_call_with_limits_memory() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            let hungry = test_function(
+                &lua,
+                "local t = {}; for i = 1, 1e6 do t[i] = tostring(i) end",
+            );
+            let limits = Limits {
+                memory: Some(1 << 20),
+                ..Default::default()
+            };
+            let result = call_with_limits::<()>(&lua, &hungry, LuaMultiValue::new(), limits);
+            let err = result.await.unwrap_err();
+            assert!(err.to_string().contains("memory quota"));
+        });
+    }
+
+    #[test]
+    fn test_call_with_limits_memory_freed() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            let pause = lua
+                .create_async_function(|_, ()| async {
+                    smol::future::yield_now().await;
+                    Ok(())
+                })
+                .unwrap();
+            lua.globals().set("pause", pause).unwrap();
+            // each table is freed in a later poll than the one which allocated it
+            let churn = test_function(
+                &lua,
+                r#"
+                    for _ = 1, 20 do
+                        local t = {}
+                        for i = 1, 1e4 do t[i] = tostring(i) end
+                        pause()
+                        t = nil
+                        collectgarbage()
+                        pause()
+                    end
+                "#,
+            );
+            let limits = Limits {
+                memory: Some(4 << 20),
+                ..Default::default()
+            };
+            let result = call_with_limits::<()>(&lua, &churn, LuaMultiValue::new(), limits);
+            result.await.unwrap();
+        });
+    }
+
+    #[test]
+    fn test_call_with_limits_cpu_slice() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            let endless = test_function(&lua, "while true do end");
+            let limits = Limits {
+                cpu_slice: Some(Duration::from_millis(5)),
+                ..Default::default()
+            };
+            let result = call_with_limits::<()>(&lua, &endless, LuaMultiValue::new(), limits);
+            let err = result.await.unwrap_err();
+            assert!(err.to_string().contains("cpu slice"));
+        });
+    }
+
+    #[test]
+    fn test_running() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            let event = crate::sync::Event::default();
+            let wait = event.clone();
+            let func = lua
+                .create_async_function(move |_, ()| {
+                    let wait = wait.clone();
+                    async move {
+                        wait.wait().await;
+                        Ok(())
+                    }
+                })
+                .unwrap();
+            let handle = spawn_task(func, LuaMultiValue::new());
+            assert!(running() >= 1);
+            event.set();
+            handle.wait().await.unwrap();
+        });
+    }
+
+    #[test]
+    fn test_spawn_budget() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            let options: LuaTable = lua
+                .load("{ function() while true do end end, instructions = 10000 }")
+                .eval()
+                .unwrap();
+            let handle = spawn(
+                lua.clone(),
+                (LuaValue::Table(options), LuaMultiValue::new()),
+            )
+            .await
+            .unwrap();
+            assert!(handle.wait().await.is_err());
+        });
+    }
+
+    #[test]
+    fn test_task_handle_lua() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            let func = test_function(&lua, "return 'done'");
+            lua.globals()
+                .set("task", spawn_task(func, LuaMultiValue::new()))
+                .unwrap();
+            let chunk = lua.load("local value = task:wait(); return value, task:done()");
+            let (value, done) = chunk.eval_async::<(String, bool)>().await.unwrap();
+            assert_eq!(value, "done");
+            assert!(done);
+        });
+    }
+
+    #[test]
+    fn test_pool() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            let pool = pool(lua.clone(), 2).await.unwrap();
+            lua.globals().set("pool", pool).unwrap();
+            let chunk = lua.load(
+                r#"
+                local running, peak = 0, 0
+                local handles = {}
+                for i = 1, 6 do
+                    handles[i] = pool:submit(function(n)
+                        running = running + 1
+                        peak = math.max(peak, running)
+                        sleep(0)
+                        running = running - 1
+                        if n == 6 then error('boom') end
+                        return n * 10
+                    end, i)
+                end
+                local failed = pool:wait()
+                return peak, failed, handles[3]:wait()
+                "#,
+            );
+            let sleep = lua
+                .create_async_function(|_, _: f64| async {
+                    smol::future::yield_now().await;
+                    Ok(())
+                })
+                .unwrap();
+            lua.globals().set("sleep", sleep).unwrap();
+            let result = chunk.eval_async::<(usize, usize, i32)>().await.unwrap();
+            assert_eq!(result, (2, 1, 30));
+        });
+    }
+
+    #[test]
+    fn test_pool_empty() {
+        smol::block_on(async {
+            assert!(pool(Lua::new(), 0).await.is_err());
+        });
+    }
+
+    #[test]
+    fn test_report_error() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            let handler = test_function(&lua, "errors = { ... }");
+            let err = LuaError::runtime("boom");
+            report_error(&lua, "init.every", err.clone(), Some(handler)).await;
+            let errors: Vec<String> = lua.globals().get("errors").unwrap();
+            assert!(errors[0].contains("boom"));
+            assert_eq!(errors[1], "init.every");
+            // the global handler is used when no handler is given
+            let global = test_function(&lua, "global = ...");
+            on_task_error(lua.clone(), Some(global)).await.unwrap();
+            report_error(&lua, "init.debounce", err.clone(), None).await;
+            assert!(lua
+                .globals()
+                .get::<String>("global")
+                .unwrap()
+                .contains("boom"));
+            on_task_error(lua.clone(), None).await.unwrap();
+            report_error(&lua, "init.debounce", err, None).await;
+        });
+    }
+
+    #[test]
+    fn test_all() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            let items: LuaTable = lua
+                .load(
+                    r#"{
+                        function() return 1 end,
+                        function() return 2 end,
+                        { status = function() return 0 end },
+                    }"#,
+                )
+                .eval()
+                .unwrap();
+            let results = all(lua.clone(), items).await.unwrap();
+            let results: Vec<i32> = results.sequence_values().collect::<LuaResult<_>>().unwrap();
+            assert_eq!(results, vec![1, 2, 0]);
+        });
+    }
+
+    #[test]
+    fn test_all_error() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            let items = lua.load("{ function() error('boom') end }").eval().unwrap();
+            assert!(all(lua.clone(), items).await.is_err());
+        });
+    }
+
+    #[test]
+    fn test_any() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            let sleeper = lua.create_async_function(|_, ()| async {
+                smol::Timer::after(std::time::Duration::from_secs(5)).await;
+                Ok(1)
+            });
+            lua.globals().set("sleeper", sleeper.unwrap()).unwrap();
+            let items = lua
+                .load("{ sleeper, function() return 'fast' end }")
+                .eval()
+                .unwrap();
+            let (i, value) = any(lua.clone(), items).await.unwrap();
+            assert_eq!(i, 2);
+            assert_eq!(value.to_string().unwrap(), "fast");
+        });
+    }
+
+    #[test]
+    fn test_any_empty() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            let items = lua.create_table().unwrap();
+            assert!(any(lua.clone(), items).await.is_err());
+        });
+    }
+
+    #[test]
+    fn test_join() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            let items = lua
+                .load("{ function() return 1 end, function() error('boom') end }")
+                .eval()
+                .unwrap();
+            let results = join(lua.clone(), items).await.unwrap();
+            let ok: LuaTable = results.get(1).unwrap();
+            let err: LuaTable = results.get(2).unwrap();
+            assert!(ok.get::<bool>("ok").unwrap());
+            assert_eq!(ok.get::<i32>("value").unwrap(), 1);
+            assert!(!err.get::<bool>("ok").unwrap());
+            assert!(err.get::<String>("error").unwrap().contains("boom"));
+        });
+    }
+
+    #[test]
+    fn test_awaitable_err() {
+        assert!(awaitable(LuaValue::Integer(1)).is_err());
+    }
+}
@@ -0,0 +1,395 @@
+use std::{
+    io::{Read, Write},
+    sync::Mutex,
+};
+
+use mlua::prelude::*;
+
+use crate::{stdin, unix};
+
+/// Maximum number of lines kept in the prompt history
+const HISTORY_SIZE: usize = 100;
+
+/// Lines previously entered at a prompt
+static HISTORY: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// Keys understood by the line editor
+#[derive(Debug, PartialEq)]
+enum Key {
+    Char(char),
+    Enter,
+    Backspace,
+    Delete,
+    Left,
+    Right,
+    Home,
+    End,
+    Up,
+    Down,
+    ClearLine,
+    Eof,
+    Ignore,
+}
+
+/// Read a single byte, or `None` at end of input
+fn read_byte(reader: &mut impl Read) -> std::io::Result<Option<u8>> {
+    let mut byte = [0u8; 1];
+    match reader.read(&mut byte)? {
+        0 => Ok(None),
+        _ => Ok(Some(byte[0])),
+    }
+}
+
+/// Decode the rest of an escape sequence
+fn read_escape(reader: &mut impl Read) -> std::io::Result<Key> {
+    let Some(b'[' | b'O') = read_byte(reader)? else {
+        return Ok(Key::Ignore);
+    };
+    let key = match read_byte(reader)? {
+        Some(b'A') => Key::Up,
+        Some(b'B') => Key::Down,
+        Some(b'C') => Key::Right,
+        Some(b'D') => Key::Left,
+        Some(b'H') => Key::Home,
+        Some(b'F') => Key::End,
+        Some(digit @ b'0'..=b'9') => {
+            // consume the remainder of sequences such as `ESC [ 3 ~`
+            let mut last = digit;
+            while let Some(byte) = read_byte(reader)? {
+                if !byte.is_ascii_digit() && byte != b';' {
+                    break;
+                }
+                last = byte;
+            }
+            match last {
+                b'1' | b'7' => Key::Home,
+                b'4' | b'8' => Key::End,
+                b'3' => Key::Delete,
+                _ => Key::Ignore,
+            }
+        }
+        _ => Key::Ignore,
+    };
+    Ok(key)
+}
+
+/// Decode a key press from raw terminal input, or `None` at end of input
+fn read_key(reader: &mut impl Read) -> std::io::Result<Option<Key>> {
+    let Some(byte) = read_byte(reader)? else {
+        return Ok(None);
+    };
+    let key = match byte {
+        b'\r' | b'\n' => Key::Enter,
+        0x7f | 0x08 => Key::Backspace,
+        0x01 => Key::Home,
+        0x02 => Key::Left,
+        0x04 => Key::Eof,
+        0x05 => Key::End,
+        0x06 => Key::Right,
+        0x0e => Key::Down,
+        0x10 => Key::Up,
+        0x15 => Key::ClearLine,
+        0x1b => read_escape(reader)?,
+        0x00..=0x1f => Key::Ignore,
+        _ => {
+            // collect the continuation bytes of a UTF-8 sequence
+            let len = match byte {
+                0xc0..=0xdf => 2,
+                0xe0..=0xef => 3,
+                0xf0..=0xf7 => 4,
+                _ => 1,
+            };
+            let mut bytes = vec![byte];
+            for _ in 1..len {
+                match read_byte(reader)? {
+                    Some(byte) => bytes.push(byte),
+                    None => break,
+                }
+            }
+            match std::str::from_utf8(&bytes)
+                .ok()
+                .and_then(|s| s.chars().next())
+            {
+                Some(c) => Key::Char(c),
+                None => Key::Ignore,
+            }
+        }
+    };
+    Ok(Some(key))
+}
+
+/// Result of feeding a key to the line editor
+#[derive(Debug, PartialEq)]
+enum Outcome {
+    Submit(String),
+    Eof,
+}
+
+/// Minimal line editor with cursor movement and history
+struct Editor {
+    buffer: Vec<char>,
+    cursor: usize,
+    history: Vec<String>,
+    index: usize,
+    saved: Vec<char>,
+}
+
+impl Editor {
+    /// Create an editor which can recall `history`
+    fn new(history: Vec<String>) -> Self {
+        let index = history.len();
+        Editor {
+            buffer: Vec::new(),
+            cursor: 0,
+            history,
+            index,
+            saved: Vec::new(),
+        }
+    }
+
+    /// Replace the buffer with the history entry at `index`
+    fn recall(&mut self, index: usize) {
+        if self.index == self.history.len() {
+            self.saved = self.buffer.clone();
+        }
+        self.index = index;
+        self.buffer = match self.history.get(index) {
+            Some(line) => line.chars().collect(),
+            None => self.saved.clone(),
+        };
+        self.cursor = self.buffer.len();
+    }
+
+    /// Apply a key press to the buffer
+    fn handle(&mut self, key: Key) -> Option<Outcome> {
+        match key {
+            Key::Char(c) => {
+                self.buffer.insert(self.cursor, c);
+                self.cursor += 1;
+            }
+            Key::Enter => return Some(Outcome::Submit(self.buffer.iter().collect())),
+            Key::Backspace if self.cursor > 0 => {
+                self.cursor -= 1;
+                self.buffer.remove(self.cursor);
+            }
+            Key::Eof if self.buffer.is_empty() => return Some(Outcome::Eof),
+            Key::Delete | Key::Eof if self.cursor < self.buffer.len() => {
+                self.buffer.remove(self.cursor);
+            }
+            Key::Left if self.cursor > 0 => self.cursor -= 1,
+            Key::Right if self.cursor < self.buffer.len() => self.cursor += 1,
+            Key::Home => self.cursor = 0,
+            Key::End => self.cursor = self.buffer.len(),
+            Key::Up if self.index > 0 => self.recall(self.index - 1),
+            Key::Down if self.index < self.history.len() => self.recall(self.index + 1),
+            Key::ClearLine => {
+                self.buffer.clear();
+                self.cursor = 0;
+            }
+            _ => {}
+        }
+        None
+    }
+
+    /// Redraw the prompt and buffer with the cursor in place
+    fn render(&self, prompt: &str) -> String {
+        let line: String = self.buffer.iter().collect();
+        let mut out = format!("\r{}{}\x1b[K", prompt, line);
+        let back = self.buffer.len() - self.cursor;
+        if back > 0 {
+            out.push_str(&format!("\x1b[{}D", back));
+        }
+        out
+    }
+}
+
+/// Record a submitted line in the prompt history
+fn remember(history: &mut Vec<String>, line: &str) {
+    if line.is_empty() || history.last().is_some_and(|last| last == line) {
+        return;
+    }
+    history.push(line.to_string());
+    if history.len() > HISTORY_SIZE {
+        history.remove(0);
+    }
+}
+
+/// Run the line editor on the terminal until a line is submitted
+fn edit_line(prompt: &str) -> std::io::Result<Option<String>> {
+    let history = HISTORY
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .clone();
+    let mut editor = Editor::new(history);
+    let mut stdin = std::io::stdin();
+    let mut stdout = std::io::stdout();
+    let _mode = unix::terminal_mode(0, unix::ICANON | unix::ECHO)?;
+    write!(stdout, "{}", prompt)?;
+    stdout.flush()?;
+    let outcome = loop {
+        let Some(key) = read_key(&mut stdin)? else {
+            break Outcome::Eof;
+        };
+        if let Some(outcome) = editor.handle(key) {
+            break outcome;
+        }
+        write!(stdout, "{}", editor.render(prompt))?;
+        stdout.flush()?;
+    };
+    writeln!(stdout, "\r")?;
+    match outcome {
+        Outcome::Submit(line) => {
+            remember(
+                &mut HISTORY.lock().unwrap_or_else(|err| err.into_inner()),
+                &line,
+            );
+            Ok(Some(line))
+        }
+        Outcome::Eof => Ok(None),
+    }
+}
+
+/// Prompt for a line of input with line editing and history on a terminal
+pub async fn prompt(_lua: Lua, text: Option<String>) -> LuaResult<Option<String>> {
+    let text = text.unwrap_or_default();
+    // hold the shared reader so other tasks cannot read concurrently
+    let mut reader = stdin::stdin().lock().await;
+    if unix::isatty(0) && unix::isatty(1) {
+        return Ok(smol::unblock(move || edit_line(&text)).await?);
+    }
+    print!("{}", text);
+    std::io::stdout().flush()?;
+    let line = stdin::next_line(&mut *reader).await?;
+    Ok(line.map(|line| String::from_utf8_lossy(&line).into_owned()))
+}
+
+/// Prompt for a line of input without echoing it to the terminal
+pub async fn prompt_secret(_lua: Lua, text: Option<String>) -> LuaResult<Option<String>> {
+    let mut reader = stdin::stdin().lock().await;
+    print!("{}", text.unwrap_or_default());
+    std::io::stdout().flush()?;
+    let mode = match unix::isatty(0) {
+        true => Some(unix::terminal_mode(0, unix::ECHO)?),
+        false => None,
+    };
+    let line = stdin::next_line(&mut *reader).await;
+    // restore echo before reporting errors and move past the hidden input
+    if mode.is_some() {
+        drop(mode);
+        println!();
+    }
+    Ok(line?.map(|line| String::from_utf8_lossy(&line).into_owned()))
+}
+
+/// Return the rows and columns of the terminal on stdout, or nil if it is not a terminal
+pub async fn terminal_size(_lua: Lua, _: ()) -> LuaResult<(Option<u16>, Option<u16>)> {
+    match unix::window_size(1) {
+        Ok(size) => Ok((Some(size.rows), Some(size.cols))),
+        Err(_) => Ok((None, None)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(input: &[u8]) -> Vec<Key> {
+        let mut reader = std::io::Cursor::new(input.to_vec());
+        let mut keys = Vec::new();
+        while let Some(key) = read_key(&mut reader).unwrap() {
+            keys.push(key);
+        }
+        keys
+    }
+
+    fn type_keys(editor: &mut Editor, input: &[u8]) -> Option<Outcome> {
+        for key in keys(input) {
+            if let Some(outcome) = editor.handle(key) {
+                return Some(outcome);
+            }
+        }
+        None
+    }
+
+    #[test]
+    fn test_read_key() {
+        let keys = keys(b"a\x7f\r\x1b[A\x1b[D\x1b[3~\x1bOH\x04");
+        assert_eq!(
+            keys,
+            vec![
+                Key::Char('a'),
+                Key::Backspace,
+                Key::Enter,
+                Key::Up,
+                Key::Left,
+                Key::Delete,
+                Key::Home,
+                Key::Eof,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_read_key_utf8() {
+        assert_eq!(keys("é".as_bytes()), vec![Key::Char('é')]);
+        assert_eq!(keys(&[0xc3]), vec![Key::Ignore]);
+    }
+
+    #[test]
+    fn test_editor_insert() {
+        let mut editor = Editor::new(Vec::new());
+        let outcome = type_keys(&mut editor, b"hllo\x1b[D\x1b[D\x1b[De\r");
+        assert_eq!(outcome, Some(Outcome::Submit("hello".to_string())));
+    }
+
+    #[test]
+    fn test_editor_delete() {
+        let mut editor = Editor::new(Vec::new());
+        let outcome = type_keys(&mut editor, b"abc\x01\x1b[3~\x05\x7f\r");
+        assert_eq!(outcome, Some(Outcome::Submit("b".to_string())));
+    }
+
+    #[test]
+    fn test_editor_clear_line() {
+        let mut editor = Editor::new(Vec::new());
+        let outcome = type_keys(&mut editor, b"abc\x15d\r");
+        assert_eq!(outcome, Some(Outcome::Submit("d".to_string())));
+    }
+
+    #[test]
+    fn test_editor_eof() {
+        let mut editor = Editor::new(Vec::new());
+        assert_eq!(type_keys(&mut editor, b"\x04"), Some(Outcome::Eof));
+    }
+
+    #[test]
+    fn test_editor_history() {
+        let history = vec!["first".to_string(), "second".to_string()];
+        let mut editor = Editor::new(history);
+        assert!(type_keys(&mut editor, b"new\x1b[A\x1b[A").is_none());
+        assert_eq!(editor.buffer.iter().collect::<String>(), "first");
+        assert!(type_keys(&mut editor, b"\x1b[B\x1b[B").is_none());
+        assert_eq!(editor.buffer.iter().collect::<String>(), "new");
+    }
+
+    #[test]
+    fn test_editor_render() {
+        let mut editor = Editor::new(Vec::new());
+        type_keys(&mut editor, b"abc\x1b[D");
+        assert_eq!(editor.render("> "), "\r> abc\x1b[K\x1b[1D");
+    }
+
+    #[test]
+    fn test_remember() {
+        let mut history = Vec::new();
+        remember(&mut history, "one");
+        remember(&mut history, "one");
+        remember(&mut history, "");
+        assert_eq!(history, vec!["one".to_string()]);
+        for i in 0..HISTORY_SIZE {
+            remember(&mut history, &i.to_string());
+        }
+        assert_eq!(history.len(), HISTORY_SIZE);
+        assert_eq!(history[0], "0");
+    }
+}
@@ -0,0 +1,1005 @@
+use std::{
+    path::{Component, Path, PathBuf},
+    time::SystemTime,
+};
+
+use mlua::prelude::*;
+
+/// Default format used by `init.time.format` and `init.time.parse`
+const DEFAULT_FORMAT: &str = "%Y-%m-%dT%H:%M:%S%z";
+
+/// Directory searched for zoneinfo files when `TZDIR` is not set
+const ZONEINFO: &str = "/usr/share/zoneinfo";
+
+/// Weekday names starting on Sunday
+const WEEKDAYS: [&str; 7] = [
+    "Sunday",
+    "Monday",
+    "Tuesday",
+    "Wednesday",
+    "Thursday",
+    "Friday",
+    "Saturday",
+];
+
+/// Month names starting in January
+const MONTHS: [&str; 12] = [
+    "January",
+    "February",
+    "March",
+    "April",
+    "May",
+    "June",
+    "July",
+    "August",
+    "September",
+    "October",
+    "November",
+    "December",
+];
+
+/// Return the current time in seconds since the Unix epoch
+pub fn now() -> f64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs_f64())
+        .unwrap_or_default()
+}
+
+/// Check whether a year is a leap year
+fn is_leap(year: i64) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+/// Number of days in a month of a year
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if is_leap(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Days since the Unix epoch of a civil date
+pub fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let month = month as i64;
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+/// Civil date of a number of days since the Unix epoch
+pub fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let doe = days.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Day of the week of a number of days since the Unix epoch, with Sunday as 0
+pub fn weekday(days: i64) -> u32 {
+    (days + 4).rem_euclid(7) as u32
+}
+
+/// Calendar fields of a moment in a timezone
+#[derive(Debug, Clone, PartialEq)]
+pub struct DateTime {
+    pub year: i64,
+    pub month: u32,
+    pub day: u32,
+    pub hour: u32,
+    pub minute: u32,
+    pub second: u32,
+    pub weekday: u32,
+    pub yday: u32,
+    pub offset: i32,
+    pub abbr: String,
+}
+
+impl DateTime {
+    /// Break a Unix timestamp into calendar fields for a timezone
+    pub fn new(ts: i64, zone: &Zone) -> Self {
+        let (offset, abbr) = zone.offset_at(ts);
+        let local = ts + offset as i64;
+        let days = local.div_euclid(86400);
+        let secs = local.rem_euclid(86400);
+        let (year, month, day) = civil_from_days(days);
+        DateTime {
+            year,
+            month,
+            day,
+            hour: (secs / 3600) as u32,
+            minute: (secs / 60 % 60) as u32,
+            second: (secs % 60) as u32,
+            weekday: weekday(days),
+            yday: (days - days_from_civil(year, 1, 1)) as u32 + 1,
+            offset,
+            abbr,
+        }
+    }
+}
+
+/// Day of the year on which a daylight saving time rule changes
+#[derive(Debug, Clone, PartialEq)]
+enum RuleDay {
+    /// Day 1 to 365 ignoring February 29
+    Julian(u32),
+    /// Day 0 to 365 counting February 29
+    Ordinal(u32),
+    /// Weekday `d` of week `w` of month `m`, where week 5 is the last
+    Weekday(u32, u32, u32),
+}
+
+impl RuleDay {
+    /// Days since the Unix epoch on which the rule applies in `year`
+    fn days(&self, year: i64) -> i64 {
+        let jan1 = days_from_civil(year, 1, 1);
+        match *self {
+            RuleDay::Julian(n) => jan1 + n as i64 - 1 + i64::from(is_leap(year) && n >= 60),
+            RuleDay::Ordinal(n) => jan1 + n as i64,
+            RuleDay::Weekday(month, week, wday) => {
+                let first = days_from_civil(year, month, 1);
+                let mut day = 1 + (wday + 7 - weekday(first)) % 7 + (week - 1) * 7;
+                while day > days_in_month(year, month) {
+                    day -= 7;
+                }
+                first + day as i64 - 1
+            }
+        }
+    }
+}
+
+/// Daylight saving time part of a POSIX `TZ` string
+#[derive(Debug, Clone, PartialEq)]
+struct DstRule {
+    abbr: String,
+    offset: i32,
+    start: (RuleDay, i32),
+    end: (RuleDay, i32),
+}
+
+/// Timezone described by a POSIX `TZ` string such as `CET-1CEST,M3.5.0,M10.5.0/3`
+#[derive(Debug, Clone, PartialEq)]
+pub struct PosixTz {
+    abbr: String,
+    offset: i32,
+    dst: Option<DstRule>,
+}
+
+/// Parser over the characters of a POSIX `TZ` string
+struct TzParser<'a> {
+    rest: &'a str,
+}
+
+impl TzParser<'_> {
+    /// Consume `c` if it is the next character
+    fn eat(&mut self, c: char) -> bool {
+        match self.rest.strip_prefix(c) {
+            Some(rest) => {
+                self.rest = rest;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Parse an unsigned decimal number
+    fn number(&mut self) -> Option<u32> {
+        let len = self
+            .rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(self.rest.len());
+        let (digits, rest) = self.rest.split_at(len);
+        self.rest = rest;
+        digits.parse().ok()
+    }
+
+    /// Parse a zone abbreviation, either alphabetic or quoted in angle brackets
+    fn abbr(&mut self) -> Option<String> {
+        let (abbr, rest) = match self.rest.strip_prefix('<') {
+            Some(quoted) => {
+                let end = quoted.find('>')?;
+                (&quoted[..end], &quoted[end + 1..])
+            }
+            None => {
+                let len = self
+                    .rest
+                    .find(|c: char| !c.is_ascii_alphabetic())
+                    .unwrap_or(self.rest.len());
+                self.rest.split_at(len)
+            }
+        };
+        self.rest = rest;
+        (abbr.len() >= 3).then(|| abbr.to_string())
+    }
+
+    /// Parse a signed `hh[:mm[:ss]]` time in seconds with at most `max_hours` hours
+    fn time(&mut self, max_hours: u32) -> Option<i32> {
+        let sign = match self.eat('-') {
+            true => -1,
+            false => {
+                self.eat('+');
+                1
+            }
+        };
+        let mut secs = self.number().filter(|n| *n <= max_hours)? * 3600;
+        if self.eat(':') {
+            secs += self.number().filter(|n| *n <= 59)? * 60;
+            if self.eat(':') {
+                secs += self.number().filter(|n| *n <= 59)?;
+            }
+        }
+        Some(sign * secs as i32)
+    }
+
+    /// Parse a rule date with an optional time of day
+    fn rule(&mut self) -> Option<(RuleDay, i32)> {
+        let day = if self.eat('J') {
+            RuleDay::Julian(self.number().filter(|n| (1..=365).contains(n))?)
+        } else if self.eat('M') {
+            let month = self.number().filter(|n| (1..=12).contains(n))?;
+            self.eat('.').then_some(())?;
+            let week = self.number().filter(|n| (1..=5).contains(n))?;
+            self.eat('.').then_some(())?;
+            let wday = self.number().filter(|n| *n <= 6)?;
+            RuleDay::Weekday(month, week, wday)
+        } else {
+            RuleDay::Ordinal(self.number().filter(|n| *n <= 365)?)
+        };
+        let time = match self.eat('/') {
+            // rule times may run past the end of the day into the following week
+            true => self.time(167)?,
+            false => 7200,
+        };
+        Some((day, time))
+    }
+}
+
+impl PosixTz {
+    /// Parse a POSIX `TZ` string
+    pub fn parse(s: &str) -> Option<Self> {
+        let mut parser = TzParser { rest: s };
+        let abbr = parser.abbr()?;
+        // POSIX offsets count hours west of UTC
+        let offset = -parser.time(24)?;
+        if parser.rest.is_empty() {
+            return Some(PosixTz {
+                abbr,
+                offset,
+                dst: None,
+            });
+        }
+        let dst_abbr = parser.abbr()?;
+        let dst_offset = match parser.rest.starts_with(',') || parser.rest.is_empty() {
+            true => offset + 3600,
+            false => -parser.time(24)?,
+        };
+        // zones without rules follow the United States rules
+        let (start, end) = match parser.eat(',') {
+            true => {
+                let start = parser.rule()?;
+                parser.eat(',').then_some(())?;
+                (start, parser.rule()?)
+            }
+            false => (
+                (RuleDay::Weekday(3, 2, 0), 7200),
+                (RuleDay::Weekday(11, 1, 0), 7200),
+            ),
+        };
+        parser.rest.is_empty().then_some(PosixTz {
+            abbr,
+            offset,
+            dst: Some(DstRule {
+                abbr: dst_abbr,
+                offset: dst_offset,
+                start,
+                end,
+            }),
+        })
+    }
+
+    /// UTC offset in seconds and abbreviation in effect at a Unix timestamp
+    fn offset_at(&self, ts: i64) -> (i32, String) {
+        let Some(dst) = &self.dst else {
+            return (self.offset, self.abbr.clone());
+        };
+        let (year, _, _) = civil_from_days((ts + self.offset as i64).div_euclid(86400));
+        // transitions happen at local wall clock times before the change
+        let start = dst.start.0.days(year) * 86400 + dst.start.1 as i64 - self.offset as i64;
+        let end = dst.end.0.days(year) * 86400 + dst.end.1 as i64 - dst.offset as i64;
+        let in_dst = match start < end {
+            true => start <= ts && ts < end,
+            false => !(end <= ts && ts < start),
+        };
+        match in_dst {
+            true => (dst.offset, dst.abbr.clone()),
+            false => (self.offset, self.abbr.clone()),
+        }
+    }
+}
+
+/// Local time type from a zoneinfo file
+#[derive(Debug, Clone, PartialEq)]
+pub struct LocalType {
+    offset: i32,
+    abbr: String,
+}
+
+/// Timezone used to convert between timestamps and calendar fields
+#[derive(Debug, Clone, PartialEq)]
+pub enum Zone {
+    /// Constant offset from UTC in seconds
+    Fixed(i32, String),
+    /// Rules from a POSIX `TZ` string
+    Posix(PosixTz),
+    /// Transitions from a zoneinfo file, with a rule for times after the last one
+    Tzif {
+        transitions: Vec<(i64, usize)>,
+        types: Vec<LocalType>,
+        footer: Option<PosixTz>,
+    },
+}
+
+/// Read big endian integers from zoneinfo data
+struct TzifReader<'a> {
+    data: &'a [u8],
+}
+
+impl TzifReader<'_> {
+    /// Take the next `n` bytes
+    fn take(&mut self, n: usize) -> Option<&[u8]> {
+        if self.data.len() < n {
+            return None;
+        }
+        let (bytes, rest) = self.data.split_at(n);
+        self.data = rest;
+        Some(bytes)
+    }
+
+    /// Take a big endian signed integer of `n` bytes
+    fn int(&mut self, n: usize) -> Option<i64> {
+        let bytes = self.take(n)?;
+        let value = bytes.iter().fold(0i64, |acc, byte| acc << 8 | *byte as i64);
+        // sign extend values narrower than 64 bits
+        let shift = 64 - 8 * n as u32;
+        Some(value << shift >> shift)
+    }
+
+    /// Read a header and return the version and the six counts
+    fn header(&mut self) -> Option<(u8, [usize; 6])> {
+        (self.take(4)? == b"TZif").then_some(())?;
+        let version = self.take(16)?[0];
+        let mut counts = [0; 6];
+        for count in counts.iter_mut() {
+            *count = usize::try_from(self.int(4)?).ok()?;
+        }
+        Some((version, counts))
+    }
+}
+
+impl Zone {
+    /// Size of the data following a zoneinfo header, or `None` if it overflows
+    fn tzif_len(counts: [usize; 6], time_size: usize) -> Option<usize> {
+        let [isut, isstd, leap, time, kind, chars] = counts;
+        [
+            time.checked_mul(time_size + 1)?,
+            kind.checked_mul(6)?,
+            chars,
+            leap.checked_mul(time_size + 4)?,
+            isstd,
+            isut,
+        ]
+        .into_iter()
+        .try_fold(0usize, usize::checked_add)
+    }
+
+    /// Parse the contents of a zoneinfo file
+    fn from_tzif(data: &[u8]) -> Option<Self> {
+        let mut reader = TzifReader { data };
+        let (version, mut counts) = reader.header()?;
+        let mut time_size = 4;
+        if version >= b'2' {
+            // skip the legacy 32 bit data and use the 64 bit data which follows
+            reader.take(Self::tzif_len(counts, time_size)?)?;
+            counts = reader.header()?.1;
+            time_size = 8;
+        }
+        let [isut, isstd, leap, time, kind, chars] = counts;
+        let times = (0..time)
+            .map(|_| reader.int(time_size))
+            .collect::<Option<Vec<_>>>()?;
+        let indices = reader.take(time)?.to_vec();
+        let raw_types = (0..kind)
+            .map(|_| Some((reader.int(4)? as i32, reader.take(2)?[1] as usize)))
+            .collect::<Option<Vec<_>>>()?;
+        let abbrs = reader.take(chars)?;
+        let types = raw_types
+            .into_iter()
+            .map(|(offset, index)| {
+                let abbr = abbrs.get(index..)?.split(|byte| *byte == 0).next()?;
+                Some(LocalType {
+                    offset,
+                    abbr: String::from_utf8_lossy(abbr).into_owned(),
+                })
+            })
+            .collect::<Option<Vec<_>>>()?;
+        let transitions = times
+            .into_iter()
+            .zip(indices.into_iter().map(usize::from))
+            .filter(|(_, index)| *index < types.len())
+            .collect();
+        let mut footer = None;
+        if version >= b'2' {
+            let rest = leap.checked_mul(time_size + 4)?.checked_add(isstd + isut)?;
+            reader.take(rest)?;
+            let text = String::from_utf8_lossy(reader.data);
+            footer = text.trim().lines().next().and_then(PosixTz::parse);
+        }
+        (!types.is_empty()).then_some(Zone::Tzif {
+            transitions,
+            types,
+            footer,
+        })
+    }
+
+    /// Parse a fixed offset such as `+02:00`, `-0530`, or `+01`
+    fn from_offset(s: &str) -> Option<Self> {
+        let sign = match s.chars().next()? {
+            '+' => 1,
+            '-' => -1,
+            _ => return None,
+        };
+        let digits: String = s[1..].chars().filter(|c| *c != ':').collect();
+        if !digits.chars().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+        let (hours, minutes) = match digits.len() {
+            2 => (digits.parse::<i32>().ok()?, 0),
+            4 => (digits[..2].parse().ok()?, digits[2..].parse().ok()?),
+            _ => return None,
+        };
+        if hours > 23 || minutes > 59 {
+            return None;
+        }
+        Some(Zone::Fixed(
+            sign * (hours * 3600 + minutes * 60),
+            s.to_string(),
+        ))
+    }
+
+    /// Find a zoneinfo file for a name such as `Europe/Paris`
+    fn zoneinfo_path(name: &str) -> Option<PathBuf> {
+        let relative = Path::new(name);
+        // only plain relative names are looked up in the zoneinfo directory
+        if !relative
+            .components()
+            .all(|c| matches!(c, Component::Normal(_)))
+        {
+            return None;
+        }
+        let dir = std::env::var_os("TZDIR").unwrap_or_else(|| ZONEINFO.into());
+        let path = Path::new(&dir).join(relative);
+        path.is_file().then_some(path)
+    }
+
+    /// Load a zoneinfo file
+    fn from_file(path: &Path) -> Option<Self> {
+        Zone::from_tzif(&std::fs::read(path).ok()?)
+    }
+
+    /// The local timezone from `TZ` or `/etc/localtime`, defaulting to UTC
+    fn local() -> Option<Self> {
+        match std::env::var("TZ") {
+            Ok(tz) if !tz.is_empty() => Zone::named(tz.strip_prefix(':').unwrap_or(&tz)),
+            _ => Some(Zone::from_file(Path::new("/etc/localtime")).unwrap_or_else(Zone::utc)),
+        }
+    }
+
+    /// Coordinated universal time
+    pub fn utc() -> Self {
+        Zone::Fixed(0, "UTC".to_string())
+    }
+
+    /// Resolve a timezone name, offset, zoneinfo path, or POSIX `TZ` string
+    pub fn named(name: &str) -> Option<Self> {
+        match name {
+            "UTC" | "Z" | "GMT" | "Etc/UTC" => Some(Zone::utc()),
+            "local" => Zone::local(),
+            _ if name.starts_with('+') || name.starts_with('-') => Zone::from_offset(name),
+            _ if name.starts_with('/') => Zone::from_file(Path::new(name)),
+            _ => match Zone::zoneinfo_path(name) {
+                Some(path) => Zone::from_file(&path),
+                None => PosixTz::parse(name).map(Zone::Posix),
+            },
+        }
+    }
+
+    /// UTC offset in seconds and abbreviation in effect at a Unix timestamp
+    pub fn offset_at(&self, ts: i64) -> (i32, String) {
+        match self {
+            Zone::Fixed(offset, abbr) => (*offset, abbr.clone()),
+            Zone::Posix(tz) => tz.offset_at(ts),
+            Zone::Tzif {
+                transitions,
+                types,
+                footer,
+            } => {
+                let index = transitions.partition_point(|(at, _)| *at <= ts);
+                if index == transitions.len() {
+                    if let Some(footer) = footer {
+                        return footer.offset_at(ts);
+                    }
+                }
+                let kind = match index {
+                    0 => &types[0],
+                    _ => &types[transitions[index - 1].1],
+                };
+                (kind.offset, kind.abbr.clone())
+            }
+        }
+    }
+
+    /// Convert seconds of local wall clock time since the epoch to a Unix timestamp
+    pub fn to_utc(&self, local: i64) -> i64 {
+        let guess = local - self.offset_at(local).0 as i64;
+        local - self.offset_at(guess).0 as i64
+    }
+}
+
+/// Resolve an optional timezone name from Lua, defaulting to UTC
+fn lua_zone(tz: Option<String>) -> LuaResult<Zone> {
+    match tz {
+        Some(tz) => {
+            Zone::named(&tz).ok_or_else(|| LuaError::runtime(format!("unknown timezone '{}'", tz)))
+        }
+        None => Ok(Zone::utc()),
+    }
+}
+
+/// Format a UTC offset as `+hhmm`, with a colon if `colon` is set
+fn format_offset(offset: i32, colon: bool) -> String {
+    let sign = if offset < 0 { '-' } else { '+' };
+    let offset = offset.unsigned_abs();
+    let separator = if colon { ":" } else { "" };
+    format!(
+        "{}{:02}{}{:02}",
+        sign,
+        offset / 3600,
+        separator,
+        offset / 60 % 60
+    )
+}
+
+/// Format calendar fields using `strftime` style conversions
+pub fn format(date: &DateTime, ts: i64, fmt: &str) -> Result<String, String> {
+    let mut out = String::new();
+    let mut chars = fmt.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+        let hour12 = match date.hour % 12 {
+            0 => 12,
+            hour => hour,
+        };
+        let weekday = WEEKDAYS[date.weekday as usize];
+        let month = MONTHS[date.month as usize - 1];
+        let part = match chars.next() {
+            Some('Y') => date.year.to_string(),
+            Some('y') => format!("{:02}", date.year.rem_euclid(100)),
+            Some('m') => format!("{:02}", date.month),
+            Some('d') => format!("{:02}", date.day),
+            Some('e') => format!("{:2}", date.day),
+            Some('j') => format!("{:03}", date.yday),
+            Some('H') => format!("{:02}", date.hour),
+            Some('I') => format!("{:02}", hour12),
+            Some('M') => format!("{:02}", date.minute),
+            Some('S') => format!("{:02}", date.second),
+            Some('p') => (if date.hour < 12 { "AM" } else { "PM" }).to_string(),
+            Some('a') => weekday[..3].to_string(),
+            Some('A') => weekday.to_string(),
+            Some('b') => month[..3].to_string(),
+            Some('B') => month.to_string(),
+            Some('u') => (if date.weekday == 0 { 7 } else { date.weekday }).to_string(),
+            Some('w') => date.weekday.to_string(),
+            Some('z') => format_offset(date.offset, false),
+            Some(':') if chars.next() == Some('z') => format_offset(date.offset, true),
+            Some('Z') => date.abbr.clone(),
+            Some('s') => ts.to_string(),
+            Some('F') => format!("{}-{:02}-{:02}", date.year, date.month, date.day),
+            Some('T') => format!("{:02}:{:02}:{:02}", date.hour, date.minute, date.second),
+            Some('n') => "\n".to_string(),
+            Some('t') => "\t".to_string(),
+            Some('%') => "%".to_string(),
+            Some(c) => return Err(format!("unknown conversion '%{}'", c)),
+            None => return Err("trailing '%' in format".to_string()),
+        };
+        out.push_str(&part);
+    }
+    Ok(out)
+}
+
+/// Fields collected while parsing a time string
+#[derive(Default)]
+struct Parsed {
+    year: i64,
+    month: u32,
+    day: u32,
+    hour: u32,
+    minute: u32,
+    second: u32,
+    pm: Option<bool>,
+    offset: Option<i32>,
+    epoch: Option<i64>,
+}
+
+/// Parser over a time string being matched against a format
+struct TimeParser<'a> {
+    rest: &'a str,
+    input: &'a str,
+}
+
+impl TimeParser<'_> {
+    /// Error describing where the input stopped matching
+    fn error(&self) -> String {
+        format!(
+            "time '{}' does not match format at '{}'",
+            self.input, self.rest
+        )
+    }
+
+    /// Parse a number of at most `max` digits with an optional sign
+    fn number(&mut self, max: usize, signed: bool) -> Result<i64, String> {
+        let sign_len = usize::from(signed && self.rest.starts_with(['-', '+']));
+        let digits = self.rest[sign_len..]
+            .chars()
+            .take(max)
+            .take_while(char::is_ascii_digit)
+            .count();
+        if digits == 0 {
+            return Err(self.error());
+        }
+        let (number, rest) = self.rest.split_at(sign_len + digits);
+        self.rest = rest;
+        number.parse().map_err(|_| self.error())
+    }
+
+    /// Parse a number within a range
+    fn field(&mut self, max: usize, range: std::ops::RangeInclusive<u32>) -> Result<u32, String> {
+        let value = self.number(max, false)?;
+        u32::try_from(value)
+            .ok()
+            .filter(|value| range.contains(value))
+            .ok_or_else(|| format!("value {} out of range in time '{}'", value, self.input))
+    }
+
+    /// Match one of `names` by full name or three letter abbreviation
+    fn name(&mut self, names: &[&str]) -> Result<usize, String> {
+        for (i, name) in names.iter().enumerate() {
+            for candidate in [Some(*name), name.get(..3)].into_iter().flatten() {
+                let matched = self
+                    .rest
+                    .get(..candidate.len())
+                    .is_some_and(|prefix| prefix.eq_ignore_ascii_case(candidate));
+                if matched {
+                    self.rest = &self.rest[candidate.len()..];
+                    return Ok(i);
+                }
+            }
+        }
+        Err(self.error())
+    }
+
+    /// Parse a UTC offset such as `Z`, `+0200`, or `-05:30`
+    fn offset(&mut self) -> Result<i32, String> {
+        if let Some(rest) = self.rest.strip_prefix('Z') {
+            self.rest = rest;
+            return Ok(0);
+        }
+        let len = self
+            .rest
+            .char_indices()
+            .skip(1)
+            .find(|(_, c)| !c.is_ascii_digit() && *c != ':')
+            .map_or(self.rest.len(), |(i, _)| i);
+        match Zone::from_offset(&self.rest[..len]) {
+            Some(Zone::Fixed(offset, _)) => {
+                self.rest = &self.rest[len..];
+                Ok(offset)
+            }
+            _ => Err(self.error()),
+        }
+    }
+
+    /// Consume a literal character from the input
+    fn literal(&mut self, c: char) -> Result<(), String> {
+        match self.rest.strip_prefix(c) {
+            Some(rest) => {
+                self.rest = rest;
+                Ok(())
+            }
+            None => Err(self.error()),
+        }
+    }
+}
+
+/// Parse a time string with `strptime` style conversions into a Unix timestamp
+pub fn parse(input: &str, fmt: &str, zone: &Zone) -> Result<i64, String> {
+    let mut parser = TimeParser { rest: input, input };
+    let mut parsed = Parsed {
+        year: 1970,
+        month: 1,
+        day: 1,
+        ..Default::default()
+    };
+    // expand composite conversions before matching
+    let fmt = fmt.replace("%F", "%Y-%m-%d").replace("%T", "%H:%M:%S");
+    let mut chars = fmt.chars();
+    while let Some(c) = chars.next() {
+        if c.is_whitespace() {
+            parser.rest = parser.rest.trim_start();
+            continue;
+        }
+        if c != '%' {
+            parser.literal(c)?;
+            continue;
+        }
+        match chars.next() {
+            Some('Y') => parsed.year = parser.number(4, true)?,
+            Some('y') => {
+                let year = parser.number(2, false)?;
+                parsed.year = if year < 69 { 2000 + year } else { 1900 + year };
+            }
+            Some('m') => parsed.month = parser.field(2, 1..=12)?,
+            Some('b' | 'B') => parsed.month = parser.name(&MONTHS)? as u32 + 1,
+            Some('d' | 'e') => {
+                parser.rest = parser.rest.trim_start();
+                parsed.day = parser.field(2, 1..=31)?;
+            }
+            Some('H') => parsed.hour = parser.field(2, 0..=23)?,
+            Some('I') => parsed.hour = parser.field(2, 1..=12)?,
+            Some('M') => parsed.minute = parser.field(2, 0..=59)?,
+            // allow a leap second which is folded into the next minute
+            Some('S') => parsed.second = parser.field(2, 0..=60)?,
+            Some('p') => parsed.pm = Some(parser.name(&["AM", "PM"])? == 1),
+            Some('a' | 'A') => {
+                parser.name(&WEEKDAYS)?;
+            }
+            Some('z') => parsed.offset = Some(parser.offset()?),
+            Some(':') if chars.next() == Some('z') => parsed.offset = Some(parser.offset()?),
+            Some('Z') => {
+                let len = parser
+                    .rest
+                    .find(|c: char| !c.is_ascii_alphabetic())
+                    .unwrap_or(parser.rest.len());
+                parser.rest = &parser.rest[len..];
+            }
+            Some('s') => parsed.epoch = Some(parser.number(20, true)?),
+            Some('%') => parser.literal('%')?,
+            Some(c) => return Err(format!("unknown conversion '%{}'", c)),
+            None => return Err("trailing '%' in format".to_string()),
+        }
+    }
+    if !parser.rest.is_empty() {
+        return Err(format!(
+            "unexpected '{}' at end of time '{}'",
+            parser.rest, input
+        ));
+    }
+    if let Some(epoch) = parsed.epoch {
+        return Ok(epoch);
+    }
+    if parsed.day > days_in_month(parsed.year, parsed.month) {
+        return Err(format!(
+            "day {} out of range in time '{}'",
+            parsed.day, input
+        ));
+    }
+    match parsed.pm {
+        Some(true) if parsed.hour < 12 => parsed.hour += 12,
+        Some(false) if parsed.hour == 12 => parsed.hour = 0,
+        _ => {}
+    }
+    let local = days_from_civil(parsed.year, parsed.month, parsed.day) * 86400
+        + parsed.hour as i64 * 3600
+        + parsed.minute as i64 * 60
+        + parsed.second as i64;
+    Ok(match parsed.offset {
+        Some(offset) => local - offset as i64,
+        None => zone.to_utc(local),
+    })
+}
+
+/// Return the current time in seconds since the epoch from Lua
+async fn lua_now(_lua: Lua, _: ()) -> LuaResult<f64> {
+    Ok(now())
+}
+
+/// Format a timestamp from Lua, defaulting to now in UTC as ISO 8601
+async fn lua_format(
+    _lua: Lua,
+    (ts, fmt, tz): (Option<f64>, Option<String>, Option<String>),
+) -> LuaResult<String> {
+    let ts = ts.unwrap_or_else(now).floor() as i64;
+    let zone = lua_zone(tz)?;
+    let date = DateTime::new(ts, &zone);
+    format(&date, ts, fmt.as_deref().unwrap_or(DEFAULT_FORMAT)).map_err(LuaError::runtime)
+}
+
+/// Parse a time string from Lua into seconds since the epoch
+async fn lua_parse(
+    _lua: Lua,
+    (input, fmt, tz): (String, Option<String>, Option<String>),
+) -> LuaResult<i64> {
+    let zone = lua_zone(tz)?;
+    parse(&input, fmt.as_deref().unwrap_or(DEFAULT_FORMAT), &zone).map_err(LuaError::runtime)
+}
+
+/// Return the `init.time` Lua table
+pub fn time_table(lua: &Lua) -> LuaResult<LuaTable> {
+    let table = lua.create_table()?;
+    table.set("now", lua.create_async_function(lua_now)?)?;
+    table.set("format", lua.create_async_function(lua_format)?)?;
+    table.set("parse", lua.create_async_function(lua_parse)?)?;
+    Ok(table)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 2024-03-10T06:59:59Z, one second before New York enters daylight saving time
+    const BEFORE_DST: i64 = 1710053999;
+
+    fn new_york() -> Zone {
+        Zone::named("EST5EDT,M3.2.0,M11.1.0").unwrap()
+    }
+
+    #[test]
+    fn test_civil_days_roundtrip() {
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert_eq!(days_from_civil(2000, 3, 1), 11017);
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+        for days in (-800_000..800_000).step_by(997) {
+            let (year, month, day) = civil_from_days(days);
+            assert_eq!(days_from_civil(year, month, day), days);
+        }
+        assert_eq!(weekday(0), 4);
+    }
+
+    #[test]
+    fn test_format_utc() {
+        let date = DateTime::new(1700000000, &Zone::utc());
+        let text = format(&date, 1700000000, DEFAULT_FORMAT).unwrap();
+        assert_eq!(text, "2023-11-14T22:13:20+0000");
+        let text = format(&date, 1700000000, "%a %b %e %I:%M %p %j %Z %s %%").unwrap();
+        assert_eq!(text, "Tue Nov 14 10:13 PM 318 UTC 1700000000 %");
+        assert!(format(&date, 0, "%Q").is_err());
+        assert!(format(&date, 0, "%").is_err());
+    }
+
+    #[test]
+    fn test_fixed_offset() {
+        let zone = Zone::named("+05:30").unwrap();
+        let date = DateTime::new(0, &zone);
+        assert_eq!(
+            format(&date, 0, "%F %T %:z").unwrap(),
+            "1970-01-01 05:30:00 +05:30"
+        );
+        assert!(Zone::named("+25:00").is_none());
+        assert!(Zone::named("+5").is_none());
+    }
+
+    #[test]
+    fn test_posix_tz() {
+        let zone = new_york();
+        let date = DateTime::new(BEFORE_DST, &zone);
+        assert_eq!(format(&date, 0, "%T %Z %z").unwrap(), "01:59:59 EST -0500");
+        let date = DateTime::new(BEFORE_DST + 1, &zone);
+        assert_eq!(format(&date, 0, "%T %Z %z").unwrap(), "03:00:00 EDT -0400");
+        // southern hemisphere rules wrap around the new year
+        let sydney = Zone::named("AEST-10AEDT,M10.1.0,M4.1.0/3").unwrap();
+        assert_eq!(sydney.offset_at(1704067200).0, 11 * 3600);
+        assert_eq!(sydney.offset_at(1719792000).0, 10 * 3600);
+        assert!(PosixTz::parse("<+03>-3").is_some());
+        assert!(PosixTz::parse("E5").is_none());
+        assert!(PosixTz::parse("EST5EDT,M13.1.0,M11.1.0").is_none());
+        // hours past the POSIX limits are rejected rather than overflowing
+        assert!(PosixTz::parse("ABC1000000").is_none());
+        assert!(PosixTz::parse("ABC25").is_none());
+        assert!(PosixTz::parse("EST5EDT,M3.2.0/1000000,M11.1.0").is_none());
+        assert!(PosixTz::parse("EST5EDT,M3.2.0/167,M11.1.0").is_some());
+    }
+
+    #[test]
+    fn test_zoneinfo() {
+        // zoneinfo is optional in containers
+        let Some(zone) = Zone::named("America/New_York") else {
+            return;
+        };
+        assert_eq!(zone.offset_at(BEFORE_DST).1, "EST");
+        assert_eq!(zone.offset_at(BEFORE_DST + 1).1, "EDT");
+        // far future times follow the footer rule
+        assert_eq!(zone.offset_at(4102444800 + 180 * 86400).0, -4 * 3600);
+        assert!(Zone::named("../etc/passwd").is_none());
+    }
+
+    #[test]
+    fn test_from_tzif_err() {
+        assert!(Zone::from_tzif(b"").is_none());
+        assert!(Zone::from_tzif(b"TZif2 truncated").is_none());
+        let mut header = b"TZif2".to_vec();
+        header.extend([0; 15]);
+        header.extend([0xff; 24]);
+        assert!(Zone::from_tzif(&header).is_none());
+        header.truncate(20);
+        header.extend([0x7f, 0xff, 0xff, 0xff].repeat(6));
+        assert!(Zone::from_tzif(&header).is_none());
+    }
+
+    #[test]
+    fn test_parse() {
+        let utc = Zone::utc();
+        assert_eq!(
+            parse("2023-11-14T22:13:20+0000", DEFAULT_FORMAT, &utc),
+            Ok(1700000000)
+        );
+        assert_eq!(
+            parse("2023-11-14T23:13:20+01:00", DEFAULT_FORMAT, &utc),
+            Ok(1700000000)
+        );
+        assert_eq!(parse("2023-11-14 22:13:20", "%F %T", &utc), Ok(1700000000));
+        assert_eq!(
+            parse("14 nov 2023 10:13:20 PM", "%d %b %Y %I:%M:%S %p", &utc),
+            Ok(1700000000)
+        );
+        assert_eq!(parse("1700000000", "%s", &utc), Ok(1700000000));
+        assert_eq!(
+            parse("2024-03-10 01:59:59", "%F %T", &new_york()),
+            Ok(BEFORE_DST)
+        );
+    }
+
+    #[test]
+    fn test_parse_err() {
+        let utc = Zone::utc();
+        assert!(parse("2023-13-01", "%Y-%m-%d", &utc).is_err());
+        assert!(parse("2023-02-30", "%Y-%m-%d", &utc).is_err());
+        assert!(parse("2023-01-01 extra", "%Y-%m-%d", &utc).is_err());
+        assert!(parse("2023/01/01", "%Y-%m-%d", &utc).is_err());
+    }
+
+    #[test]
+    fn test_time_table() {
+        smol::block_on(async {
+            let lua = Lua::new();
+            lua.globals()
+                .set("time", time_table(&lua).unwrap())
+                .unwrap();
+            let chunk = lua.load(
+                r#"
+                local text = time.format(0, '%F %T %Z', 'UTC')
+                return text, time.parse(text, '%F %T %Z'), time.now() > 0
+                "#,
+            );
+            let result = chunk.eval_async::<(String, i64, bool)>().await.unwrap();
+            assert_eq!(result, ("1970-01-01 00:00:00 UTC".to_string(), 0, true));
+        });
+    }
+
+    #[test]
+    fn test_unknown_zone() {
+        assert!(lua_zone(Some("Nowhere/Special".to_string())).is_err());
+    }
+}
@@ -55,16 +55,207 @@ pub fn valid_signals() -> Vec<Signal> {
     signals
 }
 
+/// Bitmask of signals handled by the supervisor which are not forwarded to children
+static RESERVED: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+
+/// Stop forwarding a signal to children since the supervisor handles it itself
+pub fn reserve_signal(sig: i32) {
+    RESERVED.fetch_or(1 << sig, std::sync::atomic::Ordering::Relaxed);
+}
+
+/// Check whether a signal is handled by the supervisor instead of forwarded
+pub fn is_reserved(sig: i32) -> bool {
+    RESERVED.load(std::sync::atomic::Ordering::Relaxed) & (1 << sig) != 0
+}
+
 /// Wait for valid signals
-pub async fn signal_wait() -> AppResult<Signals> {
-    Ok(Signals::new(valid_signals())?)
+pub async fn signal_wait() -> std::io::Result<Signals> {
+    Signals::new(valid_signals())
 }
 
-/// Wrap the C `kill` function
+/// Wrap the required C library functions
 mod libc {
     extern "C" {
         pub fn kill(pid: i32, sig: i32) -> i32;
+        pub fn flock(fd: i32, operation: i32) -> i32;
+        pub fn isatty(fd: i32) -> i32;
+        pub fn getuid() -> u32;
+        pub fn getpgid(pid: i32) -> i32;
+        pub fn setsid() -> i32;
+        pub fn setpriority(which: i32, who: i32, prio: i32) -> i32;
+        pub fn sched_setscheduler(pid: i32, policy: i32, param: *const i32) -> i32;
+        pub fn open(path: *const std::ffi::c_char, flags: i32, ...) -> i32;
+        pub fn write(fd: i32, buf: *const std::ffi::c_void, len: usize) -> isize;
+        pub fn close(fd: i32) -> i32;
+        pub fn umask(mask: u32) -> u32;
+        pub fn unshare(flags: i32) -> i32;
+        pub fn prctl(option: i32, arg2: u64, arg3: u64, arg4: u64, arg5: u64) -> i32;
+        pub fn capset(header: *mut super::CapHeader, data: *const super::CapData) -> i32;
+        pub fn chroot(path: *const std::ffi::c_char) -> i32;
+        pub fn chdir(path: *const std::ffi::c_char) -> i32;
+        pub fn setgroups(size: usize, list: *const u32) -> i32;
+        pub fn setgid(gid: u32) -> i32;
+        pub fn setuid(uid: u32) -> i32;
+        pub fn sched_setaffinity(pid: i32, size: usize, mask: *const super::CpuSet) -> i32;
+        pub fn read(fd: i32, buf: *mut std::ffi::c_void, len: usize) -> isize;
+        pub fn fcntl(fd: i32, cmd: i32, ...) -> i32;
+        pub fn dup2(fd: i32, target: i32) -> i32;
+        pub fn poll(fds: *mut super::PollFd, count: u64, timeout: i32) -> i32;
+        pub fn splice(
+            fd_in: i32,
+            off_in: *mut i64,
+            fd_out: i32,
+            off_out: *mut i64,
+            len: usize,
+            flags: u32,
+        ) -> isize;
+        pub fn socket(domain: i32, kind: i32, protocol: i32) -> i32;
+        pub fn send(fd: i32, buf: *const std::ffi::c_void, len: usize, flags: i32) -> isize;
+        pub fn recv(fd: i32, buf: *mut std::ffi::c_void, len: usize, flags: i32) -> isize;
+        pub fn connect(fd: i32, addr: *const super::SockaddrNl, len: u32) -> i32;
+        pub fn if_nametoindex(name: *const std::ffi::c_char) -> u32;
+        pub fn gethostname(name: *mut std::ffi::c_char, len: usize) -> i32;
+        pub fn sethostname(name: *const std::ffi::c_char, len: usize) -> i32;
+        pub fn mount(
+            source: *const std::ffi::c_char,
+            target: *const std::ffi::c_char,
+            fstype: *const std::ffi::c_char,
+            flags: u64,
+            data: *const std::ffi::c_void,
+        ) -> i32;
+        pub fn umount2(target: *const std::ffi::c_char, flags: i32) -> i32;
+        pub fn sysconf(name: i32) -> i64;
+        pub fn reboot(cmd: i32) -> i32;
+        pub fn sync();
+        pub fn fflush(stream: *mut std::ffi::c_void) -> i32;
+        pub fn getrlimit(resource: i32, rlim: *mut super::Rlimit) -> i32;
+        pub fn setrlimit(resource: i32, rlim: *const super::Rlimit) -> i32;
+        pub fn ioctl(fd: i32, request: u64, ...) -> i32;
+        pub fn tcgetattr(fd: i32, termios: *mut super::Termios) -> i32;
+        pub fn tcsetattr(fd: i32, action: i32, termios: *const super::Termios) -> i32;
+        pub fn syscall(number: i64, ...) -> i64;
+        pub fn waitpid(pid: i32, status: *mut i32, options: i32) -> i32;
+    }
+}
+
+/// Canonical (line buffered) terminal input flag
+pub const ICANON: u32 = 0o000002;
+/// Terminal echo flag
+pub const ECHO: u32 = 0o000010;
+
+/// Linux `struct termios`
+#[repr(C)]
+#[derive(Clone, Copy)]
+#[allow(dead_code)]
+pub struct Termios {
+    c_iflag: u32,
+    c_oflag: u32,
+    c_cflag: u32,
+    c_lflag: u32,
+    c_line: u8,
+    c_cc: [u8; 32],
+    c_ispeed: u32,
+    c_ospeed: u32,
+}
+
+/// `ioctl` request which reads the window size of a terminal
+const TIOCGWINSZ: u64 = 0x5413;
+
+/// Linux `struct winsize`
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Winsize {
+    pub rows: u16,
+    pub cols: u16,
+    xpixel: u16,
+    ypixel: u16,
+}
+
+/// Return the window size of a terminal
+#[allow(unsafe_code)]
+pub fn window_size(fd: i32) -> std::io::Result<Winsize> {
+    let mut size = Winsize::default();
+    // SAFETY: `size` is a valid winsize which the kernel fills in
+    if unsafe { libc::ioctl(fd, TIOCGWINSZ, &mut size as *mut Winsize) } == -1 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(size)
+}
+
+/// Terminal settings which are restored when dropped
+pub struct TerminalMode {
+    fd: i32,
+    original: Termios,
+}
+
+/// Restore the original terminal settings
+impl Drop for TerminalMode {
+    #[allow(unsafe_code)]
+    fn drop(&mut self) {
+        // SAFETY: `original` was filled in by `tcgetattr` for the same fd
+        unsafe { libc::tcsetattr(self.fd, 0, &self.original) };
+    }
+}
+
+/// Check whether a file descriptor refers to a terminal
+#[allow(unsafe_code)]
+pub fn isatty(fd: i32) -> bool {
+    // SAFETY: safe because an invalid fd returns 0
+    unsafe { libc::isatty(fd) == 1 }
+}
+
+/// Return the real user id of the current process
+#[allow(unsafe_code)]
+pub fn getuid() -> u32 {
+    // SAFETY: safe because getuid always succeeds
+    unsafe { libc::getuid() }
+}
+
+/// Return the process group of a process, where pid 0 is the current process
+#[allow(unsafe_code)]
+pub fn getpgid(pid: i32) -> std::io::Result<i32> {
+    // SAFETY: safe because an invalid pid will return an error
+    let pgid = unsafe { libc::getpgid(pid) };
+    if pgid == -1 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(pgid)
+}
+
+/// Make the child of a command start a new session, and so a new process group, before exec
+#[allow(unsafe_code)]
+pub fn setsid_before_exec(cmd: &mut std::process::Command) {
+    use std::os::unix::process::CommandExt;
+    // SAFETY: setsid is async-signal-safe and the closure allocates nothing
+    // before it, so it is safe to run in the forked child before exec
+    unsafe {
+        cmd.pre_exec(|| match libc::setsid() {
+            -1 => Err(std::io::Error::last_os_error()),
+            _ => Ok(()),
+        });
+    }
+}
+
+/// Clear local mode `flags` on a terminal until the returned guard is dropped
+#[allow(unsafe_code)]
+pub fn terminal_mode(fd: i32, flags: u32) -> std::io::Result<TerminalMode> {
+    let mut termios = std::mem::MaybeUninit::<Termios>::uninit();
+    // SAFETY: `tcgetattr` fully initializes `termios` when it succeeds
+    if unsafe { libc::tcgetattr(fd, termios.as_mut_ptr()) } == -1 {
+        return Err(std::io::Error::last_os_error());
+    }
+    // SAFETY: initialized by the successful call above
+    let original = unsafe { termios.assume_init() };
+    let mut raw = original;
+    raw.c_lflag &= !flags;
+    // VMIN = 1, VTIME = 0
+    raw.c_cc[6] = 1;
+    raw.c_cc[5] = 0;
+    // SAFETY: `raw` is a valid termios copied from the terminal
+    if unsafe { libc::tcsetattr(fd, 0, &raw) } == -1 {
+        return Err(std::io::Error::last_os_error());
     }
+    Ok(TerminalMode { fd, original })
 }
 
 /// Send a signal to a process
@@ -79,10 +270,951 @@ pub async fn kill(pid: i32, sig: i32) -> AppResult<i32> {
     Ok(result)
 }
 
+/// `sysconf` name of the maximum number of child processes per user
+pub const SC_CHILD_MAX: i32 = 1;
+/// `sysconf` name of the number of clock ticks per second
+pub const SC_CLK_TCK: i32 = 2;
+/// `sysconf` name of the maximum number of open files
+pub const SC_OPEN_MAX: i32 = 4;
+/// `sysconf` name of the page size in bytes
+pub const SC_PAGESIZE: i32 = 30;
+/// `sysconf` name of the number of online processors
+pub const SC_NPROCESSORS_ONLN: i32 = 84;
+
+/// Return a system configuration value, or `None` if it is unlimited or unknown
+#[allow(unsafe_code)]
+pub fn sysconf(name: i32) -> Option<i64> {
+    // SAFETY: safe because an invalid name returns -1
+    let value = unsafe { libc::sysconf(name) };
+    (value >= 0).then_some(value)
+}
+
+/// Value of an unlimited resource limit
+pub const RLIM_INFINITY: u64 = u64::MAX;
+
+/// Linux `struct rlimit`
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rlimit {
+    pub soft: u64,
+    pub hard: u64,
+}
+
+/// Return the soft and hard limits of a resource
+#[allow(unsafe_code)]
+pub fn getrlimit(resource: i32) -> std::io::Result<Rlimit> {
+    let mut rlim = Rlimit { soft: 0, hard: 0 };
+    // SAFETY: `rlim` is a valid pointer and an invalid resource returns an error
+    if unsafe { libc::getrlimit(resource, &mut rlim) } == -1 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(rlim)
+}
+
+/// Set the soft and hard limits of a resource
+#[allow(unsafe_code)]
+pub fn setrlimit(resource: i32, rlim: Rlimit) -> std::io::Result<()> {
+    // SAFETY: `rlim` is a valid pointer and invalid limits return an error
+    if unsafe { libc::setrlimit(resource, &rlim) } == -1 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Number of the `waitid` syscall, which unlike the libc wrapper also returns resource usage
+#[cfg(target_arch = "x86_64")]
+const SYS_WAITID: i64 = 247;
+#[cfg(not(target_arch = "x86_64"))]
+const SYS_WAITID: i64 = 95;
+
+/// Number of the `pidfd_open` syscall
+const SYS_PIDFD_OPEN: i64 = 434;
+
+/// `waitid` id type which selects a single pid
+const P_PID: i32 = 1;
+/// `waitid` flag which reports children which exited
+const WEXITED: i32 = 4;
+/// `waitid` flag which returns immediately if no child has exited
+const WNOHANG: i32 = 1;
+/// `waitid` flag which leaves the child waitable so it can still be reaped
+const WNOWAIT: i32 = 0x01000000;
+
+/// Linux `struct timeval`
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Timeval {
+    pub sec: i64,
+    pub usec: i64,
+}
+
+impl Timeval {
+    /// Return the time in seconds
+    pub fn seconds(&self) -> f64 {
+        self.sec as f64 + self.usec as f64 / 1e6
+    }
+}
+
+/// Linux `struct rusage`
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Rusage {
+    pub utime: Timeval,
+    pub stime: Timeval,
+    /// Maximum resident set size in kilobytes
+    pub maxrss: i64,
+    ixrss: i64,
+    idrss: i64,
+    isrss: i64,
+    pub minflt: i64,
+    pub majflt: i64,
+    nswap: i64,
+    inblock: i64,
+    oublock: i64,
+    msgsnd: i64,
+    msgrcv: i64,
+    nsignals: i64,
+    nvcsw: i64,
+    nivcsw: i64,
+}
+
+/// Return the resource usage of a child which exited without reaping it, or `None` while it runs
+#[allow(unsafe_code)]
+pub fn exited_usage(pid: i32) -> std::io::Result<Option<Rusage>> {
+    // siginfo_t is 128 bytes, with si_pid after the signal, errno, code, and padding
+    let mut info = [0i32; 32];
+    let mut usage = Rusage::default();
+    // SAFETY: `info` and `usage` are large enough for the siginfo and rusage the kernel fills in
+    let result = unsafe {
+        libc::syscall(
+            SYS_WAITID,
+            P_PID,
+            pid,
+            info.as_mut_ptr(),
+            WEXITED | WNOHANG | WNOWAIT,
+            &mut usage as *mut Rusage,
+        )
+    };
+    if result == -1 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok((info[4] != 0).then_some(usage))
+}
+
+/// Reap a child if it exited, or return `None` while it runs
+#[allow(unsafe_code)]
+pub fn waitpid_nohang(pid: i32) -> std::io::Result<Option<std::process::ExitStatus>> {
+    use std::os::unix::process::ExitStatusExt;
+
+    let mut status = 0;
+    // SAFETY: `status` is a valid pointer and a pid which is not a child returns an error
+    match unsafe { libc::waitpid(pid, &mut status, WNOHANG) } {
+        -1 => Err(std::io::Error::last_os_error()),
+        0 => Ok(None),
+        _ => Ok(Some(std::process::ExitStatus::from_raw(status))),
+    }
+}
+
+/// `prctl` option which makes orphaned descendants children of the calling process
+const PR_SET_CHILD_SUBREAPER: i32 = 36;
+/// `prctl` option which reads whether the calling process is a subreaper
+const PR_GET_CHILD_SUBREAPER: i32 = 37;
+
+/// Make orphaned descendants children of this process like they are of pid 1, or stop doing so
+#[allow(unsafe_code)]
+pub fn set_child_subreaper(on: bool) -> std::io::Result<()> {
+    // SAFETY: safe because prctl only changes an attribute of this process
+    check(unsafe { libc::prctl(PR_SET_CHILD_SUBREAPER, on.into(), 0, 0, 0) })
+}
+
+/// Check whether orphaned descendants become children of this process
+#[allow(unsafe_code)]
+pub fn is_child_subreaper() -> std::io::Result<bool> {
+    let mut on = 0i32;
+    // SAFETY: `on` is a valid int which the kernel fills in
+    check(unsafe { libc::prctl(PR_GET_CHILD_SUBREAPER, &mut on as *mut i32 as u64, 0, 0, 0) })?;
+    Ok(on != 0)
+}
+
+/// Open a pidfd which becomes readable once a process exits
+#[allow(unsafe_code)]
+pub fn pidfd_open(pid: i32) -> std::io::Result<std::os::fd::OwnedFd> {
+    use std::os::fd::FromRawFd;
+
+    // SAFETY: safe because an invalid pid returns an error
+    let fd = unsafe { libc::syscall(SYS_PIDFD_OPEN, pid, 0) };
+    if fd == -1 {
+        return Err(std::io::Error::last_os_error());
+    }
+    // SAFETY: `fd` is a new file descriptor which nothing else owns
+    Ok(unsafe { std::os::fd::OwnedFd::from_raw_fd(fd as i32) })
+}
+
+/// Make the child of a command set resource limits, as soft and hard limits alike, before exec
+#[allow(unsafe_code)]
+pub fn setrlimits_before_exec(cmd: &mut std::process::Command, limits: Vec<(i32, u64)>) {
+    use std::os::unix::process::CommandExt;
+    // SAFETY: setrlimit is async-signal-safe and the limits were allocated before
+    // the fork, so the closure is safe to run in the forked child before exec
+    unsafe {
+        cmd.pre_exec(move || {
+            for (resource, limit) in &limits {
+                let rlim = Rlimit {
+                    soft: *limit,
+                    hard: *limit,
+                };
+                if libc::setrlimit(*resource, &rlim) == -1 {
+                    return Err(std::io::Error::last_os_error());
+                }
+            }
+            Ok(())
+        });
+    }
+}
+
+/// `setpriority` target of a single process
+const PRIO_PROCESS: i32 = 0;
+
+/// Set the nice value of a process, where pid 0 is the current process
+#[allow(unsafe_code)]
+pub fn setpriority(pid: i32, nice: i32) -> std::io::Result<()> {
+    // SAFETY: safe because an invalid pid or value will return an error
+    if unsafe { libc::setpriority(PRIO_PROCESS, pid, nice) } == -1 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Set the scheduling policy of a process with a static priority of 0
+#[allow(unsafe_code)]
+pub fn sched_setscheduler(pid: i32, policy: i32) -> std::io::Result<()> {
+    // `struct sched_param` only holds the static priority
+    let priority: i32 = 0;
+    // SAFETY: `priority` is a valid `struct sched_param` and an invalid policy returns an error
+    if unsafe { libc::sched_setscheduler(pid, policy, &priority) } == -1 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Make the child of a command set its scheduling policy and nice value before exec
+#[allow(unsafe_code)]
+pub fn priority_before_exec(
+    cmd: &mut std::process::Command,
+    policy: Option<i32>,
+    nice: Option<i32>,
+) {
+    use std::os::unix::process::CommandExt;
+    // SAFETY: both calls are async-signal-safe and the closure allocates
+    // nothing, so it is safe to run in the forked child before exec
+    unsafe {
+        cmd.pre_exec(move || {
+            if let Some(policy) = policy {
+                sched_setscheduler(0, policy)?;
+            }
+            if let Some(nice) = nice {
+                setpriority(0, nice)?;
+            }
+            Ok(())
+        });
+    }
+}
+
+/// Number of CPUs a `cpu_set_t` can hold
+pub const CPU_SETSIZE: usize = 1024;
+
+/// Linux `cpu_set_t`, a bit mask of CPUs
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CpuSet([u64; CPU_SETSIZE / 64]);
+
+impl CpuSet {
+    /// Return the set of the given CPUs, or `None` if one is out of range
+    pub fn new(cpus: &[usize]) -> Option<Self> {
+        let mut set = CpuSet([0; CPU_SETSIZE / 64]);
+        for cpu in cpus {
+            *set.0.get_mut(cpu / 64)? |= 1 << (cpu % 64);
+        }
+        Some(set)
+    }
+}
+
+/// Restrict a process to a set of CPUs, where pid 0 is the current process
+#[allow(unsafe_code)]
+pub fn sched_setaffinity(pid: i32, set: &CpuSet) -> std::io::Result<()> {
+    // SAFETY: `set` is a valid `cpu_set_t` of the given size
+    if unsafe { libc::sched_setaffinity(pid, std::mem::size_of::<CpuSet>(), set) } == -1 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Make the child of a command restrict itself to a set of CPUs before exec
+#[allow(unsafe_code)]
+pub fn affinity_before_exec(cmd: &mut std::process::Command, set: CpuSet) {
+    use std::os::unix::process::CommandExt;
+    // SAFETY: sched_setaffinity is async-signal-safe and the closure allocates
+    // nothing, so it is safe to run in the forked child before exec
+    unsafe {
+        cmd.pre_exec(move || sched_setaffinity(0, &set));
+    }
+}
+
+/// Open a file for writing only
+const O_WRONLY: i32 = 1;
+/// Close a file descriptor on exec
+const O_CLOEXEC: i32 = 0o2000000;
+
+/// Write a value to a file from the forked child of a command, where nothing may be allocated
+#[allow(unsafe_code)]
+fn write_in_child(path: &std::ffi::CStr, value: &[u8]) -> std::io::Result<()> {
+    // SAFETY: open, write, and close are async-signal-safe, and the path and
+    // value are valid for the duration of the calls
+    unsafe {
+        let fd = libc::open(path.as_ptr(), O_WRONLY | O_CLOEXEC);
+        if fd == -1 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let written = libc::write(fd, value.as_ptr().cast(), value.len());
+        let error = std::io::Error::last_os_error();
+        libc::close(fd);
+        match written {
+            -1 => Err(error),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Make the child of a command set how likely it is chosen by the OOM killer before exec
+#[allow(unsafe_code)]
+pub fn oom_score_adj_before_exec(cmd: &mut std::process::Command, adj: i32) {
+    use std::os::unix::process::CommandExt;
+    let path = c"/proc/self/oom_score_adj";
+    let value = adj.to_string().into_bytes();
+    // SAFETY: the path and value were allocated before the fork, so the closure
+    // is safe to run in the forked child before exec
+    unsafe {
+        cmd.pre_exec(move || write_in_child(path, &value));
+    }
+}
+
+/// Make the child of a command join a cgroup through its `cgroup.procs` file before exec
+#[allow(unsafe_code)]
+pub fn cgroup_before_exec(
+    cmd: &mut std::process::Command,
+    procs: &std::path::Path,
+) -> std::io::Result<()> {
+    use std::os::unix::{ffi::OsStrExt, process::CommandExt};
+    let path = std::ffi::CString::new(procs.as_os_str().as_bytes())?;
+    // SAFETY: the path was allocated before the fork, so the closure is safe to
+    // run in the forked child before exec
+    unsafe {
+        cmd.pre_exec(move || write_in_child(&path, b"0"));
+    }
+    Ok(())
+}
+
+/// Make the child of a command set its file mode creation mask before exec
+#[allow(unsafe_code)]
+pub fn umask_before_exec(cmd: &mut std::process::Command, mask: u32) {
+    use std::os::unix::process::CommandExt;
+    // SAFETY: umask is async-signal-safe and always succeeds, so it is safe to
+    // run in the forked child before exec
+    unsafe {
+        cmd.pre_exec(move || {
+            libc::umask(mask);
+            Ok(())
+        });
+    }
+}
+
+/// Set the file descriptor flags, such as close-on-exec
+const F_SETFD: i32 = 2;
+
+/// Make the child of a command inherit a file descriptor as `target` before exec
+#[allow(unsafe_code)]
+pub fn inherit_fd_before_exec(cmd: &mut std::process::Command, fd: i32, target: i32) {
+    use std::os::unix::process::CommandExt;
+    // SAFETY: dup2 and fcntl are async-signal-safe, and `fd` is kept open by the
+    // parent until the child is spawned, so the closure is safe to run before exec
+    unsafe {
+        cmd.pre_exec(move || {
+            // a duplicate does not inherit close-on-exec, but the same number keeps it
+            let result = match fd == target {
+                true => libc::fcntl(fd, F_SETFD, 0),
+                false => libc::dup2(fd, target),
+            };
+            if result == -1 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+}
+
+/// New mount namespace
+pub const CLONE_NEWNS: i32 = 0x00020000;
+/// New cgroup namespace
+pub const CLONE_NEWCGROUP: i32 = 0x02000000;
+/// New hostname namespace
+pub const CLONE_NEWUTS: i32 = 0x04000000;
+/// New System V IPC namespace
+pub const CLONE_NEWIPC: i32 = 0x08000000;
+/// New user namespace
+pub const CLONE_NEWUSER: i32 = 0x10000000;
+/// New pid namespace, which only the children of the caller enter
+pub const CLONE_NEWPID: i32 = 0x20000000;
+/// New network namespace
+pub const CLONE_NEWNET: i32 = 0x40000000;
+/// Keep mount and unmount events from propagating to or from other namespaces
+const MS_PRIVATE: u64 = 1 << 18;
+
+/// Make the child of a command move into new namespaces before exec
+///
+/// A new mount namespace is made private first, like `unshare` does, so mounts
+/// made by the child do not leak back into the namespace of the supervisor.
+#[allow(unsafe_code)]
+pub fn unshare_before_exec(cmd: &mut std::process::Command, flags: i32) {
+    use std::os::unix::process::CommandExt;
+    // SAFETY: unshare and mount are async-signal-safe and the closure allocates
+    // nothing, so it is safe to run in the forked child before exec
+    unsafe {
+        cmd.pre_exec(move || {
+            check(libc::unshare(flags))?;
+            if flags & CLONE_NEWNS != 0 {
+                let root = c"/";
+                let null = std::ptr::null();
+                check(libc::mount(
+                    null,
+                    root.as_ptr(),
+                    null,
+                    MS_REC | MS_PRIVATE,
+                    null.cast(),
+                ))?;
+            }
+            Ok(())
+        });
+    }
+}
+
+/// Convert a result of a C library function into an error if it failed
+fn check(result: i32) -> std::io::Result<()> {
+    match result {
+        -1 => Err(std::io::Error::last_os_error()),
+        _ => Ok(()),
+    }
+}
+
+/// Make the child of a command change its root and then its directory before exec
+#[allow(unsafe_code)]
+pub fn chroot_before_exec(
+    cmd: &mut std::process::Command,
+    root: &str,
+    dir: &str,
+) -> std::io::Result<()> {
+    use std::os::unix::process::CommandExt;
+    let root = std::ffi::CString::new(root)?;
+    let dir = std::ffi::CString::new(dir)?;
+    // SAFETY: chroot and chdir are async-signal-safe and the paths were allocated
+    // before the fork, so the closure is safe to run in the forked child before exec
+    unsafe {
+        cmd.pre_exec(move || {
+            check(libc::chroot(root.as_ptr()))?;
+            check(libc::chdir(dir.as_ptr()))
+        });
+    }
+    Ok(())
+}
+
+/// Version of the capability structures passed to capset
+const LINUX_CAPABILITY_VERSION_3: u32 = 0x20080522;
+/// Keep the permitted capabilities when changing from root to another user
+const PR_SET_KEEPCAPS: i32 = 8;
+/// Check whether a capability is in the bounding set
+const PR_CAPBSET_READ: i32 = 23;
+/// Remove a capability from the bounding set
+const PR_CAPBSET_DROP: i32 = 24;
+/// Change the ambient capabilities, which are kept across exec by other users
+const PR_CAP_AMBIENT: i32 = 47;
+/// Add a capability to the ambient set
+const PR_CAP_AMBIENT_RAISE: u64 = 2;
+/// Number of capability bits which can be set
+const CAP_BITS: u32 = 64;
+
+/// Header of the capability sets passed to capset
+#[repr(C)]
+pub struct CapHeader {
+    version: u32,
+    pid: i32,
+}
+
+/// Lower or upper half of the effective, permitted, and inheritable capability sets
+#[repr(C)]
+pub struct CapData {
+    effective: u32,
+    permitted: u32,
+    inheritable: u32,
+}
+
+/// Make the child of a command change its group and user, then keep only some capabilities, before exec
+///
+/// Capabilities which are not kept are dropped from the bounding set first, and
+/// the kept ones are raised into the ambient set so they survive exec for users
+/// other than root. This replaces setting the user and group on the command.
+#[allow(unsafe_code)]
+pub fn credentials_before_exec(
+    cmd: &mut std::process::Command,
+    uid: Option<u32>,
+    gid: Option<u32>,
+    caps: Option<u64>,
+) {
+    use std::os::unix::process::CommandExt;
+    // SAFETY: prctl, setgroups, setgid, setuid, and capset are async-signal-safe
+    // and the closure allocates nothing, so it is safe to run in the forked child
+    // before exec
+    unsafe {
+        cmd.pre_exec(move || {
+            if let Some(keep) = caps {
+                check(libc::prctl(PR_SET_KEEPCAPS, 1, 0, 0, 0))?;
+                for cap in (0..CAP_BITS).filter(|cap| keep & (1 << cap) == 0) {
+                    if libc::prctl(PR_CAPBSET_READ, cap.into(), 0, 0, 0) == 1 {
+                        check(libc::prctl(PR_CAPBSET_DROP, cap.into(), 0, 0, 0))?;
+                    }
+                }
+            }
+            // drop supplementary groups like std does when it changes the user
+            if uid.is_some() && libc::getuid() == 0 {
+                check(libc::setgroups(0, std::ptr::null()))?;
+            }
+            if let Some(gid) = gid {
+                check(libc::setgid(gid))?;
+            }
+            if let Some(uid) = uid {
+                check(libc::setuid(uid))?;
+            }
+            if let Some(keep) = caps {
+                let mut header = CapHeader {
+                    version: LINUX_CAPABILITY_VERSION_3,
+                    pid: 0,
+                };
+                let data = [keep as u32, (keep >> 32) as u32].map(|set| CapData {
+                    effective: set,
+                    permitted: set,
+                    inheritable: set,
+                });
+                check(libc::capset(&mut header, data.as_ptr()))?;
+                for cap in (0..CAP_BITS).filter(|cap| keep & (1 << cap) != 0) {
+                    check(libc::prctl(
+                        PR_CAP_AMBIENT,
+                        PR_CAP_AMBIENT_RAISE,
+                        cap.into(),
+                        0,
+                        0,
+                    ))?;
+                }
+            }
+            Ok(())
+        });
+    }
+}
+
+/// Keep exec from granting privileges, which installing a filter without CAP_SYS_ADMIN needs
+const PR_SET_NO_NEW_PRIVS: i32 = 38;
+/// Set the secure computing mode of the calling thread
+const PR_SET_SECCOMP: i32 = 22;
+/// Secure computing mode which filters syscalls with a BPF program
+const SECCOMP_MODE_FILTER: u64 = 2;
+
+/// Instruction of a classic BPF program
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SockFilter {
+    code: u16,
+    jt: u8,
+    jf: u8,
+    k: u32,
+}
+
+impl SockFilter {
+    /// Create an instruction from its opcode, jump offsets, and constant
+    pub fn new(code: u16, jt: u8, jf: u8, k: u32) -> Self {
+        SockFilter { code, jt, jf, k }
+    }
+}
+
+/// Classic BPF program passed to prctl
+#[repr(C)]
+struct SockFprog {
+    len: u16,
+    filter: *const SockFilter,
+}
+
+/// Make the child of a command install a seccomp filter as the last step before exec
+#[allow(unsafe_code)]
+pub fn seccomp_before_exec(
+    cmd: &mut std::process::Command,
+    program: Vec<SockFilter>,
+) -> std::io::Result<()> {
+    use std::os::unix::process::CommandExt;
+    let len = u16::try_from(program.len())
+        .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidInput, err))?;
+    // SAFETY: prctl is async-signal-safe and the program was allocated before
+    // the fork and outlives the call, so the closure is safe to run in the forked
+    // child before exec
+    unsafe {
+        cmd.pre_exec(move || {
+            let prog = SockFprog {
+                len,
+                filter: program.as_ptr(),
+            };
+            check(libc::prctl(PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0))?;
+            let prog = &prog as *const SockFprog as u64;
+            check(libc::prctl(PR_SET_SECCOMP, SECCOMP_MODE_FILTER, prog, 0, 0))
+        });
+    }
+    Ok(())
+}
+
+/// Mount read only
+pub const MS_RDONLY: u64 = 1;
+/// Ignore set-user-id and set-group-id bits
+pub const MS_NOSUID: u64 = 2;
+/// Disallow access to device files
+pub const MS_NODEV: u64 = 4;
+/// Disallow executing programs
+pub const MS_NOEXEC: u64 = 8;
+/// Change the flags of an existing mount
+pub const MS_REMOUNT: u64 = 32;
+/// Do not update access times
+pub const MS_NOATIME: u64 = 1024;
+/// Mount an existing directory at another location
+pub const MS_BIND: u64 = 4096;
+/// Apply the mount to every submount as well
+pub const MS_REC: u64 = 16384;
+/// Detach a mount now and clean it up once it is no longer busy
+pub const MNT_DETACH: i32 = 2;
+
+/// Convert a string into a C string, rejecting interior nul bytes
+fn c_string(s: &str) -> std::io::Result<std::ffi::CString> {
+    std::ffi::CString::new(s)
+        .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidInput, err))
+}
+
+/// Attach a filesystem, where `None` arguments are passed as null pointers
+#[allow(unsafe_code)]
+pub fn mount(
+    source: Option<&str>,
+    target: &str,
+    fstype: Option<&str>,
+    flags: u64,
+    data: Option<&str>,
+) -> std::io::Result<()> {
+    let source = source.map(c_string).transpose()?;
+    let target = c_string(target)?;
+    let fstype = fstype.map(c_string).transpose()?;
+    let data = data.map(c_string).transpose()?;
+    let ptr = |s: &Option<std::ffi::CString>| s.as_ref().map_or(std::ptr::null(), |s| s.as_ptr());
+    // SAFETY: every pointer is null or a nul terminated string which outlives the call
+    let result = unsafe {
+        libc::mount(
+            ptr(&source),
+            target.as_ptr(),
+            ptr(&fstype),
+            flags,
+            ptr(&data).cast(),
+        )
+    };
+    if result == -1 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Detach a filesystem
+#[allow(unsafe_code)]
+pub fn umount(target: &str, flags: i32) -> std::io::Result<()> {
+    let target = c_string(target)?;
+    // SAFETY: `target` is a nul terminated string which outlives the call
+    if unsafe { libc::umount2(target.as_ptr(), flags) } == -1 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// `reboot` command which makes ctrl-alt-del send `SIGINT` to pid 1
+pub const RB_DISABLE_CAD: i32 = 0;
+/// `reboot` command which restarts the system
+pub const RB_AUTOBOOT: i32 = 0x01234567;
+/// `reboot` command which powers off the system
+pub const RB_POWER_OFF: i32 = 0x4321fedc;
+
+/// Flush filesystems and reboot, power off, or configure ctrl-alt-del
+///
+/// Only returns on error for commands which restart or stop the system.
+#[allow(unsafe_code)]
+pub fn reboot(cmd: i32) -> std::io::Result<()> {
+    // SAFETY: sync always succeeds and reboot validates its command
+    unsafe {
+        if cmd != RB_DISABLE_CAD {
+            libc::sync();
+        }
+        if libc::reboot(cmd) == -1 {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// Flush the C standard streams which Lua writes to, such as before an exec
+#[allow(unsafe_code)]
+pub fn flush_stdio() {
+    // SAFETY: a null stream flushes every open output stream
+    unsafe { libc::fflush(std::ptr::null_mut()) };
+}
+
+/// Return the hostname of the system
+#[allow(unsafe_code)]
+pub fn gethostname() -> std::io::Result<String> {
+    // HOST_NAME_MAX is 64 on Linux, plus the nul terminator
+    let mut buf = [0 as std::ffi::c_char; 65];
+    // SAFETY: `buf` is valid for `buf.len()` bytes
+    if unsafe { libc::gethostname(buf.as_mut_ptr(), buf.len()) } == -1 {
+        return Err(std::io::Error::last_os_error());
+    }
+    // SAFETY: the last byte is never written, so the buffer is nul terminated
+    let name = unsafe { std::ffi::CStr::from_ptr(buf.as_ptr()) };
+    Ok(name.to_string_lossy().into_owned())
+}
+
+/// Set the hostname of the system, or of the current UTS namespace
+#[allow(unsafe_code)]
+pub fn sethostname(name: &str) -> std::io::Result<()> {
+    // SAFETY: `name` is valid for `name.len()` bytes, which need no terminator
+    if unsafe { libc::sethostname(name.as_ptr().cast(), name.len()) } == -1 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Netlink socket address family
+const AF_NETLINK: i32 = 16;
+/// Raw socket type
+const SOCK_RAW: i32 = 3;
+/// Close the socket when executing a child
+const SOCK_CLOEXEC: i32 = 0o2000000;
+/// Netlink protocol for routing and link configuration
+const NETLINK_ROUTE: i32 = 0;
+
+/// Linux `struct sockaddr_nl`
+#[repr(C)]
+pub struct SockaddrNl {
+    family: u16,
+    pad: u16,
+    pid: u32,
+    groups: u32,
+}
+
+/// Open a routing netlink socket connected to the kernel
+#[allow(unsafe_code)]
+pub fn netlink_route() -> std::io::Result<std::os::fd::OwnedFd> {
+    use std::os::fd::FromRawFd;
+    // SAFETY: safe because invalid arguments return an error
+    let fd = unsafe { libc::socket(AF_NETLINK, SOCK_RAW | SOCK_CLOEXEC, NETLINK_ROUTE) };
+    if fd == -1 {
+        return Err(std::io::Error::last_os_error());
+    }
+    // SAFETY: `fd` was just opened and is owned by nothing else
+    let fd = unsafe { std::os::fd::OwnedFd::from_raw_fd(fd) };
+    let kernel = SockaddrNl {
+        family: AF_NETLINK as u16,
+        pad: 0,
+        pid: 0,
+        groups: 0,
+    };
+    let len = std::mem::size_of::<SockaddrNl>() as u32;
+    // SAFETY: `kernel` is a valid netlink address of `len` bytes
+    if unsafe { libc::connect(std::os::fd::AsRawFd::as_raw_fd(&fd), &kernel, len) } == -1 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(fd)
+}
+
+/// Send a message on a connected socket
+#[allow(unsafe_code)]
+pub fn send(fd: &impl std::os::fd::AsRawFd, buf: &[u8]) -> std::io::Result<usize> {
+    // SAFETY: `buf` is valid for `buf.len()` bytes
+    let sent = unsafe { libc::send(fd.as_raw_fd(), buf.as_ptr().cast(), buf.len(), 0) };
+    if sent == -1 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(sent as usize)
+}
+
+/// Receive a message from a connected socket
+#[allow(unsafe_code)]
+pub fn recv(fd: &impl std::os::fd::AsRawFd, buf: &mut [u8]) -> std::io::Result<usize> {
+    // SAFETY: `buf` is valid for writes of `buf.len()` bytes
+    let read = unsafe { libc::recv(fd.as_raw_fd(), buf.as_mut_ptr().cast(), buf.len(), 0) };
+    if read == -1 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(read as usize)
+}
+
+/// Return the index of a network interface
+#[allow(unsafe_code)]
+pub fn if_nametoindex(name: &str) -> std::io::Result<u32> {
+    let name = c_string(name)?;
+    // SAFETY: `name` is a nul terminated string which outlives the call
+    match unsafe { libc::if_nametoindex(name.as_ptr()) } {
+        0 => Err(std::io::Error::last_os_error()),
+        index => Ok(index),
+    }
+}
+
+/// Invalid argument error number
+pub const EINVAL: i32 = 22;
+/// Get the status flags of a file descriptor
+const F_GETFL: i32 = 3;
+/// Set the status flags of a file descriptor
+const F_SETFL: i32 = 4;
+/// Non-blocking status flag
+const O_NONBLOCK: i32 = 0o4000;
+/// Move pages instead of copying them where possible
+const SPLICE_F_MOVE: u32 = 1;
+/// Fail instead of waiting on the pipe of a `splice`
+const SPLICE_F_NONBLOCK: u32 = 2;
+/// Error raised when a non-blocking operation would have to wait
+pub const EAGAIN: i32 = 11;
+/// Poll event of a file descriptor with data to read
+const POLLIN: i16 = 1;
+
+/// File descriptor and the events `poll` waits for on it
+#[repr(C)]
+pub struct PollFd {
+    fd: i32,
+    events: i16,
+    revents: i16,
+}
+
+/// Wait until a file descriptor has data to read or is closed
+#[allow(unsafe_code)]
+pub fn wait_readable(fd: &impl std::os::fd::AsRawFd) -> std::io::Result<()> {
+    let mut poll = PollFd {
+        fd: fd.as_raw_fd(),
+        events: POLLIN,
+        revents: 0,
+    };
+    loop {
+        // SAFETY: `poll` is a single valid entry and an invalid fd is reported in it
+        if unsafe { libc::poll(&mut poll, 1, -1) } != -1 {
+            return Ok(());
+        }
+        let err = std::io::Error::last_os_error();
+        if err.kind() != std::io::ErrorKind::Interrupted {
+            return Err(err);
+        }
+    }
+}
+
+/// Make reads and writes on a file descriptor wait instead of failing
+#[allow(unsafe_code)]
+pub fn set_blocking(fd: &impl std::os::fd::AsRawFd) -> std::io::Result<()> {
+    let fd = fd.as_raw_fd();
+    // SAFETY: safe because an invalid fd returns an error
+    let flags = unsafe { libc::fcntl(fd, F_GETFL) };
+    // SAFETY: as above, with flags read from the same fd
+    if flags == -1 || unsafe { libc::fcntl(fd, F_SETFL, flags & !O_NONBLOCK) } == -1 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Read from a file descriptor
+#[allow(unsafe_code)]
+pub fn read(fd: &impl std::os::fd::AsRawFd, buf: &mut [u8]) -> std::io::Result<usize> {
+    // SAFETY: `buf` is valid for writes of `buf.len()` bytes
+    let read = unsafe { libc::read(fd.as_raw_fd(), buf.as_mut_ptr().cast(), buf.len()) };
+    if read == -1 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(read as usize)
+}
+
+/// Move up to `len` bytes from a pipe into a file at `offset`, advancing it, without waiting for the pipe
+#[allow(unsafe_code)]
+pub fn splice(
+    pipe: &impl std::os::fd::AsRawFd,
+    file: &impl std::os::fd::AsRawFd,
+    offset: &mut i64,
+    len: usize,
+) -> std::io::Result<usize> {
+    let (pipe, file) = (pipe.as_raw_fd(), file.as_raw_fd());
+    let null = std::ptr::null_mut();
+    // SAFETY: `offset` is a valid pointer and invalid descriptors return an error
+    let flags = SPLICE_F_MOVE | SPLICE_F_NONBLOCK;
+    let moved = unsafe { libc::splice(pipe, null, file, offset, len, flags) };
+    if moved == -1 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(moved as usize)
+}
+
+/// Shared `flock` lock
+pub const LOCK_SH: i32 = 1;
+/// Exclusive `flock` lock
+pub const LOCK_EX: i32 = 2;
+/// Fail instead of waiting for a `flock` lock
+pub const LOCK_NB: i32 = 4;
+/// Release a `flock` lock
+pub const LOCK_UN: i32 = 8;
+
+/// Apply or remove an advisory lock on an open file
+#[allow(unsafe_code)]
+pub fn flock(file: &impl std::os::fd::AsRawFd, operation: i32) -> std::io::Result<()> {
+    // SAFETY: safe because an invalid fd or operation will return an error
+    if unsafe { libc::flock(file.as_raw_fd(), operation) } == -1 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_cpu_set() {
+        let set = CpuSet::new(&[0, 1, 65]).unwrap();
+        assert_eq!(set.0[0], 0b11);
+        assert_eq!(set.0[1], 0b10);
+        assert!(CpuSet::new(&[CPU_SETSIZE]).is_none());
+    }
+
+    #[test]
+    fn test_exited_usage() {
+        let mut child = std::process::Command::new("sh")
+            .args(["-c", "cat >/dev/null"])
+            .stdin(std::process::Stdio::piped())
+            .spawn()
+            .unwrap();
+        let pid = child.id() as i32;
+        assert_eq!(exited_usage(pid).unwrap(), None);
+        drop(child.stdin.take());
+        let pidfd = pidfd_open(pid).unwrap();
+        smol::block_on(async { smol::Async::new(pidfd).unwrap().readable().await.unwrap() });
+        assert!(exited_usage(pid).unwrap().unwrap().maxrss > 0);
+        assert!(child.wait().unwrap().success());
+        assert!(exited_usage(pid).is_err());
+    }
+
+    #[test]
+    fn test_child_subreaper() {
+        set_child_subreaper(true).unwrap();
+        assert!(is_child_subreaper().unwrap());
+        set_child_subreaper(false).unwrap();
+        assert!(!is_child_subreaper().unwrap());
+    }
+
     #[test]
     fn test_signal_table() {
         assert_eq!(SIGNAL_TABLE.len(), 29);
@@ -117,6 +1249,100 @@ mod tests {
         });
     }
 
+    #[test]
+    fn test_getuid() {
+        let status = std::fs::read_to_string("/proc/self/status").unwrap();
+        let line = status
+            .lines()
+            .find(|line| line.starts_with("Uid:"))
+            .unwrap();
+        let uid: u32 = line.split_whitespace().nth(1).unwrap().parse().unwrap();
+        assert_eq!(getuid(), uid);
+    }
+
+    #[test]
+    fn test_sysconf() {
+        assert!(sysconf(SC_PAGESIZE).is_some_and(|size| size >= 4096));
+        assert!(sysconf(SC_CLK_TCK).is_some_and(|ticks| ticks > 0));
+        assert!(sysconf(-1).is_none());
+    }
+
+    #[test]
+    fn test_rlimit() {
+        // RLIMIT_NOFILE
+        let rlim = getrlimit(7).unwrap();
+        assert!(rlim.soft <= rlim.hard);
+        setrlimit(7, rlim).unwrap();
+        assert!(getrlimit(-1).is_err());
+    }
+
+    #[test]
+    fn test_mount_err() {
+        let err = mount(
+            Some("none"),
+            "/nonexistent/luavisors",
+            Some("tmpfs"),
+            0,
+            None,
+        );
+        assert!(err.is_err());
+        assert!(mount(None, "bad\0target", None, 0, None).is_err());
+        assert!(umount("/nonexistent/luavisors", 0).is_err());
+    }
+
+    #[test]
+    fn test_hostname() {
+        let name = gethostname().unwrap();
+        assert!(!name.is_empty());
+        assert!(sethostname(&"x".repeat(300)).is_err());
+    }
+
+    #[test]
+    fn test_if_nametoindex() {
+        assert!(if_nametoindex("lo").unwrap() > 0);
+        assert!(if_nametoindex("luavisors0").is_err());
+    }
+
+    #[test]
+    fn test_reserve_signal() {
+        assert!(!is_reserved(Signal::Usr2 as i32));
+        reserve_signal(Signal::Usr2 as i32);
+        assert!(is_reserved(Signal::Usr2 as i32));
+    }
+
+    #[test]
+    fn test_termios_size() {
+        assert_eq!(std::mem::size_of::<Termios>(), 60);
+    }
+
+    #[test]
+    fn test_window_size_err() {
+        let file = std::fs::File::open("/dev/null").unwrap();
+        assert!(window_size(std::os::fd::AsRawFd::as_raw_fd(&file)).is_err());
+    }
+
+    #[test]
+    fn test_isatty() {
+        assert!(!isatty(-1));
+    }
+
+    #[test]
+    fn test_terminal_mode_err() {
+        assert!(terminal_mode(-1, ICANON | ECHO).is_err());
+    }
+
+    #[test]
+    fn test_flock() {
+        let path = std::env::temp_dir().join(format!("luavisors-flock-{}", std::process::id()));
+        let first = std::fs::File::create(&path).unwrap();
+        let second = std::fs::File::open(&path).unwrap();
+        assert!(flock(&first, LOCK_EX | LOCK_NB).is_ok());
+        assert!(flock(&second, LOCK_SH | LOCK_NB).is_err());
+        assert!(flock(&first, LOCK_UN).is_ok());
+        assert!(flock(&second, LOCK_SH | LOCK_NB).is_ok());
+        std::fs::remove_file(path).unwrap();
+    }
+
     #[test]
     fn test_kill_err() {
         let pid = std::process::id() as i32;
@@ -0,0 +1,136 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Mutex, OnceLock},
+};
+
+use mlua::prelude::*;
+
+use crate::{digest, path};
+
+/// Digests which executables must match before they are spawned
+#[derive(Default)]
+struct Policy {
+    digests: HashMap<PathBuf, String>,
+    require: bool,
+}
+
+/// Return the supervisor-wide verification policy
+fn policy() -> &'static Mutex<Policy> {
+    static POLICY: OnceLock<Mutex<Policy>> = OnceLock::new();
+    POLICY.get_or_init(Mutex::default)
+}
+
+/// Normalize a hexadecimal digest for comparison
+fn normalize(digest: &str) -> String {
+    digest.trim().to_ascii_lowercase()
+}
+
+/// Resolve a command to its executable, then check its digest against `expected` or the policy
+///
+/// The digest is checked just before spawning, so a file replaced in between is
+/// not detected, which is acceptable for images which are expected to be immutable.
+pub fn check(cmd: &str, expected: Option<&str>) -> Result<Option<PathBuf>, String> {
+    let policy = policy().lock().unwrap_or_else(|err| err.into_inner());
+    check_with(&policy, cmd, expected)
+}
+
+/// Check the digest of a command against `expected` or a policy
+fn check_with(
+    policy: &Policy,
+    cmd: &str,
+    expected: Option<&str>,
+) -> Result<Option<PathBuf>, String> {
+    if expected.is_none() && !policy.require && policy.digests.is_empty() {
+        return Ok(None);
+    }
+    let path = path::find_executable(cmd);
+    let known = path
+        .as_deref()
+        .and_then(|path| path.canonicalize().ok())
+        .and_then(|path| policy.digests.get(&path));
+    let expected = match expected.map(normalize).or(known.cloned()) {
+        Some(expected) => expected,
+        None if policy.require => {
+            return Err(format!("refusing to run '{}': no known sha256", cmd))
+        }
+        None => return Ok(None),
+    };
+    let path = path.ok_or_else(|| format!("refusing to run '{}': executable not found", cmd))?;
+    let actual = digest::sha256_file(&path)
+        .map_err(|err| format!("refusing to run '{}': {}", path.display(), err))?;
+    match actual == expected {
+        true => Ok(Some(path)),
+        false => Err(format!(
+            "refusing to run '{}': sha256 is {} but expected {}",
+            path.display(),
+            actual,
+            expected
+        )),
+    }
+}
+
+/// Set the digests every spawned executable must match from Lua
+///
+/// Paths are canonicalized when the policy is set, so links to a listed binary
+/// are checked against its digest as well.
+pub async fn verify_policy(_lua: Lua, options: LuaTable) -> LuaResult<()> {
+    let new = policy_options(&options)?;
+    *policy().lock().unwrap_or_else(|err| err.into_inner()) = new;
+    Ok(())
+}
+
+/// Read a verification policy from an options table
+fn policy_options(options: &LuaTable) -> LuaResult<Policy> {
+    let mut digests = HashMap::new();
+    if let Some(sha256) = options.get::<Option<LuaTable>>("sha256")? {
+        for pair in sha256.pairs::<String, String>() {
+            let (path, digest) = pair?;
+            let path = Path::new(&path);
+            let path = path
+                .canonicalize()
+                .unwrap_or_else(|_| std::path::absolute(path).unwrap_or(path.to_path_buf()));
+            digests.insert(path, normalize(&digest));
+        }
+    }
+    let require = options.get::<Option<bool>>("require")?.unwrap_or(false);
+    Ok(Policy { digests, require })
+}
+
+/// Return the hexadecimal SHA-256 digest of a file from Lua
+pub async fn sha256(_lua: Lua, path: String) -> LuaResult<String> {
+    Ok(smol::unblock(move || digest::sha256_file(Path::new(&path))).await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_expected() {
+        let sh = path::find_executable("sh").unwrap();
+        let digest = digest::sha256_file(&sh).unwrap();
+        assert_eq!(check("sh", Some(&digest.to_uppercase())), Ok(Some(sh)));
+        let err = check("sh", Some("00")).unwrap_err();
+        assert!(err.contains("sha256 is"));
+        assert!(check("luavisors-missing-command", Some("00")).is_err());
+    }
+
+    #[test]
+    fn test_policy_options() {
+        let lua = Lua::new();
+        let sh = path::find_executable("sh").unwrap();
+        let digest = digest::sha256_file(&sh).unwrap();
+        let options = lua.create_table().unwrap();
+        let sha256 = lua.create_table().unwrap();
+        sha256.set(sh.display().to_string(), digest).unwrap();
+        options.set("sha256", sha256).unwrap();
+        options.set("require", true).unwrap();
+        let policy = policy_options(&options).unwrap();
+        assert!(check_with(&policy, "sh", None).unwrap().is_some());
+        let err = check_with(&policy, "rustc", None).unwrap_err();
+        assert!(err.contains("no known sha256"));
+        let policy = policy_options(&lua.create_table().unwrap()).unwrap();
+        assert_eq!(check_with(&policy, "rustc", None), Ok(None));
+    }
+}